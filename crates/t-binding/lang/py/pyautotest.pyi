@@ -1,3 +1,42 @@
+class DriverException(Exception):
+    """
+    runner stopped or answered an invalid response
+    """
+
+class UserException(Exception):
+    """
+    interrupted by user
+    """
+
+class AssertException(Exception):
+    """
+    assert failed, e.g. assert_screen not matched
+    """
+
+class TimeoutException(Exception): ...
+
+class UnexpectedException(Exception): ...
+
+class NoConsoleException(UnexpectedException):
+    """
+    console needed by the call is not connected
+    """
+
+class ConnectionBrokenException(UnexpectedException):
+    """
+    console is connected but reading or writing it failed
+    """
+
+class RegexBuildException(UnexpectedException):
+    """
+    pattern passed to wait_regex is not a valid regex
+    """
+
+class CommandFailedException(AssertException):
+    """
+    assert_script_run returned non-zero
+    """
+
 class Driver:
     """
     A driver for running test
@@ -7,9 +46,22 @@ class Driver:
 
     def __init__(self, toml_str: str) -> Driver: ...
 
+    def __enter__(self) -> Driver:
+        """
+        use as `with Driver(config) as d:`, the runner is stopped when the block exits
+        """
+
+    def __exit__(self, exc_type, exc_value, traceback) -> bool: ...
+
     def stop(self):
         """
-        stop the runner
+        stop the runner, calling it again is a no-op
+        """
+
+    def run_script(self, path: str):
+        """
+        run a whole script file like `autotest run`, only .js is supported,
+        blocks until the script's main returns, throw exception if it failed
         """
 
     def sleep(self, secs: int):
@@ -22,9 +74,60 @@ class Driver:
         get environment variable by key from toml env section
         """
 
-    def assert_script_run(self, cmd: str, timeout: int) -> str:
+    def get_env_int(self, key: str) -> int | None:
+        """
+        get environment variable as int, throw TypeError if it is not an integer
+        """
+
+    def get_env_bool(self, key: str) -> bool | None:
+        """
+        get environment variable as bool, throw TypeError if it is not a bool
+        """
+
+    def set_soft_assert(self, on: bool):
+        """
+        when on, failed assert_script_run, assert_screen and assert_image are recorded instead of throwing
+        """
+
+    def collect_failures(self) -> list[str]:
+        """
+        failures recorded in soft assert mode so far
+        """
+
+    def list_needles(self) -> list[str]:
+        """
+        tags of all needles in needle_dir
+        """
+
+    def needle_exists(self, tag: str) -> bool:
         """
-        run script in console, return stdout, throw exception if return code is not 0
+        whether needle png and json of tag are found, no screen is compared
+        """
+
+    def flush_assertions(self):
+        """
+        clear recorded failures, throw AssertException if there was any
+        """
+
+    def reconnect(self) -> list[str]:
+        """
+        stop all consoles and connect again with current config, return names of consoles reconnected
+        """
+
+    def assert_script_run(self, cmd: str, timeout: int, console: str | None = None) -> str:
+        """
+        run script in console, return stdout, throw exception if return code is not 0,
+        console is "serial" or "ssh", picked by connected ones if None
+        """
+
+    def assert_script_run_retry(self, cmd: str, timeout: int, retries: int, interval: int) -> str:
+        """
+        like assert_script_run, but rerun up to retries times with interval secs sleep, throw exception after last failure
+        """
+
+    def script_output_capture(self, cmd: str, left: str, right: str, timeout: int) -> str:
+        """
+        run script like assert_script_run, return output between left and right, throw exception if not found
         """
 
     def script_run(self, cmd: str, timeout: int) -> str:
@@ -32,11 +135,21 @@ class Driver:
         like assert_script_run, but not throw exception if return code is not 0
         """
 
+    def script_run_full(self, cmd: str, timeout: int) -> tuple[int, str, str]:
+        """
+        run script, return (code, stdout, stderr), stderr is only separated over ssh, empty for serial
+        """
+
     def write(self, s: str):
         """
         write string to console
         """
 
+    def write_password(self, s: str):
+        """
+        write password to console, content is not logged
+        """
+
     def writeln(self, s: str):
         """
         write string with '\n' to console
@@ -52,6 +165,29 @@ class Driver:
         wait pattern in console output, if timeout, throw exception
         """
 
+    def wait_regex(self, pattern: str, timeout: int) -> str:
+        """
+        wait regex in console output, return the matched text, throw exception if timeout or regex is invalid
+        """
+
+    def expect(self, steps: list[tuple[str, str]], timeout: int):
+        """
+        for each (pattern, response), wait pattern in console output then write response,
+        timeout is per step, throw exception naming the step if timeout
+        """
+
+    def wait_any_string(self, targets: list[tuple[str, str]], timeout: int) -> tuple[str, str]:
+        """
+        wait on several consoles at once, targets are (console, string) with console "serial" or "ssh",
+        return the target matched first, throw exception if timeout
+        """
+
+    def wait_boot(self, timeout: int, serial: str | None = None, screen: str | None = None) -> str:
+        """
+        wait until serial output matches regex serial or screen matches needle tag screen, whichever first,
+        return "serial" or "screen", throw exception if timeout
+        """
+
     def ssh_assert_script_run(self, cmd: str, timeout: int) -> str:
         """
         run script in ssh, return stdout, throw exception if return code is not 0
@@ -67,6 +203,16 @@ class Driver:
         write string to ssh console
         """
 
+    def ssh_expect(self, steps: list[tuple[str, str]], timeout: int):
+        """
+        like expect, but on ssh console
+        """
+
+    def ssh_write_password(self, s: str):
+        """
+        write password to ssh console, content is not logged
+        """
+
     def ssh_assert_script_run_seperate(self, cmd: str, timeout: int) -> str:
         """
         run script in seperate ssh session, return stdout, throw exception if return code is not 0
@@ -87,6 +233,21 @@ class Driver:
         write string to ssh console
         """
 
+    def serial_expect(self, steps: list[tuple[str, str]], timeout: int):
+        """
+        like expect, but on serial console
+        """
+
+    def serial_write_password(self, s: str):
+        """
+        write password to serial console, content is not logged
+        """
+
+    def serial_send_break(self, ms: int):
+        """
+        hold serial break for ms, e.g. to stop bootloader autoboot, throw exception if adapter doesn't support it
+        """
+
     def assert_screen(self, tag: str, timeout: int):
         """
         check screen, throw exception if timeout, or not similar to tag
@@ -97,29 +258,127 @@ class Driver:
         check screen, return false if timeout, or not similar to tag
         """
 
+    def check_screen_stats(self, tag: str, timeout: int) -> dict | None:
+        """
+        check screen, return {"similarity", "elapsed_ms", "iterations"} when matched, None if timeout
+        """
+
+    def is_screen(self, tag: str) -> bool:
+        """
+        compare current screen with tag once, return immediately
+        """
+
+    def assert_image(self, path: str, region: tuple[int, int, int, int] | None, threshold: float, timeout: int):
+        """
+        check screen against a png file without json, region is (left, top, width, height), None for the whole image, throw exception if timeout
+        """
+
+    def check_image(self, path: str, region: tuple[int, int, int, int] | None, threshold: float, timeout: int) -> bool:
+        """
+        like assert_image, but return false if timeout
+        """
+
+    def assert_screen_any(self, tags: list[str], timeout: int) -> str:
+        """
+        check screen against multiple tags, return the first matched tag, throw exception if timeout
+        """
+
+    def check_screen_any(self, tags: list[str], timeout: int) -> str | None:
+        """
+        check screen against multiple tags, return the first matched tag, or None if timeout
+        """
+
     def type_string(self, s: str):
         """
         type string
         """
 
+    def type_password(self, s: str):
+        """
+        type password, content is not logged
+        """
+
+    def paste_string(self, s: str):
+        """
+        paste string through guest clipboard, much faster than type_string for long text,
+        falls back to type_string when clipboard is disabled in config
+        """
+
     def send_key(self):
         """
         send event
         """
 
+    def batch(self, actions: list[tuple[str | int, ...]]):
+        """
+        run vnc actions in one round trip, e.g. [("mouse_move", 10, 20), ("mouse_click",), ("send_key", "ret")],
+        stops at first error and raises it.
+        supports mouse_move, mouse_move_rel, mouse_drag, mouse_hide, mouse_click, mouse_mclick, mouse_rclick,
+        mouse_click_button, mouse_dclick, mouse_keydown, mouse_keyup, send_key, send_keysym, send_key_down,
+        send_key_up and type_string
+        """
+
+    def send_keysym(self, codes: list[int]):
+        """
+        press and release raw x11 keysyms together, e.g. [0xffb1] for keypad 1
+        """
+
+    def send_key_down(self, s: str):
+        """
+        press key combo and hold, e.g. "ctrl-alt"
+        """
+
+    def send_key_up(self, s: str):
+        """
+        release key combo pressed by send_key_down
+        """
+
     def vnc_refresh(self):
         """
         force refresh
         """
 
-    def check_and_click(self, tag: str, timeout: int) -> bool:
+    def refresh_and_wait(self, timeout: int) -> bytes:
+        """
+        force refresh and wait the next frame, return it encoded as png, throw exception if timeout
+        """
+
+    def get_screenshot_png(self) -> bytes:
+        """
+        latest vnc screen encoded as png
+        """
+
+    def get_screen_size(self) -> tuple[int, int]:
         """
-        check screen, click if similar to tag
+        current vnc screen size as (width, height), follows resolution change
         """
 
-    def assert_and_click(self, tag: str, timeout: int):
+    def vnc_get_resolution(self) -> tuple[int, int]:
         """
-        check screen, click if similar to tag, throw exception if timeout
+        same as get_screen_size
+        """
+
+    def vnc_set_resolution(self, width: int, height: int):
+        """
+        ask vnc server to resize the desktop and wait for its answer,
+        throw exception with the reason if it refuses, needs extended desktop size support, e.g. qemu
+        """
+
+    def vnc_is_connected(self) -> bool:
+        """
+        whether vnc is connected right now, false while reconnecting, e.g. during guest reboot
+        """
+
+    def check_and_click(self, tag: str, timeout: int, area: int | str | None = None) -> bool:
+        """
+        check screen, click if similar to tag,
+        area is the index or name of needle area to click, the first clickable area if None
+        """
+
+    def assert_and_click(self, tag: str, timeout: int, area: int | str | None = None):
+        """
+        check screen, click if similar to tag, throw exception if timeout,
+        area is the index or name of needle area to click, the first clickable area if None
         """
 
     def mouse_click(self):
@@ -132,6 +391,21 @@ class Driver:
         click mouse right button
         """
 
+    def mouse_mclick(self):
+        """
+        click mouse middle button
+        """
+
+    def mouse_click_button(self, button: int):
+        """
+        click mouse button 1..=8, 1 left, 2 middle, 3 right, 4 and 5 wheel up and down
+        """
+
+    def mouse_dclick(self):
+        """
+        double click mouse
+        """
+
     def mouse_keydown(self):
         """
         mouse left button down
@@ -147,6 +421,50 @@ class Driver:
         move mouse to x, y
         """
 
+    def mouse_move_rel(self, dx: int, dy: int):
+        """
+        move mouse by dx, dy from current position, clamped to screen
+        """
+
+    def mouse_pos(self) -> tuple[int, int]:
+        """
+        current mouse position as (x, y)
+        """
+
+    def bell_count(self) -> int:
+        """
+        bells rung by vnc server since connected
+        """
+
+    def wait_bell(self, timeout: int):
+        """
+        wait a bell rung after this call, throw exception if timeout,
+        compare bell_count before and after an action to catch an earlier one
+        """
+
+    def wait_screen_still(self, timeout: int, stable_for: int):
+        """
+        wait until screen not change for stable_for secs, throw exception if timeout
+        """
+
+    def wait_screen_gone(self, tag: str, timeout: int, stable_for: int = 1):
+        """
+        wait until needle has not matched for stable_for secs, e.g. a loading spinner,
+        throw exception with last similarity if timeout
+        """
+
+    def assert_no_screen(self, tag: str, duration: int):
+        """
+        check needle for duration secs, throw AssertException as soon as it matches,
+        the matched screen is saved with the screenshots
+        """
+
+    def golden_screen(self, tag: str, timeout: int):
+        """
+        save current screen as needle tag into needle_dir if it doesn't exist, assert_screen it otherwise,
+        set UPDATE_GOLDEN=1 in environment or config env to capture it again
+        """
+
     def mouse_hide(self):
         """
         hide mouse
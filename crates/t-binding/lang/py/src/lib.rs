@@ -4,11 +4,13 @@
 mod api;
 use api::PyApi;
 use pyo3::{
-    exceptions::{self, PyException, PyTypeError},
+    exceptions::{self, PyException, PyFileNotFoundError, PyTypeError, PyValueError},
     prelude::*,
+    types::{PyBytes, PyDict},
 };
 use std::{
     env,
+    path::Path,
     sync::{
         mpsc::{Receiver, Sender},
         Arc, Mutex,
@@ -16,8 +18,9 @@ use std::{
     time::Duration,
 };
 use t_binding::{
-    api::{Api, ApiTx},
-    ApiError, MsgReq, MsgRes,
+    api::{ActionArg, Api, ApiTx},
+    msg::AreaSelector,
+    ApiError, JSEngine, MsgReq, MsgRes, TextConsole,
 };
 use t_config::{Config, ConsoleSSH};
 use t_console::SSH;
@@ -25,11 +28,35 @@ use t_runner::{Driver as InnerDriver, DriverBuilder};
 use tracing::{error, Level};
 use tracing_subscriber::FmtSubscriber;
 
-pyo3::create_exception!(defaultmodule, DriverException, PyException);
-pyo3::create_exception!(defaultmodule, UserException, PyException);
-pyo3::create_exception!(defaultmodule, AssertException, PyException);
-pyo3::create_exception!(defaultmodule, TimeoutException, PyException);
-pyo3::create_exception!(defaultmodule, UnexpectedException, PyException);
+// module of exceptions and the pymodule fn must both be the [lib] name in Cargo.toml,
+// python imports PyInit_<lib name>, test_module_name checks they agree
+pyo3::create_exception!(pyautotest, DriverException, PyException);
+pyo3::create_exception!(pyautotest, UserException, PyException);
+pyo3::create_exception!(pyautotest, AssertException, PyException);
+pyo3::create_exception!(pyautotest, TimeoutException, PyException);
+pyo3::create_exception!(pyautotest, UnexpectedException, PyException);
+// subclasses, so scripts catching the general ones keep working
+pyo3::create_exception!(pyautotest, NoConsoleException, UnexpectedException);
+pyo3::create_exception!(pyautotest, ConnectionBrokenException, UnexpectedException);
+pyo3::create_exception!(pyautotest, RegexBuildException, UnexpectedException);
+pyo3::create_exception!(pyautotest, CommandFailedException, AssertException);
+
+fn py_console(name: &str) -> PyResult<TextConsole> {
+    TextConsole::from_name(name).ok_or_else(|| {
+        PyValueError::new_err(format!(
+            "unknown console {name:?}, should be \"serial\" or \"ssh\""
+        ))
+    })
+}
+
+fn py_action_arg(arg: &Bound<'_, PyAny>) -> PyResult<ActionArg> {
+    if let Ok(n) = arg.extract::<i64>() {
+        return Ok(ActionArg::Int(n));
+    }
+    arg.extract::<String>().map(ActionArg::Str).map_err(|_| {
+        PyTypeError::new_err(format!("batch action arg should be int or str, got {arg}"))
+    })
+}
 
 fn into_pyerr(e: ApiError) -> PyErr {
     match e {
@@ -41,6 +68,27 @@ fn into_pyerr(e: ApiError) -> PyErr {
         ApiError::Timeout => TimeoutException::new_err("timeout"),
         ApiError::AssertFailed => AssertException::new_err("assert failed"),
         ApiError::Interrupt => UserException::new_err("interrupted by user"),
+        ApiError::InvalidEnvType(s) => PyTypeError::new_err(s),
+        e @ ApiError::NoConsole(_) => NoConsoleException::new_err(e.to_string()),
+        e @ ApiError::ConnectionBroken(_) => ConnectionBrokenException::new_err(e.to_string()),
+        e @ ApiError::RegexBuild(_) => RegexBuildException::new_err(e.to_string()),
+        e @ ApiError::CommandFailed { .. } => CommandFailedException::new_err(e.to_string()),
+    }
+}
+
+// needle area from python, int is index and str is name
+#[derive(FromPyObject)]
+enum AreaArg {
+    Index(usize),
+    Name(String),
+}
+
+impl From<AreaArg> for AreaSelector {
+    fn from(value: AreaArg) -> Self {
+        match value {
+            AreaArg::Index(i) => Self::Index(i),
+            AreaArg::Name(name) => Self::Name(name),
+        }
     }
 }
 
@@ -49,8 +97,38 @@ fn into_pyerr(e: ApiError) -> PyErr {
 fn pyautotest(py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     init_logger();
 
-    tracing::info!("pyautotest module initialized");
+    tracing::info!("{} module initialized", env!("CARGO_CRATE_NAME"));
     m.add_class::<Driver>()?;
+    add_exceptions(py, m)?;
+    Ok(())
+}
+
+// so scripts can `except pyautotest.AssertException`
+fn add_exceptions(py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("DriverException", py.get_type_bound::<DriverException>())?;
+    m.add("UserException", py.get_type_bound::<UserException>())?;
+    m.add("AssertException", py.get_type_bound::<AssertException>())?;
+    m.add("TimeoutException", py.get_type_bound::<TimeoutException>())?;
+    m.add(
+        "UnexpectedException",
+        py.get_type_bound::<UnexpectedException>(),
+    )?;
+    m.add(
+        "NoConsoleException",
+        py.get_type_bound::<NoConsoleException>(),
+    )?;
+    m.add(
+        "ConnectionBrokenException",
+        py.get_type_bound::<ConnectionBrokenException>(),
+    )?;
+    m.add(
+        "RegexBuildException",
+        py.get_type_bound::<RegexBuildException>(),
+    )?;
+    m.add(
+        "CommandFailedException",
+        py.get_type_bound::<CommandFailedException>(),
+    )?;
     Ok(())
 }
 
@@ -86,6 +164,7 @@ struct Driver {
     config: Config,
     driver: InnerDriver,
     tx: ApiTx,
+    stopped: bool,
 }
 
 #[pymethods]
@@ -104,9 +183,25 @@ impl Driver {
             tx: driver.msg_tx.clone(),
             driver,
             config,
+            stopped: false,
         })
     }
 
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    // stop even if the block raised, the exception is not suppressed
+    fn __exit__(
+        &mut self,
+        _exc_type: &Bound<'_, PyAny>,
+        _exc_value: &Bound<'_, PyAny>,
+        _traceback: &Bound<'_, PyAny>,
+    ) -> bool {
+        self.stop();
+        false
+    }
+
     // ssh
     fn new_ssh(&self) -> PyResult<DriverSSH> {
         let Some(ssh) = self.config.ssh.clone() else {
@@ -115,10 +210,35 @@ impl Driver {
         DriverSSH::new(ssh)
     }
 
+    // safe to call more than once
     fn stop(&mut self) {
+        if self.stopped {
+            return;
+        }
+        self.stopped = true;
         self.driver.stop();
     }
 
+    // run a whole script file like cli `run`, engine is picked by file extension
+    fn run_script(&self, py: Python<'_>, path: String) -> PyResult<()> {
+        let ext = Path::new(&path)
+            .extension()
+            .map(|e| e.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if ext != "js" {
+            return Err(DriverException::new_err(format!(
+                "unsupported script type: [{ext}]"
+            )));
+        }
+        if !Path::new(&path).is_file() {
+            return Err(PyFileNotFoundError::new_err(path));
+        }
+        let tx = self.tx.clone();
+        // engine talks to server directly, no need to hold gil
+        py.allow_threads(move || JSEngine::new(tx).run_file(&path))
+            .map_err(|e| into_pyerr(ApiError::String(e)))
+    }
+
     fn sleep(&self, py: Python<'_>, miles: i32) {
         PyApi::new(&self.tx, py).sleep(miles as u64);
     }
@@ -127,9 +247,88 @@ impl Driver {
         PyApi::new(&self.tx, py).get_env(key).map_err(into_pyerr)
     }
 
-    fn assert_script_run(&self, py: Python<'_>, cmd: String, timeout: i32) -> PyResult<String> {
+    fn get_env_int(&self, py: Python<'_>, key: String) -> PyResult<Option<i64>> {
+        PyApi::new(&self.tx, py)
+            .get_env_int(key)
+            .map_err(into_pyerr)
+    }
+
+    fn get_env_bool(&self, py: Python<'_>, key: String) -> PyResult<Option<bool>> {
+        PyApi::new(&self.tx, py)
+            .get_env_bool(key)
+            .map_err(into_pyerr)
+    }
+
+    // soft assert
+    fn set_soft_assert(&self, py: Python<'_>, on: bool) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .set_soft_assert(on)
+            .map_err(into_pyerr)
+    }
+
+    fn collect_failures(&self, py: Python<'_>) -> PyResult<Vec<String>> {
+        PyApi::new(&self.tx, py)
+            .collect_failures()
+            .map_err(into_pyerr)
+    }
+
+    fn list_needles(&self, py: Python<'_>) -> PyResult<Vec<String>> {
+        PyApi::new(&self.tx, py).list_needles().map_err(into_pyerr)
+    }
+
+    fn needle_exists(&self, py: Python<'_>, tag: String) -> PyResult<bool> {
+        PyApi::new(&self.tx, py)
+            .needle_exists(tag)
+            .map_err(into_pyerr)
+    }
+
+    fn flush_assertions(&self, py: Python<'_>) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .flush_assertions()
+            .map_err(into_pyerr)
+    }
+
+    fn reconnect(&self, py: Python<'_>) -> PyResult<Vec<String>> {
+        PyApi::new(&self.tx, py).reconnect().map_err(into_pyerr)
+    }
+
+    #[pyo3(signature = (cmd, timeout, console=None))]
+    fn assert_script_run(
+        &self,
+        py: Python<'_>,
+        cmd: String,
+        timeout: i32,
+        console: Option<String>,
+    ) -> PyResult<String> {
+        let console = console.as_deref().map(py_console).transpose()?;
+        PyApi::new(&self.tx, py)
+            .assert_script_run_console(cmd, console, timeout)
+            .map_err(into_pyerr)
+    }
+
+    fn assert_script_run_retry(
+        &self,
+        py: Python<'_>,
+        cmd: String,
+        timeout: i32,
+        retries: u32,
+        interval: i32,
+    ) -> PyResult<String> {
+        PyApi::new(&self.tx, py)
+            .assert_script_run_retry(cmd, timeout, retries, interval)
+            .map_err(into_pyerr)
+    }
+
+    fn script_output_capture(
+        &self,
+        py: Python<'_>,
+        cmd: String,
+        left: String,
+        right: String,
+        timeout: i32,
+    ) -> PyResult<String> {
         PyApi::new(&self.tx, py)
-            .assert_script_run(cmd, timeout)
+            .script_output_capture(cmd, left, right, timeout)
             .map_err(into_pyerr)
     }
 
@@ -139,10 +338,27 @@ impl Driver {
             .map_err(into_pyerr)
     }
 
+    fn script_run_full(
+        &self,
+        py: Python<'_>,
+        cmd: String,
+        timeout: i32,
+    ) -> PyResult<(i32, String, String)> {
+        PyApi::new(&self.tx, py)
+            .script_run_full(cmd, timeout)
+            .map_err(into_pyerr)
+    }
+
     fn write(&self, py: Python<'_>, s: String) -> PyResult<()> {
         PyApi::new(&self.tx, py).write(s).map_err(into_pyerr)
     }
 
+    fn write_password(&self, py: Python<'_>, s: String) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .write_password(s)
+            .map_err(into_pyerr)
+    }
+
     fn writeln(&self, py: Python<'_>, s: String) -> PyResult<()> {
         PyApi::new(&self.tx, py)
             .write(format!("{s}\n"))
@@ -159,6 +375,47 @@ impl Driver {
             .map_err(into_pyerr)
     }
 
+    fn wait_regex(&self, py: Python<'_>, pattern: String, timeout: i32) -> PyResult<String> {
+        PyApi::new(&self.tx, py)
+            .wait_regex(pattern, timeout)
+            .map_err(into_pyerr)
+    }
+
+    fn expect(&self, py: Python<'_>, steps: Vec<(String, String)>, timeout: i32) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .expect(steps, timeout)
+            .map_err(into_pyerr)
+    }
+
+    #[pyo3(signature = (timeout, serial=None, screen=None))]
+    fn wait_boot(
+        &self,
+        py: Python<'_>,
+        timeout: i32,
+        serial: Option<String>,
+        screen: Option<String>,
+    ) -> PyResult<String> {
+        PyApi::new(&self.tx, py)
+            .wait_boot(serial, screen, timeout)
+            .map_err(into_pyerr)
+    }
+
+    fn wait_any_string(
+        &self,
+        py: Python<'_>,
+        targets: Vec<(String, String)>,
+        timeout: i32,
+    ) -> PyResult<(String, String)> {
+        let targets = targets
+            .into_iter()
+            .map(|(name, s)| py_console(&name).map(|c| (c, s)))
+            .collect::<PyResult<Vec<_>>>()?;
+        PyApi::new(&self.tx, py)
+            .wait_any_string(targets, timeout)
+            .map(|(console, s)| (console.name().to_string(), s))
+            .map_err(into_pyerr)
+    }
+
     // ssh
     fn ssh_assert_script_run(&self, py: Python<'_>, cmd: String, timeout: i32) -> PyResult<String> {
         PyApi::new(&self.tx, py)
@@ -176,6 +433,23 @@ impl Driver {
         PyApi::new(&self.tx, py).ssh_write(s);
     }
 
+    fn ssh_expect(
+        &self,
+        py: Python<'_>,
+        steps: Vec<(String, String)>,
+        timeout: i32,
+    ) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .ssh_expect(steps, timeout)
+            .map_err(into_pyerr)
+    }
+
+    fn ssh_write_password(&self, py: Python<'_>, s: String) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .ssh_write_password(s)
+            .map_err(into_pyerr)
+    }
+
     fn ssh_assert_script_run_seperate(
         &self,
         py: Python<'_>,
@@ -214,6 +488,29 @@ impl Driver {
         PyApi::new(&self.tx, py).serial_write(s);
     }
 
+    fn serial_expect(
+        &self,
+        py: Python<'_>,
+        steps: Vec<(String, String)>,
+        timeout: i32,
+    ) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .serial_expect(steps, timeout)
+            .map_err(into_pyerr)
+    }
+
+    fn serial_write_password(&self, py: Python<'_>, s: String) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .serial_write_password(s)
+            .map_err(into_pyerr)
+    }
+
+    fn serial_send_break(&self, py: Python<'_>, ms: u64) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .serial_send_break(ms)
+            .map_err(into_pyerr)
+    }
+
     // vnc
     fn check_screen(&self, py: Python<'_>, tag: String, timeout: i32) -> PyResult<bool> {
         PyApi::new(&self.tx, py)
@@ -221,35 +518,205 @@ impl Driver {
             .map_err(into_pyerr)
     }
 
+    fn check_screen_stats(
+        &self,
+        py: Python<'_>,
+        tag: String,
+        timeout: i32,
+    ) -> PyResult<Option<Py<PyDict>>> {
+        let Some(stats) = PyApi::new(&self.tx, py)
+            .vnc_check_screen_stats(tag, timeout)
+            .map_err(into_pyerr)?
+        else {
+            return Ok(None);
+        };
+        let dict = PyDict::new_bound(py);
+        dict.set_item("similarity", stats.similarity)?;
+        dict.set_item("elapsed_ms", stats.elapsed.as_millis() as u64)?;
+        dict.set_item("iterations", stats.iterations)?;
+        Ok(Some(dict.unbind()))
+    }
+
+    fn is_screen(&self, py: Python<'_>, tag: String) -> PyResult<bool> {
+        PyApi::new(&self.tx, py)
+            .vnc_is_screen(tag)
+            .map_err(into_pyerr)
+    }
+
     fn assert_screen(&self, py: Python<'_>, tag: String, timeout: i32) -> PyResult<()> {
         PyApi::new(&self.tx, py)
             .vnc_assert_screen(tag, timeout)
             .map_err(into_pyerr)
     }
 
+    #[pyo3(signature = (path, region, threshold, timeout))]
+    fn check_image(
+        &self,
+        py: Python<'_>,
+        path: String,
+        region: Option<(u16, u16, u16, u16)>,
+        threshold: f32,
+        timeout: i32,
+    ) -> PyResult<bool> {
+        PyApi::new(&self.tx, py)
+            .vnc_check_image(path, region, threshold, timeout)
+            .map_err(into_pyerr)
+    }
+
+    #[pyo3(signature = (path, region, threshold, timeout))]
+    fn assert_image(
+        &self,
+        py: Python<'_>,
+        path: String,
+        region: Option<(u16, u16, u16, u16)>,
+        threshold: f32,
+        timeout: i32,
+    ) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_assert_image(path, region, threshold, timeout)
+            .map_err(into_pyerr)
+    }
+
+    fn check_screen_any(
+        &self,
+        py: Python<'_>,
+        tags: Vec<String>,
+        timeout: i32,
+    ) -> PyResult<Option<String>> {
+        PyApi::new(&self.tx, py)
+            .vnc_check_screen_any(tags, timeout)
+            .map_err(into_pyerr)
+    }
+
+    fn assert_screen_any(
+        &self,
+        py: Python<'_>,
+        tags: Vec<String>,
+        timeout: i32,
+    ) -> PyResult<String> {
+        PyApi::new(&self.tx, py)
+            .vnc_assert_screen_any(tags, timeout)
+            .map_err(into_pyerr)
+    }
+
     fn type_string(&self, py: Python<'_>, s: String) -> PyResult<()> {
         PyApi::new(&self.tx, py)
             .vnc_type_string(s)
             .map_err(into_pyerr)
     }
 
+    fn type_password(&self, py: Python<'_>, s: String) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_type_password(s)
+            .map_err(into_pyerr)
+    }
+
+    fn paste_string(&self, py: Python<'_>, s: String) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_paste_string(s)
+            .map_err(into_pyerr)
+    }
+
     fn send_key(&self, py: Python<'_>, s: String) -> PyResult<()> {
         PyApi::new(&self.tx, py).vnc_send_key(s).map_err(into_pyerr)
     }
 
+    // actions like ("mouse_move", 10, 20), args are int or str
+    fn batch(&self, py: Python<'_>, actions: Vec<Vec<Bound<'_, PyAny>>>) -> PyResult<()> {
+        let actions = actions
+            .iter()
+            .map(|action| action.iter().map(py_action_arg).collect())
+            .collect::<PyResult<Vec<_>>>()?;
+        PyApi::new(&self.tx, py)
+            .batch_actions(actions)
+            .map_err(into_pyerr)
+    }
+
+    fn send_keysym(&self, py: Python<'_>, codes: Vec<u32>) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_send_keysym(codes)
+            .map_err(into_pyerr)
+    }
+
+    fn send_key_down(&self, py: Python<'_>, s: String) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_send_key_down(s)
+            .map_err(into_pyerr)
+    }
+
+    fn send_key_up(&self, py: Python<'_>, s: String) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_send_key_up(s)
+            .map_err(into_pyerr)
+    }
+
+    fn get_screenshot_png<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let screen = PyApi::new(&self.tx, py)
+            .vnc_get_screenshot()
+            .map_err(into_pyerr)?;
+        Ok(PyBytes::new_bound(py, &screen.to_png_bytes()))
+    }
+
     fn vnc_refresh(&self, py: Python<'_>) -> PyResult<()> {
         PyApi::new(&self.tx, py).vnc_refresh().map_err(into_pyerr)
     }
 
-    fn check_and_click(&self, py: Python<'_>, tag: String, timeout: i32) -> PyResult<bool> {
+    fn refresh_and_wait<'py>(
+        &self,
+        py: Python<'py>,
+        timeout: i32,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let screen = PyApi::new(&self.tx, py)
+            .vnc_refresh_and_wait(timeout)
+            .map_err(into_pyerr)?;
+        Ok(PyBytes::new_bound(py, &screen.to_png_bytes()))
+    }
+
+    fn get_screen_size(&self, py: Python<'_>) -> PyResult<(u16, u16)> {
+        PyApi::new(&self.tx, py).vnc_get_size().map_err(into_pyerr)
+    }
+
+    fn vnc_get_resolution(&self, py: Python<'_>) -> PyResult<(u16, u16)> {
+        PyApi::new(&self.tx, py)
+            .vnc_get_resolution()
+            .map_err(into_pyerr)
+    }
+
+    fn vnc_set_resolution(&self, py: Python<'_>, width: u16, height: u16) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_set_resolution(width, height)
+            .map_err(into_pyerr)
+    }
+
+    fn vnc_is_connected(&self, py: Python<'_>) -> PyResult<bool> {
+        PyApi::new(&self.tx, py)
+            .vnc_is_connected()
+            .map_err(into_pyerr)
+    }
+
+    #[pyo3(signature = (tag, timeout, area=None))]
+    fn check_and_click(
+        &self,
+        py: Python<'_>,
+        tag: String,
+        timeout: i32,
+        area: Option<AreaArg>,
+    ) -> PyResult<bool> {
         PyApi::new(&self.tx, py)
-            .vnc_check_and_click(tag, timeout)
+            .vnc_check_and_click_area(tag, area.map(AreaSelector::from), timeout)
             .map_err(into_pyerr)
     }
 
-    fn assert_and_click(&self, py: Python<'_>, tag: String, timeout: i32) -> PyResult<()> {
+    #[pyo3(signature = (tag, timeout, area=None))]
+    fn assert_and_click(
+        &self,
+        py: Python<'_>,
+        tag: String,
+        timeout: i32,
+        area: Option<AreaArg>,
+    ) -> PyResult<()> {
         PyApi::new(&self.tx, py)
-            .vnc_assert_and_click(tag, timeout)
+            .vnc_assert_and_click_area(tag, area.map(AreaSelector::from), timeout)
             .map_err(into_pyerr)
     }
 
@@ -265,6 +732,24 @@ impl Driver {
             .map_err(into_pyerr)
     }
 
+    fn mouse_mclick(&self, py: Python<'_>) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_mouse_mclick()
+            .map_err(into_pyerr)
+    }
+
+    fn mouse_click_button(&self, py: Python<'_>, button: u8) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_mouse_click_button(button)
+            .map_err(into_pyerr)
+    }
+
+    fn mouse_dclick(&self, py: Python<'_>) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_mouse_dclick()
+            .map_err(into_pyerr)
+    }
+
     fn mouse_keydown(&self, py: Python<'_>) -> PyResult<()> {
         PyApi::new(&self.tx, py)
             .vnc_mouse_keydown()
@@ -283,6 +768,59 @@ impl Driver {
             .map_err(into_pyerr)
     }
 
+    fn mouse_move_rel(&self, py: Python<'_>, dx: i16, dy: i16) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_mouse_move_rel(dx, dy)
+            .map_err(into_pyerr)
+    }
+
+    fn mouse_pos(&self, py: Python<'_>) -> PyResult<(u16, u16)> {
+        PyApi::new(&self.tx, py).vnc_mouse_pos().map_err(into_pyerr)
+    }
+
+    fn bell_count(&self, py: Python<'_>) -> PyResult<u64> {
+        PyApi::new(&self.tx, py)
+            .vnc_bell_count()
+            .map_err(into_pyerr)
+    }
+
+    fn wait_bell(&self, py: Python<'_>, timeout: i32) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_wait_bell(timeout)
+            .map_err(into_pyerr)
+    }
+
+    fn wait_screen_still(&self, py: Python<'_>, timeout: i32, stable_for: i32) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_wait_screen_still(timeout, stable_for)
+            .map_err(into_pyerr)
+    }
+
+    #[pyo3(signature = (tag, timeout, stable_for=1))]
+    fn wait_screen_gone(
+        &self,
+        py: Python<'_>,
+        tag: String,
+        timeout: i32,
+        stable_for: i32,
+    ) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_wait_screen_gone(tag, timeout, stable_for)
+            .map_err(into_pyerr)
+    }
+
+    fn assert_no_screen(&self, py: Python<'_>, tag: String, duration: i32) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_assert_no_screen(tag, duration)
+            .map_err(into_pyerr)
+    }
+
+    fn golden_screen(&self, py: Python<'_>, tag: String, timeout: i32) -> PyResult<()> {
+        PyApi::new(&self.tx, py)
+            .vnc_golden_screen(tag, timeout)
+            .map_err(into_pyerr)
+    }
+
     fn mouse_hide(&self, py: Python<'_>) -> PyResult<()> {
         PyApi::new(&self.tx, py)
             .vnc_mouse_hide()
@@ -357,4 +895,48 @@ mod test {
         })
         .unwrap()
     }
+
+    #[test]
+    fn test_module_name() {
+        pyo3::Python::with_gil(|py| -> pyo3::PyResult<()> {
+            let module = PyModule::new_bound(py, env!("CARGO_CRATE_NAME"))?;
+            super::add_exceptions(py, &module)?;
+            for name in [
+                "DriverException",
+                "UserException",
+                "AssertException",
+                "TimeoutException",
+                "UnexpectedException",
+                "NoConsoleException",
+                "ConnectionBrokenException",
+                "RegexBuildException",
+                "CommandFailedException",
+            ] {
+                let exception = module.getattr(name)?;
+                let module_name: String = exception.getattr("__module__")?.extract()?;
+                assert_eq!(module_name, env!("CARGO_CRATE_NAME"));
+            }
+            // catchable by type from python
+            let locals = pyo3::types::PyDict::new_bound(py);
+            locals.set_item("m", &module)?;
+            py.run_bound(
+                "try:\n    raise m.TimeoutException('t')\nexcept m.TimeoutException:\n    pass",
+                None,
+                Some(&locals),
+            )?;
+            // specific errors are still caught by the general ones
+            py.run_bound(
+                "try:\n    raise m.CommandFailedException('c')\nexcept m.AssertException:\n    pass",
+                None,
+                Some(&locals),
+            )?;
+            py.run_bound(
+                "try:\n    raise m.NoConsoleException('n')\nexcept m.UnexpectedException:\n    pass",
+                None,
+                Some(&locals),
+            )?;
+            Ok(())
+        })
+        .unwrap()
+    }
 }
@@ -1,13 +1,15 @@
 use super::error::{ApiError, Result};
 use crate::{
-    msg::{TextConsole, VNC},
-    MsgReq, MsgRes,
+    msg::{AreaSelector, MatchStats, TextConsole, VNC},
+    MsgReq, MsgRes, MsgResError,
 };
 use std::{
     sync::{mpsc, Arc},
+    thread,
     time::Duration,
 };
-use tracing::{info, trace, Level};
+use t_console::{ConnectionState, Rect};
+use tracing::{error, info, trace, Level};
 
 pub type ApiTx = mpsc::Sender<(MsgReq, mpsc::Sender<MsgRes>)>;
 
@@ -46,6 +48,104 @@ pub trait Api {
         Ok(res)
     }
 
+    // negative timeout means using default timeout in config
+    fn timeout(&self, secs: i32) -> Result<Duration> {
+        if secs >= 0 {
+            return Ok(Duration::from_secs(secs as u64));
+        }
+        match self.req(MsgReq::GetDefaultTimeout)? {
+            MsgRes::DefaultTimeout(timeout) => Ok(timeout),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    fn default_threshold(&self) -> Result<f32> {
+        match self.req(MsgReq::GetDefaultThreshold)? {
+            MsgRes::DefaultThreshold(threshold) => Ok(threshold),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // in soft assert mode the failure is recorded on server and Ok is returned
+    fn assert_failed(&self, msg: String) -> Result<()> {
+        self._assert_failed(msg, None)
+    }
+
+    // only counted by server metrics, so errors are ignored
+    fn assert_passed(&self) {
+        let _ = self.req(MsgReq::AssertPassed);
+    }
+
+    fn _assert_failed(&self, msg: String, code: Option<i32>) -> Result<()> {
+        match self.req(MsgReq::AssertFailed { msg, code })? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e @ MsgResError::CommandFailed { .. }) => Err(e.into()),
+            _ => Err(ApiError::AssertFailed),
+        }
+    }
+
+    fn set_soft_assert(&self, on: bool) -> Result<()> {
+        match self.req(MsgReq::SetSoftAssert(on))? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // failures recorded in soft assert mode so far
+    fn collect_failures(&self) -> Result<Vec<String>> {
+        match self.req(MsgReq::CollectFailures { clear: false })? {
+            MsgRes::Failures(failures) => Ok(failures),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // tags of all needles in needle_dir
+    fn list_needles(&self) -> Result<Vec<String>> {
+        match self.req(MsgReq::ListNeedles)? {
+            MsgRes::Needles(tags) => Ok(tags),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // needle png and json of tag are found and valid
+    fn needle_exists(&self, tag: String) -> Result<bool> {
+        match self.req(MsgReq::NeedleExists { tag })? {
+            MsgRes::NeedleExists(exists) => Ok(exists),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // clear recorded failures, fail if there was any
+    fn flush_assertions(&self) -> Result<()> {
+        let failures = match self.req(MsgReq::CollectFailures { clear: true })? {
+            MsgRes::Failures(failures) => failures,
+            MsgRes::Error(e) => return Err(e.into()),
+            _ => return Err(ApiError::ServerInvalidResponse),
+        };
+        if failures.is_empty() {
+            return Ok(());
+        }
+        for failure in failures.iter() {
+            error!(msg = "soft assert failed", reason = failure);
+        }
+        Err(ApiError::AssertFailed)
+    }
+
+    // return consoles reconnected, failed ones are no longer available
+    fn reconnect(&self) -> Result<Vec<String>> {
+        match self.req(MsgReq::Reconnect)? {
+            MsgRes::Reconnected(consoles) => Ok(consoles),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
     fn _script_run(
         &self,
         cmd: String,
@@ -55,7 +155,7 @@ pub trait Api {
         match self.req(MsgReq::ScriptRun {
             cmd,
             console,
-            timeout: Duration::from_secs(timeout as u64),
+            timeout: self.timeout(timeout)?,
         })? {
             MsgRes::ScriptRun { code, value } => Ok((code, value)),
             MsgRes::Error(e) => Err(e.into()),
@@ -70,16 +170,20 @@ pub trait Api {
         timeout: i32,
     ) -> Result<String> {
         match self.req(MsgReq::ScriptRun {
-            cmd,
+            cmd: cmd.clone(),
             console,
-            timeout: Duration::from_secs(timeout as u64),
+            timeout: self.timeout(timeout)?,
         })? {
             MsgRes::ScriptRun { code, value } => {
-                if code == 0 {
-                    Ok(value)
+                if code != 0 {
+                    self._assert_failed(
+                        format!("assert_script_run {cmd:?} returned {code}"),
+                        Some(code),
+                    )?;
                 } else {
-                    Err(ApiError::AssertFailed)
+                    self.assert_passed();
                 }
+                Ok(value)
             }
             MsgRes::Error(e) => Err(e.into()),
             _ => Err(ApiError::ServerInvalidResponse),
@@ -91,6 +195,21 @@ pub trait Api {
             s,
             console,
             timeout: Duration::from_secs(60),
+            secret: false,
+        })? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // like _write, but s is redacted from logs
+    fn _write_password(&self, s: String, console: Option<TextConsole>) -> Result<()> {
+        match self.req(MsgReq::WriteString {
+            s,
+            console,
+            timeout: Duration::from_secs(60),
+            secret: true,
         })? {
             MsgRes::Done => Ok(()),
             MsgRes::Error(e) => Err(e.into()),
@@ -102,7 +221,42 @@ pub trait Api {
         match self.req(MsgReq::WaitString {
             console,
             s,
-            timeout: Duration::from_secs(timeout as u64),
+            timeout: self.timeout(timeout)?,
+        })? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    fn _wait_regex(
+        &self,
+        console: Option<TextConsole>,
+        pattern: String,
+        timeout: i32,
+    ) -> Result<String> {
+        match self.req(MsgReq::WaitRegex {
+            console,
+            pattern,
+            timeout: self.timeout(timeout)?,
+        })? {
+            MsgRes::Matched(s) => Ok(s),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // steps are (pattern, response), error names the step timed out
+    fn _expect(
+        &self,
+        console: Option<TextConsole>,
+        steps: Vec<(String, String)>,
+        timeout: i32,
+    ) -> Result<()> {
+        match self.req(MsgReq::Expect {
+            console,
+            steps,
+            timeout: self.timeout(timeout)?,
         })? {
             MsgRes::Done => Ok(()),
             MsgRes::Error(e) => Err(e.into()),
@@ -110,6 +264,44 @@ pub trait Api {
         }
     }
 
+    // wait until any pattern shows on its console, return the target matched first
+    fn wait_any_string(
+        &self,
+        targets: Vec<(TextConsole, String)>,
+        timeout: i32,
+    ) -> Result<(TextConsole, String)> {
+        match self.req(MsgReq::WaitAnyString {
+            targets: targets.clone(),
+            timeout: self.timeout(timeout)?,
+        })? {
+            MsgRes::AnyMatched(i) => targets
+                .get(i)
+                .cloned()
+                .ok_or(ApiError::ServerInvalidResponse),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // serial regex or screen needle, whichever shows first, returns "serial" or "screen"
+    fn wait_boot(
+        &self,
+        serial: Option<String>,
+        screen: Option<String>,
+        timeout: i32,
+    ) -> Result<String> {
+        match self.req(MsgReq::WaitBoot {
+            serial,
+            screen,
+            threshold: self.default_threshold()?,
+            timeout: self.timeout(timeout)?,
+        })? {
+            MsgRes::Booted(source) => Ok(source),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
     // general
     fn print(&self, level: tracing::Level, msg: String) {
         match level {
@@ -133,7 +325,7 @@ pub trait Api {
         }
     }
 
-    fn get_env(&self, key: String) -> Result<Option<String>> {
+    fn get_env_value(&self, key: String) -> Result<Option<toml::Value>> {
         match self.req(MsgReq::GetConfig { key })? {
             MsgRes::ConfigValue(res) => Ok(res),
             MsgRes::Error(e) => Err(e.into()),
@@ -141,6 +333,47 @@ pub trait Api {
         }
     }
 
+    fn get_env(&self, key: String) -> Result<Option<String>> {
+        Ok(self.get_env_value(key)?.map(|v| match v {
+            toml::Value::String(s) => s,
+            v => v.to_string(),
+        }))
+    }
+
+    fn get_env_int(&self, key: String) -> Result<Option<i64>> {
+        let Some(v) = self.get_env_value(key.clone())? else {
+            return Ok(None);
+        };
+        match v {
+            toml::Value::Integer(i) => Ok(Some(i)),
+            toml::Value::String(s) => {
+                s.trim().parse().map(Some).map_err(|_| {
+                    ApiError::InvalidEnvType(format!("{key} = {s:?} is not an integer"))
+                })
+            }
+            v => Err(ApiError::InvalidEnvType(format!(
+                "{key} = {v} is not an integer"
+            ))),
+        }
+    }
+
+    fn get_env_bool(&self, key: String) -> Result<Option<bool>> {
+        let Some(v) = self.get_env_value(key.clone())? else {
+            return Ok(None);
+        };
+        match v {
+            toml::Value::Boolean(b) => Ok(Some(b)),
+            toml::Value::String(s) => s
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|_| ApiError::InvalidEnvType(format!("{key} = {s:?} is not a bool"))),
+            v => Err(ApiError::InvalidEnvType(format!(
+                "{key} = {v} is not a bool"
+            ))),
+        }
+    }
+
     // default
     fn script_run(&self, cmd: String, timeout: i32) -> Result<(i32, String)> {
         self._script_run(cmd, None, timeout)
@@ -150,10 +383,85 @@ pub trait Api {
         self._assert_script_run(cmd, None, timeout)
     }
 
+    // like assert_script_run on the given console, none picks one like assert_script_run
+    fn assert_script_run_console(
+        &self,
+        cmd: String,
+        console: Option<TextConsole>,
+        timeout: i32,
+    ) -> Result<String> {
+        self._assert_script_run(cmd, console, timeout)
+    }
+
+    // rerun cmd up to retries times until it returns 0, sleep interval secs between attempts
+    fn assert_script_run_retry(
+        &self,
+        cmd: String,
+        timeout: i32,
+        retries: u32,
+        interval: i32,
+    ) -> Result<String> {
+        let mut attempt = 0;
+        loop {
+            // only the last failed attempt counts as an assert failure
+            let (code, value) = self.script_run(cmd.clone(), timeout)?;
+            if code == 0 {
+                self.assert_passed();
+                return Ok(value);
+            }
+            if attempt >= retries {
+                self._assert_failed(
+                    format!("assert_script_run {cmd:?} returned {code}"),
+                    Some(code),
+                )?;
+                return Ok(value);
+            }
+            attempt += 1;
+            info!(
+                msg = "assert_script_run failed, retry",
+                cmd = cmd,
+                attempt = attempt
+            );
+            thread::sleep(Duration::from_secs(interval.max(0) as u64));
+        }
+    }
+
+    // run cmd and return output between left and right, like `assert_script_run` + regex
+    fn script_output_capture(
+        &self,
+        cmd: String,
+        left: String,
+        right: String,
+        timeout: i32,
+    ) -> Result<String> {
+        let output = self.assert_script_run(cmd, timeout)?;
+        capture_between(&output, &left, &right)
+    }
+
+    // return (code, stdout, stderr)
+    fn script_run_full(&self, cmd: String, timeout: i32) -> Result<(i32, String, String)> {
+        match self.req(MsgReq::ScriptRunFull {
+            cmd,
+            timeout: self.timeout(timeout)?,
+        })? {
+            MsgRes::ScriptRunFull {
+                code,
+                stdout,
+                stderr,
+            } => Ok((code, stdout, stderr)),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
     fn write(&self, s: String) -> Result<()> {
         self._write(s, None)
     }
 
+    fn write_password(&self, s: String) -> Result<()> {
+        self._write_password(s, None)
+    }
+
     fn try_wait_string(&self, s: String, timeout: i32) -> bool {
         self._wait_string(None, s, timeout).is_ok()
     }
@@ -162,6 +470,15 @@ pub trait Api {
         self._wait_string(None, s, timeout)
     }
 
+    // return the matched text
+    fn wait_regex(&self, pattern: String, timeout: i32) -> Result<String> {
+        self._wait_regex(None, pattern, timeout)
+    }
+
+    fn expect(&self, steps: Vec<(String, String)>, timeout: i32) -> Result<()> {
+        self._expect(None, steps, timeout)
+    }
+
     // serial
     fn serial_script_run(&self, cmd: String, timeout: i32) -> Result<(i32, String)> {
         self._script_run(cmd, Some(TextConsole::Serial), timeout)
@@ -175,18 +492,41 @@ pub trait Api {
         self._write(s, Some(TextConsole::Serial))
     }
 
+    fn serial_write_password(&self, s: String) -> Result<()> {
+        self._write_password(s, Some(TextConsole::Serial))
+    }
+
+    fn serial_expect(&self, steps: Vec<(String, String)>, timeout: i32) -> Result<()> {
+        self._expect(Some(TextConsole::Serial), steps, timeout)
+    }
+
+    // hold serial break for ms, e.g. to stop bootloader autoboot
+    fn serial_send_break(&self, ms: u64) -> Result<()> {
+        match self.req(MsgReq::SerialSendBreak {
+            duration: Duration::from_millis(ms),
+        })? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
     // ssh
     fn ssh_assert_script_run_seperate(&self, cmd: String, timeout: i32) -> Result<String> {
         match self.req(MsgReq::SSHScriptRunSeperate {
-            cmd,
-            timeout: Duration::from_secs(timeout as u64),
+            cmd: cmd.clone(),
+            timeout: self.timeout(timeout)?,
         })? {
             MsgRes::ScriptRun { code, value } => {
-                if code == 0 {
-                    Ok(value)
+                if code != 0 {
+                    self._assert_failed(
+                        format!("assert_script_run {cmd:?} returned {code}"),
+                        Some(code),
+                    )?;
                 } else {
-                    Err(ApiError::AssertFailed)
+                    self.assert_passed();
                 }
+                Ok(value)
             }
             MsgRes::Error(e) => Err(e.into()),
             _ => Err(ApiError::ServerInvalidResponse),
@@ -205,71 +545,198 @@ pub trait Api {
         self._write(s, Some(TextConsole::SSH))
     }
 
+    fn ssh_write_password(&self, s: String) -> Result<()> {
+        self._write_password(s, Some(TextConsole::SSH))
+    }
+
+    fn ssh_expect(&self, steps: Vec<(String, String)>, timeout: i32) -> Result<()> {
+        self._expect(Some(TextConsole::SSH), steps, timeout)
+    }
+
     // vnc
     fn vnc_check_screen(&self, tag: String, timeout: i32) -> Result<bool> {
         match self.req(MsgReq::VNC(VNC::CheckScreen {
-            tag: tag.clone(),
-            threshold: 0.95,
-            timeout: Duration::from_secs(timeout as u64),
+            tags: vec![tag],
+            threshold: self.default_threshold()?,
+            timeout: self.timeout(timeout)?,
             click: false,
             r#move: false,
             delay: None,
+            area: None,
         }))? {
-            MsgRes::Done => Ok(true),
+            MsgRes::ScreenMatched { .. } => Ok(true),
             MsgRes::Error(_) => Ok(false),
             _ => Err(ApiError::ServerInvalidResponse),
         }
     }
 
+    // like vnc_check_screen, but return how it matched, none if not matched
+    fn vnc_check_screen_stats(&self, tag: String, timeout: i32) -> Result<Option<MatchStats>> {
+        match self.req(MsgReq::VNC(VNC::CheckScreen {
+            tags: vec![tag],
+            threshold: self.default_threshold()?,
+            timeout: self.timeout(timeout)?,
+            click: false,
+            r#move: false,
+            delay: None,
+            area: None,
+        }))? {
+            MsgRes::ScreenMatched { stats, .. } => Ok(Some(stats)),
+            MsgRes::Error(_) => Ok(None),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // compare current screen once, no waiting
+    fn vnc_is_screen(&self, tag: String) -> Result<bool> {
+        self.vnc_check_screen(tag, 0)
+    }
+
     fn vnc_assert_screen(&self, tag: String, timeout: i32) -> Result<()> {
-        if self.vnc_check_screen(tag, timeout)? {
+        if self.vnc_check_screen(tag.clone(), timeout)? {
+            self.assert_passed();
             Ok(())
         } else {
-            Err(ApiError::AssertFailed)
+            self.assert_failed(format!("assert_screen {tag:?} not matched"))
+        }
+    }
+
+    fn vnc_check_screen_any(&self, tags: Vec<String>, timeout: i32) -> Result<Option<String>> {
+        match self.req(MsgReq::VNC(VNC::CheckScreen {
+            tags,
+            threshold: self.default_threshold()?,
+            timeout: self.timeout(timeout)?,
+            click: false,
+            r#move: false,
+            delay: None,
+            area: None,
+        }))? {
+            MsgRes::ScreenMatched { tag, .. } => Ok(Some(tag)),
+            MsgRes::Error(_) => Ok(None),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    fn vnc_assert_screen_any(&self, tags: Vec<String>, timeout: i32) -> Result<String> {
+        match self.vnc_check_screen_any(tags.clone(), timeout)? {
+            Some(tag) => {
+                self.assert_passed();
+                Ok(tag)
+            }
+            None => self
+                .assert_failed(format!("assert_screen_any {tags:?} not matched"))
+                .map(|_| String::new()),
+        }
+    }
+
+    // png file as needle without json, region is (left, top, width, height), whole image if none
+    fn vnc_check_image(
+        &self,
+        path: String,
+        region: Option<(u16, u16, u16, u16)>,
+        threshold: f32,
+        timeout: i32,
+    ) -> Result<bool> {
+        match self.req(MsgReq::VNC(VNC::CheckImage {
+            path,
+            region: region.map(|(left, top, width, height)| Rect {
+                left,
+                top,
+                width,
+                height,
+            }),
+            threshold,
+            timeout: self.timeout(timeout)?,
+        }))? {
+            MsgRes::ScreenMatched { .. } => Ok(true),
+            MsgRes::Error(_) => Ok(false),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    fn vnc_assert_image(
+        &self,
+        path: String,
+        region: Option<(u16, u16, u16, u16)>,
+        threshold: f32,
+        timeout: i32,
+    ) -> Result<()> {
+        if self.vnc_check_image(path.clone(), region, threshold, timeout)? {
+            self.assert_passed();
+            Ok(())
+        } else {
+            self.assert_failed(format!("assert_image {path:?} not matched"))
         }
     }
 
     fn vnc_check_and_click(&self, tag: String, timeout: i32) -> Result<bool> {
+        self.vnc_check_and_click_area(tag, None, timeout)
+    }
+
+    fn vnc_assert_and_click(&self, tag: String, timeout: i32) -> Result<()> {
+        self.vnc_assert_and_click_area(tag, None, timeout)
+    }
+
+    // click the selected area instead of the first clickable one
+    fn vnc_check_and_click_area(
+        &self,
+        tag: String,
+        area: Option<AreaSelector>,
+        timeout: i32,
+    ) -> Result<bool> {
         match self.req(MsgReq::VNC(VNC::CheckScreen {
-            tag: tag.clone(),
-            threshold: 0.95,
-            timeout: Duration::from_secs(timeout as u64),
+            tags: vec![tag],
+            threshold: self.default_threshold()?,
+            timeout: self.timeout(timeout)?,
             click: true,
             r#move: false,
             delay: None,
+            area,
         }))? {
-            MsgRes::Done => Ok(true),
+            MsgRes::ScreenMatched { .. } => Ok(true),
             MsgRes::Error(_) => Ok(false),
             _ => Err(ApiError::ServerInvalidResponse),
         }
     }
 
-    fn vnc_assert_and_click(&self, tag: String, timeout: i32) -> Result<()> {
-        match self.vnc_check_and_click(tag, timeout)? {
-            true => Ok(()),
-            false => Err(ApiError::AssertFailed),
+    fn vnc_assert_and_click_area(
+        &self,
+        tag: String,
+        area: Option<AreaSelector>,
+        timeout: i32,
+    ) -> Result<()> {
+        match self.vnc_check_and_click_area(tag.clone(), area, timeout)? {
+            true => {
+                self.assert_passed();
+                Ok(())
+            }
+            false => self.assert_failed(format!("assert_and_click {tag:?} not matched")),
         }
     }
 
     fn vnc_check_and_move(&self, tag: String, timeout: i32) -> Result<bool> {
         match self.req(MsgReq::VNC(VNC::CheckScreen {
-            tag: tag.clone(),
-            threshold: 0.95,
-            timeout: Duration::from_secs(timeout as u64),
+            tags: vec![tag],
+            threshold: self.default_threshold()?,
+            timeout: self.timeout(timeout)?,
             click: false,
             r#move: true,
             delay: None,
+            area: None,
         }))? {
-            MsgRes::Done => Ok(true),
+            MsgRes::ScreenMatched { .. } => Ok(true),
             MsgRes::Error(_) => Ok(false),
             _ => Err(ApiError::ServerInvalidResponse),
         }
     }
 
     fn vnc_assert_and_move(&self, tag: String, timeout: i32) -> Result<()> {
-        match self.vnc_check_and_move(tag, timeout)? {
-            true => Ok(()),
-            false => Err(ApiError::AssertFailed),
+        match self.vnc_check_and_move(tag.clone(), timeout)? {
+            true => {
+                self.assert_passed();
+                Ok(())
+            }
+            false => self.assert_failed(format!("assert_and_move {tag:?} not matched")),
         }
     }
 
@@ -281,6 +748,17 @@ pub trait Api {
         }
     }
 
+    // refresh and return the first frame completed after it, for capture right after a change
+    fn vnc_refresh_and_wait(&self, timeout: i32) -> Result<Arc<t_console::PNG>> {
+        match self.req(MsgReq::VNC(VNC::RefreshAndWait {
+            timeout: self.timeout(timeout)?,
+        }))? {
+            MsgRes::Screenshot(res) => Ok(res),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
     fn vnc_take_screenshot(&self) -> Result<()> {
         match self.req(MsgReq::VNC(VNC::TakeScreenShot))? {
             MsgRes::Done => Ok(()),
@@ -297,6 +775,60 @@ pub trait Api {
         }
     }
 
+    // (width, height) of current framebuffer
+    fn vnc_get_size(&self) -> Result<(u16, u16)> {
+        match self.req(MsgReq::VNC(VNC::GetScreenSize))? {
+            MsgRes::ScreenSize(w, h) => Ok((w, h)),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    fn vnc_get_resolution(&self) -> Result<(u16, u16)> {
+        self.vnc_get_size()
+    }
+
+    // guest must support client initiated resize, e.g. qemu with a resizable display
+    fn vnc_set_resolution(&self, width: u16, height: u16) -> Result<()> {
+        match self.req(MsgReq::VNC(VNC::SetScreenSize { width, height }))? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    fn vnc_connection_state(&self) -> Result<ConnectionState> {
+        match self.req(MsgReq::VNC(VNC::ConnectionState))? {
+            MsgRes::ConnectionState(state) => Ok(state),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // bells rung by vnc server since connected
+    fn vnc_bell_count(&self) -> Result<u64> {
+        match self.req(MsgReq::VNC(VNC::BellCount))? {
+            MsgRes::BellCount(n) => Ok(n),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // wait a bell rung after this call, compare vnc_bell_count for one rung before
+    fn vnc_wait_bell(&self, timeout: i32) -> Result<()> {
+        match self.req(MsgReq::VNC(VNC::WaitBell {
+            timeout: self.timeout(timeout)?,
+        }))? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    fn vnc_is_connected(&self) -> Result<bool> {
+        Ok(self.vnc_connection_state()? == ConnectionState::Connected)
+    }
+
     fn vnc_mouse_move(&self, x: u16, y: u16) -> Result<()> {
         match self.req(MsgReq::VNC(VNC::MouseMove { x, y }))? {
             MsgRes::Done => Ok(()),
@@ -305,6 +837,23 @@ pub trait Api {
         }
     }
 
+    // move from current position, clamped to screen
+    fn vnc_mouse_move_rel(&self, dx: i16, dy: i16) -> Result<()> {
+        match self.req(MsgReq::VNC(VNC::MouseMoveRelative { dx, dy }))? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    fn vnc_mouse_pos(&self) -> Result<(u16, u16)> {
+        match self.req(MsgReq::VNC(VNC::GetMousePos))? {
+            MsgRes::MousePos(x, y) => Ok((x, y)),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
     fn vnc_mouse_drag(&self, x: u16, y: u16) -> Result<()> {
         match self.req(MsgReq::VNC(VNC::MouseDrag { x, y }))? {
             MsgRes::Done => Ok(()),
@@ -337,16 +886,82 @@ pub trait Api {
         }
     }
 
-    fn vnc_mouse_click(&self) -> Result<()> {
-        match self.req(MsgReq::VNC(VNC::MouseClick))? {
+    fn vnc_wait_screen_still(&self, timeout: i32, stable_for: i32) -> Result<()> {
+        match self.req(MsgReq::VNC(VNC::WaitStill {
+            timeout: self.timeout(timeout)?,
+            stable_for: Duration::from_secs(stable_for.max(0) as u64),
+        }))? {
             MsgRes::Done => Ok(()),
             MsgRes::Error(e) => Err(e.into()),
             _ => Err(ApiError::ServerInvalidResponse),
         }
     }
 
+    // wait until needle has not matched for stable_for secs, e.g. a loading spinner
+    fn vnc_wait_screen_gone(&self, tag: String, timeout: i32, stable_for: i32) -> Result<()> {
+        match self.req(MsgReq::VNC(VNC::WaitGone {
+            tag,
+            threshold: self.default_threshold()?,
+            timeout: self.timeout(timeout)?,
+            stable_for: Duration::from_secs(stable_for.max(0) as u64),
+        }))? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // first run saves current screen as needle tag, later runs assert against it,
+    // UPDATE_GOLDEN=1 captures it again
+    fn vnc_golden_screen(&self, tag: String, timeout: i32) -> Result<()> {
+        match self.req(MsgReq::VNC(VNC::SaveGolden { tag: tag.clone() }))? {
+            MsgRes::GoldenSaved(true) => Ok(()),
+            MsgRes::GoldenSaved(false) => self.vnc_assert_screen(tag, timeout),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // fail as soon as needle appears within duration secs, e.g. an error dialog
+    fn vnc_assert_no_screen(&self, tag: String, duration: i32) -> Result<()> {
+        match self.req(MsgReq::VNC(VNC::AssertNoScreen {
+            tag: tag.clone(),
+            threshold: self.default_threshold()?,
+            duration: Duration::from_secs(duration.max(0) as u64),
+        }))? {
+            MsgRes::Done => {
+                self.assert_passed();
+                Ok(())
+            }
+            MsgRes::Matched(_) => self.assert_failed(format!("assert_no_screen {tag:?} matched")),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    fn vnc_mouse_click(&self) -> Result<()> {
+        self.vnc_mouse_click_button(1)
+    }
+
     fn vnc_mouse_rclick(&self) -> Result<()> {
-        match self.req(MsgReq::VNC(VNC::MouseRClick))? {
+        self.vnc_mouse_click_button(3)
+    }
+
+    fn vnc_mouse_mclick(&self) -> Result<()> {
+        self.vnc_mouse_click_button(2)
+    }
+
+    // button in 1..=8, 1 left, 2 middle, 3 right, 4 and 5 wheel up and down
+    fn vnc_mouse_click_button(&self, button: u8) -> Result<()> {
+        match self.req(MsgReq::VNC(VNC::MouseClick(button)))? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    fn vnc_mouse_dclick(&self) -> Result<()> {
+        match self.req(MsgReq::VNC(VNC::MouseDoubleClick))? {
             MsgRes::Done => Ok(()),
             MsgRes::Error(e) => Err(e.into()),
             _ => Err(ApiError::ServerInvalidResponse),
@@ -361,6 +976,31 @@ pub trait Api {
         }
     }
 
+    // x11 keysyms pressed together then released, for keys send_key has no name for
+    fn vnc_send_keysym(&self, codes: Vec<u32>) -> Result<()> {
+        match self.req(MsgReq::VNC(VNC::SendKeysym(codes)))? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    fn vnc_send_key_down(&self, s: String) -> Result<()> {
+        match self.req(MsgReq::VNC(VNC::KeyDown(s)))? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    fn vnc_send_key_up(&self, s: String) -> Result<()> {
+        match self.req(MsgReq::VNC(VNC::KeyUp(s)))? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
     fn vnc_type_string(&self, s: String) -> Result<()> {
         match self.req(MsgReq::VNC(VNC::TypeString(s)))? {
             MsgRes::Done => Ok(()),
@@ -368,4 +1008,288 @@ pub trait Api {
             _ => Err(ApiError::ServerInvalidResponse),
         }
     }
+
+    // like vnc_type_string, but s is redacted from logs
+    fn vnc_type_password(&self, s: String) -> Result<()> {
+        match self.req(MsgReq::VNC(VNC::TypePassword(s)))? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // paste through guest clipboard, server types it when clipboard is unavailable
+    fn vnc_paste_string(&self, s: String) -> Result<()> {
+        match self.req(MsgReq::VNC(VNC::PasteString(s)))? {
+            MsgRes::Done => Ok(()),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // reqs run in one round trip, results of those run before first error
+    fn batch(&self, reqs: Vec<MsgReq>) -> Result<Vec<MsgRes>> {
+        match self.req(MsgReq::Batch(reqs))? {
+            MsgRes::Batch(results) => Ok(results),
+            MsgRes::Error(e) => Err(e.into()),
+            _ => Err(ApiError::ServerInvalidResponse),
+        }
+    }
+
+    // script actions like ["mouse_move", 10, 20], first error is returned
+    fn batch_actions(&self, actions: Vec<Vec<ActionArg>>) -> Result<()> {
+        let reqs = actions
+            .iter()
+            .enumerate()
+            .map(|(i, action)| {
+                batch_action(action).map_err(|e| ApiError::String(format!("batch action {i}: {e}")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for res in self.batch(reqs)? {
+            match res {
+                MsgRes::Done => {}
+                MsgRes::Error(e) => return Err(e.into()),
+                _ => return Err(ApiError::ServerInvalidResponse),
+            }
+        }
+        Ok(())
+    }
+}
+
+// argument of a batch action from scripts
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActionArg {
+    Int(i64),
+    Str(String),
+}
+
+// action is the vnc function name followed by its args, e.g. ["send_key", "ret"]
+pub fn batch_action(action: &[ActionArg]) -> std::result::Result<MsgReq, String> {
+    let Some((ActionArg::Str(name), args)) = action.split_first() else {
+        return Err("should start with function name".to_string());
+    };
+    let int = |i: usize| match args.get(i) {
+        Some(ActionArg::Int(n)) => Ok(*n),
+        _ => Err(format!("{name} needs number at arg {i}")),
+    };
+    let num = |i: usize| int(i).and_then(|n| u16::try_from(n).map_err(|e| e.to_string()));
+    let rel = |i: usize| int(i).and_then(|n| i16::try_from(n).map_err(|e| e.to_string()));
+    let s = || match args.first() {
+        Some(ActionArg::Str(s)) => Ok(s.clone()),
+        _ => Err(format!("{name} needs string at arg 0")),
+    };
+    let req = match name.as_str() {
+        "mouse_move" => VNC::MouseMove {
+            x: num(0)?,
+            y: num(1)?,
+        },
+        "mouse_move_rel" => VNC::MouseMoveRelative {
+            dx: rel(0)?,
+            dy: rel(1)?,
+        },
+        "mouse_drag" => VNC::MouseDrag {
+            x: num(0)?,
+            y: num(1)?,
+        },
+        "mouse_hide" => VNC::MouseHide,
+        "mouse_click" => VNC::MouseClick(1),
+        "mouse_mclick" => VNC::MouseClick(2),
+        "mouse_rclick" => VNC::MouseClick(3),
+        "mouse_click_button" => {
+            VNC::MouseClick(int(0).and_then(|n| u8::try_from(n).map_err(|e| e.to_string()))?)
+        }
+        "mouse_dclick" => VNC::MouseDoubleClick,
+        "mouse_keydown" => VNC::MouseKeyDown(true),
+        "mouse_keyup" => VNC::MouseKeyDown(false),
+        "send_key" => VNC::SendKey(s()?),
+        "send_keysym" => VNC::SendKeysym(
+            (0..args.len())
+                .map(|i| int(i).and_then(|n| u32::try_from(n).map_err(|e| e.to_string())))
+                .collect::<std::result::Result<_, _>>()?,
+        ),
+        "send_key_down" => VNC::KeyDown(s()?),
+        "send_key_up" => VNC::KeyUp(s()?),
+        "type_string" => VNC::TypeString(s()?),
+        _ => return Err(format!("{name} can't be batched")),
+    };
+    Ok(MsgReq::VNC(req))
+}
+
+fn capture_between(output: &str, left: &str, right: &str) -> Result<String> {
+    match t_util::assert_capture_between(output, left, right) {
+        Ok(Some((_, res))) => Ok(res),
+        Ok(None) => Err(ApiError::String(format!(
+            "capture failed, {left:?} and {right:?} not found in output"
+        ))),
+        Err(e) => Err(ApiError::String(format!("capture failed, {e:?}"))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // reply script_run with given exit codes in order
+    fn mock_script_run(codes: Vec<i32>) -> RustApi {
+        let (tx, rx) = mpsc::channel::<(MsgReq, mpsc::Sender<MsgRes>)>();
+        thread::spawn(move || {
+            for (i, code) in codes.into_iter().enumerate() {
+                let Ok((_, res_tx)) = rx.recv() else {
+                    return;
+                };
+                let _ = res_tx.send(MsgRes::ScriptRun {
+                    code,
+                    value: format!("attempt {i}"),
+                });
+            }
+        });
+        RustApi::new(tx)
+    }
+
+    #[test]
+    fn test_command_failed() {
+        // server with soft assert off, like server.rs
+        let (tx, rx) = mpsc::channel::<(MsgReq, mpsc::Sender<MsgRes>)>();
+        thread::spawn(move || {
+            while let Ok((req, res_tx)) = rx.recv() {
+                let res = match req {
+                    MsgReq::ScriptRun { .. } => MsgRes::ScriptRun {
+                        code: 2,
+                        value: "".to_string(),
+                    },
+                    MsgReq::AssertFailed {
+                        code: Some(code), ..
+                    } => MsgRes::Error(MsgResError::CommandFailed { code }),
+                    _ => MsgRes::Error(MsgResError::String("failed".to_string())),
+                };
+                let _ = res_tx.send(res);
+            }
+        });
+        let api = RustApi::new(tx);
+        assert!(matches!(
+            api.assert_script_run("false".to_string(), 1),
+            Err(ApiError::CommandFailed { code: 2 })
+        ));
+        assert!(matches!(
+            api.assert_failed("screen".to_string()),
+            Err(ApiError::AssertFailed)
+        ));
+    }
+
+    #[test]
+    fn test_batch_action() {
+        let s = |s: &str| ActionArg::Str(s.to_string());
+        assert!(matches!(
+            batch_action(&[s("mouse_move"), ActionArg::Int(10), ActionArg::Int(20)]),
+            Ok(MsgReq::VNC(VNC::MouseMove { x: 10, y: 20 }))
+        ));
+        assert!(matches!(
+            batch_action(&[s("mouse_move_rel"), ActionArg::Int(-5), ActionArg::Int(0)]),
+            Ok(MsgReq::VNC(VNC::MouseMoveRelative { dx: -5, dy: 0 }))
+        ));
+        assert!(matches!(
+            batch_action(&[s("send_key"), s("ret")]),
+            Ok(MsgReq::VNC(VNC::SendKey(k))) if k == "ret"
+        ));
+        assert!(matches!(
+            batch_action(&[s("send_keysym"), ActionArg::Int(0xffb1), ActionArg::Int(0xffb2)]),
+            Ok(MsgReq::VNC(VNC::SendKeysym(codes))) if codes == vec![0xffb1, 0xffb2]
+        ));
+        assert!(matches!(
+            batch_action(&[s("mouse_rclick")]),
+            Ok(MsgReq::VNC(VNC::MouseClick(3)))
+        ));
+
+        assert_eq!(
+            batch_action(&[s("mouse_move"), ActionArg::Int(10)]).unwrap_err(),
+            "mouse_move needs number at arg 1"
+        );
+        assert!(batch_action(&[s("mouse_move"), ActionArg::Int(-1), ActionArg::Int(0)]).is_err());
+        assert_eq!(
+            batch_action(&[s("check_screen"), s("login")]).unwrap_err(),
+            "check_screen can't be batched"
+        );
+        assert!(batch_action(&[ActionArg::Int(1)]).is_err());
+        assert!(batch_action(&[]).is_err());
+    }
+
+    #[test]
+    fn test_assert_script_run_retry() {
+        let api = mock_script_run(vec![1, 1, 0]);
+        assert_eq!(
+            api.assert_script_run_retry("true".to_string(), 1, 2, 0)
+                .unwrap(),
+            "attempt 2"
+        );
+
+        let api = mock_script_run(vec![1, 1, 0]);
+        assert!(matches!(
+            api.assert_script_run_retry("true".to_string(), 1, 1, 0),
+            Err(ApiError::AssertFailed)
+        ));
+    }
+
+    #[test]
+    fn test_soft_assert() {
+        // server in soft assert mode, every command fails
+        let (tx, rx) = mpsc::channel::<(MsgReq, mpsc::Sender<MsgRes>)>();
+        thread::spawn(move || {
+            let mut failures = Vec::new();
+            while let Ok((req, res_tx)) = rx.recv() {
+                let res = match req {
+                    MsgReq::ScriptRun { .. } => MsgRes::ScriptRun {
+                        code: 1,
+                        value: "failed".to_string(),
+                    },
+                    MsgReq::AssertFailed { msg, .. } => {
+                        failures.push(msg);
+                        MsgRes::Done
+                    }
+                    MsgReq::CollectFailures { clear } => MsgRes::Failures(if clear {
+                        std::mem::take(&mut failures)
+                    } else {
+                        failures.clone()
+                    }),
+                    MsgReq::GetDefaultThreshold => MsgRes::DefaultThreshold(0.95),
+                    MsgReq::VNC(VNC::CheckScreen { .. }) => MsgRes::Error(MsgResError::Timeout),
+                    _ => MsgRes::Done,
+                };
+                let _ = res_tx.send(res);
+            }
+        });
+        let api = RustApi::new(tx);
+
+        assert_eq!(api.assert_script_run("a".to_string(), 1).unwrap(), "failed");
+        assert_eq!(
+            api.vnc_assert_screen_any(vec!["c".to_string()], 0).unwrap(),
+            ""
+        );
+        api.vnc_assert_and_click_area("d".to_string(), None, 0)
+            .unwrap();
+        api.vnc_assert_and_move("e".to_string(), 0).unwrap();
+        api.assert_script_run_retry("b".to_string(), 1, 2, 0)
+            .unwrap();
+        // retries are not recorded
+        assert_eq!(api.collect_failures().unwrap().len(), 5);
+        assert!(matches!(
+            api.flush_assertions(),
+            Err(ApiError::AssertFailed)
+        ));
+        assert!(api.flush_assertions().is_ok());
+    }
+
+    #[test]
+    fn test_capture_between() {
+        let output = "NAME=\"openEuler\"\nVERSION=\"23.09\"\nID=\"openEuler\"\n";
+        assert_eq!(
+            capture_between(output, "VERSION=\"", "\"\nID").unwrap(),
+            "23.09"
+        );
+        // capture across lines
+        assert_eq!(
+            capture_between(output, "NAME=", "\nID").unwrap(),
+            "\"openEuler\"\nVERSION=\"23.09\""
+        );
+        assert!(capture_between(output, "PRETTY_NAME=", "\n").is_err());
+    }
 }
@@ -1,4 +1,4 @@
 mod js;
 mod perl;
 
-pub use js::JSEngine;
+pub use js::{search_needle_tags, JSEngine};
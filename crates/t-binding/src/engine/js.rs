@@ -2,11 +2,15 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::{mpsc, Arc};
 
-use crate::api::{Api, RustApi};
-use crate::{ApiError, MsgReq, MsgRes, ScriptEngine};
-use rquickjs::function::Args;
+use crate::api::{ActionArg, Api, RustApi};
+use crate::{
+    msg::{AreaSelector, MatchStats},
+    ApiError, MsgReq, MsgRes, ScriptEngine, TextConsole,
+};
+use rquickjs::convert::Coerced;
+use rquickjs::function::{Args, Opt};
 use rquickjs::Function;
-use rquickjs::{Context, Runtime};
+use rquickjs::{Context, Ctx, FromJs, IntoJs, Object, Runtime, Value};
 use serde::{Deserialize, Serialize};
 use tracing::{error, Level};
 
@@ -70,6 +74,26 @@ impl JSEngine {
                     )
                     .unwrap();
 
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "get_env_int",
+                        Function::new(ctx.clone(), move |key| -> rquickjs::Result<Option<i64>> {
+                            api.get_env_int(key).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "get_env_bool",
+                        Function::new(ctx.clone(), move |key| -> rquickjs::Result<Option<bool>> {
+                            api.get_env_bool(key).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
@@ -95,6 +119,67 @@ impl JSEngine {
                 )
                 .map_err(|_| ())?;
 
+                // soft assert
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "set_soft_assert",
+                        Function::new(ctx.clone(), move |on: bool| -> rquickjs::Result<()> {
+                            api.set_soft_assert(on).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "collect_failures",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<Vec<String>> {
+                            api.collect_failures().map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "list_needles",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<Vec<String>> {
+                            api.list_needles().map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "needle_exists",
+                        Function::new(ctx.clone(), move |tag: String| -> rquickjs::Result<bool> {
+                            api.needle_exists(tag).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "flush_assertions",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<()> {
+                            api.flush_assertions().map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "reconnect",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<Vec<String>> {
+                            api.reconnect().map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
                 // general console
                 let api = rustapi.clone();
                 ctx.globals()
@@ -102,8 +187,13 @@ impl JSEngine {
                         "assert_script_run",
                         Function::new(
                             ctx.clone(),
-                            move |cmd: String, timeout: i32| -> rquickjs::Result<String> {
-                                let res = api.assert_script_run(cmd, timeout);
+                            // console is "serial" or "ssh", picked by server if omitted
+                            move |cmd: String,
+                                  timeout: i32,
+                                  console: Opt<String>|
+                                  -> rquickjs::Result<String> {
+                                let console = console.0.as_deref().map(js_console).transpose()?;
+                                let res = api.assert_script_run_console(cmd, console, timeout);
                                 res.map_err(into_jserr)
                             },
                         ),
@@ -113,75 +203,646 @@ impl JSEngine {
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "script_run",
-                        Function::new(
-                            ctx.clone(),
-                            move |cmd: String, timeout: i32| -> Option<String> {
-                                api.script_run(cmd, timeout).map(|v| v.1).ok()
-                            },
-                        ),
+                        "assert_script_run_retry",
+                        Function::new(
+                            ctx.clone(),
+                            move |cmd: String,
+                                  timeout: i32,
+                                  retries: u32,
+                                  interval: i32|
+                                  -> rquickjs::Result<String> {
+                                api.assert_script_run_retry(cmd, timeout, retries, interval)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "script_output_capture",
+                        Function::new(
+                            ctx.clone(),
+                            move |cmd: String,
+                                  left: String,
+                                  right: String,
+                                  timeout: i32|
+                                  -> rquickjs::Result<String> {
+                                api.script_output_capture(cmd, left, right, timeout)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "script_run",
+                        Function::new(
+                            ctx.clone(),
+                            move |cmd: String, timeout: i32| -> Option<ScriptRunRes> {
+                                api.script_run(cmd, timeout).map(ScriptRunRes::from).ok()
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "script_run_full",
+                        Function::new(
+                            ctx.clone(),
+                            move |cmd: String, timeout: i32| -> Option<ScriptRunFullRes> {
+                                api.script_run_full(cmd, timeout)
+                                    .map(ScriptRunFullRes::from)
+                                    .ok()
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "write",
+                        Function::new(ctx.clone(), move |s: String| -> rquickjs::Result<()> {
+                            api.write(s).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "write_password",
+                        Function::new(ctx.clone(), move |s: String| -> rquickjs::Result<()> {
+                            api.write_password(s).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "writeln",
+                        Function::new(ctx.clone(), move |s: String| -> rquickjs::Result<()> {
+                            api.write(format!("{s}\n")).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "wait_string",
+                        Function::new(
+                            ctx.clone(),
+                            move |s: String, timeout: i32| -> rquickjs::Result<()> {
+                                api.wait_string(s, timeout).map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "wait_regex",
+                        Function::new(
+                            ctx.clone(),
+                            move |pattern: String, timeout: i32| -> rquickjs::Result<String> {
+                                api.wait_regex(pattern, timeout).map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "expect",
+                        Function::new(
+                            ctx.clone(),
+                            move |steps: Vec<Vec<String>>, timeout: i32| -> rquickjs::Result<()> {
+                                api.expect(js_steps(steps)?, timeout).map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "wait_any_string",
+                        Function::new(
+                            ctx.clone(),
+                            move |targets: Vec<Vec<String>>,
+                                  timeout: i32|
+                                  -> rquickjs::Result<Vec<String>> {
+                                api.wait_any_string(js_targets(targets)?, timeout)
+                                    .map(|(console, s)| vec![console.name().to_string(), s])
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "wait_boot",
+                        Function::new(
+                            ctx.clone(),
+                            move |targets: BootTargets, timeout: i32| -> rquickjs::Result<String> {
+                                api.wait_boot(targets.serial, targets.screen, timeout)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "try_wait_string",
+                        Function::new(
+                            ctx.clone(),
+                            move |s: String, timeout: i32| -> rquickjs::Result<bool> {
+                                if !api.try_wait_string(s, timeout) {
+                                    Err(rquickjs::Error::Exception)
+                                } else {
+                                    Ok(true)
+                                }
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                // ssh
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "ssh_assert_script_run",
+                        Function::new(
+                            ctx.clone(),
+                            move |cmd: String, timeout: i32| -> rquickjs::Result<String> {
+                                api.ssh_assert_script_run(cmd, timeout).map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "ssh_script_run",
+                        Function::new(
+                            ctx.clone(),
+                            move |cmd, timeout| -> rquickjs::Result<ScriptRunRes> {
+                                api.ssh_script_run(cmd, timeout)
+                                    .map(ScriptRunRes::from)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "ssh_assert_script_run_seperate",
+                        Function::new(
+                            ctx.clone(),
+                            move |cmd: String, timeout: i32| -> rquickjs::Result<String> {
+                                api.ssh_assert_script_run_seperate(cmd, timeout)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "ssh_write",
+                        Function::new(ctx.clone(), move |s: String| -> rquickjs::Result<()> {
+                            api.ssh_write(s).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "ssh_expect",
+                        Function::new(
+                            ctx.clone(),
+                            move |steps: Vec<Vec<String>>, timeout: i32| -> rquickjs::Result<()> {
+                                api.ssh_expect(js_steps(steps)?, timeout).map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "ssh_write_password",
+                        Function::new(ctx.clone(), move |s: String| -> rquickjs::Result<()> {
+                            api.ssh_write_password(s).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                // serial
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "serial_assert_script_run",
+                        Function::new(
+                            ctx.clone(),
+                            move |cmd: String, timeout: i32| -> rquickjs::Result<String> {
+                                api.serial_assert_script_run(cmd, timeout)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "serial_script_run",
+                        Function::new(
+                            ctx.clone(),
+                            move |cmd: String, timeout: i32| -> Option<ScriptRunRes> {
+                                api.serial_script_run(cmd, timeout)
+                                    .map(ScriptRunRes::from)
+                                    .ok()
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "serial_write",
+                        Function::new(ctx.clone(), move |s: String| -> rquickjs::Result<()> {
+                            api.serial_write(s).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "serial_expect",
+                        Function::new(
+                            ctx.clone(),
+                            move |steps: Vec<Vec<String>>, timeout: i32| -> rquickjs::Result<()> {
+                                api.serial_expect(js_steps(steps)?, timeout).map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "serial_write_password",
+                        Function::new(ctx.clone(), move |s: String| -> rquickjs::Result<()> {
+                            api.serial_write_password(s).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "serial_send_break",
+                        Function::new(ctx.clone(), move |ms: u64| -> rquickjs::Result<()> {
+                            api.serial_send_break(ms).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                // vnc
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "assert_screen",
+                        Function::new(
+                            ctx.clone(),
+                            move |tag: String, timeout: i32| -> rquickjs::Result<()> {
+                                api.vnc_assert_screen(tag.clone(), timeout)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "check_screen",
+                        Function::new(
+                            ctx.clone(),
+                            move |tag: String, timeout: i32| -> rquickjs::Result<bool> {
+                                api.vnc_check_screen(tag.clone(), timeout)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "check_screen_stats",
+                        Function::new(
+                            ctx.clone(),
+                            move |tag: String, timeout: i32| -> rquickjs::Result<Option<MatchStats>> {
+                                api.vnc_check_screen_stats(tag, timeout)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "is_screen",
+                        Function::new(ctx.clone(), move |tag: String| -> rquickjs::Result<bool> {
+                            api.vnc_is_screen(tag).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "assert_image",
+                        Function::new(
+                            ctx.clone(),
+                            move |path: String,
+                                  region: Option<Vec<u16>>,
+                                  threshold: f32,
+                                  timeout: i32|
+                                  -> rquickjs::Result<()> {
+                                api.vnc_assert_image(path, js_region(region)?, threshold, timeout)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "check_image",
+                        Function::new(
+                            ctx.clone(),
+                            move |path: String,
+                                  region: Option<Vec<u16>>,
+                                  threshold: f32,
+                                  timeout: i32|
+                                  -> rquickjs::Result<bool> {
+                                api.vnc_check_image(path, js_region(region)?, threshold, timeout)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "assert_screen_any",
+                        Function::new(
+                            ctx.clone(),
+                            move |tags: Vec<String>, timeout: i32| -> rquickjs::Result<String> {
+                                api.vnc_assert_screen_any(tags, timeout).map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "check_screen_any",
+                        Function::new(
+                            ctx.clone(),
+                            move |tags: Vec<String>,
+                                  timeout: i32|
+                                  -> rquickjs::Result<Option<String>> {
+                                api.vnc_check_screen_any(tags, timeout).map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "assert_and_click",
+                        Function::new(
+                            ctx.clone(),
+                            // area is a name or an index in needle json, first clickable if omitted
+                            move |tag: String,
+                                  timeout: i32,
+                                  area: Opt<AreaSelector>|
+                                  -> rquickjs::Result<()> {
+                                api.vnc_assert_and_click_area(tag.clone(), area.0, timeout)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "check_and_click",
+                        Function::new(
+                            ctx.clone(),
+                            // area is a name or an index in needle json, first clickable if omitted
+                            move |tag: String,
+                                  timeout: i32,
+                                  area: Opt<AreaSelector>|
+                                  -> rquickjs::Result<bool> {
+                                api.vnc_check_and_click_area(tag.clone(), area.0, timeout)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "assert_and_move",
+                        Function::new(
+                            ctx.clone(),
+                            move |tag: String, timeout: i32| -> rquickjs::Result<()> {
+                                api.vnc_assert_and_move(tag.clone(), timeout)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "check_and_move",
+                        Function::new(
+                            ctx.clone(),
+                            move |tag: String, timeout: i32| -> rquickjs::Result<bool> {
+                                api.vnc_check_and_move(tag.clone(), timeout)
+                                    .map_err(into_jserr)
+                            },
+                        ),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "mouse_click",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<()> {
+                            api.vnc_mouse_click().map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "mouse_rclick",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<()> {
+                            api.vnc_mouse_rclick().map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "mouse_mclick",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<()> {
+                            api.vnc_mouse_mclick().map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "mouse_click_button",
+                        Function::new(ctx.clone(), move |button: u8| -> rquickjs::Result<()> {
+                            api.vnc_mouse_click_button(button).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "mouse_dclick",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<()> {
+                            api.vnc_mouse_dclick().map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "mouse_move",
+                        Function::new(ctx.clone(), move |x, y| -> rquickjs::Result<()> {
+                            api.vnc_mouse_move(x, y).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "mouse_move_rel",
+                        Function::new(ctx.clone(), move |dx, dy| -> rquickjs::Result<()> {
+                            api.vnc_mouse_move_rel(dx, dy).map_err(into_jserr)
+                        }),
+                    )
+                    .unwrap();
+                let api = rustapi.clone();
+                ctx.globals()
+                    .set(
+                        "mouse_pos",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<MousePos> {
+                            api.vnc_mouse_pos().map(Into::into).map_err(into_jserr)
+                        }),
                     )
                     .unwrap();
-
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "write",
-                        Function::new(ctx.clone(), move |s: String| -> rquickjs::Result<()> {
-                            api.write(s).map_err(into_jserr)
+                        "mouse_drag",
+                        Function::new(ctx.clone(), move |x, y| -> rquickjs::Result<()> {
+                            api.vnc_mouse_drag(x, y).map_err(into_jserr)
                         }),
                     )
                     .unwrap();
-
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "writeln",
-                        Function::new(ctx.clone(), move |s: String| -> rquickjs::Result<()> {
-                            api.write(format!("{s}\n")).map_err(into_jserr)
+                        "mouse_keydown",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<()> {
+                            api.vnc_mouse_keydown().map_err(into_jserr)
                         }),
                     )
                     .unwrap();
-
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "wait_string",
-                        Function::new(
-                            ctx.clone(),
-                            move |s: String, timeout: i32| -> rquickjs::Result<()> {
-                                api.wait_string(s, timeout).map_err(into_jserr)
-                            },
-                        ),
+                        "mouse_keyup",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<()> {
+                            api.vnc_mouse_keyup().map_err(into_jserr)
+                        }),
                     )
                     .unwrap();
 
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "try_wait_string",
+                        "wait_screen_gone",
                         Function::new(
                             ctx.clone(),
-                            move |s: String, timeout: i32| -> rquickjs::Result<bool> {
-                                if !api.try_wait_string(s, timeout) {
-                                    Err(rquickjs::Error::Exception)
-                                } else {
-                                    Ok(true)
-                                }
+                            move |tag: String,
+                                  timeout: i32,
+                                  stable_for: Opt<i32>|
+                                  -> rquickjs::Result<()> {
+                                api.vnc_wait_screen_gone(tag, timeout, stable_for.0.unwrap_or(1))
+                                    .map_err(into_jserr)
                             },
                         ),
                     )
                     .unwrap();
 
-                // ssh
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "ssh_assert_script_run",
+                        "assert_no_screen",
                         Function::new(
                             ctx.clone(),
-                            move |cmd: String, timeout: i32| -> rquickjs::Result<String> {
-                                api.ssh_assert_script_run(cmd, timeout).map_err(into_jserr)
+                            move |tag: String, duration: i32| -> rquickjs::Result<()> {
+                                api.vnc_assert_no_screen(tag, duration)
+                                    .map_err(into_jserr)
                             },
                         ),
                     )
@@ -190,66 +851,58 @@ impl JSEngine {
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "ssh_script_run",
+                        "golden_screen",
                         Function::new(
                             ctx.clone(),
-                            move |cmd, timeout| -> rquickjs::Result<String> {
-                                api.ssh_script_run(cmd, timeout)
-                                    .map(|v| v.1)
-                                    .map_err(into_jserr)
+                            move |tag: String, timeout: i32| -> rquickjs::Result<()> {
+                                api.vnc_golden_screen(tag, timeout).map_err(into_jserr)
                             },
                         ),
                     )
                     .unwrap();
 
+                // frame is not returned, following checks compare against it
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "ssh_assert_script_run_seperate",
-                        Function::new(
-                            ctx.clone(),
-                            move |cmd: String, timeout: i32| -> rquickjs::Result<String> {
-                                api.ssh_assert_script_run_seperate(cmd, timeout)
-                                    .map_err(into_jserr)
-                            },
-                        ),
+                        "refresh_and_wait",
+                        Function::new(ctx.clone(), move |timeout: i32| -> rquickjs::Result<()> {
+                            api.vnc_refresh_and_wait(timeout)
+                                .map(|_| ())
+                                .map_err(into_jserr)
+                        }),
                     )
                     .unwrap();
 
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "ssh_write",
-                        Function::new(ctx.clone(), move |s: String| -> rquickjs::Result<()> {
-                            api.ssh_write(s).map_err(into_jserr)
+                        "bell_count",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<u64> {
+                            api.vnc_bell_count().map_err(into_jserr)
                         }),
                     )
                     .unwrap();
 
-                // serial
-
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "serial_assert_script_run",
-                        Function::new(
-                            ctx.clone(),
-                            move |cmd: String, timeout: i32| -> rquickjs::Result<String> {
-                                api.serial_assert_script_run(cmd, timeout)
-                                    .map_err(into_jserr)
-                            },
-                        ),
+                        "wait_bell",
+                        Function::new(ctx.clone(), move |timeout: i32| -> rquickjs::Result<()> {
+                            api.vnc_wait_bell(timeout).map_err(into_jserr)
+                        }),
                     )
                     .unwrap();
 
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "serial_script_run",
+                        "wait_screen_still",
                         Function::new(
                             ctx.clone(),
-                            move |cmd: String, timeout: i32| -> Option<String> {
-                                api.serial_script_run(cmd, timeout).map(|v| v.1).ok()
+                            move |timeout: i32, stable_for: i32| -> rquickjs::Result<()> {
+                                api.vnc_wait_screen_still(timeout, stable_for)
+                                    .map_err(into_jserr)
                             },
                         ),
                     )
@@ -258,38 +911,31 @@ impl JSEngine {
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "serial_write",
-                        Function::new(ctx.clone(), move |s: String| -> rquickjs::Result<()> {
-                            api.serial_write(s).map_err(into_jserr)
+                        "get_screen_size",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<ScreenSize> {
+                            api.vnc_get_size().map(Into::into).map_err(into_jserr)
                         }),
                     )
                     .unwrap();
 
-                // vnc
-
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "assert_screen",
-                        Function::new(
-                            ctx.clone(),
-                            move |tag: String, timeout: i32| -> rquickjs::Result<()> {
-                                api.vnc_assert_screen(tag.clone(), timeout)
-                                    .map_err(into_jserr)
-                            },
-                        ),
+                        "vnc_get_resolution",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<ScreenSize> {
+                            api.vnc_get_resolution().map(Into::into).map_err(into_jserr)
+                        }),
                     )
                     .unwrap();
 
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "check_screen",
+                        "vnc_set_resolution",
                         Function::new(
                             ctx.clone(),
-                            move |tag: String, timeout: i32| -> rquickjs::Result<bool> {
-                                api.vnc_check_screen(tag.clone(), timeout)
-                                    .map_err(into_jserr)
+                            move |width: u16, height: u16| -> rquickjs::Result<()> {
+                                api.vnc_set_resolution(width, height).map_err(into_jserr)
                             },
                         ),
                     )
@@ -298,53 +944,41 @@ impl JSEngine {
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "assert_and_click",
-                        Function::new(
-                            ctx.clone(),
-                            move |tag: String, timeout: i32| -> rquickjs::Result<()> {
-                                api.vnc_assert_and_click(tag.clone(), timeout)
-                                    .map_err(into_jserr)
-                            },
-                        ),
+                        "vnc_is_connected",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<bool> {
+                            api.vnc_is_connected().map_err(into_jserr)
+                        }),
                     )
                     .unwrap();
 
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "check_and_click",
-                        Function::new(
-                            ctx.clone(),
-                            move |tag: String, timeout: i32| -> rquickjs::Result<bool> {
-                                api.vnc_check_and_click(tag.clone(), timeout)
-                                    .map_err(into_jserr)
-                            },
-                        ),
+                        "mouse_hide",
+                        Function::new(ctx.clone(), move || -> rquickjs::Result<()> {
+                            api.vnc_mouse_hide().map_err(into_jserr)
+                        }),
                     )
                     .unwrap();
 
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "assert_and_move",
-                        Function::new(
-                            ctx.clone(),
-                            move |tag: String, timeout: i32| -> rquickjs::Result<()> {
-                                api.vnc_assert_and_move(tag.clone(), timeout)
-                                    .map_err(into_jserr)
-                            },
-                        ),
+                        "send_key",
+                        Function::new(ctx.clone(), move |s| -> rquickjs::Result<()> {
+                            api.vnc_send_key(s).map_err(into_jserr)
+                        }),
                     )
                     .unwrap();
+
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "check_and_move",
+                        "batch",
                         Function::new(
                             ctx.clone(),
-                            move |tag: String, timeout: i32| -> rquickjs::Result<bool> {
-                                api.vnc_check_and_move(tag.clone(), timeout)
-                                    .map_err(into_jserr)
+                            move |actions: Vec<Vec<ActionArg>>| -> rquickjs::Result<()> {
+                                api.batch_actions(actions).map_err(into_jserr)
                             },
                         ),
                     )
@@ -353,9 +987,9 @@ impl JSEngine {
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "mouse_click",
-                        Function::new(ctx.clone(), move || -> rquickjs::Result<()> {
-                            api.vnc_mouse_click().map_err(into_jserr)
+                        "send_keysym",
+                        Function::new(ctx.clone(), move |codes| -> rquickjs::Result<()> {
+                            api.vnc_send_keysym(codes).map_err(into_jserr)
                         }),
                     )
                     .unwrap();
@@ -363,18 +997,19 @@ impl JSEngine {
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "mouse_move",
-                        Function::new(ctx.clone(), move |x, y| -> rquickjs::Result<()> {
-                            api.vnc_mouse_move(x, y).map_err(into_jserr)
+                        "send_key_down",
+                        Function::new(ctx.clone(), move |s| -> rquickjs::Result<()> {
+                            api.vnc_send_key_down(s).map_err(into_jserr)
                         }),
                     )
                     .unwrap();
+
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "mouse_drag",
-                        Function::new(ctx.clone(), move |x, y| -> rquickjs::Result<()> {
-                            api.vnc_mouse_drag(x, y).map_err(into_jserr)
+                        "send_key_up",
+                        Function::new(ctx.clone(), move |s| -> rquickjs::Result<()> {
+                            api.vnc_send_key_up(s).map_err(into_jserr)
                         }),
                     )
                     .unwrap();
@@ -382,9 +1017,9 @@ impl JSEngine {
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "mouse_hide",
-                        Function::new(ctx.clone(), move || -> rquickjs::Result<()> {
-                            api.vnc_mouse_hide().map_err(into_jserr)
+                        "type_string",
+                        Function::new(ctx.clone(), move |s| -> rquickjs::Result<()> {
+                            api.vnc_type_string(s).map_err(into_jserr)
                         }),
                     )
                     .unwrap();
@@ -392,9 +1027,9 @@ impl JSEngine {
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "send_key",
-                        Function::new(ctx.clone(), move |s| -> rquickjs::Result<()> {
-                            api.vnc_send_key(s).map_err(into_jserr)
+                        "type_password",
+                        Function::new(ctx.clone(), move |s: String| -> rquickjs::Result<()> {
+                            api.vnc_type_password(s).map_err(into_jserr)
                         }),
                     )
                     .unwrap();
@@ -402,9 +1037,9 @@ impl JSEngine {
                 let api = rustapi.clone();
                 ctx.globals()
                     .set(
-                        "type_string",
+                        "paste_string",
                         Function::new(ctx.clone(), move |s| -> rquickjs::Result<()> {
-                            api.vnc_type_string(s).map_err(into_jserr)
+                            api.vnc_paste_string(s).map_err(into_jserr)
                         }),
                     )
                     .unwrap();
@@ -449,6 +1084,32 @@ impl JSEngine {
         })
     }
 
+    // evaluate code as global script, globals are kept between calls, return the value as json
+    // if possible, e.g. for repl
+    pub fn eval(&mut self, code: &str) -> Result<String, String> {
+        self.context.with(|ctx| {
+            let value = ctx.eval::<Value, _>(code).map_err(|e| match e {
+                rquickjs::Error::Exception => {
+                    let exception = ctx.catch();
+                    match exception.get::<Coerced<String>>() {
+                        Ok(s) if !exception.is_undefined() => s.0,
+                        _ => e.to_string(),
+                    }
+                }
+                e => e.to_string(),
+            })?;
+            if let Ok(Some(s)) = ctx.json_stringify(value.clone()) {
+                if let Ok(s) = s.to_string() {
+                    return Ok(s);
+                }
+            }
+            value
+                .get::<Coerced<String>>()
+                .map(|s| s.0)
+                .map_err(|e| e.to_string())
+        })
+    }
+
     pub fn run_file(&mut self, file: &str) -> Result<(), String> {
         let base_folder = Path::new(file).parent().unwrap();
         let filename = Path::new(file).file_name().unwrap().to_str().unwrap();
@@ -515,6 +1176,233 @@ fn search_path(script: &str) -> Vec<String> {
     paths
 }
 
+const NEEDLE_CALL_PATTERN: &str = r#"\b(?:assert_screen|check_screen|check_screen_stats|is_screen|assert_and_click|check_and_click|assert_and_move|check_and_move|wait_screen_gone|assert_no_screen|golden_screen)\s*\(\s*('|")([^'"]+)('|")"#;
+const NEEDLE_ANY_CALL_PATTERN: &str =
+    r#"\b(?:assert_screen_any|check_screen_any)\s*\(\s*\[([^\]]*)\]"#;
+const STRING_PATTERN: &str = r#"('|")([^'"]+)('|")"#;
+
+// needle tags used by screen apis in script, only literal string args can be found
+pub fn search_needle_tags(script: &str) -> Vec<String> {
+    let call_re = regex::Regex::new(NEEDLE_CALL_PATTERN).unwrap();
+    let any_call_re = regex::Regex::new(NEEDLE_ANY_CALL_PATTERN).unwrap();
+    let string_re = regex::Regex::new(STRING_PATTERN).unwrap();
+
+    let mut tags: Vec<String> = vec![];
+    for (_, [_, tag, _]) in call_re.captures_iter(script).map(|c| c.extract()) {
+        tags.push(tag.to_string());
+    }
+    for (_, [list]) in any_call_re.captures_iter(script).map(|c| c.extract()) {
+        for (_, [_, tag, _]) in string_re.captures_iter(list).map(|c| c.extract()) {
+            tags.push(tag.to_string());
+        }
+    }
+    let mut seen = std::collections::HashSet::new();
+    tags.retain(|t| seen.insert(t.clone()));
+    tags
+}
+
+// result of script_run, exposed to js as `{ code, output }`
+struct ScriptRunRes {
+    code: i32,
+    output: String,
+}
+
+impl From<(i32, String)> for ScriptRunRes {
+    fn from((code, output): (i32, String)) -> Self {
+        Self { code, output }
+    }
+}
+
+impl<'js> IntoJs<'js> for ScriptRunRes {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        let obj = Object::new(ctx.clone())?;
+        obj.set("code", self.code)?;
+        obj.set("output", self.output)?;
+        Ok(obj.into_value())
+    }
+}
+
+// result of script_run_full, exposed to js as `{ code, stdout, stderr }`
+struct ScriptRunFullRes {
+    code: i32,
+    stdout: String,
+    stderr: String,
+}
+
+impl From<(i32, String, String)> for ScriptRunFullRes {
+    fn from((code, stdout, stderr): (i32, String, String)) -> Self {
+        Self {
+            code,
+            stdout,
+            stderr,
+        }
+    }
+}
+
+impl<'js> IntoJs<'js> for ScriptRunFullRes {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        let obj = Object::new(ctx.clone())?;
+        obj.set("code", self.code)?;
+        obj.set("stdout", self.stdout)?;
+        obj.set("stderr", self.stderr)?;
+        Ok(obj.into_value())
+    }
+}
+
+// vnc framebuffer size, exposed to js as `{ width, height }`
+struct ScreenSize {
+    width: u16,
+    height: u16,
+}
+
+impl From<(u16, u16)> for ScreenSize {
+    fn from((width, height): (u16, u16)) -> Self {
+        Self { width, height }
+    }
+}
+
+impl<'js> IntoJs<'js> for ScreenSize {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        let obj = Object::new(ctx.clone())?;
+        obj.set("width", self.width)?;
+        obj.set("height", self.height)?;
+        Ok(obj.into_value())
+    }
+}
+
+// region from js `[left, top, width, height]`, null or undefined for none
+fn js_region(region: Option<Vec<u16>>) -> rquickjs::Result<Option<(u16, u16, u16, u16)>> {
+    match region.as_deref() {
+        None => Ok(None),
+        Some(&[left, top, width, height]) => Ok(Some((left, top, width, height))),
+        Some(_) => Err(rquickjs::Error::new_from_js_message(
+            "array",
+            "region",
+            "region should be [left, top, width, height]",
+        )),
+    }
+}
+
+// steps from js `[["login:", "root"], ["Password:", "1234"]]`
+fn js_steps(steps: Vec<Vec<String>>) -> rquickjs::Result<Vec<(String, String)>> {
+    steps
+        .into_iter()
+        .map(|step| match step.as_slice() {
+            [pattern, response] => Ok((pattern.clone(), response.clone())),
+            _ => Err(rquickjs::Error::new_from_js_message(
+                "array",
+                "step",
+                "step should be [pattern, response]",
+            )),
+        })
+        .collect()
+}
+
+fn js_console(name: &str) -> rquickjs::Result<TextConsole> {
+    TextConsole::from_name(name).ok_or_else(|| {
+        rquickjs::Error::new_from_js_message(
+            "string",
+            "console",
+            format!("unknown console {name:?}, should be \"serial\" or \"ssh\""),
+        )
+    })
+}
+
+// targets from js `[["serial", "panic"], ["ssh", "panic"]]`
+fn js_targets(targets: Vec<Vec<String>>) -> rquickjs::Result<Vec<(TextConsole, String)>> {
+    targets
+        .into_iter()
+        .map(|target| match target.as_slice() {
+            [name, s] => js_console(name).map(|c| (c, s.clone())),
+            _ => Err(rquickjs::Error::new_from_js_message(
+                "array",
+                "target",
+                "target should be [console, string]",
+            )),
+        })
+        .collect()
+}
+
+// mouse position, exposed to js as `{ x, y }`
+struct MousePos {
+    x: u16,
+    y: u16,
+}
+
+impl From<(u16, u16)> for MousePos {
+    fn from((x, y): (u16, u16)) -> Self {
+        Self { x, y }
+    }
+}
+
+impl<'js> IntoJs<'js> for MousePos {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        let obj = Object::new(ctx.clone())?;
+        obj.set("x", self.x)?;
+        obj.set("y", self.y)?;
+        Ok(obj.into_value())
+    }
+}
+
+// exposed to js as `{ similarity, elapsed_ms, iterations }`
+impl<'js> IntoJs<'js> for MatchStats {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        let obj = Object::new(ctx.clone())?;
+        obj.set("similarity", self.similarity)?;
+        obj.set("elapsed_ms", self.elapsed.as_millis() as u64)?;
+        obj.set("iterations", self.iterations)?;
+        Ok(obj.into_value())
+    }
+}
+
+// `{ serial: "login:", screen: "desktop" }`, either can be omitted
+struct BootTargets {
+    serial: Option<String>,
+    screen: Option<String>,
+}
+
+impl<'js> FromJs<'js> for BootTargets {
+    fn from_js(_ctx: &Ctx<'js>, value: Value<'js>) -> rquickjs::Result<Self> {
+        let obj = Object::from_value(value)?;
+        Ok(Self {
+            serial: obj.get("serial")?,
+            screen: obj.get("screen")?,
+        })
+    }
+}
+
+// batch action args are numbers or strings
+impl<'js> FromJs<'js> for ActionArg {
+    fn from_js(_ctx: &Ctx<'js>, value: Value<'js>) -> rquickjs::Result<Self> {
+        if let Some(n) = value.as_int() {
+            return Ok(ActionArg::Int(n as i64));
+        }
+        if let Some(f) = value.as_float().filter(|f| f.fract() == 0.0) {
+            return Ok(ActionArg::Int(f as i64));
+        }
+        match value.as_string() {
+            Some(s) => s.to_string().map(ActionArg::Str),
+            None => Err(rquickjs::Error::new_from_js(
+                value.type_name(),
+                "action arg",
+            )),
+        }
+    }
+}
+
+// area is an index number or a name, digit strings are index too
+impl<'js> FromJs<'js> for AreaSelector {
+    fn from_js(_ctx: &Ctx<'js>, value: Value<'js>) -> rquickjs::Result<Self> {
+        if let Some(n) = value.as_int().filter(|n| *n >= 0) {
+            return Ok(AreaSelector::Index(n as usize));
+        }
+        match value.as_string() {
+            Some(s) => s.to_string().map(|s| AreaSelector::parse(&s)),
+            None => Err(rquickjs::Error::new_from_js(value.type_name(), "area")),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct Response {
     code: i32,
@@ -527,6 +1415,9 @@ mod test {
 
     use rquickjs::{function::Args, Context, Runtime};
 
+    use super::{search_needle_tags, JSEngine, ScriptRunRes};
+    use crate::msg::AreaSelector;
+
     fn get_context() -> rquickjs::Context {
         let runtime = Runtime::new().unwrap();
 
@@ -555,6 +1446,80 @@ mod test {
         });
     }
 
+    #[test]
+    fn test_eval() {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let mut engine = JSEngine::new(tx);
+        assert_eq!(engine.eval("let a = 1").unwrap(), "undefined");
+        assert_eq!(engine.eval("a + 1").unwrap(), "2");
+        assert_eq!(engine.eval("({b: [a]})").unwrap(), r#"{"b":[1]}"#);
+        assert!(engine
+            .eval("throw new Error('boom')")
+            .unwrap_err()
+            .contains("boom"));
+        assert!(engine.eval("a +").is_err());
+    }
+
+    #[test]
+    fn test_search_needle_tags() {
+        let script = r#"
+            assert_screen("login", 10);
+            if (check_screen('desktop', 5)) {
+                assert_and_click("login", 10);
+            }
+            let tag = assert_screen_any(["menu", 'dialog'], 10);
+            d.check_and_move("icon", 10)
+            check_screen(tag, 10);
+            if (is_screen("splash")) {
+                wait_screen_gone("splash", 30, 2);
+            }
+            assert_no_screen('error', 5);
+            const stats = check_screen_stats("panel", 5);
+            check_screen_any(['prompt', "shell"], 5);
+            golden_screen("about", 10);
+        "#;
+        assert_eq!(
+            search_needle_tags(script),
+            vec![
+                "login", "desktop", "icon", "splash", "error", "panel", "about", "menu", "dialog",
+                "prompt", "shell"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_script_run_res() {
+        get_context().with(|ctx| {
+            let func = rquickjs::Function::new(ctx.clone(), move || -> ScriptRunRes {
+                (1, "hello".to_string()).into()
+            })
+            .unwrap();
+            ctx.globals().set("script_run", func).unwrap();
+
+            let value = ctx
+                .eval::<String, &str>(
+                    r#"
+            const res = script_run();
+            `${res.code}-${res.output}`
+            "#,
+                )
+                .unwrap();
+            assert_eq!(value, "1-hello");
+        });
+    }
+
+    #[test]
+    fn test_area_selector() {
+        get_context().with(|ctx| {
+            let area = |code: &str| ctx.eval::<AreaSelector, _>(code);
+            assert!(matches!(area("0").unwrap(), AreaSelector::Index(0)));
+            assert!(matches!(area("'1'").unwrap(), AreaSelector::Index(1)));
+            assert!(matches!(area("'ok'").unwrap(), AreaSelector::Name(n) if n == "ok"));
+            assert!(area("-1").is_err());
+            assert!(area("null").is_err());
+        });
+    }
+
     #[test]
     // #[should_panic]
     fn test_quickjs_module() {
@@ -10,6 +10,12 @@ pub enum ApiError {
     Timeout,
     AssertFailed,
     Interrupt,
+    InvalidEnvType(String),
+    NoConsole(String),
+    ConnectionBroken(String),
+    // script return code, like AssertFailed
+    CommandFailed { code: i32 },
+    RegexBuild(String),
 }
 
 impl Error for ApiError {}
@@ -25,6 +31,11 @@ impl Display for ApiError {
             ApiError::Timeout => write!(f, "command timeout"),
             ApiError::AssertFailed => write!(f, "assert command failed, like return code != 0"),
             ApiError::Interrupt => write!(f, "interrupted by signal"),
+            ApiError::InvalidEnvType(s) => write!(f, "invalid env type, {}", s),
+            ApiError::NoConsole(s) => write!(f, "no {} console", s),
+            ApiError::ConnectionBroken(s) => write!(f, "connection broken, {}", s),
+            ApiError::CommandFailed { code } => write!(f, "command returned {}", code),
+            ApiError::RegexBuild(s) => write!(f, "invalid regex, {}", s),
         }
     }
 }
@@ -3,7 +3,7 @@ mod engine;
 pub mod error;
 pub mod msg;
 
-pub use engine::JSEngine;
+pub use engine::{search_needle_tags, JSEngine};
 pub use error::{ApiError, Result};
 pub use msg::{MsgReq, MsgRes, MsgResError, TextConsole};
 
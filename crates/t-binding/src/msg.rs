@@ -1,15 +1,49 @@
-use std::{sync::Arc, time::Duration};
+use std::{fmt::Display, sync::Arc, time::Duration};
 
-use t_console::PNG;
+use t_console::{ConnectionState, Rect, PNG};
 
 use crate::ApiError;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TextConsole {
     SSH,
     Serial,
 }
 
+impl TextConsole {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "ssh" => Some(Self::SSH),
+            "serial" => Some(Self::Serial),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SSH => "ssh",
+            Self::Serial => "serial",
+        }
+    }
+}
+
+// which needle area to click or move to
+#[derive(Debug, Clone, PartialEq)]
+pub enum AreaSelector {
+    // position in needle json areas
+    Index(usize),
+    Name(String),
+}
+
+impl AreaSelector {
+    // digits are index, anything else is name
+    pub fn parse(s: &str) -> Self {
+        s.parse()
+            .map(Self::Index)
+            .unwrap_or_else(|_| Self::Name(s.to_string()))
+    }
+}
+
 #[derive(Debug)]
 pub enum MsgReq {
     // runner
@@ -19,6 +53,32 @@ pub enum MsgReq {
     GetConfig {
         key: String,
     },
+    GetDefaultTimeout,
+    GetDefaultThreshold,
+    // stop all consoles and connect again with current config
+    Reconnect,
+    // soft assert records failed asserts and lets script continue
+    SetSoftAssert(bool),
+    // recorded and answered with done in soft assert mode, error otherwise,
+    // code is set if a script return code failed the assert
+    AssertFailed {
+        msg: String,
+        code: Option<i32>,
+    },
+    // only counted in metrics
+    AssertPassed,
+    CollectFailures {
+        clear: bool,
+    },
+    // needles in needle_dir, no screen is compared
+    ListNeedles,
+    NeedleExists {
+        tag: String,
+    },
+    // serial
+    SerialSendBreak {
+        duration: Duration,
+    },
     // ssh
     SSHScriptRunSeperate {
         cmd: String,
@@ -29,59 +89,181 @@ pub enum MsgReq {
         cmd: String,
         timeout: Duration,
     },
+    ScriptRunFull {
+        cmd: String,
+        timeout: Duration,
+    },
     WriteString {
         console: Option<TextConsole>,
         s: String,
         timeout: Duration,
+        // password, s is redacted from logs and events
+        secret: bool,
     },
     WaitString {
         console: Option<TextConsole>,
         s: String,
         timeout: Duration,
     },
+    WaitRegex {
+        console: Option<TextConsole>,
+        pattern: String,
+        timeout: Duration,
+    },
+    // wait each pattern then write its response, timeout is per step
+    Expect {
+        console: Option<TextConsole>,
+        steps: Vec<(String, String)>,
+        timeout: Duration,
+    },
+    // answered with index of the first matched target
+    WaitAnyString {
+        targets: Vec<(TextConsole, String)>,
+        timeout: Duration,
+    },
+    // ready when serial output matches regex or screen matches needle, whichever first,
+    // missing consoles are ignored, answered with Booted
+    WaitBoot {
+        serial: Option<String>,
+        screen: Option<String>,
+        threshold: f32,
+        timeout: Duration,
+    },
     VNC(VNC),
+    // run back to back in one round trip, stops at first error,
+    // answered with Batch of results of those run
+    Batch(Vec<MsgReq>),
 }
 
 #[derive(Debug)]
 pub enum VNC {
     TakeScreenShot,
     GetScreenShot,
+    GetScreenSize,
+    // ask server to resize desktop, answered after framebuffer has the new size
+    SetScreenSize {
+        width: u16,
+        height: u16,
+    },
+    ConnectionState,
+    // bells rung since connected, answered with BellCount
+    BellCount,
+    // done once a bell rings after request received
+    WaitBell {
+        timeout: Duration,
+    },
     Refresh,
+    // full refresh, answered with the first frame completed after it
+    RefreshAndWait {
+        timeout: Duration,
+    },
     CheckScreen {
-        tag: String,
+        tags: Vec<String>,
         threshold: f32,
         timeout: Duration,
         click: bool,
         r#move: bool,
         delay: Option<Duration>,
+        // first area with click point if none
+        area: Option<AreaSelector>,
+    },
+    // png file as needle, region defaults to the whole image
+    CheckImage {
+        path: String,
+        region: Option<Rect>,
+        threshold: f32,
+        timeout: Duration,
     },
     MouseMove {
         x: u16,
         y: u16,
     },
+    MouseMoveRelative {
+        dx: i16,
+        dy: i16,
+    },
+    GetMousePos,
     MouseDrag {
         x: u16,
         y: u16,
     },
     MouseHide,
-    MouseClick,
-    MouseRClick,
+    WaitStill {
+        timeout: Duration,
+        stable_for: Duration,
+    },
+    // done once needle has not matched for stable_for
+    WaitGone {
+        tag: String,
+        threshold: f32,
+        timeout: Duration,
+        stable_for: Duration,
+    },
+    // save current screen as needle tag if it's missing or UPDATE_GOLDEN is set,
+    // answered with whether it was saved
+    SaveGolden {
+        tag: String,
+    },
+    // done if needle never matched within duration, matched as soon as it appears
+    AssertNoScreen {
+        tag: String,
+        threshold: f32,
+        duration: Duration,
+    },
+    // button number, 1 left, 2 middle, 3 right, 4-8 wheel and extra buttons
+    MouseClick(u8),
+    MouseDoubleClick,
     MouseKeyDown(bool),
     SendKey(String),
+    // raw x11 keysyms pressed together, bypasses key name parsing
+    SendKeysym(Vec<u32>),
+    KeyDown(String),
+    KeyUp(String),
     TypeString(String),
+    // like TypeString, but redacted from logs and events
+    TypePassword(String),
+    PasteString(String),
 }
 
 #[derive(Debug)]
 pub enum MsgResError {
     Timeout,
+    Interrupt,
     String(String),
+    // console the request needs is not connected, e.g. "vnc", "ssh or serial"
+    NoConsole(String),
+    // console is connected but reading or writing it failed
+    ConnectionBroken(String),
+    // assert on script return code failed, soft assert off
+    CommandFailed { code: i32 },
+    // pattern from script is not a valid regex
+    RegexBuild(String),
+}
+
+impl Display for MsgResError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MsgResError::Timeout => write!(f, "timeout"),
+            MsgResError::Interrupt => write!(f, "interrupted"),
+            MsgResError::String(s) => write!(f, "{s}"),
+            MsgResError::NoConsole(console) => write!(f, "no {console} console"),
+            MsgResError::ConnectionBroken(s) => write!(f, "connection broken, {s}"),
+            MsgResError::CommandFailed { code } => write!(f, "command returned {code}"),
+            MsgResError::RegexBuild(s) => write!(f, "invalid regex, {s}"),
+        }
+    }
 }
 
 impl From<MsgResError> for ApiError {
     fn from(value: MsgResError) -> Self {
         match value {
             MsgResError::Timeout => Self::Timeout,
+            MsgResError::Interrupt => Self::Interrupt,
             MsgResError::String(s) => Self::String(s),
+            MsgResError::NoConsole(s) => Self::NoConsole(s),
+            MsgResError::ConnectionBroken(s) => Self::ConnectionBroken(s),
+            MsgResError::CommandFailed { code } => Self::CommandFailed { code },
+            MsgResError::RegexBuild(s) => Self::RegexBuild(s),
         }
     }
 }
@@ -89,8 +271,49 @@ impl From<MsgResError> for ApiError {
 #[derive(Debug)]
 pub enum MsgRes {
     Done,
-    ConfigValue(Option<String>),
-    ScriptRun { code: i32, value: String },
+    ConfigValue(Option<toml::Value>),
+    DefaultTimeout(Duration),
+    DefaultThreshold(f32),
+    // names of consoles reconnected
+    Reconnected(Vec<String>),
+    ScriptRun {
+        code: i32,
+        value: String,
+    },
+    ScriptRunFull {
+        code: i32,
+        stdout: String,
+        stderr: String,
+    },
     Error(MsgResError),
     Screenshot(Arc<PNG>),
+    ScreenSize(u16, u16),
+    MousePos(u16, u16),
+    ConnectionState(ConnectionState),
+    BellCount(u64),
+    Matched(String),
+    Failures(Vec<String>),
+    // needle tags, sorted
+    Needles(Vec<String>),
+    NeedleExists(bool),
+    GoldenSaved(bool),
+    AnyMatched(usize),
+    // "serial" or "screen", whichever signaled boot first
+    Booted(String),
+    Batch(Vec<MsgRes>),
+    // check screen success, with the matched tag
+    ScreenMatched {
+        tag: String,
+        stats: MatchStats,
+    },
+}
+
+// how a check screen matched, for tracking slow steps
+#[derive(Debug, Clone)]
+pub struct MatchStats {
+    pub similarity: f32,
+    // from request received to match, excluding click and delay
+    pub elapsed: Duration,
+    // screenshots compared, including the matched one
+    pub iterations: u32,
 }
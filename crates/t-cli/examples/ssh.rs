@@ -1,5 +1,5 @@
 use clap::Parser;
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 use t_config::ConsoleSSH;
 use t_console::SSH;
 use tracing::{info, Level};
@@ -35,9 +35,13 @@ fn main() {
             .map(|p| p.as_path().to_string_lossy().to_string()),
         password: cli.password,
         timeout: None,
+        log_file_max_mb: None,
         log_file: None,
         enable_echo: Some(false),
         linebreak: Some("\n".to_string()),
+        rows: None,
+        cols: None,
+        jump: None,
     }) {
         Ok(mut ssh) => {
             info!("Connected");
@@ -48,8 +52,12 @@ fn main() {
                 .map(|x| x.as_ref()) // arguments are escaped manually since the SSH protocol doesn't support quoting
                 .collect::<Vec<_>>()
                 .join(";");
-            let code = ssh.exec_seperate(command_str).unwrap();
+            let (code, stdout, stderr) = ssh
+                .exec_seperate(command_str, Duration::from_secs(60))
+                .unwrap();
             println!("Exitcode: {:?}", code);
+            println!("Stdout: {}", stdout);
+            println!("Stderr: {}", stderr);
         }
         Err(e) => {
             println!("connect failed: {:?}", e.to_string());
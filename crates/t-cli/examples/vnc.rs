@@ -1,5 +1,6 @@
 use clap::Parser;
-use t_console::VNC;
+use t_config::ConsoleVNC;
+use t_console::{VNCAddr, VNC};
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
 
@@ -39,10 +40,11 @@ fn main() {
         cli.password
     );
 
-    VNC::connect(
-        format!("{}:{}", cli.host, cli.port).parse().unwrap(),
-        cli.password,
-        None,
-    )
-    .unwrap();
+    let c = ConsoleVNC {
+        host: cli.host,
+        port: cli.port,
+        password: cli.password,
+        ..Default::default()
+    };
+    VNC::connect(VNCAddr::parse(&c.host, c.port).unwrap(), &c, None).unwrap();
 }
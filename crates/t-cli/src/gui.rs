@@ -1,4 +1,5 @@
 mod editor;
+mod recorder;
 mod viwer;
 
 // #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
@@ -14,7 +15,7 @@ use std::{
     time::{Duration, Instant},
 };
 use t_binding::api::Api;
-use t_console::PNG;
+use t_console::{ConnectionState, PNG};
 use tracing::{debug, error};
 use tracing_core::Level;
 use util::*;
@@ -41,6 +42,7 @@ struct SharedState {
     sample_status: RwLock<SampleStatus>,
     use_rayon: RwLock<bool>,
     screen: RwLock<Option<Screenshot>>,
+    vnc_state: RwLock<ConnectionState>,
 }
 
 impl SharedState {
@@ -50,6 +52,7 @@ impl SharedState {
             sample_status: RwLock::new(SampleStatus::default()),
             use_rayon: RwLock::new(true),
             screen: RwLock::new(None),
+            vnc_state: RwLock::new(ConnectionState::Disconnected),
         }
     }
 }
@@ -202,8 +205,13 @@ impl Gui {
             return;
         };
 
+        let has_vnc = self.state.has_vnc();
         ui.horizontal(|ui| {
-            if ui.button("force refresh").clicked() && api.vnc_refresh().is_err() {
+            if ui
+                .add_enabled(has_vnc, egui::Button::new("force refresh"))
+                .clicked()
+                && api.vnc_refresh().is_err()
+            {
                 self.state
                     .logs_toasts
                     .push((Level::ERROR, "force refresh failed".to_string()));
@@ -219,10 +227,12 @@ impl Gui {
                 .heading(),
             );
 
-            ui.colored_label(
-                Color32::YELLOW,
-                RichText::new(format!("VNC FPS {:>2}", sample_status.vnc_fps)).heading(),
-            );
+            if has_vnc {
+                ui.colored_label(
+                    Color32::YELLOW,
+                    RichText::new(format!("VNC FPS {:>2}", sample_status.vnc_fps)).heading(),
+                );
+            }
             drop(sample_status);
 
             let use_rayon = *self.viwer.share_state.use_rayon.read();
@@ -233,15 +243,25 @@ impl Gui {
                 *self.viwer.share_state.use_rayon.write() = !use_rayon;
             }
 
-            ui.colored_label(
-                Color32::GREEN,
-                RichText::new(format!(
-                    "vnc no update:{}s",
-                    (Instant::now() - self.viwer.share_state.frame_status.read().last_screenshot)
-                        .as_secs()
-                ))
-                .heading(),
-            );
+            if has_vnc {
+                ui.colored_label(
+                    Color32::GREEN,
+                    RichText::new(format!(
+                        "vnc no update:{}s",
+                        (Instant::now()
+                            - self.viwer.share_state.frame_status.read().last_screenshot)
+                            .as_secs()
+                    ))
+                    .heading(),
+                );
+
+                let (color, state) = match *self.viwer.share_state.vnc_state.read() {
+                    ConnectionState::Connected => (Color32::GREEN, "connected"),
+                    ConnectionState::Reconnecting => (Color32::YELLOW, "reconnecting"),
+                    ConnectionState::Disconnected => (Color32::RED, "disconnected"),
+                };
+                ui.colored_label(color, RichText::new(format!("● vnc {state}")).heading());
+            }
         });
     }
 
@@ -1,11 +1,10 @@
 use std::{
     fs,
     path::{Path, PathBuf},
-    str::FromStr,
 };
 
 use eframe::egui::{self, Color32, Pos2, Rect, RichText, Sense, Vec2};
-use t_runner::needle::NeedleConfig;
+use t_runner::needle::{Capture, NeedleConfig};
 use tracing::Level;
 
 use super::{
@@ -206,7 +205,8 @@ impl NeedleEditor {
                     .config
                     .as_ref()
                     .and_then(|c| c.vnc.as_ref().and_then(|c| c.needle_dir.as_ref()))
-                    .and_then(|s| PathBuf::from_str(s).ok());
+                    // new needle saved to the first dir
+                    .and_then(|d| d.dirs().into_iter().next());
 
                 let needle_dir_clone = needle_dir.clone();
                 ui.vertical(|ui| {
@@ -383,7 +383,10 @@ impl NeedleSource {
                 click: click.map(|(x, y)| t_runner::needle::AreaClick {
                     left: x as u16,
                     top: y as u16,
+                    left_ratio: (rect.width > 0.).then(|| x / rect.width),
+                    top_ratio: (rect.height > 0.).then(|| y / rect.height),
                 }),
+                name: None,
             };
             areas.push(area);
         }
@@ -391,6 +394,16 @@ impl NeedleSource {
             areas,
             properties: Vec::new(),
             tags: vec![self.name.clone()],
+            capture: Some(Capture {
+                width: self.screenshot.source.width,
+                height: self.screenshot.source.height,
+                time: self
+                    .screenshot
+                    .recv_time
+                    .format(Capture::TIME_FORMAT)
+                    .to_string(),
+                source: Some("editor".to_string()),
+            }),
         };
         let s = serde_json::to_string_pretty(&cfg).map_err(|_| ())?;
         fs::write(p, s).map_err(|_| ())?;
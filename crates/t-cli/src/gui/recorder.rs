@@ -0,0 +1,124 @@
+use std::fmt::Write;
+
+// user action on vnc screen, coordinates are relative to the framebuffer
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    MouseClick(u16, u16),
+    MouseRClick(u16, u16),
+    MouseDrag((u16, u16), (u16, u16)),
+    TypeString(String),
+    SendKey(String),
+}
+
+#[derive(Default)]
+pub struct Recorder {
+    actions: Vec<Action>,
+    // drag start position, pushed as one action when drag stopped
+    drag_start: Option<(u16, u16)>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.actions.clear();
+        self.drag_start = None;
+    }
+
+    pub fn push(&mut self, action: Action) {
+        // merge continuous typing into one type_string
+        if let Action::TypeString(s) = &action {
+            if let Some(Action::TypeString(last)) = self.actions.last_mut() {
+                last.push_str(s);
+                return;
+            }
+        }
+        self.actions.push(action);
+    }
+
+    pub fn drag_start(&mut self, x: u16, y: u16) {
+        self.drag_start = Some((x, y));
+    }
+
+    pub fn drag_stop(&mut self, x: u16, y: u16) {
+        if let Some(start) = self.drag_start.take() {
+            self.push(Action::MouseDrag(start, (x, y)));
+        }
+    }
+
+    pub fn to_script(&self) -> String {
+        let mut res = String::new();
+        res.push_str("export function main() {\n");
+        for action in self.actions.iter() {
+            let _ = match action {
+                Action::MouseClick(x, y) => {
+                    writeln!(res, "    mouse_move({x}, {y});\n    mouse_click();")
+                }
+                Action::MouseRClick(x, y) => {
+                    writeln!(res, "    mouse_move({x}, {y});\n    mouse_rclick();")
+                }
+                Action::MouseDrag((x1, y1), (x2, y2)) => writeln!(
+                    res,
+                    "    mouse_move({x1}, {y1});\n    mouse_keydown();\n    mouse_drag({x2}, {y2});\n    mouse_keyup();"
+                ),
+                Action::TypeString(s) => writeln!(res, "    type_string({});", js_string(s)),
+                Action::SendKey(s) => writeln!(res, "    send_key({});", js_string(s)),
+            };
+        }
+        res.push_str("}\n");
+        res
+    }
+}
+
+fn js_string(s: &str) -> String {
+    let mut res = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_script() {
+        let mut r = Recorder::new();
+        r.push(Action::MouseClick(10, 20));
+        r.push(Action::TypeString("echo ".to_string()));
+        r.push(Action::TypeString("\"a\\b\"".to_string()));
+        r.push(Action::SendKey("ret".to_string()));
+        r.drag_start(1, 2);
+        r.drag_stop(3, 4);
+        assert_eq!(r.len(), 4);
+        assert_eq!(
+            r.to_script(),
+            r#"export function main() {
+    mouse_move(10, 20);
+    mouse_click();
+    type_string("echo \"a\\b\"");
+    send_key("ret");
+    mouse_move(1, 2);
+    mouse_keydown();
+    mouse_drag(3, 4);
+    mouse_keyup();
+}
+"#
+        );
+    }
+}
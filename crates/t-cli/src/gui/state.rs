@@ -1,7 +1,11 @@
 use std::{
     collections::VecDeque,
     path::Path,
-    sync::{mpsc::Sender, Arc},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
@@ -210,6 +214,8 @@ impl Default for EguiFrameStatus {
 
 pub struct PanelState {
     pub driver: Option<(RustApi, Sender<Sender<()>>)>,
+    // set on stop, screenshot polling thread exits on next iteration
+    pub capture_stop: Arc<AtomicBool>,
 
     #[allow(unused)]
     pub screenshots: RwLock<VecDeque<Screenshot>>,
@@ -250,12 +256,13 @@ impl PanelState {
         # host = "127.0.0.1"
         # port = 5901
         # password = "123456" # optional
-        # needle_dir = "./needles" # optional
+        # needle_dir = "./needles" # optional, or a list searched in order
                 "#
             .to_string(),
         );
         Self {
             driver: None,
+            capture_stop: Arc::new(AtomicBool::new(false)),
             screenshots: RwLock::new(VecDeque::new()),
 
             mode: RecordMode::Interact,
@@ -285,7 +292,13 @@ export function afterhook() {
         }
     }
 
+    // serial or ssh only targets have no screen, vnc widgets are disabled
+    pub fn has_vnc(&self) -> bool {
+        self.config.as_ref().is_some_and(|c| c.vnc.is_some())
+    }
+
     pub fn stop(&mut self) {
+        self.capture_stop.store(true, Ordering::Relaxed);
         let (tx, rx) = std::sync::mpsc::channel();
         let Some((_, stop_tx)) = self.driver.as_ref() else {
             return;
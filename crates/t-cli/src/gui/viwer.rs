@@ -1,8 +1,9 @@
 // #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
-use crate::gui::RecordMode;
+use crate::gui::{RecordMode, Tab};
 
 use super::{
+    recorder::{Action, Recorder},
     state::{PanelState, Screenshot},
     SharedState, CAPS_MAP,
 };
@@ -11,12 +12,13 @@ use eframe::egui::{
     self,
     ahash::{HashMap, HashMapExt},
     text::CursorRange,
-    Layout, RichText, Sense, TextEdit, Widget,
+    Color32, Layout, RichText, Sense, TextEdit, Widget,
 };
 use std::{
     fs,
     path::{Path, PathBuf},
     sync::{
+        atomic::{AtomicBool, Ordering},
         mpsc::{channel, Receiver},
         Arc,
     },
@@ -24,12 +26,48 @@ use std::{
     time::{Duration, Instant},
 };
 use t_binding::api::{Api, RustApi};
-use t_runner::{error::DriverError, DriverBuilder};
+use t_runner::{error::DriverError, DriverBuilder, Stepper};
 use tracing::{debug, info};
 use tracing_core::Level;
 
+// lines of a watched file, lines from fresh_from on were added by the last change
+#[derive(Default)]
+struct WatchedFile {
+    lines: Vec<String>,
+    fresh_from: usize,
+}
+
+impl WatchedFile {
+    fn update(&mut self, lines: Vec<String>) {
+        self.fresh_from = fresh_from(&self.lines, &lines);
+        self.lines = lines;
+    }
+}
+
+// index of the first line in new which is not in old, a partial last line of old counts as
+// new once completed, old lines may be cut at front when the file outgrows the tail window
+fn fresh_from(old: &[String], new: &[String]) -> usize {
+    let same = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    if same > 0 || old.len() <= 1 {
+        return same;
+    }
+    // last complete line of old, it can't be after where old ends in new
+    let anchor = &old[old.len() - 2];
+    let Some(i) = new[..new.len().min(old.len() - 1)]
+        .iter()
+        .rposition(|l| l == anchor)
+    else {
+        return 0;
+    };
+    if new.get(i + 1) == old.last() {
+        i + 2
+    } else {
+        i + 1
+    }
+}
+
 pub struct FileWatcher {
-    cache: Arc<parking_lot::RwLock<HashMap<PathBuf, Vec<String>>>>,
+    cache: Arc<parking_lot::RwLock<HashMap<PathBuf, WatchedFile>>>,
     watchers: parking_lot::Mutex<Vec<notify::RecommendedWatcher>>,
 }
 
@@ -52,14 +90,25 @@ impl FileWatcher {
         // let path_clone = path.as_ref().to_path_buf();
         let cache = self.cache.clone();
         if cache.read().get(path.as_path()).is_none() {
-            if let Ok(file) = fs::read_to_string(path.as_path()) {
+            if let Ok(file) = read_tail(path.as_path()) {
                 let mut lock = cache.write();
                 // double check
                 if lock.get(path.as_path()).is_some() {
                     return;
                 }
                 // lock.insert(path.clone(), file);
-                lock.insert(path.clone(), file.lines().map(|s| s.to_string()).collect());
+                // stripped like updates, so the first change diffs against same lines
+                let lines = console::strip_ansi_codes(&file)
+                    .lines()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<_>>();
+                lock.insert(
+                    path.clone(),
+                    WatchedFile {
+                        fresh_from: lines.len(),
+                        lines,
+                    },
+                );
                 drop(lock);
 
                 // spawn watcher
@@ -67,14 +116,22 @@ impl FileWatcher {
                 let path_clone = path.clone();
                 let mut watcher = notify::recommended_watcher(
                     move |res: Result<notify::Event, notify::Error>| match res {
-                        Ok(_event) => {
-                            let content = fs::read_to_string(&path_clone).unwrap_or_default();
+                        Ok(event) => {
+                            // parent dir is watched, skip other files in it
+                            if !event
+                                .paths
+                                .iter()
+                                .any(|p| p.file_name() == path_clone.file_name())
+                            {
+                                return;
+                            }
+                            let content = read_tail(&path_clone).unwrap_or_default();
                             let stripped = console::strip_ansi_codes(&content);
-                            cache.write().insert(
-                                path_clone.clone(),
-                                stripped.lines().map(|s| s.to_string()).collect(),
-                                // stripped.to_string(),
-                            );
+                            cache
+                                .write()
+                                .entry(path_clone.clone())
+                                .or_default()
+                                .update(stripped.lines().map(|s| s.to_string()).collect());
                         }
                         Err(e) => {
                             info!("watch error: {:?}", e);
@@ -88,8 +145,13 @@ impl FileWatcher {
 
                 let pathname = path.as_path().display();
                 info!(msg = "watcher started", path = ?pathname);
+                // watch the dir, a rolled log file is renamed and the path is a new file
+                let dir = path
+                    .parent()
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .unwrap_or(Path::new("."));
                 watcher
-                    .watch(path.as_path(), notify::RecursiveMode::NonRecursive)
+                    .watch(dir, notify::RecursiveMode::NonRecursive)
                     .unwrap();
                 self.watchers.lock().push(watcher);
             }
@@ -97,6 +159,25 @@ impl FileWatcher {
     }
 }
 
+// only the end of a large log is shown, the first partial line is dropped
+fn read_tail(path: &Path) -> std::io::Result<String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    const TAIL_BYTES: u64 = 1024 * 1024;
+    let mut file = fs::File::open(path)?;
+    let start = file.metadata()?.len().saturating_sub(TAIL_BYTES);
+    file.seek(SeekFrom::Start(start))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    let mut content = String::from_utf8_lossy(&buf).into_owned();
+    if start > 0 {
+        if let Some(i) = content.find('\n') {
+            content.drain(..=i);
+        }
+    }
+    Ok(content)
+}
+
 pub struct Viewer {
     // viewer
     pub share_state: Arc<SharedState>,
@@ -104,10 +185,15 @@ pub struct Viewer {
 
     // screenshot
     code_receiver: Option<Receiver<Result<(), String>>>,
+    // holds api calls of the running script while paused
+    stepper: Stepper,
     cursor_range: Option<CursorRange>,
 
     last_move_interval: Instant,
     minimal_move_interval: Duration,
+
+    // record user actions, can be exported as script
+    recorder: Recorder,
 }
 
 impl Viewer {
@@ -116,6 +202,7 @@ impl Viewer {
             // only used in PNG to egui::ColorImage, take more cpu usage
             share_state: Arc::new(SharedState::new()),
             code_receiver: None,
+            stepper: Stepper::default(),
 
             cursor_range: None,
 
@@ -124,6 +211,8 @@ impl Viewer {
 
             last_move_interval: Instant::now(),
             minimal_move_interval: Duration::from_millis(50),
+
+            recorder: Recorder::new(),
         }
     }
 
@@ -138,14 +227,28 @@ impl Viewer {
         d.start();
         state.driver = Some((RustApi::new(d.msg_tx), d.stop_tx));
 
+        // text only target, keep log tabs and script runner but don't poll screenshots
+        if !state.has_vnc() {
+            if state.tab == Tab::Vnc {
+                state.tab = if state.config.as_ref().is_some_and(|c| c.serial.is_some()) {
+                    Tab::Serial
+                } else {
+                    Tab::Ssh
+                };
+            }
+            return Ok(());
+        }
+
         let Some((api, _)) = state.driver.as_ref() else {
             return Ok(());
         };
         let api = api.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        state.capture_stop = stop.clone();
 
         thread::spawn(move || {
             let interval = shared_state.frame_status.read().screenshot_interval;
-            loop {
+            while !stop.load(Ordering::Relaxed) {
                 // if already got new screenshot in this egui frame, then skip
                 if let Some(screenshot_interval) = interval {
                     if Instant::now()
@@ -173,6 +276,9 @@ impl Viewer {
                         s.update(screenshot);
                     }
                 }
+                if let Ok(state) = api.vnc_connection_state() {
+                    *shared_state.vnc_state.write() = state;
+                }
                 thread::sleep(Duration::from_millis(50));
             }
         });
@@ -188,7 +294,18 @@ impl Viewer {
 
             // render current screenshot
             let img = screenshot.image();
+            let (width, height) = (screenshot.source.width, screenshot.source.height);
             let screenshot = ui.add(img.sense(Sense::click_and_drag()));
+            // map egui pos to framebuffer pos, image may be scaled by pixels_per_point
+            let to_framebuffer = |pos: egui::Pos2| {
+                let rect = screenshot.rect;
+                let x = (pos.x - rect.left()) / rect.width() * width as f32;
+                let y = (pos.y - rect.top()) / rect.height() * height as f32;
+                (
+                    (x.max(0.) as u16).min(width.saturating_sub(1)),
+                    (y.max(0.) as u16).min(height.saturating_sub(1)),
+                )
+            };
 
             let Some((api, _)) = state.driver.as_ref() else {
                 return;
@@ -196,8 +313,7 @@ impl Viewer {
 
             // if mouse move out of image, do nothing
             if let Some(pos) = screenshot.hover_pos() {
-                let relative_x = (pos.x as u16).saturating_sub(screenshot.rect.left() as u16);
-                let relative_y = (pos.y as u16).saturating_sub(screenshot.rect.top() as u16);
+                let (relative_x, relative_y) = to_framebuffer(pos);
 
                 if Instant::now() - self.last_move_interval > self.minimal_move_interval {
                     if api.vnc_mouse_move(relative_x, relative_y).is_err() {
@@ -227,7 +343,10 @@ impl Viewer {
                                         }
                                         keys.push(*c as char);
                                         debug!(msg = "text input", text = keys);
-                                        let _ = api.vnc_send_key(keys);
+                                        if api.vnc_send_key(keys).is_ok() {
+                                            self.recorder
+                                                .push(Action::TypeString((*c as char).to_string()));
+                                        }
                                     }
                                 }
                             } // Event::Key would be enough?
@@ -257,7 +376,9 @@ impl Viewer {
                                     }
                                     keys.push_str(key.name());
                                     debug!(msg = "key input", final_key = keys.to_string());
-                                    let _ = api.vnc_send_key(keys);
+                                    if api.vnc_send_key(keys.clone()).is_ok() {
+                                        self.recorder.push(Action::SendKey(keys));
+                                    }
                                 }
                             }
                             _ => {}
@@ -267,18 +388,19 @@ impl Viewer {
             }
 
             // handle drag
-            if let Some(_pos) = screenshot.interact_pointer_pos() {
-                let relative_x = (_pos.x as u16).saturating_sub(screenshot.rect.left() as u16);
-                let relative_y = (_pos.y as u16).saturating_sub(screenshot.rect.top() as u16);
+            if let Some(pos) = screenshot.interact_pointer_pos() {
+                let (relative_x, relative_y) = to_framebuffer(pos);
 
                 if screenshot.drag_started() {
                     // init current pos
                     let _ = api.vnc_mouse_keydown();
                     let _ = api.vnc_mouse_drag(relative_x, relative_y);
+                    self.recorder.drag_start(relative_x, relative_y);
                 } else if screenshot.dragged() {
                     let _ = api.vnc_mouse_drag(relative_x, relative_y);
                 } else if screenshot.drag_stopped() {
                     let _ = api.vnc_mouse_keyup();
+                    self.recorder.drag_stop(relative_x, relative_y);
                 }
 
                 if screenshot.clicked() {
@@ -287,6 +409,9 @@ impl Viewer {
                             Level::ERROR,
                             format!("mouse click failed, reason = {:?}", e),
                         ));
+                    } else {
+                        self.recorder
+                            .push(Action::MouseClick(relative_x, relative_y));
                     }
                 }
 
@@ -296,6 +421,9 @@ impl Viewer {
                             Level::ERROR,
                             format!("mouse right click failed, reason = {:?}", e),
                         ));
+                    } else {
+                        self.recorder
+                            .push(Action::MouseRClick(relative_x, relative_y));
                     }
                 }
             }
@@ -308,8 +436,13 @@ impl Viewer {
             "selected: {:?}",
             self.cursor_range.map(|r| r.as_sorted_char_range())
         ));
+        // ctrl-enter in editor runs script, taken before editor inserts a newline
+        let editor_id = egui::Id::new("script_editor");
+        let run_shortcut = ui.memory(|m| m.has_focus(editor_id))
+            && ui.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::Enter));
         egui::ScrollArea::both().show(ui, |ui| {
             let script_editor = TextEdit::multiline(&mut state.code_str)
+                .id(editor_id)
                 .code_editor()
                 .lock_focus(true)
                 .desired_width(f32::INFINITY)
@@ -332,35 +465,108 @@ impl Viewer {
                 }
             }
         }
-        ui.add_enabled_ui(self.code_receiver.is_none(), |ui| {
+        let idle = self.code_receiver.is_none();
+        ui.add_enabled_ui(idle, |ui| {
             ui.horizontal(|ui| {
-                if ui.button("run script").clicked() {
-                    let code = state.code_str.clone();
-                    let (tx, rx) = channel();
-                    self.code_receiver = Some(rx);
-
-                    let Some((api, _)) = state.driver.as_ref() else {
-                        return;
-                    };
-
-                    let msg_tx = api.tx.clone();
-                    info!(msg = "run script");
-                    state.mode = RecordMode::View;
-                    thread::spawn(move || {
-                        let res = t_binding::JSEngine::new(msg_tx).run_string(code.as_str());
-                        tx.send(res)
-                    });
+                let clicked = ui
+                    .button("run script")
+                    .on_hover_text("ctrl-enter in editor")
+                    .clicked();
+                // a running script is never launched twice
+                if idle && (clicked || run_shortcut) {
+                    self.run_script(state);
                 }
                 if self.code_receiver.is_some() {
                     ui.spinner();
                 }
             });
         });
+
+        // step through api calls of script, the screen stays live in between
+        ui.horizontal(|ui| {
+            let mut paused = self.stepper.is_paused();
+            if ui
+                .checkbox(&mut paused, "pause before each api call")
+                .changed()
+            {
+                self.stepper.set_paused(paused);
+            }
+            let held = self.stepper.held();
+            if ui
+                .add_enabled(held.is_some(), egui::Button::new("step"))
+                .clicked()
+            {
+                self.stepper.step();
+            }
+            if let Some(held) = held {
+                ui.label(format!("held: {held}"));
+            }
+        });
+
+        // recorded actions
+        ui.horizontal(|ui| {
+            ui.label(format!("recorded actions: {}", self.recorder.len()));
+            if ui.button("export script").clicked() {
+                let script = self.recorder.to_script();
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("javascript", &["js"])
+                    .set_file_name("record.js")
+                    .save_file()
+                {
+                    match fs::write(&path, &script) {
+                        Ok(_) => state.logs_toasts.push((
+                            Level::INFO,
+                            format!("script exported to {}", path.display()),
+                        )),
+                        Err(e) => state
+                            .logs_toasts
+                            .push((Level::ERROR, format!("export script failed: {:?}", e))),
+                    }
+                }
+                // editor may hold user's own script, don't drop it silently
+                let replace = state.code_str.trim().is_empty()
+                    || rfd::MessageDialog::new()
+                        .set_title("export script")
+                        .set_description("replace the script in editor with recorded actions?")
+                        .set_buttons(rfd::MessageButtons::YesNo)
+                        .show()
+                        == rfd::MessageDialogResult::Yes;
+                if replace {
+                    state.code_str = script;
+                }
+            }
+            if ui.button("clear").clicked() {
+                self.recorder.clear();
+            }
+        });
+    }
+
+    // run editor code in background, result is polled by render_code_editor
+    fn run_script(&mut self, state: &mut PanelState) {
+        let code = state.code_str.clone();
+        let (tx, rx) = channel();
+        self.code_receiver = Some(rx);
+
+        let Some((api, _)) = state.driver.as_ref() else {
+            return;
+        };
+
+        let msg_tx = self.stepper.bridge(api.tx.clone());
+        info!(msg = "run script");
+        state.mode = RecordMode::View;
+        thread::spawn(move || {
+            let res = t_binding::JSEngine::new(msg_tx).run_string(code.as_str());
+            tx.send(res)
+        });
     }
 
     pub fn render_file(&mut self, ui: &mut egui::Ui, path: &PathBuf) {
         self.file_watcher.try_watch(path);
-        if let Some(file_content) = self.file_watcher.cache.read().get(path) {
+        if let Some(WatchedFile {
+            lines: file_content,
+            fresh_from,
+        }) = self.file_watcher.cache.read().get(path)
+        {
             // let pathname = path.as_path().display();
             // warn!(msg = "watcher received event", path = ?pathname);
             // let mut file_content = fs::read_to_string(&path).unwrap_or_default();
@@ -387,10 +593,12 @@ impl Viewer {
                                 );
                             });
                             row.col(|ui| {
-                                egui::Label::new(RichText::new(&file_content[i]).code())
-                                    .wrap(false)
-                                    .selectable(true)
-                                    .ui(ui);
+                                // mark output added by the last change
+                                let mut text = RichText::new(&file_content[i]).code();
+                                if i >= *fresh_from {
+                                    text = text.color(Color32::LIGHT_GREEN);
+                                }
+                                egui::Label::new(text).wrap(false).selectable(true).ui(ui);
                             });
                         });
                     });
@@ -399,3 +607,35 @@ impl Viewer {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_fresh_from() {
+        // appended
+        assert_eq!(fresh_from(&lines("a\nb"), &lines("a\nb\nc\nd")), 2);
+        // partial last line completed
+        assert_eq!(fresh_from(&lines("a\nlog"), &lines("a\nlogin:\nb")), 1);
+        // tail window moved
+        assert_eq!(fresh_from(&lines("a\nb\nc\nd"), &lines("c\nd\ne")), 2);
+        assert_eq!(
+            fresh_from(&lines("a\nb\nc\npart"), &lines("c\npartial\ne")),
+            1
+        );
+        // rolled to a new file
+        assert_eq!(fresh_from(&lines("a\nb\nc"), &lines("x\ny")), 0);
+        assert_eq!(fresh_from(&[], &lines("x")), 0);
+        // unchanged
+        let mut f = WatchedFile::default();
+        f.update(lines("a\nb"));
+        assert_eq!(f.fresh_from, 0);
+        f.update(lines("a\nb"));
+        assert_eq!(f.fresh_from, 2);
+    }
+}
@@ -1,10 +1,21 @@
 pub mod gui;
 
 use clap::{Parser, Subcommand};
-use std::{env, fs, io::IsTerminal, path::Path};
-use t_binding::api::{Api, RustApi};
+use std::{
+    env, fs,
+    io::{self, BufRead, IsTerminal, Write},
+    path::Path,
+    process,
+};
+use t_binding::{
+    api::{Api, RustApi},
+    ApiError, JSEngine,
+};
 use t_config::Config;
-use t_runner::{DriverBuilder, DriverForScript};
+use t_runner::{
+    needle::{Needle, NeedleManager},
+    DriverBuilder, DriverForScript,
+};
 use tracing::{error, info, Level};
 use tracing_subscriber::FmtSubscriber;
 
@@ -21,6 +32,12 @@ enum Commands {
         config: String,
         #[clap(short, long)]
         script: String,
+        // write json line events to file, "-" means stdout
+        #[clap(long)]
+        events: Option<String>,
+        // override config value, e.g. --set vnc.host=10.0.0.5, can be set multiple times
+        #[clap(long = "set", value_name = "KEY=VALUE")]
+        overrides: Vec<String>,
     },
     Record {
         #[clap(short, long)]
@@ -32,6 +49,33 @@ enum Commands {
         #[command(subcommand)]
         action: VNCAction,
     },
+    NeedleCheck {
+        // can be set multiple times, searched in order
+        #[clap(short, long, required = true)]
+        needle_dir: Vec<String>,
+        #[clap(short, long)]
+        image: String,
+        #[clap(short, long)]
+        tag: String,
+        #[clap(long)]
+        threshold: Option<f32>,
+    },
+    Validate {
+        #[clap(short, long)]
+        config: String,
+        #[clap(short, long)]
+        script: String,
+    },
+    // print all needles in needle_dir as json
+    Needles {
+        #[clap(short, long)]
+        config: String,
+    },
+    // evaluate js api calls line by line from stdin
+    Repl {
+        #[clap(short, long)]
+        config: String,
+    },
 }
 
 #[derive(Debug, Clone, Subcommand)]
@@ -39,6 +83,11 @@ enum VNCAction {
     Move { x: u16, y: u16 },
     Click,
     RClick,
+    Type { text: String },
+    // key combo like "ctrl-alt-delete"
+    Key { combo: String },
+    // save current frame to png file
+    Screenshot { out: String },
 }
 
 fn main() {
@@ -70,9 +119,15 @@ fn main() {
     info!(msg = "current cli", cli = ?cli);
 
     match cli.command {
-        Commands::Run { script, config } => {
+        Commands::Run {
+            script,
+            config,
+            events,
+            overrides,
+        } => {
             // init config
-            let config = Config::from_toml_file(config.as_str()).expect("config not valid");
+            let config = Config::from_toml_file_with_overrides(config.as_str(), &overrides)
+                .expect("config not valid");
             info!(msg = "current config", config = ?config);
 
             let ext = Path::new(script.as_str())
@@ -81,9 +136,12 @@ fn main() {
                 .to_string_lossy()
                 .to_string();
 
-            match DriverForScript::new_with_engine(config, ext.as_str()) {
+            match DriverForScript::new_with_engine(config, ext.as_str(), events.map(Into::into)) {
                 Ok(mut d) => {
                     d.start().run_file(script).stop();
+                    if let Some(dir) = d.log_dir() {
+                        println!("artifacts in {}", dir.display());
+                    }
                 }
                 Err(e) => {
                     error!(msg = "Driver init failed", reason = ?e)
@@ -115,6 +173,16 @@ fn main() {
                         VNCAction::Move { x, y } => api.vnc_mouse_move(x, y),
                         VNCAction::Click => api.vnc_mouse_click(),
                         VNCAction::RClick => api.vnc_mouse_rclick(),
+                        VNCAction::Type { text } => api.vnc_type_string(text),
+                        VNCAction::Key { combo } => api.vnc_send_key(combo),
+                        VNCAction::Screenshot { out } => {
+                            api.vnc_get_screenshot().and_then(|screen| {
+                                screen
+                                    .as_img()
+                                    .save(&out)
+                                    .map_err(|e| ApiError::String(e.to_string()))
+                            })
+                        }
                     } {
                         error!(msg = "do vnc action failed", reason=?e);
                     }
@@ -125,5 +193,103 @@ fn main() {
                 }
             }
         }
+        Commands::NeedleCheck {
+            needle_dir,
+            image,
+            tag,
+            threshold,
+        } => {
+            let nmg = NeedleManager::with_dirs(needle_dir);
+            let Some(needle) = nmg.load(&tag) else {
+                eprintln!("needle not found: {tag}");
+                process::exit(2);
+            };
+            let Some(screen) = nmg.load_image(&image) else {
+                eprintln!("image not valid: {image}");
+                process::exit(2);
+            };
+            let (similarity, matched) = Needle::cmp(&screen, &needle, threshold);
+            println!(
+                "{tag}: similarity {similarity:.4}, {}",
+                if matched { "pass" } else { "fail" }
+            );
+            if !matched {
+                process::exit(1);
+            }
+        }
+        Commands::Validate { config, script } => {
+            let config = Config::from_toml_file(config.as_str()).expect("config not valid");
+            let Ok(content) = fs::read_to_string(&script) else {
+                eprintln!("script not found: {script}");
+                process::exit(2);
+            };
+
+            let dirs = config
+                .vnc
+                .as_ref()
+                .and_then(|vnc| vnc.needle_dir.as_ref().map(|d| d.dirs()))
+                .unwrap_or(vec![env::current_dir().unwrap()]);
+            let nmg = NeedleManager::with_dirs(dirs);
+
+            let tags = t_binding::search_needle_tags(&content);
+            let missing: Vec<&String> = tags.iter().filter(|t| nmg.load(t).is_none()).collect();
+            for tag in missing.iter() {
+                println!("needle not found: {tag}");
+            }
+            println!(
+                "{} needles referenced, {} missing",
+                tags.len(),
+                missing.len()
+            );
+            if !missing.is_empty() {
+                process::exit(1);
+            }
+        }
+        Commands::Needles { config } => {
+            let config = Config::from_toml_file(config.as_str()).expect("config not valid");
+            let dirs = config
+                .vnc
+                .as_ref()
+                .and_then(|vnc| vnc.needle_dir.as_ref().map(|d| d.dirs()))
+                .unwrap_or(vec![env::current_dir().unwrap()]);
+            let list = NeedleManager::with_dirs(dirs).list();
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&list).expect("needle list is always valid json")
+            );
+        }
+        Commands::Repl { config } => {
+            let config = Config::from_toml_file(config.as_str()).expect("config not valid");
+            info!(msg = "current config", config = ?config);
+
+            match DriverBuilder::new(Some(config)).build() {
+                Ok(mut d) => {
+                    d.start();
+                    let mut engine = JSEngine::new(d.msg_tx.clone());
+                    let prompt = || {
+                        print!("> ");
+                        let _ = io::stdout().flush();
+                    };
+                    prompt();
+                    for line in io::stdin().lock().lines() {
+                        let Ok(line) = line else {
+                            break;
+                        };
+                        if !line.trim().is_empty() {
+                            match engine.eval(&line) {
+                                Ok(v) => println!("{v}"),
+                                Err(e) => eprintln!("error: {e}"),
+                            }
+                        }
+                        prompt();
+                    }
+                    println!();
+                    d.stop();
+                }
+                Err(e) => {
+                    error!(msg = "Driver init failed", reason = ?e)
+                }
+            }
+        }
     }
 }
@@ -1,5 +1,11 @@
+use crate::probe;
 use serde::Deserialize;
-use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
@@ -7,8 +13,37 @@ pub struct Config {
     pub arch: Option<String>,
     pub os: Option<String>,
 
+    // relative to config file if loaded from file, default "log" in working dir
     pub log_dir: Option<String>,
+    // each driver start writes into log_dir/run-YYYYMMDD-HHMMSS, set true to write into log_dir directly
+    pub flat_log_dir: Option<bool>,
+    // skip saving screenshot same as one of last n saved, default only compare with last one
+    pub screenshot_dedup: Option<usize>,
+    // screenshot file name, placeholders: {span}, {trace}, {ts}, {name}
+    // default "{span}-{trace}-{ts}-{name}.png"
+    pub log_filename_template: Option<String>,
+    // strftime format of {ts}, default "%H:%M:%S", e.g. "%Y%m%dT%H%M%S" is safe on windows
+    pub log_time_format: Option<String>,
+    // file format of log screenshots: png, webp, bmp, jpeg, tiff, default png, needles are always png
+    pub screenshot_format: Option<String>,
+    // abort pending requests when none completes for this long, catches a run stuck in
+    // generous waits, default disabled
+    pub max_idle_secs: Option<u64>,
+    // save a vnc screenshot named "auto" this often as a timeline of the run, also without gui,
+    // unchanged frames are skipped by screenshot_dedup, default disabled
+    pub auto_screenshot_secs: Option<u64>,
+    // requests are handled as soon as they arrive, the request loop wakes this often when idle
+    // to check stop, max_idle_secs and auto screenshots, default 20
+    pub poll_interval_ms: Option<u64>,
+    // serve prometheus metrics at this address, e.g. "127.0.0.1:9100", needs metrics feature
+    pub metrics_addr: Option<String>,
+    // retry connecting consoles at start this many more times, covers a target powered on
+    // right before the run, default 0
+    pub connect_retries: Option<u32>,
+    // wait between connect attempts, default 1000
+    pub connect_retry_interval_ms: Option<u64>,
     pub env: Option<HashMap<String, toml::Value>>,
+    pub defaults: Option<ConfigDefaults>,
 
     pub ssh: Option<ConsoleSSH>,
     pub serial: Option<ConsoleSerial>,
@@ -24,6 +59,10 @@ impl Config {
 
     fn init(&mut self) {
         let log_dir = self.log_dir.clone().unwrap_or("log".to_string());
+        self.set_log_dir(log_dir);
+    }
+
+    fn set_log_dir(&mut self, log_dir: String) {
         if let Some(serial) = self.serial.as_mut() {
             serial.log_file = Some(PathBuf::from_iter(vec![&log_dir, "serial.log"]));
         }
@@ -39,11 +78,154 @@ impl Config {
         self.log_dir = Some(log_dir);
     }
 
+    // move logs of this run into a new dir under log_dir, so runs don't overwrite each other,
+    // return the dir logs are written to
+    pub fn start_run(&mut self) -> io::Result<PathBuf> {
+        let log_dir = PathBuf::from(self.log_dir.clone().unwrap_or("log".to_string()));
+        if self.flat_log_dir.unwrap_or(false) {
+            return Ok(log_dir);
+        }
+        let name = format!(
+            "run-{}",
+            t_util::get_time_with_format("%Y%m%d-%H%M%S").unwrap_or_default()
+        );
+        // runs started in the same second get a suffix
+        let mut run_dir = log_dir.join(&name);
+        let mut i = 0;
+        loop {
+            match fs::create_dir(&run_dir) {
+                Ok(()) => break,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    i += 1;
+                    run_dir = log_dir.join(format!("{name}-{i}"));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        self.set_log_dir(run_dir.to_string_lossy().to_string());
+        Ok(run_dir)
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms.unwrap_or(20).max(1))
+    }
+
+    pub fn connect_retry_interval(&self) -> Duration {
+        Duration::from_millis(self.connect_retry_interval_ms.unwrap_or(1000))
+    }
+
+    pub fn defaults(&self) -> ConfigDefaults {
+        self.defaults.clone().unwrap_or_default()
+    }
+
     pub fn from_toml_file(s: &str) -> Result<Self, toml::de::Error> {
-        let mut config: Config = toml::from_str(fs::read_to_string(s).unwrap().as_str()).unwrap();
+        Self::from_toml_file_with_overrides(s, &[])
+    }
+
+    // overrides are "key=value" applied on top of the file, see apply_override
+    pub fn from_toml_file_with_overrides(
+        s: &str,
+        overrides: &[String],
+    ) -> Result<Self, toml::de::Error> {
+        let mut table: toml::Table =
+            toml::from_str(fs::read_to_string(s).unwrap().as_str()).unwrap();
+        for o in overrides {
+            apply_override(&mut table, o)?;
+        }
+        let mut config: Config = toml::Value::Table(table).try_into()?;
+        if let Some(base) = Path::new(s).parent() {
+            config.resolve_paths(base);
+        }
         config.init();
         Ok(config)
     }
+
+    // relative log_dir and needle_dir are relative to base instead of working dir,
+    // console log files follow log_dir
+    fn resolve_paths(&mut self, base: &Path) {
+        let resolve = |p: &str| {
+            if Path::new(p).is_absolute() {
+                p.to_string()
+            } else {
+                base.join(p).to_string_lossy().to_string()
+            }
+        };
+        if let Some(log_dir) = self.log_dir.as_mut() {
+            *log_dir = resolve(log_dir);
+        }
+        if let Some(needle_dir) = self.vnc.as_mut().and_then(|v| v.needle_dir.as_mut()) {
+            match needle_dir {
+                NeedleDir::Single(d) => *d = resolve(d),
+                NeedleDir::Multiple(dirs) => dirs.iter_mut().for_each(|d| *d = resolve(d)),
+            }
+        }
+    }
+}
+
+// "vnc.port=5902" sets port in [vnc], missing tables are created.
+// value is parsed as toml, e.g. 5902, true or "1234", anything else is a string,
+// it stays a string if the key already holds one or the field is a string, e.g. ssh.password=1234
+pub fn apply_override(table: &mut toml::Table, s: &str) -> Result<(), toml::de::Error> {
+    let invalid = |msg: String| <toml::de::Error as serde::de::Error>::custom(msg);
+    let Some((path, value)) = s.split_once('=') else {
+        return Err(invalid(format!("override {s:?} should be key=value")));
+    };
+    let keys: Vec<&str> = path.trim().split('.').collect();
+    if keys.iter().any(|k| k.is_empty()) {
+        return Err(invalid(format!("override {s:?} has an empty key")));
+    }
+    let as_string = probe::takes_string::<Config>(&keys);
+    let (last, parents) = keys.split_last().unwrap();
+    let mut current = table;
+    for key in parents {
+        current = match current
+            .entry(key.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+        {
+            toml::Value::Table(t) => t,
+            _ => return Err(invalid(format!("override {s:?}: {key} is not a table"))),
+        };
+    }
+    let value = value.trim();
+    let value = match current.get(*last) {
+        Some(toml::Value::String(_)) => toml::Value::String(value.to_string()),
+        _ if as_string => toml::Value::String(value.to_string()),
+        _ => toml::from_str::<toml::Table>(&format!("v = {value}"))
+            .ok()
+            .and_then(|mut t| t.remove("v"))
+            .unwrap_or_else(|| toml::Value::String(value.to_string())),
+    };
+    current.insert(last.to_string(), value);
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ConfigDefaults {
+    // used when api timeout is negative
+    pub timeout_secs: Option<u64>,
+    pub assert_screen_retry_interval_ms: Option<u64>,
+    // warn when a needle keeps scoring within this below its threshold, default 0.02, 0 disables
+    pub near_miss_margin: Option<f32>,
+    // needle similarity threshold used by screen apis, default 0.95
+    pub threshold: Option<f32>,
+}
+
+impl ConfigDefaults {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs.unwrap_or(60))
+    }
+
+    pub fn assert_screen_retry_interval(&self) -> Duration {
+        Duration::from_millis(self.assert_screen_retry_interval_ms.unwrap_or(200))
+    }
+
+    pub fn near_miss_margin(&self) -> f32 {
+        self.near_miss_margin.unwrap_or(0.02)
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.threshold.unwrap_or(0.95)
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -56,6 +238,11 @@ pub struct ConsoleSSH {
     pub timeout: Option<Duration>,
     pub enable_echo: Option<bool>,
     pub linebreak: Option<String>,
+    pub rows: Option<u16>,
+    pub cols: Option<u16>,
+    pub jump: Option<Box<ConsoleSSH>>,
+    // roll log_file to .1, .2, .3 when it reaches this size, default never
+    pub log_file_max_mb: Option<u64>,
 
     #[serde(skip_serializing)]
     pub log_file: Option<PathBuf>,
@@ -68,27 +255,304 @@ pub struct ConsoleSerial {
     pub r#type: Option<ConsoleSerialType>,
     pub disable_echo: Option<bool>,
     pub linebreak: Option<String>,
+    pub rows: Option<u16>,
+    pub cols: Option<u16>,
+    // login after connected, each step waits for its prompt
+    pub login: Option<SerialLogin>,
+    // roll log_file to .1, .2, .3 when it reaches this size, default never
+    pub log_file_max_mb: Option<u64>,
 
     #[serde(skip_serializing)]
     pub log_file: Option<PathBuf>,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct SerialLogin {
+    pub username: String,
+    pub password: Option<String>,
+    // regex, default "login:"
+    pub login_prompt: Option<String>,
+    // regex, default "assword:"
+    pub password_prompt: Option<String>,
+}
+
+impl SerialLogin {
+    pub fn login_prompt(&self) -> &str {
+        self.login_prompt.as_deref().unwrap_or("login:")
+    }
+
+    pub fn password_prompt(&self) -> &str {
+        self.password_prompt.as_deref().unwrap_or("assword:")
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub enum ConsoleSerialType {
     Pts,
     Sock,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, Default)]
 pub struct ConsoleVNC {
+    // bare host, or url like "tcp://127.0.0.1:5900", "unix:///tmp/vnc.sock"
     pub host: String,
+    #[serde(default = "default_vnc_port")]
     pub port: u16,
     pub password: Option<String>,
-    pub needle_dir: Option<String>,
+    pub needle_dir: Option<NeedleDir>,
+    // draw cursor into screenshots, default false
+    pub render_cursor: Option<bool>,
+    // sleep between key press and release when typing string, default 0
+    pub type_delay_ms: Option<u64>,
+    // encoding names sent to server in preference order, e.g. ["zrle", "raw"]
+    pub encodings: Option<Vec<String>>,
+    // guest keyboard layout for type_string: us, de, fr, default us
+    pub keymap: Option<String>,
+    // tcp connect timeout of first connect and reconnects, default 3000
+    pub connect_timeout_ms: Option<u64>,
+    // guest picks up vnc clipboard (e.g. qemu vdagent), paste_string types text when false, default false
+    pub clipboard: Option<bool>,
+    // guest shortcut used by paste_string, default "ctrl-v"
+    pub paste_key: Option<String>,
+    // sleep around move and click of check and click, default 1000
+    pub click_settle_ms: Option<u64>,
+    // sleep between the two clicks of double click, default 100
+    pub double_click_interval_ms: Option<u64>,
+    // vencrypt with x509 certificate, needs vnc-tls feature, default false
+    pub tls: Option<bool>,
+    // verify tls server certificate and address, default true
+    pub tls_verify: Option<bool>,
+    // pem ca certificate trusted besides system ones, e.g. of a self signed hypervisor
+    pub tls_ca_file: Option<PathBuf>,
+    // screenshots of actions within this long after a saved one are held back, only the last
+    // of them is saved when the interval passed, check screen and take_screenshot frames are
+    // always saved, default 0
+    pub min_screenshot_interval_ms: Option<u64>,
 
     #[serde(skip_serializing)]
     pub screenshot_dir: Option<PathBuf>,
 }
 
+impl ConsoleVNC {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout_ms.unwrap_or(3000))
+    }
+
+    pub fn click_settle(&self) -> Duration {
+        Duration::from_millis(self.click_settle_ms.unwrap_or(1000))
+    }
+
+    pub fn double_click_interval(&self) -> Duration {
+        Duration::from_millis(self.double_click_interval_ms.unwrap_or(100))
+    }
+
+    pub fn min_screenshot_interval(&self) -> Duration {
+        Duration::from_millis(self.min_screenshot_interval_ms.unwrap_or(0))
+    }
+}
+
+fn default_vnc_port() -> u16 {
+    5900
+}
+
+// one dir or a list of dirs, searched in order
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum NeedleDir {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl NeedleDir {
+    pub fn dirs(&self) -> Vec<PathBuf> {
+        match self {
+            NeedleDir::Single(d) => vec![PathBuf::from(d)],
+            NeedleDir::Multiple(d) => d.iter().map(PathBuf::from).collect(),
+        }
+    }
+}
+
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let c: Config = toml::from_str("").unwrap();
+        assert_eq!(c.defaults().timeout(), Duration::from_secs(60));
+        assert_eq!(
+            c.defaults().assert_screen_retry_interval(),
+            Duration::from_millis(200)
+        );
+        assert_eq!(c.defaults().near_miss_margin(), 0.02);
+
+        let c: Config = toml::from_str(
+            r#"
+            [defaults]
+            timeout_secs = 10
+            assert_screen_retry_interval_ms = 500
+            "#,
+        )
+        .unwrap();
+        assert_eq!(c.defaults().timeout(), Duration::from_secs(10));
+        assert_eq!(
+            c.defaults().assert_screen_retry_interval(),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn test_apply_override() {
+        let mut table: toml::Table = toml::from_str(
+            r#"
+            [vnc]
+            host = "127.0.0.1"
+            port = 5901
+            password = "x"
+            "#,
+        )
+        .unwrap();
+        for o in [
+            "vnc.host=10.0.0.5",
+            "vnc.port = 5902",
+            "vnc.password=1234",
+            "env.ISO=/tmp/a.iso",
+            "env.RETRY=true",
+            "max_idle_secs=30",
+        ] {
+            apply_override(&mut table, o).unwrap();
+        }
+        let c: Config = toml::Value::Table(table.clone()).try_into().unwrap();
+        let vnc = c.vnc.unwrap();
+        assert_eq!(vnc.host, "10.0.0.5");
+        assert_eq!(vnc.port, 5902);
+        assert_eq!(vnc.password.as_deref(), Some("1234"));
+        assert_eq!(c.max_idle_secs, Some(30));
+        let env = c.env.unwrap();
+        assert_eq!(env["ISO"].as_str(), Some("/tmp/a.iso"));
+        assert_eq!(env["RETRY"].as_bool(), Some(true));
+
+        // new keys follow the field type
+        for o in [
+            "ssh.password=1234",
+            "ssh.port=2222",
+            "ssh.host=10.0.0.6",
+            "ssh.username=1000",
+        ] {
+            apply_override(&mut table, o).unwrap();
+        }
+        let c: Config = toml::Value::Table(table.clone()).try_into().unwrap();
+        let ssh = c.ssh.unwrap();
+        assert_eq!(ssh.password.as_deref(), Some("1234"));
+        assert_eq!(ssh.username, "1000");
+        assert_eq!(ssh.port, Some(2222));
+        // a value the field doesn't take is still an error later
+        apply_override(&mut table, "ssh.port=abc").unwrap();
+        assert!(toml::Value::Table(table.clone())
+            .try_into::<Config>()
+            .is_err());
+
+        assert!(apply_override(&mut table, "vnc.host").is_err());
+        assert!(apply_override(&mut table, "vnc..host=a").is_err());
+        assert!(apply_override(&mut table, "vnc.host.name=a").is_err());
+    }
+
+    #[test]
+    fn test_serial_login() {
+        let c: SerialLogin = toml::from_str(r#"username = "root""#).unwrap();
+        assert_eq!(c.login_prompt(), "login:");
+        assert_eq!(c.password_prompt(), "assword:");
+
+        let c: SerialLogin = toml::from_str(
+            r#"
+            username = "root"
+            login_prompt = "\\w+ login:"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(c.login_prompt(), r"\w+ login:");
+    }
+
+    #[test]
+    fn test_needle_dir() {
+        let c: ConsoleVNC = toml::from_str(
+            r#"
+            host = "127.0.0.1"
+            port = 5900
+            needle_dir = "needles"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(c.needle_dir.unwrap().dirs(), vec![PathBuf::from("needles")]);
+
+        let c: ConsoleVNC = toml::from_str(
+            r#"
+            host = "127.0.0.1"
+            port = 5900
+            needle_dir = ["needles", "../shared/needles"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            c.needle_dir.unwrap().dirs(),
+            vec![PathBuf::from("needles"), PathBuf::from("../shared/needles")]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_paths() {
+        let mut c: Config = toml::from_str(
+            r#"
+            log_dir = "log"
+            [vnc]
+            host = "127.0.0.1"
+            port = 5900
+            needle_dir = ["needles", "/shared/needles"]
+            "#,
+        )
+        .unwrap();
+        c.resolve_paths(Path::new("/etc/autotest"));
+        assert_eq!(c.log_dir.as_deref(), Some("/etc/autotest/log"));
+        assert_eq!(
+            c.vnc.unwrap().needle_dir.unwrap().dirs(),
+            vec![
+                PathBuf::from("/etc/autotest/needles"),
+                PathBuf::from("/shared/needles")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vnc_connect_timeout() {
+        let c: ConsoleVNC = toml::from_str(r#"host = "127.0.0.1""#).unwrap();
+        assert_eq!(c.connect_timeout(), Duration::from_secs(3));
+
+        let c: ConsoleVNC = toml::from_str(
+            r#"
+            host = "127.0.0.1"
+            connect_timeout_ms = 10000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(c.connect_timeout(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_vnc_click_delays() {
+        let c: ConsoleVNC = toml::from_str(r#"host = "127.0.0.1""#).unwrap();
+        assert_eq!(c.click_settle(), Duration::from_secs(1));
+        assert_eq!(c.double_click_interval(), Duration::from_millis(100));
+
+        let c: ConsoleVNC = toml::from_str(
+            r#"
+            host = "127.0.0.1"
+            click_settle_ms = 200
+            double_click_interval_ms = 50
+            "#,
+        )
+        .unwrap();
+        assert_eq!(c.click_settle(), Duration::from_millis(200));
+        assert_eq!(c.double_click_interval(), Duration::from_millis(50));
+    }
+}
@@ -1,4 +1,5 @@
 mod config;
+mod probe;
 pub use config::*;
 use std::{error::Error, fmt::Display, fs, io, path::Path};
 
@@ -59,4 +60,37 @@ private_key = ""
         use super::Config;
         toml::from_str::<Config>(s).unwrap();
     }
+
+    #[test]
+    fn test_start_run() {
+        use super::Config;
+
+        let log_dir = std::env::temp_dir().join(format!("t-config-run-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&log_dir);
+        let s = format!(
+            "log_dir = {:?}\n[serial]\nserial_file = \"/dev/null\"\n",
+            log_dir
+        );
+        let mut c = Config::from_toml_str(&s).unwrap();
+
+        let run1 = c.clone().start_run().unwrap();
+        let run2 = c.start_run().unwrap();
+        assert_ne!(run1, run2);
+        assert_eq!(run2.parent(), Some(log_dir.as_path()));
+        assert!(run2
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("run-"));
+        assert_eq!(
+            c.serial.as_ref().unwrap().log_file,
+            Some(run2.join("serial.log"))
+        );
+
+        let mut c2 = Config::from_toml_str(&s).unwrap();
+        c2.flat_log_dir = Some(true);
+        assert_eq!(c2.start_run().unwrap(), log_dir);
+
+        let _ = std::fs::remove_dir_all(&log_dir);
+    }
 }
@@ -0,0 +1,125 @@
+use serde::de::{
+    self, value::Error, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, Visitor,
+};
+use serde::{forward_to_deserialize_any, Deserialize};
+use std::cell::Cell;
+
+// true if field at path of T is read as a string, e.g. ["ssh", "password"] of Config,
+// found by deserializing T from a fake input which only has that path
+pub(crate) fn takes_string<'de, T: Deserialize<'de>>(path: &[&str]) -> bool {
+    let found = Cell::new(false);
+    let _ = T::deserialize(Probe {
+        path,
+        found: &found,
+    });
+    found.get()
+}
+
+struct Probe<'a> {
+    path: &'a [&'a str],
+    found: &'a Cell<bool>,
+}
+
+impl<'de> Deserializer<'de> for Probe<'_> {
+    type Error = Error;
+
+    // field is reached but not a string, or path goes through something not a table
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> {
+        Err(de::Error::custom("not a string"))
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> {
+        self.found.set(self.path.is_empty());
+        Err(de::Error::custom("probe done"))
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.path.split_first() {
+            Some((key, rest)) => visitor.visit_map(ProbeMap {
+                key: Some(key),
+                rest,
+                found: self.found,
+            }),
+            None => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char bytes byte_buf unit
+        unit_struct seq tuple tuple_struct enum identifier ignored_any
+    }
+}
+
+// table with the next key of path only
+struct ProbeMap<'a> {
+    key: Option<&'a str>,
+    rest: &'a [&'a str],
+    found: &'a Cell<bool>,
+}
+
+impl<'de> MapAccess<'de> for ProbeMap<'_> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        self.key
+            .take()
+            .map(|key| seed.deserialize(key.into_deserializer()))
+            .transpose()
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        seed.deserialize(Probe {
+            path: self.rest,
+            found: self.found,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Config;
+
+    #[test]
+    fn test_takes_string() {
+        assert!(takes_string::<Config>(&["ssh", "password"]));
+        assert!(takes_string::<Config>(&["vnc", "host"]));
+        assert!(takes_string::<Config>(&["ssh", "jump", "username"]));
+        assert!(takes_string::<Config>(&["serial", "log_file"]));
+        assert!(!takes_string::<Config>(&["ssh", "port"]));
+        assert!(!takes_string::<Config>(&["max_idle_secs"]));
+        assert!(!takes_string::<Config>(&["ssh"]));
+        // env takes any value
+        assert!(!takes_string::<Config>(&["env", "A"]));
+        assert!(!takes_string::<Config>(&["ssh", "nonexistent"]));
+        assert!(!takes_string::<Config>(&["ssh", "password", "x"]));
+    }
+}
@@ -1,30 +1,50 @@
 use std::{
-    fs::{File, OpenOptions},
     io::{self, Read, Write},
-    path::PathBuf,
-    sync::mpsc::{self, channel, Receiver, Sender},
+    sync::{
+        mpsc::{self, channel, Receiver, Sender},
+        Arc,
+    },
     thread,
     time::Duration,
 };
 
+use super::log::{LogFile, LogWriter};
 use crate::{ConsoleError, Result};
+use parking_lot::Mutex;
 use tracing::{debug, error, warn};
 
 #[derive(Debug)]
 pub enum Req {
     Write(Vec<u8>),
     Read,
+    Break(Duration),
 }
 
 #[derive(Debug)]
 pub enum Res {
     Done,
     Value(Vec<u8>),
+    Error(io::Error),
 }
 
+// connection driven by event loop
+pub trait Conn: Read + Write + Send + 'static {
+    // hold break condition for duration, only serial port supports it
+    fn send_break(&mut self, _duration: Duration) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "break is not supported by this connection",
+        ))
+    }
+}
+
+// called with new bytes as soon as they are read from the connection
+pub type OutputHook = Box<dyn Fn(&[u8]) + Send>;
+
 pub struct EvLoopCtl {
     req_tx: Sender<(Req, Sender<Res>)>,
     stop_tx: Sender<Sender<()>>,
+    on_output: Arc<Mutex<Option<OutputHook>>>,
 }
 
 impl EvLoopCtl {
@@ -41,6 +61,10 @@ impl EvLoopCtl {
         rx.recv_timeout(timeout)
     }
 
+    pub fn set_on_output(&self, hook: Option<OutputHook>) {
+        *self.on_output.lock() = hook;
+    }
+
     pub fn stop(&self) {
         let (tx, rx) = channel();
         if self.stop_tx.send(tx).is_err() {
@@ -58,36 +82,29 @@ pub struct EventLoop<T> {
     req_rx: Receiver<(Req, Sender<Res>)>,
     stop_rx: Receiver<Sender<()>>,
     history: Vec<u8>,
-    log_file: Option<File>,
+    log_file: Option<LogWriter>,
+    on_output: Arc<Mutex<Option<OutputHook>>>,
     last_read_index: usize,
     buffer: Vec<u8>,
 }
 
 impl<T> EventLoop<T>
 where
-    T: Read + Write + Send + 'static,
+    T: Conn,
 {
     pub fn spawn(
         make_conn: impl Fn() -> Result<T> + Send + 'static,
-        log_file: Option<PathBuf>,
+        log_file: Option<LogFile>,
     ) -> Result<EvLoopCtl> {
         let conn = make_conn()?;
 
-        let log_file = if let Some(ref log_file) = log_file {
-            let file = OpenOptions::new()
-                .create(true)
-                .truncate(true)
-                .write(true)
-                .open(log_file)
-                .expect("Failed to open file");
-            Some(file)
-        } else {
-            None
-        };
+        let log_file = log_file.map(|log| log.create().expect("Failed to open file"));
 
         let (req_tx, req_rx) = mpsc::channel();
         let (stop_tx, stop_rx) = mpsc::channel();
+        let on_output = Arc::new(Mutex::new(None));
 
+        let on_output_clone = on_output.clone();
         thread::spawn(move || {
             Self {
                 conn: Some(conn),
@@ -95,13 +112,18 @@ where
                 req_rx,
                 stop_rx,
                 log_file,
+                on_output: on_output_clone,
                 history: Vec::new(),
                 last_read_index: 0,
                 buffer: vec![0u8; 4096],
             }
             .pool();
         });
-        Ok(EvLoopCtl { req_tx, stop_tx })
+        Ok(EvLoopCtl {
+            req_tx,
+            stop_tx,
+            on_output,
+        })
     }
 
     fn pool(&mut self) {
@@ -142,6 +164,13 @@ where
                             Res::Done
                         }
                         Req::Read => Res::Value(self.consume_buffer()),
+                        Req::Break(duration) => match self.conn.as_mut() {
+                            Some(conn) => match conn.send_break(duration) {
+                                Ok(()) => Res::Done,
+                                Err(e) => Res::Error(e),
+                            },
+                            None => Res::Error(io::ErrorKind::NotConnected.into()),
+                        },
                     };
                     if let Err(e) = tx.send(res) {
                         warn!("req sender side closed before recv response: {}", e);
@@ -176,6 +205,10 @@ where
                             self.log_file = None;
                         }
                     }
+
+                    if let Some(hook) = self.on_output.lock().as_ref() {
+                        hook(received);
+                    }
                     return Ok(received.to_vec());
                 }
                 Err(e) => match e.kind() {
@@ -246,3 +279,69 @@ where
         res.to_vec()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // loopback connection, everything written can be read back
+    #[derive(Clone, Default)]
+    struct Loopback(Arc<Mutex<VecDeque<u8>>>);
+
+    impl Read for Loopback {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut inner = self.0.lock();
+            let n = buf.len().min(inner.len());
+            for (i, b) in inner.drain(..n).enumerate() {
+                buf[i] = b;
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for Loopback {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Conn for Loopback {}
+
+    #[test]
+    fn test_on_output() {
+        let conn = Loopback::default();
+        let ctl = EventLoop::spawn(move || Ok(conn.clone()), None).unwrap();
+
+        let (tx, rx) = channel();
+        ctl.set_on_output(Some(Box::new(move |b: &[u8]| {
+            tx.send(b.to_vec()).ok();
+        })));
+
+        let timeout = Duration::from_secs(1);
+        ctl.send_timeout(Req::Write(b"hello".to_vec()), timeout)
+            .unwrap();
+        assert_eq!(rx.recv_timeout(timeout).unwrap(), b"hello");
+
+        // history is still kept for regular reads
+        match ctl.send_timeout(Req::Read, timeout).unwrap() {
+            Res::Value(v) => assert_eq!(v, b"hello"),
+            res => panic!("unexpected res: {:?}", res),
+        }
+
+        // loopback has no break line
+        match ctl
+            .send_timeout(Req::Break(Duration::from_millis(1)), timeout)
+            .unwrap()
+        {
+            Res::Error(e) => assert_eq!(e.kind(), io::ErrorKind::Unsupported),
+            res => panic!("unexpected res: {:?}", res),
+        }
+        ctl.stop();
+    }
+}
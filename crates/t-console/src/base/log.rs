@@ -0,0 +1,114 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+// rolled files kept besides the live one, path.1 is the newest
+const ROLLED: u32 = 3;
+
+// where console output is logged, rolled to path.1, path.2 .. when max_size is reached
+#[derive(Debug, Clone)]
+pub struct LogFile {
+    pub path: PathBuf,
+    pub max_size: Option<u64>,
+}
+
+impl LogFile {
+    // max_mb from config, none never rolls
+    pub fn new(path: PathBuf, max_mb: Option<u64>) -> Self {
+        Self {
+            path,
+            max_size: max_mb.map(|mb| mb * 1024 * 1024),
+        }
+    }
+
+    fn rolled(&self, i: u32) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(format!(".{i}"));
+        PathBuf::from(path)
+    }
+
+    // truncates log of last run, rolled files of last run are removed too
+    pub(crate) fn create(self) -> io::Result<LogWriter> {
+        for i in 1..=ROLLED {
+            let _ = fs::remove_file(self.rolled(i));
+        }
+        Ok(LogWriter {
+            file: File::create(&self.path)?,
+            size: 0,
+            log: self,
+        })
+    }
+}
+
+pub(crate) struct LogWriter {
+    log: LogFile,
+    file: File,
+    size: u64,
+}
+
+impl LogWriter {
+    pub fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        if let Some(max) = self.log.max_size {
+            if self.size > 0 && self.size + buf.len() as u64 > max {
+                self.roll()?;
+            }
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+
+    // path.2 -> path.3, path.1 -> path.2, path -> path.1, oldest one is overwritten
+    fn roll(&mut self) -> io::Result<()> {
+        for i in (1..ROLLED).rev() {
+            let from = self.log.rolled(i);
+            if from.exists() {
+                fs::rename(from, self.log.rolled(i + 1))?;
+            }
+        }
+        fs::rename(&self.log.path, self.log.rolled(1))?;
+        self.file = File::create(&self.log.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roll() {
+        let dir = std::env::temp_dir().join(format!("t-console-log-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("serial.log");
+
+        let mut log = LogFile {
+            path: path.clone(),
+            max_size: Some(4),
+        };
+        let mut w = log.clone().create().unwrap();
+        for s in ["ab", "cd", "ef", "gh", "ij", "kl", "mn", "op", "qr"] {
+            w.write_all(s.as_bytes()).unwrap();
+        }
+        let read = |i: u32| fs::read_to_string(log.rolled(i)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "qr");
+        assert_eq!(read(1), "mnop");
+        assert_eq!(read(2), "ijkl");
+        assert_eq!(read(3), "efgh");
+        assert!(!log.rolled(4).exists());
+
+        // next run starts from scratch
+        log.max_size = None;
+        let mut w = log.clone().create().unwrap();
+        w.write_all(b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!log.rolled(1).exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,2 +1,4 @@
 pub mod evloop;
+pub mod log;
+pub mod relay;
 pub mod tty;
@@ -0,0 +1,186 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+#[cfg(windows)]
+use std::{thread::sleep, time::Duration};
+
+// connected loopback pair, the listener only lives until our own client is accepted,
+// anyone else racing to the port is dropped
+pub(crate) fn local_pair() -> io::Result<(TcpStream, TcpStream)> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let client = TcpStream::connect(listener.local_addr()?)?;
+    loop {
+        let (server, peer) = listener.accept()?;
+        if peer == client.local_addr()? {
+            return Ok((client, server));
+        }
+    }
+}
+
+// what a non-blocking socket waits for
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Interest {
+    read: bool,
+    write: bool,
+}
+
+impl Interest {
+    pub const READ: Self = Self {
+        read: true,
+        write: false,
+    };
+    pub const WRITE: Self = Self {
+        read: false,
+        write: true,
+    };
+    pub const BOTH: Self = Self {
+        read: true,
+        write: true,
+    };
+}
+
+#[cfg(unix)]
+pub(crate) type Socket = std::os::fd::RawFd;
+#[cfg(windows)]
+pub(crate) type Socket = std::os::windows::io::RawSocket;
+
+#[cfg(unix)]
+pub(crate) fn socket_of(s: &impl std::os::fd::AsRawFd) -> Socket {
+    s.as_raw_fd()
+}
+
+#[cfg(windows)]
+pub(crate) fn socket_of(s: &impl std::os::windows::io::AsRawSocket) -> Socket {
+    s.as_raw_socket()
+}
+
+// block until any socket is ready
+#[cfg(unix)]
+fn wait_ready(sockets: &[(Socket, Interest)]) -> io::Result<()> {
+    let mut fds: Vec<libc::pollfd> = sockets
+        .iter()
+        .map(|(fd, interest)| libc::pollfd {
+            fd: *fd,
+            events: if interest.read { libc::POLLIN } else { 0 }
+                | if interest.write { libc::POLLOUT } else { 0 },
+            revents: 0,
+        })
+        .collect();
+    loop {
+        // SAFETY: fds is a valid array of fds.len() pollfd
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } >= 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+// no poll binding on windows, back off instead
+#[cfg(windows)]
+fn wait_ready(_: &[(Socket, Interest)]) -> io::Result<()> {
+    sleep(Duration::from_millis(1));
+    Ok(())
+}
+
+// copy both ways until either side is closed, remote is non-blocking on remote_socket,
+// remote_interest tells what a blocked remote write waits for
+pub(crate) fn relay<R: Read + Write>(
+    mut local: TcpStream,
+    mut remote: R,
+    remote_socket: Socket,
+    remote_eof: impl Fn(&R) -> bool,
+    remote_interest: impl Fn() -> Interest,
+) -> io::Result<()> {
+    local.set_nonblocking(true)?;
+    let local_socket = socket_of(&local);
+    let mut buf = [0; 4096];
+    loop {
+        // drain both sides before waiting, ssh2 may have read data off the socket already
+        let mut idle = true;
+        match local.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => {
+                idle = false;
+                write_all_nonblocking(&mut remote, &buf[..n], || {
+                    wait_ready(&[(remote_socket, remote_interest())])
+                })?;
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+        match remote.read(&mut buf) {
+            Ok(0) if remote_eof(&remote) => return Ok(()),
+            // e.g. a window adjust packet, read again
+            Ok(0) => idle = false,
+            Ok(n) => {
+                idle = false;
+                write_all_nonblocking(&mut local, &buf[..n], || {
+                    wait_ready(&[(local_socket, Interest::WRITE)])
+                })?;
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+        if idle {
+            wait_ready(&[
+                (local_socket, Interest::READ),
+                (remote_socket, Interest::READ),
+            ])?;
+        }
+    }
+}
+
+fn write_all_nonblocking(
+    w: &mut impl Write,
+    mut buf: &[u8],
+    wait: impl Fn() -> io::Result<()>,
+) -> io::Result<()> {
+    while !buf.is_empty() {
+        match w.write(buf) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => buf = &buf[n..],
+            Err(e) if e.kind() == ErrorKind::WouldBlock => wait()?,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_relay() {
+        let (mut local, local_end) = local_pair().unwrap();
+        let (remote_end, mut remote) = local_pair().unwrap();
+        remote_end.set_nonblocking(true).unwrap();
+        let socket = socket_of(&remote_end);
+        let t = thread::spawn(move || {
+            relay(local_end, remote_end, socket, |_| true, || Interest::WRITE)
+        });
+
+        local
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        remote
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buf = [0; 4];
+        local.write_all(b"ping").unwrap();
+        remote.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        remote.write_all(b"pong").unwrap();
+        local.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        // closing local side stops relay
+        drop(local);
+        t.join().unwrap().unwrap();
+    }
+}
@@ -1,9 +1,13 @@
-use super::evloop::{EvLoopCtl, Req, Res};
-use crate::{term::Term, ConsoleError};
+use super::evloop::{EvLoopCtl, OutputHook, Req, Res};
+use crate::{
+    term::{Term, TermSize},
+    ConsoleError,
+};
 use parking_lot::Mutex;
+use regex::Regex;
 use std::{
     marker::PhantomData,
-    sync::mpsc::Receiver,
+    sync::mpsc::{Receiver, RecvTimeoutError},
     thread,
     time::{Duration, Instant},
 };
@@ -11,6 +15,28 @@ use tracing::{debug, error, info};
 
 type Result<T> = std::result::Result<T, ConsoleError>;
 
+const TOKEN_ALPHABET: [char; 62] = [
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i',
+    'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', 'A', 'B',
+    'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U',
+    'V', 'W', 'X', 'Y', 'Z',
+];
+
+// event loop stops when the link is broken, so no answer is not a timeout
+fn ctl_err(e: RecvTimeoutError) -> ConsoleError {
+    match e {
+        RecvTimeoutError::Timeout => ConsoleError::Timeout,
+        RecvTimeoutError::Disconnected => {
+            ConsoleError::NoConnection("console event loop stopped".to_string())
+        }
+    }
+}
+
+// delimiter of exec output, letters and digits only, a leading '-' could be taken as echo option
+fn exec_token() -> String {
+    nanoid::nanoid!(16, &TOKEN_ALPHABET)
+}
+
 struct State {
     // store all tty output bytes
     history: Vec<u8>,
@@ -21,6 +47,7 @@ struct State {
 pub struct TtySetting {
     pub disable_echo: bool,
     pub linebreak: String,
+    pub size: TermSize,
 }
 
 pub struct Tty<T: Term> {
@@ -57,10 +84,20 @@ where
         }
     }
 
+    // configured line ending, e.g. for answering login prompts
+    pub fn linebreak(&self) -> &str {
+        &self.setting.linebreak
+    }
+
     pub fn stop_evloop(&self) {
         self.ctl.stop();
     }
 
+    // tee raw output bytes to hook, history and regex matching are not affected
+    pub fn set_on_output(&self, hook: Option<OutputHook>) {
+        self.ctl.set_on_output(hook);
+    }
+
     fn try_handle_stop_signal(&self) -> bool {
         // stop on receive done signal
         self.stop_rx.lock().try_recv().is_ok()
@@ -69,22 +106,42 @@ where
     pub fn write(&self, s: &[u8], timeout: Duration) -> Result<()> {
         self.ctl
             .send_timeout(Req::Write(s.to_vec()), timeout)
-            .map_err(|_| ConsoleError::Timeout)?;
+            .map_err(ctl_err)?;
         Ok(())
     }
 
+    pub fn send_break(&self, duration: Duration, timeout: Duration) -> Result<()> {
+        info!(msg = "send_break", duration = ?duration);
+        match self
+            .ctl
+            .send_timeout(Req::Break(duration), duration + timeout)
+            .map_err(ctl_err)?
+        {
+            Res::Error(e) => Err(ConsoleError::IO(e)),
+            _ => Ok(()),
+        }
+    }
+
     pub fn write_string(&self, s: &str, timeout: Duration) -> Result<()> {
         info!(msg = "write_string", s = s);
         self.write(s.as_bytes(), timeout)?;
         Ok(())
     }
 
+    // like write_string, but content is not logged
+    pub fn write_password(&self, s: &str, timeout: Duration) -> Result<()> {
+        info!(msg = "write_password", s = "****");
+        self.write(s.as_bytes(), timeout)?;
+        Ok(())
+    }
+
     pub fn wait_string(&mut self, timeout: Duration, pattern: &str) -> Result<String> {
         info!(msg = "wait_string", pattern = pattern);
+        let size = self.setting.size;
         self.comsume_buffer_and_map(timeout, |buffer, new| {
             {
-                let buffer_str = Tm::parse_and_strip(buffer);
-                let new_str = Tm::parse_and_strip(new);
+                let buffer_str = Tm::parse_and_strip(buffer, size);
+                let new_str = Tm::parse_and_strip(new, size);
                 let res = count_substring(&buffer_str, pattern, 1);
                 info!(
                     msg = "wait_string",
@@ -98,6 +155,66 @@ where
         })
     }
 
+    // like wait_string, but pattern is a regex, returns the matched text
+    pub fn wait_regex(&mut self, timeout: Duration, pattern: &str) -> Result<String> {
+        info!(msg = "wait_regex", pattern = pattern);
+        let re = Regex::new(pattern).map_err(ConsoleError::InvalidRegex)?;
+        let size = self.setting.size;
+        self.comsume_buffer_and_map(timeout, |buffer, _| {
+            let buffer_str = Tm::parse_and_strip(buffer, size);
+            re.find(&buffer_str)
+                .map(|m| m.as_str().to_string())
+                .map_or(ConsumeAction::Continue, ConsumeAction::BreakValue)
+        })
+    }
+
+    // full output since connected, with pending output read first
+    pub fn dump_history(&self) -> String {
+        if let Ok(Res::Value(recv)) = self.ctl.send_timeout(Req::Read, Duration::from_millis(200)) {
+            self.state.lock().history.extend(recv);
+        }
+        Tm::parse_and_strip(&self.state.lock().history, self.setting.size)
+    }
+
+    // read new output once and search patterns in the unconsumed buffer, used to wait on several
+    // consoles at once, returns index of the first matched pattern and consumes the buffer
+    pub fn poll_strings(&self, patterns: &[&str]) -> Result<Option<usize>> {
+        self.poll(|buffer_str| {
+            patterns
+                .iter()
+                .position(|p| count_substring(buffer_str, p, 1))
+        })
+    }
+
+    // like poll_strings, returns text matched by re, compiled once by caller polling in a loop
+    pub fn poll_regex(&self, re: &Regex) -> Result<Option<String>> {
+        self.poll(|buffer_str| re.find(buffer_str).map(|m| m.as_str().to_string()))
+    }
+
+    fn poll<T>(&self, find: impl FnOnce(&str) -> Option<T>) -> Result<Option<T>> {
+        if self.try_handle_stop_signal() {
+            return Err(ConsoleError::Cancel);
+        }
+        match self
+            .ctl
+            .send_timeout(Req::Read, Duration::from_millis(1000))
+        {
+            Ok(Res::Value(recv)) => self.state.lock().history.extend(recv),
+            Ok(res) => error!(msg = "invalid msg varient", res = ?res),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(e) => return Err(ctl_err(e)),
+        }
+
+        let mut state = self.state.lock();
+        let buffer_str =
+            Tm::parse_and_strip(&state.history[state.last_buffer_start..], self.setting.size);
+        let matched = find(&buffer_str);
+        if matched.is_some() {
+            state.last_buffer_start = state.history.len();
+        }
+        Ok(matched)
+    }
+
     pub fn exec(&mut self, timeout: Duration, cmd: &str) -> Result<(i32, String)> {
         info!(msg = "exec", cmd = cmd);
         let enter_input: &'static str = "\r";
@@ -105,8 +222,8 @@ where
         // wait for prompt show, cmd may write too fast before prompt show, which will broken regex
         std::thread::sleep(Duration::from_millis(70));
 
-        // prepare
-        let nanoid = nanoid::nanoid!(6);
+        // prepare, fresh token per call so output can't contain it by accident
+        let nanoid = exec_token();
 
         let res_flag_sep = "-";
 
@@ -131,11 +248,12 @@ where
         self.write_string(&cmd, timeout)?;
 
         // wait output
+        let size = self.setting.size;
         let deadline = Instant::now() + timeout;
         self.comsume_buffer_and_map(deadline - Instant::now(), |buffer, new| {
             // find target pattern from buffer
-            let buffer_str = Tm::parse_and_strip(buffer);
-            let new_str = Tm::parse_and_strip(new);
+            let buffer_str = Tm::parse_and_strip(buffer, size);
+            let new_str = Tm::parse_and_strip(new, size);
             info!(
                 msg = "recv string",
                 nanoid = nanoid,
@@ -244,13 +362,11 @@ where
                     error!(msg = "invalid msg varient", res = ?res);
                     break;
                 }
-                Err(e) => match e {
-                    std::sync::mpsc::RecvTimeoutError::Timeout => {}
-                    std::sync::mpsc::RecvTimeoutError::Disconnected => {
-                        error!(msg = "recv failed");
-                        break;
-                    }
-                },
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(e) => {
+                    error!(msg = "recv failed");
+                    return Err(ctl_err(e));
+                }
             }
         }
         Err(ConsoleError::Timeout)
@@ -271,3 +387,16 @@ fn count_substring(s: &str, substring: &str, n: usize) -> bool {
 
     false
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exec_token() {
+        let a = exec_token();
+        assert_eq!(a.len(), 16);
+        assert!(a.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(a, exec_token());
+    }
+}
@@ -6,10 +6,14 @@ mod vnc;
 
 use std::fmt::Display;
 
+pub use base::evloop::{Conn, OutputHook};
 pub use serial::Serial;
 pub use ssh::SSH;
 pub use term::*;
-pub use vnc::{key, Log, Rect, VNCError, VNCEventReq, VNCEventRes, PNG, VNC};
+pub use vnc::{
+    key, ConnectionState, Log, Rect, VNCAddr, VNCError, VNCEventReq, VNCEventRes, VNCStats, PNG,
+    VNC,
+};
 
 pub type Result<T> = std::result::Result<T, ConsoleError>;
 
@@ -24,6 +28,7 @@ pub enum ConsoleError {
     IO(std::io::Error),
     Serial(serialport::Error),
     SSH2(ssh2::Error),
+    InvalidRegex(regex::Error),
 }
 
 impl Display for ConsoleError {
@@ -36,10 +41,7 @@ impl Display for ConsoleError {
             ConsoleError::IO(e) => write!(f, "io error, {}", e),
             ConsoleError::SSH2(e) => write!(f, "ssh error, {}", e),
             ConsoleError::Serial(e) => write!(f, "serial error, {}", e),
+            ConsoleError::InvalidRegex(e) => write!(f, "invalid regex, {}", e),
         }
     }
 }
-
-// magic string, used for regex extract in ssh or serial output
-#[allow(dead_code)]
-static MAGIC_STRING: &str = "n8acxy9o47xx7x7xw";
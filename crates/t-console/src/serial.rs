@@ -1,15 +1,17 @@
-use crate::base::evloop::EventLoop;
+use crate::base::evloop::{Conn, EventLoop};
+use crate::base::log::LogFile;
 use crate::base::tty::Tty;
 use crate::base::tty::TtySetting;
 use crate::term::Term;
+use crate::term::TermSize;
 use crate::ConsoleError;
 use crate::Result;
 use std::ops::Deref;
 use std::ops::DerefMut;
-use std::path::PathBuf;
 use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
-use t_config::ConsoleSerialType;
+use std::time::Duration;
+use t_config::{ConsoleSerialType, SerialLogin};
 use tracing::{error, info};
 
 pub struct Serial {
@@ -32,13 +34,28 @@ impl DerefMut for Serial {
 }
 
 impl Serial {
+    fn tty_setting(c: &t_config::ConsoleSerial) -> TtySetting {
+        TtySetting {
+            disable_echo: c.disable_echo.unwrap_or(false),
+            linebreak: c.linebreak.clone().unwrap_or("\n".to_string()),
+            size: TermSize {
+                rows: c.rows.unwrap_or(TermSize::default().rows),
+                cols: c.cols.unwrap_or(TermSize::default().cols),
+            },
+        }
+    }
+
+    fn log_file(c: &t_config::ConsoleSerial) -> Option<LogFile> {
+        c.log_file
+            .clone()
+            .map(|path| LogFile::new(path, c.log_file_max_mb))
+    }
+
     pub fn new(c: t_config::ConsoleSerial) -> Result<Self> {
         let (stop_tx, stop_rx) = mpsc::channel();
 
-        let setting = TtySetting {
-            disable_echo: c.disable_echo.unwrap_or(false),
-            linebreak: c.linebreak.clone().unwrap_or("\n".to_string()),
-        };
+        let setting = Self::tty_setting(&c);
+        let log_file = Self::log_file(&c);
 
         #[cfg(never)]
         if setting.disable_echo {
@@ -53,7 +70,7 @@ impl Serial {
             #[cfg(target_os = "linux")]
             Some(ConsoleSerialType::Sock) => Box::new(SockClient::connect(
                 &c.serial_file,
-                c.log_file.clone(),
+                log_file.clone(),
                 stop_rx,
                 setting,
             )?),
@@ -61,7 +78,7 @@ impl Serial {
                 let ssh_client = PtyClient::connect(
                     &c.serial_file,
                     c.bund_rate.unwrap_or(115200),
-                    c.log_file.clone(),
+                    log_file.clone(),
                     stop_rx,
                     setting,
                 )?;
@@ -71,6 +88,20 @@ impl Serial {
         Ok(Self { stop_tx, inner })
     }
 
+    // serial over any connection, e.g. a canned output stream in tests
+    // serial_file and type in c are ignored, make_conn is also called on reconnect
+    pub fn from_conn<C: Conn>(
+        c: t_config::ConsoleSerial,
+        make_conn: impl Fn() -> Result<C> + Send + 'static,
+    ) -> Result<Self> {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let evloop = EventLoop::spawn(make_conn, Self::log_file(&c))?;
+        let inner = Box::new(ConnClient {
+            tty: Tty::new(evloop, stop_rx, Self::tty_setting(&c)),
+        });
+        Ok(Self { stop_tx, inner })
+    }
+
     pub fn stop(&self) {
         if self.stop_tx.send(()).is_err() {
             error!("stop serial failed, serial may stopped already");
@@ -79,8 +110,44 @@ impl Serial {
 
         self.inner.get_tty().stop_evloop();
     }
+
+    // hold break condition, used to interrupt bootloader autoboot
+    // some usb-serial adapters ignore break, driver error is returned then
+    pub fn send_break(&self, duration: Duration, timeout: Duration) -> Result<()> {
+        self.inner.get_tty().send_break(duration, timeout)
+    }
+
+    // boot time is unpredictable, wait for prompt instead of writing blindly
+    pub fn wait_login_prompt(&mut self, timeout: Duration, pattern: &str) -> Result<()> {
+        self.wait_regex(timeout, pattern)?;
+        Ok(())
+    }
+
+    pub fn login(&mut self, login: &SerialLogin, timeout: Duration) -> Result<()> {
+        info!(msg = "serial login", username = login.username);
+        self.wait_login_prompt(timeout, login.login_prompt())?;
+        let linebreak = self.linebreak().to_string();
+        self.write_string(&format!("{}{linebreak}", login.username), timeout)?;
+        if let Some(password) = login.password.as_ref() {
+            self.wait_regex(timeout, login.password_prompt())?;
+            self.write_string(&format!("{password}{linebreak}"), timeout)?;
+        }
+        Ok(())
+    }
+}
+
+impl Conn for Box<dyn serialport::SerialPort> {
+    fn send_break(&mut self, duration: Duration) -> std::io::Result<()> {
+        self.set_break()?;
+        std::thread::sleep(duration);
+        self.clear_break()?;
+        Ok(())
+    }
 }
 
+#[cfg(target_os = "linux")]
+impl Conn for std::os::unix::net::UnixStream {}
+
 trait SerialClient<T: Term> {
     fn get_tty(&self) -> &Tty<T>;
     fn get_tty_mut(&mut self) -> &mut Tty<T>;
@@ -107,6 +174,20 @@ impl<T: Term> SerialClient<T> for SockClient<T> {
     }
 }
 
+struct ConnClient<T: Term> {
+    tty: Tty<T>,
+}
+
+impl<T: Term> SerialClient<T> for ConnClient<T> {
+    fn get_tty(&self) -> &Tty<T> {
+        &self.tty
+    }
+
+    fn get_tty_mut(&mut self) -> &mut Tty<T> {
+        &mut self.tty
+    }
+}
+
 struct PtyClient<T: Term> {
     pub tty: Tty<T>,
     pub path: String,
@@ -119,7 +200,7 @@ where
     pub fn connect(
         file: &str,
         bund_rate: u32,
-        log_file: Option<PathBuf>,
+        log_file: Option<LogFile>,
         stop_rx: Receiver<()>,
         setting: TtySetting,
     ) -> Result<Self> {
@@ -169,7 +250,7 @@ where
 {
     pub fn connect(
         file: &str,
-        log_file: Option<PathBuf>,
+        log_file: Option<LogFile>,
         stop_rx: Receiver<()>,
         setting: TtySetting,
     ) -> Result<Self> {
@@ -207,7 +288,7 @@ mod test {
 
     use crate::{
         base::tty::TtySetting,
-        term::{Term, VT102},
+        term::{Term, TermSize, VT102},
     };
     use std::{
         env,
@@ -242,7 +323,10 @@ mod test {
             let mut buf = [0; 1024];
             match port.read(&mut buf) {
                 Ok(n) => {
-                    println!("{}", VT102::parse_and_strip(&buf[0..n]));
+                    println!(
+                        "{}",
+                        VT102::parse_and_strip(&buf[0..n], TermSize::default())
+                    );
                 }
                 Err(e) if e.kind() == ErrorKind::TimedOut => {
                     println!("timeout");
@@ -271,6 +355,7 @@ mod test {
             TtySetting {
                 disable_echo: serial.disable_echo.unwrap_or(false),
                 linebreak: serial.linebreak.clone().unwrap_or("\n".to_string()),
+                size: TermSize::default(),
             },
         )
         .unwrap()
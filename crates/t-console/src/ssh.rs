@@ -1,16 +1,21 @@
-use crate::base::evloop::EventLoop;
+use crate::base::evloop::{Conn, EventLoop};
+use crate::base::log::LogFile;
+use crate::base::relay::{local_pair, relay, socket_of, Interest};
 use crate::base::tty::Tty;
 use crate::base::tty::TtySetting;
 use crate::term::Term;
+use crate::term::TermSize;
 use crate::ConsoleError;
+use std::io::{self, ErrorKind, Read};
 use std::net::TcpStream;
-use std::net::ToSocketAddrs;
 use std::ops::Deref;
 use std::ops::DerefMut;
 use std::path::Path;
-use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread;
 use std::thread::sleep;
 use std::time::Duration;
 use tracing::error;
@@ -43,37 +48,50 @@ impl DerefMut for SSH {
     }
 }
 
+impl SSHAuthAuth<String> {
+    fn from_config(c: &t_config::ConsoleSSH) -> Result<Self> {
+        if let Some(password) = c.password.as_ref() {
+            return Ok(SSHAuthAuth::Password(password.clone()));
+        }
+        if let Some(private_key) = c.private_key.as_ref() {
+            return Ok(SSHAuthAuth::PrivateKey(private_key.clone()));
+        }
+        let private_key = home::home_dir()
+            .map(|mut x| {
+                x.push(std::path::Path::new(".ssh/id_rsa"));
+                x.display().to_string()
+            })
+            .ok_or(ConsoleError::NoConnection(
+                "ssh password and private_key are both missing, and home dir not found".to_string(),
+            ))?;
+        Ok(SSHAuthAuth::PrivateKey(private_key))
+    }
+}
+
 impl SSH {
     pub fn new(c: t_config::ConsoleSSH) -> Result<Self> {
         info!(msg = "init ssh...");
-        let auth = if let Some(password) = c.password.as_ref() {
-            SSHAuthAuth::Password(password.clone())
-        } else {
-            SSHAuthAuth::PrivateKey(
-                c.private_key.clone().unwrap_or(
-                    home::home_dir()
-                        .map(|mut x| {
-                            x.push(std::path::Path::new(".ssh/id_rsa"));
-                            x.display().to_string()
-                        })
-                        .unwrap(),
-                ),
-            )
-        };
+        let auth = SSHAuthAuth::from_config(&c)?;
 
         let (stop_tx, stop_rx) = mpsc::channel();
 
         let setting = TtySetting {
             disable_echo: c.enable_echo.unwrap_or(false),
             linebreak: c.linebreak.clone().unwrap_or("\n".to_string()),
+            size: TermSize {
+                rows: c.rows.unwrap_or(TermSize::default().rows),
+                cols: c.cols.unwrap_or(TermSize::default().cols),
+            },
         };
 
         let inner = SSHClient::connect(
             c.timeout,
             &auth,
             c.username.clone(),
-            format!("{}:{}", c.host, c.port.unwrap_or(22)),
-            c.log_file.clone(),
+            connect_tcp(&c)?,
+            c.log_file
+                .clone()
+                .map(|path| LogFile::new(path, c.log_file_max_mb)),
             stop_rx,
             setting,
         )?;
@@ -92,23 +110,62 @@ impl SSH {
         self.inner.pts_file.clone()
     }
 
-    // FIXME: may blocking
+    // return (code, stdout, stderr), TimedOut error if command not finished in timeout
     pub fn exec_seperate(
         &mut self,
         command: &str,
-    ) -> std::result::Result<(i32, String), std::io::Error> {
-        use std::io::Read;
-        let mut exec_ch = self.inner.session.channel_session().unwrap();
-
-        exec_ch.exec(command)?;
-        let mut buffer = String::new();
-        exec_ch.read_to_string(&mut buffer)?;
-
-        exec_ch.exec("echo $?\n")?;
-        let mut code = String::new();
-        exec_ch.read_to_string(&mut code)?;
-
-        Ok((code.parse::<i32>().unwrap(), buffer))
+        timeout: Duration,
+    ) -> std::result::Result<(i32, String, String), std::io::Error> {
+        // channel reads block on the shared session, so run them on another thread,
+        // on timeout cancel is set and the thread closes the channel at next read timeout
+        let session = self.inner.session.clone();
+        let command = command.to_string();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel();
+        thread::spawn({
+            let cancel = cancel.clone();
+            move || {
+                let res = (|| {
+                    let mut exec_ch = session.channel_session()?;
+
+                    exec_ch.exec(&command)?;
+                    // read stderr alongside stdout, a command filling stderr first would block forever
+                    let stderr_stream = exec_ch.stderr();
+                    let stderr_cancel = cancel.clone();
+                    let stderr =
+                        thread::spawn(move || read_until_cancel(stderr_stream, &stderr_cancel));
+                    let stdout = read_until_cancel(&mut exec_ch, &cancel);
+                    if stdout.is_err() {
+                        cancel.store(true, Ordering::SeqCst);
+                    }
+                    let stderr = stderr
+                        .join()
+                        .map_err(|_| io::Error::other("read stderr failed"))?;
+                    let (stdout, stderr) = match (stdout, stderr) {
+                        (Ok(stdout), Ok(stderr)) => (stdout, stderr),
+                        (Err(e), _) | (_, Err(e)) => {
+                            // remote command loses its output pipes, channel is freed
+                            let _ = exec_ch.close();
+                            return Err(e);
+                        }
+                    };
+
+                    exec_ch.wait_close()?;
+                    let code = exec_ch.exit_status()?;
+
+                    Ok((code, stdout, stderr))
+                })();
+                let _ = tx.send(res);
+            }
+        });
+        match rx.recv_timeout(timeout) {
+            Ok(res) => res,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                cancel.store(true, Ordering::SeqCst);
+                Err(ErrorKind::TimedOut.into())
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(ErrorKind::BrokenPipe.into()),
+        }
     }
 
     pub fn upload_file(&mut self, remote_path: impl AsRef<Path>) {
@@ -122,6 +179,95 @@ impl SSH {
     }
 }
 
+// read to end, session read timeouts are retried until cancel is set
+fn read_until_cancel(mut r: impl Read, cancel: &AtomicBool) -> io::Result<String> {
+    let mut out = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        if cancel.load(Ordering::SeqCst) {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        match r.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => out.extend_from_slice(&buf[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    String::from_utf8(out).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+fn new_session<P: AsRef<Path>>(
+    timeout: Option<Duration>,
+    auth: &SSHAuthAuth<P>,
+    user: impl Into<String>,
+    tcp: TcpStream,
+) -> Result<ssh2::Session> {
+    let mut sess = ssh2::Session::new().map_err(ConsoleError::SSH2)?;
+    sess.set_tcp_stream(tcp);
+    sess.handshake().map_err(ConsoleError::SSH2)?;
+
+    // never disconnect auto
+    sess.set_timeout(timeout.map(|x| x.as_millis() as u32).unwrap_or(5000));
+
+    match auth {
+        SSHAuthAuth::PrivateKey(private_key) => {
+            sess.userauth_pubkey_file(&user.into(), None, private_key.as_ref(), None)
+                .map_err(ConsoleError::SSH2)?;
+        }
+        SSHAuthAuth::Password(password) => {
+            sess.userauth_password(&user.into(), password.as_str())
+                .map_err(ConsoleError::SSH2)?;
+        }
+    }
+    assert!(sess.authenticated());
+    debug!(msg = "ssh auth success");
+    Ok(sess)
+}
+
+// ssh2 session can only run on a socket, so bridge the direct-tcpip channel
+// opened on jump host to a local tcp connection, and connect target host through it
+fn forward_by_jump(jump: &t_config::ConsoleSSH, host: &str, port: u16) -> Result<TcpStream> {
+    info!(msg = "connect jump host", host = jump.host);
+    let sess = new_session(
+        jump.timeout,
+        &SSHAuthAuth::from_config(jump)?,
+        jump.username.clone(),
+        connect_tcp(jump)?,
+    )?;
+    let channel = sess
+        .channel_direct_tcpip(host, port, None)
+        .map_err(ConsoleError::SSH2)?;
+    let (client, server) = local_pair().map_err(ConsoleError::IO)?;
+
+    sess.set_blocking(false);
+    let socket = socket_of(&sess);
+    thread::spawn(move || {
+        let interest = || match sess.block_directions() {
+            ssh2::BlockDirections::Outbound => Interest::WRITE,
+            ssh2::BlockDirections::Both => Interest::BOTH,
+            _ => Interest::READ,
+        };
+        if let Err(e) = relay(server, channel, socket, |c| c.eof(), interest) {
+            error!(msg = "jump host forward failed", reason = ?e);
+        }
+        info!(msg = "jump host forward stopped");
+    });
+
+    Ok(client)
+}
+
+// tcp connection to host, through jump hosts if any
+fn connect_tcp(c: &t_config::ConsoleSSH) -> Result<TcpStream> {
+    match c.jump.as_ref() {
+        Some(jump) => forward_by_jump(jump, &c.host, c.port.unwrap_or(22)),
+        None => TcpStream::connect(format!("{}:{}", c.host, c.port.unwrap_or(22)))
+            .map_err(ConsoleError::IO),
+    }
+}
+
+impl Conn for ssh2::Channel {}
+
 struct SSHClient<T: Term> {
     session: ssh2::Session,
     pub pts: Tty<T>,
@@ -132,38 +278,20 @@ impl<Tm> SSHClient<Tm>
 where
     Tm: Term,
 {
-    pub fn connect<P: AsRef<Path>, A: ToSocketAddrs>(
+    pub fn connect<P: AsRef<Path>>(
         timeout: Option<Duration>,
         auth: &SSHAuthAuth<P>,
         user: impl Into<String>,
-        addrs: A,
-        log_file: Option<PathBuf>,
+        tcp: TcpStream,
+        log_file: Option<LogFile>,
         stop_rx: Receiver<()>,
         setting: TtySetting,
     ) -> std::result::Result<Self, ConsoleError> {
-        let tcp = TcpStream::connect(addrs).map_err(ConsoleError::IO)?;
-        let mut sess = ssh2::Session::new().map_err(ConsoleError::SSH2)?;
-        sess.set_tcp_stream(tcp);
-        sess.handshake().map_err(ConsoleError::SSH2)?;
-
-        // never disconnect auto
-        sess.set_timeout(timeout.map(|x| x.as_millis() as u32).unwrap_or(5000));
-
-        match auth {
-            SSHAuthAuth::PrivateKey(private_key) => {
-                sess.userauth_pubkey_file(&user.into(), None, private_key.as_ref(), None)
-                    .map_err(ConsoleError::SSH2)?;
-            }
-            SSHAuthAuth::Password(password) => {
-                sess.userauth_password(&user.into(), password.as_str())
-                    .map_err(ConsoleError::SSH2)?;
-            }
-        }
-        assert!(sess.authenticated());
-        debug!(msg = "ssh auth success");
+        let sess = new_session(timeout, auth, user, tcp)?;
 
         sleep(Duration::from_secs(3));
 
+        let size = setting.size;
         let res = Self {
             session: sess.clone(),
             pts: Tty::new(
@@ -172,7 +300,11 @@ where
                         // build shell channel
                         let mut channel = sess.channel_session().map_err(ConsoleError::SSH2)?;
                         channel
-                            .request_pty("xterm", None, Some((80, 24, 0, 0)))
+                            .request_pty(
+                                "xterm",
+                                None,
+                                Some((size.cols as u32, size.rows as u32, 0, 0)),
+                            )
                             .map_err(ConsoleError::SSH2)?;
                         channel.shell().map_err(ConsoleError::SSH2)?;
                         Ok(channel)
@@ -206,6 +338,79 @@ mod test {
         None
     }
 
+    fn ssh_config(password: Option<&str>, private_key: Option<&str>) -> t_config::ConsoleSSH {
+        t_config::ConsoleSSH {
+            host: "127.0.0.1".to_string(),
+            port: None,
+            username: "root".to_string(),
+            password: password.map(|s| s.to_string()),
+            private_key: private_key.map(|s| s.to_string()),
+            timeout: None,
+            enable_echo: None,
+            linebreak: None,
+            rows: None,
+            cols: None,
+            jump: None,
+            log_file_max_mb: None,
+            log_file: None,
+        }
+    }
+
+    #[test]
+    fn test_auth_from_config() {
+        let auth = SSHAuthAuth::from_config(&ssh_config(Some("123456"), None)).unwrap();
+        assert!(matches!(auth, SSHAuthAuth::Password(p) if p == "123456"));
+
+        let auth = SSHAuthAuth::from_config(&ssh_config(None, Some("/tmp/id_rsa"))).unwrap();
+        assert!(matches!(auth, SSHAuthAuth::PrivateKey(p) if p == "/tmp/id_rsa"));
+
+        // password is used first if both are set
+        let auth =
+            SSHAuthAuth::from_config(&ssh_config(Some("123456"), Some("/tmp/id_rsa"))).unwrap();
+        assert!(matches!(auth, SSHAuthAuth::Password(_)));
+    }
+
+    #[test]
+    fn test_read_until_cancel() {
+        // output split by session read timeouts
+        struct Chunks(Vec<io::Result<&'static [u8]>>);
+        impl Read for Chunks {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Ok(0);
+                }
+                let chunk = self.0.remove(0)?;
+                buf[..chunk.len()].copy_from_slice(chunk);
+                Ok(chunk.len())
+            }
+        }
+        let timed_out = || Err(io::Error::from(ErrorKind::TimedOut));
+        let cancel = AtomicBool::new(false);
+        let r = Chunks(vec![Ok(b"a"), timed_out(), Ok(b"b"), timed_out()]);
+        assert_eq!(read_until_cancel(r, &cancel).unwrap(), "ab");
+
+        // a command that never ends stops at the next read timeout after cancel
+        struct Hang<'a>(&'a AtomicBool, usize);
+        impl Read for Hang<'_> {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                self.1 += 1;
+                if self.1 == 3 {
+                    self.0.store(true, Ordering::SeqCst);
+                }
+                Err(ErrorKind::TimedOut.into())
+            }
+        }
+        let err = read_until_cancel(Hang(&cancel, 0), &cancel).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+        let r = Chunks(vec![Err(ErrorKind::BrokenPipe.into())]);
+        let cancel = AtomicBool::new(false);
+        assert_eq!(
+            read_until_cancel(r, &cancel).unwrap_err().kind(),
+            ErrorKind::BrokenPipe
+        );
+    }
+
     #[test]
     fn test_exec() {
         let cmds = vec![
@@ -219,11 +424,23 @@ mod test {
         }
         let mut ssh = ssh.unwrap();
         for cmd in cmds {
-            let res = ssh.exec_seperate(cmd.0).unwrap();
+            let res = ssh.exec_seperate(cmd.0, Duration::from_secs(10)).unwrap();
             assert_eq!(res.1, cmd.1);
         }
     }
 
+    #[test]
+    fn test_exec_seperate_timeout() {
+        let Some(mut ssh) = get_ssh_client() else {
+            return;
+        };
+        let err = ssh
+            .exec_seperate("sleep 3", Duration::from_secs(1))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(ssh.exec_seperate("sleep 1", Duration::from_secs(3)).is_ok());
+    }
+
     #[test]
     fn test_tty_and_read_until() {
         let ssh = get_ssh_client();
@@ -237,7 +454,10 @@ mod test {
         let tty = ssh.tty();
 
         thread::spawn(move || {
-            ssh2.exec_seperate(format!(r#"sleep 5 && echo "asdfg" > {}"#, tty).as_str())
+            ssh2.exec_seperate(
+                format!(r#"sleep 5 && echo "asdfg" > {}"#, tty).as_str(),
+                Duration::from_secs(10),
+            )
         });
 
         ssh.wait_string(Duration::from_secs(1), "asdfg").unwrap();
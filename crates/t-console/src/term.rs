@@ -1,5 +1,17 @@
+#[derive(Clone, Copy, Debug)]
+pub struct TermSize {
+    pub rows: u16,
+    pub cols: u16,
+}
+
+impl Default for TermSize {
+    fn default() -> Self {
+        Self { rows: 24, cols: 80 }
+    }
+}
+
 pub trait Term {
-    fn parse_and_strip(bytes: &[u8]) -> String {
+    fn parse_and_strip(bytes: &[u8], _size: TermSize) -> String {
         // bytes to string
         let text = String::from_utf8_lossy(bytes);
         // filter ESC and ANSI control character
@@ -17,10 +29,10 @@ impl Term for General {}
 pub struct VT100 {}
 
 impl Term for VT100 {
-    fn parse_and_strip(bytes: &[u8]) -> String {
-        let mut parser = vt100::Parser::new(24, 80, 0);
+    fn parse_and_strip(bytes: &[u8], size: TermSize) -> String {
+        let mut parser = vt100::Parser::new(size.rows, size.cols, 0);
         let mut res: String = String::new();
-        for chunk in bytes.chunks(80 * 24) {
+        for chunk in bytes.chunks(size.rows as usize * size.cols as usize) {
             parser.process(chunk);
             let contents = parser.screen().contents();
             res.push_str(contents.as_str());
@@ -41,7 +53,7 @@ impl Term for Xterm {}
 
 #[cfg(test)]
 mod test {
-    use super::General;
+    use super::{General, TermSize, VT100};
     use crate::Term;
 
     #[test]
@@ -58,7 +70,21 @@ mod test {
                 "echo $?W-x3JmwqB4C-h6yWhGTlk\r\n\r0W-x3JmwqB4C-h6yWhGTlk\r\npi@raspberrypi:~$ "
             )
         ] {
-            assert_eq!(General::parse_and_strip(src.as_bytes()), expect);
+            assert_eq!(
+                General::parse_and_strip(src.as_bytes(), TermSize::default()),
+                expect
+            );
         }
     }
+
+    #[test]
+    fn test_vt100_wide_prompt() {
+        let prompt = format!("{}$ ", "a".repeat(100));
+        let size = TermSize {
+            rows: 24,
+            cols: 120,
+        };
+        let res = VT100::parse_and_strip(prompt.as_bytes(), size);
+        assert_eq!(res.lines().next(), Some(prompt.as_str()));
+    }
 }
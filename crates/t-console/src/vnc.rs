@@ -1,12 +1,18 @@
+mod addr;
 mod data;
+mod desktop_size;
+mod keymap;
+#[cfg(feature = "vnc-tls")]
+mod tls;
 
 use std::{
     collections::VecDeque,
     error::Error,
     fmt::Display,
-    io,
-    net::{SocketAddr, TcpStream},
+    io::{self, Write},
+    net::TcpStream,
     sync::{
+        atomic::{AtomicU64, Ordering},
         mpsc::{self, channel, Receiver, RecvError, RecvTimeoutError, Sender},
         Arc,
     },
@@ -14,9 +20,13 @@ use std::{
     time::{Duration, Instant},
 };
 
+pub use addr::VNCAddr;
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
 use data::Container;
 pub use data::Rect;
+use desktop_size::DesktopSize;
+use keymap::{KeyStroke, Keymap};
+use t_config::ConsoleVNC;
 use t_vnc::{client::Event, PixelFormat};
 use tracing::{debug, error, info, trace, warn};
 
@@ -114,8 +124,16 @@ pub mod key {
 #[derive(Debug)]
 pub enum VNCEventReq {
     TypeString(String),
+    // like TypeString, but never logged
+    TypePassword(String),
+    // send text as client cut text, guest needs a clipboard agent to pick it up
+    SetClipboard(String),
     SendKey { keys: Vec<u32> },
+    KeyDown(Vec<u32>),
+    KeyUp(Vec<u32>),
     MouseMove(u16, u16),
+    MouseMoveRelative(i16, i16),
+    GetMousePos,
     MouseDrag(u16, u16),
     MouseClick(u8),
     MoveDown(u8),
@@ -123,7 +141,12 @@ pub enum VNCEventReq {
     MouseHide,
     GetScreenShot,
     TakeScreenShot(String, Option<String>),
+    SaveScreenShot(Arc<PNG>, String, Option<String>),
     Refresh,
+    GetSize,
+    // ask server to resize framebuffer, answered once server accepted or refused it
+    SetDesktopSize(u16, u16),
+    ConnectionState,
 }
 
 pub type PNG = Container;
@@ -132,11 +155,52 @@ pub enum VNCEventRes {
     NoConnection,
     Done,
     Screen(Arc<PNG>),
+    Size(u16, u16),
+    MousePos(u16, u16),
+    State(ConnectionState),
+    // answer to SetDesktopSize, error is why server refused
+    DesktopSize(Result<(), String>),
+    // request can't be done, e.g. char not on keymap, nothing was sent
+    Error(String),
+}
+
+// vnc thread keeps reconnecting after link dropped, disconnected means the thread is gone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionState {
+    Connected,
+    Reconnecting,
+    Disconnected,
 }
 
 pub struct VNC {
     pub event_tx: Sender<(VNCEventReq, Sender<VNCEventRes>)>,
     pub stop_tx: Sender<Sender<()>>,
+    pub stats: Arc<VNCStats>,
+}
+
+// counted by event loop, read without a round trip through it
+#[derive(Debug, Default)]
+pub struct VNCStats {
+    // frames with any update
+    pub frames: AtomicU64,
+    // successful reconnects after connection lost
+    pub reconnects: AtomicU64,
+    // bell messages from server, e.g. an error beep
+    pub bells: AtomicU64,
+}
+
+impl VNCStats {
+    pub fn frames(&self) -> u64 {
+        self.frames.load(Ordering::Relaxed)
+    }
+
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    pub fn bells(&self) -> u64 {
+        self.bells.load(Ordering::Relaxed)
+    }
 }
 
 pub enum Log {
@@ -166,10 +230,31 @@ impl Display for VNCError {
 }
 
 impl VNC {
-    fn make_conn(addr: &SocketAddr, password: Option<String>) -> Result<t_vnc::Client, VNCError> {
-        let stream =
-            TcpStream::connect_timeout(addr, Duration::from_millis(200)).map_err(VNCError::Io)?;
+    fn make_conn(
+        addr: &VNCAddr,
+        c: &ConsoleVNC,
+        encodings: &[t_vnc::Encoding],
+    ) -> Result<(t_vnc::Client, TcpStream, DesktopSize), VNCError> {
+        let password = c.password.clone();
+        let tls = c.tls.unwrap_or(false);
+        let stream = addr.connect(c.connect_timeout()).map_err(VNCError::Io)?;
+        #[cfg(feature = "vnc-tls")]
+        let stream = if tls {
+            tls::connect_vencrypt(stream, addr, c).map_err(VNCError::Io)?
+        } else {
+            stream
+        };
+        #[cfg(not(feature = "vnc-tls"))]
+        if tls {
+            return Err(VNCError::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "vnc tls needs the vnc-tls feature",
+            )));
+        }
 
+        let (stream, desktop_size) = desktop_size::filter(stream).map_err(VNCError::Io)?;
+        // t_vnc owns the stream, a clone sends client messages it doesn't implement
+        let raw = stream.try_clone().map_err(VNCError::Io)?;
         let mut vnc = t_vnc::Client::from_tcp_stream(stream, true, |methods| {
             for method in methods {
                 match method {
@@ -201,41 +286,61 @@ impl VNC {
         })
         .map_err(VNCError::VNCError)?;
 
-        // vnc.set_encodings(&[t_vnc::Encoding::Zrle, t_vnc::Encoding::DesktopSize])
-        vnc.set_encodings(&[
-            t_vnc::Encoding::Zrle,
-            t_vnc::Encoding::CopyRect,
-            t_vnc::Encoding::Raw,
-            t_vnc::Encoding::Cursor,
-            t_vnc::Encoding::DesktopSize,
-        ])
-        .map_err(VNCError::VNCError)?;
+        desktop_size.start(vnc.format().bits_per_pixel as u64 / 8);
+        vnc.set_encodings(encodings).map_err(VNCError::VNCError)?;
 
         info!(msg = "vnc connect success");
 
-        Ok(vnc)
+        Ok((vnc, raw, desktop_size))
     }
 
+    // server draws no cursor in framebuffer since cursor encoding is enabled,
+    // set render_cursor to composite it into screenshots
+    // connect_timeout is used by the first connect and every reconnect
+    // tls negotiates vencrypt with x509 subtypes, needs vnc-tls feature
     pub fn connect(
-        addr: SocketAddr,
-        password: Option<String>,
+        addr: VNCAddr,
+        c: &ConsoleVNC,
         screenshot_tx: Option<LogTx>,
     ) -> Result<Self, VNCError> {
-        let vnc = Self::make_conn(&addr, password.clone())?;
+        let keymap = c
+            .keymap
+            .as_ref()
+            .map(|name| {
+                Keymap::from_name(name).unwrap_or_else(|| {
+                    warn!(msg = "unsupported keymap, use us", name = name);
+                    Keymap::Us
+                })
+            })
+            .unwrap_or_default();
+        let encodings = c
+            .encodings
+            .as_ref()
+            .map(|names| parse_encodings(names))
+            .unwrap_or_else(default_encodings);
+        let (vnc, raw, desktop_size) = Self::make_conn(&addr, c, &encodings)?;
 
         let (event_tx, event_rx) = mpsc::channel();
         let (stop_tx, stop_rx) = channel();
+        let stats = Arc::new(VNCStats::default());
 
+        let conf = c.clone();
         let mut c = VncClientInner {
-            make_conn: Box::new(move || Self::make_conn(&addr, password.clone())),
+            make_conn: Box::new(move || Self::make_conn(&addr, &conf, &encodings)),
             state: State::from_vnc(&vnc),
             conn: Some(vnc),
+            raw: Some(raw),
+            desktop_size: Some(desktop_size),
+            render_cursor: c.render_cursor.unwrap_or(false),
+            type_delay: Duration::from_millis(c.type_delay_ms.unwrap_or(0)),
+            keymap,
 
             event_rx,
             stop_rx,
 
             screenshot_tx,
             screenshot_buffer: VecDeque::new(),
+            stats: stats.clone(),
         };
 
         thread::spawn(move || {
@@ -244,7 +349,11 @@ impl VNC {
             }
         });
 
-        Ok(Self { event_tx, stop_tx })
+        Ok(Self {
+            event_tx,
+            stop_tx,
+            stats,
+        })
     }
 
     pub fn send(&self, req: VNCEventReq) -> Result<VNCEventRes, RecvError> {
@@ -268,6 +377,13 @@ impl VNC {
         rx.recv_timeout(timeout)
     }
 
+    pub fn connection_state(&self) -> ConnectionState {
+        match self.send(VNCEventReq::ConnectionState) {
+            Ok(VNCEventRes::State(state)) => state,
+            _ => ConnectionState::Disconnected,
+        }
+    }
+
     pub fn stop(&self) {
         let (tx, rx) = channel();
         if self.stop_tx.send(tx).is_err() {
@@ -279,7 +395,8 @@ impl VNC {
     }
 }
 
-type MakeVncConn = Box<dyn Fn() -> Result<t_vnc::Client, VNCError> + Send + 'static>;
+type MakeVncConn =
+    Box<dyn Fn() -> Result<(t_vnc::Client, TcpStream, DesktopSize), VNCError> + Send + 'static>;
 
 struct State {
     width: u16,
@@ -294,6 +411,44 @@ struct State {
     updated_in_frame: bool,
 
     buttons: u8,
+    // keys pressed by KeyDown and not released yet
+    keys: Vec<u32>,
+
+    cursor: Option<Cursor>,
+}
+
+struct Cursor {
+    hotspot: (u16, u16),
+    image: Container,
+    // one bit per pixel, row padded to byte
+    mask_bits: Vec<u8>,
+}
+
+impl Cursor {
+    fn draw(&self, screen: &mut Container, x: u16, y: u16) {
+        let row_bytes = (self.image.width as usize).div_ceil(8);
+        for row in 0..self.image.height {
+            for col in 0..self.image.width {
+                let bit = self
+                    .mask_bits
+                    .get(row as usize * row_bytes + col as usize / 8)
+                    .map(|b| b >> (7 - col % 8) & 1 == 1)
+                    .unwrap_or(false);
+                if !bit {
+                    continue;
+                }
+                let (Some(sx), Some(sy)) = (
+                    (x + col).checked_sub(self.hotspot.0),
+                    (y + row).checked_sub(self.hotspot.1),
+                ) else {
+                    continue;
+                };
+                if sx < screen.width && sy < screen.height {
+                    screen.set(sy, sx, self.image.get(row, col));
+                }
+            }
+        }
+    }
 }
 
 impl State {
@@ -312,21 +467,40 @@ impl State {
             unstable_screen: Container::new(size.0, size.1, 3),
             updated_in_frame: true,
             buttons: 0,
+            keys: Vec::new(),
+            cursor: None,
         }
     }
+
+    fn screenshot(&self, render_cursor: bool) -> Container {
+        let mut screen = self.unstable_screen.clone();
+        if render_cursor {
+            if let Some(cursor) = self.cursor.as_ref() {
+                cursor.draw(&mut screen, self.mouse_x, self.mouse_y);
+            }
+        }
+        screen
+    }
 }
 
 struct VncClientInner {
     make_conn: MakeVncConn,
     conn: Option<t_vnc::Client>,
+    // same socket as conn, written only from event loop so messages never interleave
+    raw: Option<TcpStream>,
+    desktop_size: Option<DesktopSize>,
 
     state: State,
+    render_cursor: bool,
+    type_delay: Duration,
+    keymap: Keymap,
 
     event_rx: Receiver<(VNCEventReq, Sender<VNCEventRes>)>,
     stop_rx: Receiver<Sender<()>>,
 
     screenshot_tx: Option<LogTx>,
     screenshot_buffer: std::collections::VecDeque<Arc<PNG>>,
+    stats: Arc<VNCStats>,
 }
 
 impl VncClientInner {
@@ -339,15 +513,23 @@ impl VncClientInner {
         loop {
             // handle return
             if let Ok(tx) = self.stop_rx.try_recv() {
+                // release stuck keys before leave
+                let keys = self.state.keys.clone();
+                if let Err(e) = self.handle_key_up(keys) {
+                    warn!(msg = "release keys failed", reason = ?e);
+                }
                 tx.send(()).ok();
                 break;
             }
 
             // handle reconnect
             if self.conn.is_none() {
-                if let Ok(vnc) = self.make_conn.as_ref()() {
+                if let Ok((vnc, raw, desktop_size)) = self.make_conn.as_ref()() {
                     self.state = State::from_vnc(&vnc);
                     self.conn = Some(vnc);
+                    self.raw = Some(raw);
+                    self.desktop_size = Some(desktop_size);
+                    self.stats.reconnects.fetch_add(1, Ordering::Relaxed);
                 }
             };
 
@@ -457,6 +639,7 @@ impl VncClientInner {
                 }
                 state.count += 1;
                 state.updated_in_frame = false;
+                self.stats.frames.fetch_add(1, Ordering::Relaxed);
 
                 // save buffer
                 debug!(msg = "vnc event Event::EndOfFrame", count = state.count);
@@ -464,7 +647,7 @@ impl VncClientInner {
                     self.screenshot_buffer.pop_front();
                 }
 
-                let screenshot = Arc::new(state.unstable_screen.clone());
+                let screenshot = Arc::new(state.screenshot(self.render_cursor));
                 self.screenshot_buffer.push_back(screenshot.clone());
 
                 // FIXME: send screenshot may cause memoey overflow slowly if handler handle too slow
@@ -485,14 +668,26 @@ impl VncClientInner {
             Event::Clipboard(ref _text) => {
                 state.updated_in_frame = true;
             }
-            Event::SetCursor { .. } => {
+            Event::SetCursor {
+                size,
+                hotspot,
+                pixels,
+                mask_bits,
+            } => {
                 state.updated_in_frame = true;
+                let data = convert_to_rgb(&state.pixel_format, &pixels);
+                state.cursor = Some(Cursor {
+                    hotspot,
+                    image: Container::new_with_data(size.0, size.1, data, 3),
+                    mask_bits,
+                });
             }
             Event::SetColourMap { .. } => {
                 state.updated_in_frame = true;
             }
             Event::Bell => {
                 state.updated_in_frame = true;
+                self.stats.bells.fetch_add(1, Ordering::Relaxed);
             }
         }
         Ok(())
@@ -500,9 +695,15 @@ impl VncClientInner {
 
     fn handle_req(&mut self, msg: VNCEventReq) -> Result<VNCEventRes, t_vnc::Error> {
         match msg {
-            VNCEventReq::TypeString(s) => self.handle_type_string(s),
+            VNCEventReq::TypeString(s) => self.handle_type_string(s, false),
+            VNCEventReq::TypePassword(s) => self.handle_type_string(s, true),
+            VNCEventReq::SetClipboard(s) => self.handle_set_clipboard(s),
             VNCEventReq::SendKey { keys } => self.handle_send_key(keys),
+            VNCEventReq::KeyDown(keys) => self.handle_key_down(keys),
+            VNCEventReq::KeyUp(keys) => self.handle_key_up(keys),
             VNCEventReq::MouseMove(x, y) => self.handle_mouse_move(x, y),
+            VNCEventReq::MouseMoveRelative(dx, dy) => self.handle_mouse_move_relative(dx, dy),
+            VNCEventReq::GetMousePos => self.handle_get_mouse_pos(),
             VNCEventReq::MouseDrag(x, y) => self.handle_mouse_drag(x, y),
             VNCEventReq::MouseClick(button) => {
                 self.handle_mouse_down(button)?;
@@ -514,8 +715,64 @@ impl VncClientInner {
             VNCEventReq::Refresh => self.handle_screen_refresh(),
             VNCEventReq::GetScreenShot => self.handle_screen_getlatest(),
             VNCEventReq::TakeScreenShot(name, span) => self.handle_screen_takeshot(name, span),
+            VNCEventReq::SaveScreenShot(screen, name, span) => {
+                self.handle_screen_save(screen, name, span)
+            }
             VNCEventReq::MouseHide => self.handle_mouse_hide(),
+            VNCEventReq::GetSize => self.handle_get_size(),
+            VNCEventReq::SetDesktopSize(w, h) => self.handle_set_desktop_size(w, h),
+            VNCEventReq::ConnectionState => Ok(VNCEventRes::State(if self.conn.is_some() {
+                ConnectionState::Connected
+            } else {
+                ConnectionState::Reconnecting
+            })),
+        }
+    }
+
+    // waits for the server answer, it's read by desktop size filter, not this loop
+    fn handle_set_desktop_size(&mut self, w: u16, h: u16) -> Result<VNCEventRes, t_vnc::Error> {
+        let rect = Rect {
+            left: 0,
+            top: 0,
+            width: self.state.width,
+            height: self.state.height,
+        };
+        let (Some(vnc), Some(raw), Some(desktop_size)) = (
+            self.conn.as_mut(),
+            self.raw.as_mut(),
+            self.desktop_size.as_ref(),
+        ) else {
+            return Ok(VNCEventRes::NoConnection);
+        };
+        if !desktop_size.supported() {
+            return Ok(VNCEventRes::DesktopSize(Err(
+                "server doesn't support extended desktop size".to_string(),
+            )));
         }
+        // answers of requests which timed out
+        while desktop_size.status_rx().try_recv().is_ok() {}
+        raw.write_all(&set_desktop_size_msg(w, h))
+            .map_err(t_vnc::Error::Io)?;
+        // answer comes with a framebuffer update
+        vnc.request_update(rect, true)?;
+        let res = match desktop_size.status_rx().recv_timeout(RESIZE_TIMEOUT) {
+            Ok((0, w, h)) => {
+                // t_vnc reports the same resize later, get size is right from now on
+                let _ = self.try_handle_vnc_events(Event::Resize(w, h));
+                Ok(())
+            }
+            Ok((status, ..)) => Err(desktop_size::status_reason(status)),
+            Err(_) => Err(format!("no answer in {}s", RESIZE_TIMEOUT.as_secs())),
+        };
+        Ok(VNCEventRes::DesktopSize(res))
+    }
+
+    fn handle_set_clipboard(&mut self, s: String) -> Result<VNCEventRes, t_vnc::Error> {
+        if let Some(vnc) = self.conn.as_mut() {
+            vnc.update_clipboard(&s)?;
+            return Ok(VNCEventRes::Done);
+        }
+        Ok(VNCEventRes::NoConnection)
     }
 
     fn handle_mouse_down(&mut self, button: u8) -> Result<VNCEventRes, t_vnc::Error> {
@@ -545,16 +802,43 @@ impl VncClientInner {
             vnc.send_pointer_event(self.state.buttons, x, y)?;
             self.state.mouse_x = x;
             self.state.mouse_y = y;
+            // cursor moved, screenshot changed
+            if self.render_cursor {
+                self.state.updated_in_frame = true;
+            }
             return Ok(VNCEventRes::Done);
         }
         Ok(VNCEventRes::NoConnection)
     }
 
+    fn handle_mouse_move_relative(
+        &mut self,
+        dx: i16,
+        dy: i16,
+    ) -> Result<VNCEventRes, t_vnc::Error> {
+        let x = move_in_bounds(self.state.mouse_x, dx, self.state.width);
+        let y = move_in_bounds(self.state.mouse_y, dy, self.state.height);
+        self.handle_mouse_move(x, y)
+    }
+
+    fn handle_get_mouse_pos(&mut self) -> Result<VNCEventRes, t_vnc::Error> {
+        if self.conn.is_some() {
+            return Ok(VNCEventRes::MousePos(
+                self.state.mouse_x,
+                self.state.mouse_y,
+            ));
+        }
+        Ok(VNCEventRes::NoConnection)
+    }
+
     fn handle_mouse_hide(&mut self) -> Result<VNCEventRes, t_vnc::Error> {
         if let Some(vnc) = self.conn.as_mut() {
             vnc.send_pointer_event(self.state.buttons, self.state.width, self.state.height)?;
             self.state.mouse_x = self.state.width;
             self.state.mouse_y = self.state.height;
+            if self.render_cursor {
+                self.state.updated_in_frame = true;
+            }
             return Ok(VNCEventRes::Done);
         }
         Ok(VNCEventRes::NoConnection)
@@ -595,13 +879,54 @@ impl VncClientInner {
         Ok(VNCEventRes::NoConnection)
     }
 
-    fn handle_type_string(&mut self, s: String) -> Result<VNCEventRes, t_vnc::Error> {
-        assert!(s.is_ascii());
+    fn handle_key_down(&mut self, keys: Vec<u32>) -> Result<VNCEventRes, t_vnc::Error> {
         if let Some(vnc) = self.conn.as_mut() {
-            for c in s.as_bytes() {
-                let key = *c as u32;
-                vnc.send_key_event(true, key)?;
-                vnc.send_key_event(false, key)?;
+            for m in keys {
+                vnc.send_key_event(true, m)?;
+                if !self.state.keys.contains(&m) {
+                    self.state.keys.push(m);
+                }
+            }
+            return Ok(VNCEventRes::Done);
+        }
+        Ok(VNCEventRes::NoConnection)
+    }
+
+    fn handle_key_up(&mut self, keys: Vec<u32>) -> Result<VNCEventRes, t_vnc::Error> {
+        if let Some(vnc) = self.conn.as_mut() {
+            for m in keys.into_iter().rev() {
+                vnc.send_key_event(false, m)?;
+                self.state.keys.retain(|k| *k != m);
+            }
+            return Ok(VNCEventRes::Done);
+        }
+        Ok(VNCEventRes::NoConnection)
+    }
+
+    // secret hides typed chars from logs
+    fn handle_type_string(&mut self, s: String, secret: bool) -> Result<VNCEventRes, t_vnc::Error> {
+        // check every char first, so string is never typed half
+        let strokes = match type_strokes(self.keymap, &s, secret) {
+            Ok(strokes) => strokes,
+            Err(e) => return Ok(VNCEventRes::Error(e)),
+        };
+        if let Some(vnc) = self.conn.as_mut() {
+            for stroke in strokes {
+                let modifiers = stroke.modifiers();
+                for m in modifiers.iter() {
+                    vnc.send_key_event(true, *m)?;
+                }
+                vnc.send_key_event(true, stroke.key)?;
+                if !self.type_delay.is_zero() {
+                    thread::sleep(self.type_delay);
+                }
+                vnc.send_key_event(false, stroke.key)?;
+                for m in modifiers.iter().rev() {
+                    vnc.send_key_event(false, *m)?;
+                }
+                if !self.type_delay.is_zero() {
+                    thread::sleep(self.type_delay);
+                }
             }
             return Ok(VNCEventRes::Done);
         }
@@ -614,24 +939,34 @@ impl VncClientInner {
         span: Option<String>,
     ) -> Result<VNCEventRes, t_vnc::Error> {
         if let Some(screenshot) = self.screenshot_buffer.back() {
-            if let Some(tx) = &self.screenshot_tx {
-                // if has new frame, then save
-                let (done_tx, done_rx) = mpsc::channel();
-                if let Err(e) = tx.send(Log::Screenshot {
-                    screen: screenshot.clone(),
-                    name,
-                    span,
-                    done_tx,
-                }) {
-                    error!(msg = "screenshot channel closed", reason = ?e);
-                    self.screenshot_tx = None;
-                }
-                if let Err(e) = done_rx.recv() {
-                    error!(msg = "screenshot done recv failed", reason = ?e);
-                    self.screenshot_tx = None;
-                }
-                return Ok(VNCEventRes::Done);
+            // if has new frame, then save
+            return self.handle_screen_save(screenshot.clone(), name, span);
+        }
+        Ok(VNCEventRes::NoConnection)
+    }
+
+    fn handle_screen_save(
+        &mut self,
+        screen: Arc<PNG>,
+        name: String,
+        span: Option<String>,
+    ) -> Result<VNCEventRes, t_vnc::Error> {
+        if let Some(tx) = &self.screenshot_tx {
+            let (done_tx, done_rx) = mpsc::channel();
+            if let Err(e) = tx.send(Log::Screenshot {
+                screen,
+                name,
+                span,
+                done_tx,
+            }) {
+                error!(msg = "screenshot channel closed", reason = ?e);
+                self.screenshot_tx = None;
+            }
+            if let Err(e) = done_rx.recv() {
+                error!(msg = "screenshot done recv failed", reason = ?e);
+                self.screenshot_tx = None;
             }
+            return Ok(VNCEventRes::Done);
         }
         Ok(VNCEventRes::NoConnection)
     }
@@ -643,6 +978,14 @@ impl VncClientInner {
         Ok(VNCEventRes::NoConnection)
     }
 
+    // size follows desktop resize, so always read from state
+    fn handle_get_size(&mut self) -> Result<VNCEventRes, t_vnc::Error> {
+        if self.conn.is_some() {
+            return Ok(VNCEventRes::Size(self.state.width, self.state.height));
+        }
+        Ok(VNCEventRes::NoConnection)
+    }
+
     fn handle_screen_refresh(&mut self) -> Result<VNCEventRes, t_vnc::Error> {
         if let Some(vnc) = self.conn.as_mut() {
             vnc.request_update(
@@ -689,3 +1032,148 @@ fn convert_to_rgb(pixel_format: &PixelFormat, raw_pixel_chunks: &[u8]) -> Vec<u8
 
     image_buffer
 }
+
+// time server gets to answer SetDesktopSize
+const RESIZE_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn default_encodings() -> Vec<t_vnc::Encoding> {
+    vec![
+        t_vnc::Encoding::Zrle,
+        t_vnc::Encoding::CopyRect,
+        t_vnc::Encoding::Raw,
+        t_vnc::Encoding::Cursor,
+        t_vnc::Encoding::DesktopSize,
+        t_vnc::Encoding::Unknown(desktop_size::EXTENDED_DESKTOP_SIZE),
+    ]
+}
+
+// pos + delta, clamped into [0, size)
+fn move_in_bounds(pos: u16, delta: i16, size: u16) -> u16 {
+    (pos as i32 + delta as i32).clamp(0, size.saturating_sub(1) as i32) as u16
+}
+
+// unknown names are skipped, fallback to default list if nothing left
+fn parse_encodings(names: &[String]) -> Vec<t_vnc::Encoding> {
+    let encodings: Vec<t_vnc::Encoding> = names
+        .iter()
+        .filter_map(|name| match name.to_lowercase().as_str() {
+            "raw" => Some(t_vnc::Encoding::Raw),
+            "copyrect" => Some(t_vnc::Encoding::CopyRect),
+            "rre" => Some(t_vnc::Encoding::Rre),
+            "hextile" => Some(t_vnc::Encoding::Hextile),
+            "zrle" => Some(t_vnc::Encoding::Zrle),
+            "cursor" => Some(t_vnc::Encoding::Cursor),
+            "desktopsize" => Some(t_vnc::Encoding::DesktopSize),
+            "extendeddesktopsize" => Some(t_vnc::Encoding::Unknown(
+                desktop_size::EXTENDED_DESKTOP_SIZE,
+            )),
+            _ => {
+                warn!(msg = "unsupported vnc encoding, skipped", name = name);
+                None
+            }
+        })
+        .collect();
+    if encodings.is_empty() {
+        warn!(msg = "no valid vnc encoding, use default");
+        return default_encodings();
+    }
+    encodings
+}
+
+// SetDesktopSize client message of extended desktop size extension, one screen covering all
+fn set_desktop_size_msg(w: u16, h: u16) -> [u8; 24] {
+    let mut msg = [0; 24];
+    msg[0] = 251;
+    BigEndian::write_u16(&mut msg[2..4], w);
+    BigEndian::write_u16(&mut msg[4..6], h);
+    msg[6] = 1;
+    // screen id 0 at (0, 0), flags 0
+    BigEndian::write_u16(&mut msg[16..18], w);
+    BigEndian::write_u16(&mut msg[18..20], h);
+    msg
+}
+
+// keys for every char of s, error names the first char not on keymap, unless s is secret
+fn type_strokes(keymap: Keymap, s: &str, secret: bool) -> Result<Vec<KeyStroke>, String> {
+    s.chars()
+        .map(|c| keymap.stroke(c).ok_or(c))
+        .collect::<Result<_, _>>()
+        .map_err(|c| {
+            let c = if secret {
+                "****".to_string()
+            } else {
+                format!("{c:?}")
+            };
+            format!("unmappable character {c} for keymap {keymap:?}")
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_move_in_bounds() {
+        assert_eq!(move_in_bounds(10, 5, 100), 15);
+        assert_eq!(move_in_bounds(10, -20, 100), 0);
+        assert_eq!(move_in_bounds(90, 20, 100), 99);
+        // hidden mouse sits at (width, height)
+        assert_eq!(move_in_bounds(100, -1, 100), 99);
+    }
+
+    #[test]
+    fn test_parse_encodings() {
+        let names = ["Tight", "hextile", "ZRLE", "cursor"].map(String::from);
+        assert_eq!(
+            parse_encodings(&names),
+            vec![
+                t_vnc::Encoding::Hextile,
+                t_vnc::Encoding::Zrle,
+                t_vnc::Encoding::Cursor,
+            ]
+        );
+        assert_eq!(parse_encodings(&["tight".to_string()]), default_encodings());
+    }
+
+    #[test]
+    fn test_draw_cursor() {
+        // 2x2 cursor, only left column visible
+        let cursor = Cursor {
+            hotspot: (1, 1),
+            image: Container::new_with_data(2, 2, vec![255; 12], 3),
+            mask_bits: vec![0b1000_0000, 0b1000_0000],
+        };
+        let mut screen = Container::new(4, 4, 3);
+        cursor.draw(&mut screen, 2, 2);
+        assert_eq!(screen.get(1, 1), &[255, 255, 255]);
+        assert_eq!(screen.get(2, 1), &[255, 255, 255]);
+        assert_eq!(screen.get(1, 2), &[0, 0, 0]);
+        assert_eq!(screen.get(2, 2), &[0, 0, 0]);
+
+        // clipped at top left corner
+        let mut screen = Container::new(4, 4, 3);
+        cursor.draw(&mut screen, 0, 0);
+        assert!(screen.data.iter().all(|p| *p == 0));
+    }
+
+    #[test]
+    fn test_type_strokes() {
+        assert_eq!(type_strokes(Keymap::Us, "a!", false).unwrap().len(), 2);
+        let e = type_strokes(Keymap::Us, "pässword", false).unwrap_err();
+        assert_eq!(e, "unmappable character 'ä' for keymap Us");
+        // password is never echoed
+        let e = type_strokes(Keymap::Us, "pässword", true).unwrap_err();
+        assert!(!e.contains('ä') && !e.contains("ss"), "{e}");
+        assert_eq!(type_strokes(Keymap::De, "Größe", false).unwrap().len(), 5);
+        let e = type_strokes(Keymap::De, "café", false).unwrap_err();
+        assert_eq!(e, "unmappable character 'é' for keymap De");
+    }
+
+    #[test]
+    fn test_set_desktop_size_msg() {
+        assert_eq!(
+            set_desktop_size_msg(1024, 768),
+            [251, 0, 4, 0, 3, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0, 3, 0, 0, 0, 0, 0]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    fmt::Display,
+    io,
+    net::{SocketAddr, TcpStream},
+    path::PathBuf,
+    time::Duration,
+};
+
+// where vnc server listens, parsed from "tcp://host:port", "unix:///path" or bare host
+#[derive(Debug, Clone, PartialEq)]
+pub enum VNCAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl VNCAddr {
+    // port is only used by bare host
+    pub fn parse(host: &str, port: u16) -> Result<Self, String> {
+        let Some((scheme, rest)) = host.split_once("://") else {
+            return format!("{host}:{port}")
+                .parse()
+                .map(Self::Tcp)
+                .map_err(|e| e.to_string());
+        };
+        match scheme {
+            "tcp" => rest.parse().map(Self::Tcp).map_err(|e| e.to_string()),
+            "unix" => Ok(Self::Unix(PathBuf::from(rest))),
+            _ => Err(format!("unsupported vnc transport: {scheme}")),
+        }
+    }
+
+    // vnc client only reads from tcp stream, other transports are bridged to a local tcp port,
+    // timeout only applies to tcp, connecting unix socket doesn't block
+    pub fn connect(&self, timeout: Duration) -> io::Result<TcpStream> {
+        match self {
+            Self::Tcp(addr) => TcpStream::connect_timeout(addr, timeout),
+            #[cfg(unix)]
+            Self::Unix(path) => bridge_unix(path),
+            #[cfg(not(unix))]
+            Self::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix socket is not supported on this platform",
+            )),
+        }
+    }
+}
+
+impl From<SocketAddr> for VNCAddr {
+    fn from(value: SocketAddr) -> Self {
+        Self::Tcp(value)
+    }
+}
+
+impl Display for VNCAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "tcp://{addr}"),
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bridge_unix(path: &std::path::Path) -> io::Result<TcpStream> {
+    use std::{net::Shutdown, net::TcpListener, os::unix::net::UnixStream, thread};
+
+    let unix = UnixStream::connect(path)?;
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let local = TcpStream::connect(listener.local_addr()?)?;
+    let (remote, _) = listener.accept()?;
+
+    // pump both directions, close the other side when one side closed
+    let (mut unix_r, mut tcp_w) = (unix.try_clone()?, remote.try_clone()?);
+    thread::spawn(move || {
+        let _ = io::copy(&mut unix_r, &mut tcp_w);
+        let _ = tcp_w.shutdown(Shutdown::Both);
+    });
+    let (mut tcp_r, mut unix_w) = (remote, unix);
+    thread::spawn(move || {
+        let _ = io::copy(&mut tcp_r, &mut unix_w);
+        let _ = unix_w.shutdown(Shutdown::Both);
+    });
+    Ok(local)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let tcp = VNCAddr::Tcp("127.0.0.1:5901".parse().unwrap());
+        assert_eq!(VNCAddr::parse("127.0.0.1", 5901).unwrap(), tcp);
+        assert_eq!(VNCAddr::parse("tcp://127.0.0.1:5901", 0).unwrap(), tcp);
+        assert_eq!(
+            VNCAddr::parse("unix:///tmp/vnc.sock", 0).unwrap(),
+            VNCAddr::Unix(PathBuf::from("/tmp/vnc.sock"))
+        );
+        assert!(VNCAddr::parse("ws://127.0.0.1:5901", 0).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_bridge_unix() {
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("t-console-vnc-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let mut tcp = VNCAddr::Unix(path.clone())
+            .connect(Duration::from_secs(1))
+            .unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        server.write_all(b"RFB 003.008\n").unwrap();
+        let mut buf = [0; 12];
+        tcp.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"RFB 003.008\n");
+
+        tcp.write_all(b"ok").unwrap();
+        let mut buf = [0; 2];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ok");
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,4 +1,5 @@
-use image::{DynamicImage, RgbImage};
+use image::{DynamicImage, ImageFormat, RgbImage};
+use std::io::Cursor;
 
 pub type Rect = t_vnc::Rect;
 
@@ -94,6 +95,15 @@ impl Container {
         )
     }
 
+    // encode as png in memory, for callers that don't want a file
+    pub fn to_png_bytes(&self) -> Vec<u8> {
+        let mut buf = Cursor::new(Vec::new());
+        self.as_img()
+            .write_to(&mut buf, ImageFormat::Png)
+            .expect("encode png into memory failed");
+        buf.into_inner()
+    }
+
     pub fn cmp(&self, o: &Self) -> bool {
         // check width and height
         if self.width != o.width || self.height != o.height {
@@ -237,4 +247,13 @@ mod test {
             },
         ));
     }
+
+    #[test]
+    fn test_to_png_bytes() {
+        let sc = Container::new_with_data(2, 1, vec![1, 2, 3, 4, 5, 6], 3);
+        let bytes = sc.to_png_bytes();
+        let img = image::load_from_memory_with_format(&bytes, ImageFormat::Png).unwrap();
+        assert_eq!((img.width(), img.height()), (2, 1));
+        assert_eq!(img.into_rgb8().into_raw(), sc.data);
+    }
 }
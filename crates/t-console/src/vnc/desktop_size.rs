@@ -0,0 +1,365 @@
+use std::{
+    io::{self, BufWriter, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread,
+};
+
+use byteorder::{BigEndian, ByteOrder};
+use tracing::debug;
+
+use crate::base::relay::local_pair;
+
+const RAW: i32 = 0;
+const COPY_RECT: i32 = 1;
+const RRE: i32 = 2;
+const HEXTILE: i32 = 5;
+const ZRLE: i32 = 16;
+const CURSOR: i32 = -239;
+const DESKTOP_SIZE: i32 = -223;
+pub(crate) const EXTENDED_DESKTOP_SIZE: i32 = -308;
+
+// reason of an extended desktop size rect sent for our own SetDesktopSize
+const REASON_CLIENT: u16 = 1;
+
+// what server told about extended desktop size on one connection
+pub(crate) struct DesktopSize {
+    supported: Arc<AtomicBool>,
+    // (status, width, height) answering our SetDesktopSize, status 0 is success
+    status_rx: Receiver<(u16, u16, u16)>,
+    start_tx: Sender<u64>,
+}
+
+impl DesktopSize {
+    // server sends an extended desktop size rect once it saw the encoding advertised
+    pub fn supported(&self) -> bool {
+        self.supported.load(Ordering::SeqCst)
+    }
+
+    pub fn status_rx(&self) -> &Receiver<(u16, u16, u16)> {
+        &self.status_rx
+    }
+
+    // handshake is done, server messages follow, pixels are bytes_per_pixel wide
+    pub fn start(&self, bytes_per_pixel: u64) {
+        let _ = self.start_tx.send(bytes_per_pixel);
+    }
+}
+
+pub(crate) fn status_reason(status: u16) -> String {
+    match status {
+        1 => "resize is administratively prohibited".to_string(),
+        2 => "out of resources".to_string(),
+        3 => "invalid screen layout".to_string(),
+        n => format!("status {n}"),
+    }
+}
+
+// t_vnc can't decode extended desktop size rects, so server messages pass through here first,
+// those rects are turned into desktop size ones and their status is kept.
+// handshake is passed as is until start, t_vnc sees a server with the same handshake
+pub(crate) fn filter(server: TcpStream) -> io::Result<(TcpStream, DesktopSize)> {
+    let (client, local) = local_pair()?;
+    let (start_tx, start_rx) = mpsc::channel();
+    let (status_tx, status_rx) = mpsc::channel();
+    let supported = Arc::new(AtomicBool::new(false));
+
+    let (mut local_r, mut server_w) = (local.try_clone()?, server.try_clone()?);
+    thread::spawn(move || {
+        let _ = io::copy(&mut local_r, &mut server_w);
+        let _ = server_w.shutdown(Shutdown::Both);
+    });
+    let seen = supported.clone();
+    thread::spawn(move || {
+        let (mut server, mut local) = (server, local);
+        if let Err(e) = pass(&mut server, &mut local, start_rx, &seen, status_tx) {
+            debug!(msg = "vnc server messages stopped", reason = ?e);
+        }
+        let _ = local.shutdown(Shutdown::Both);
+        let _ = server.shutdown(Shutdown::Both);
+    });
+    Ok((
+        client,
+        DesktopSize {
+            supported,
+            status_rx,
+            start_tx,
+        },
+    ))
+}
+
+fn pass(
+    server: &mut TcpStream,
+    local: &mut TcpStream,
+    start_rx: Receiver<u64>,
+    supported: &AtomicBool,
+    status_tx: Sender<(u16, u16, u16)>,
+) -> io::Result<()> {
+    // server says nothing between its init message and our first request after start
+    let mut buf = [0; 4096];
+    loop {
+        let n = server.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        if let Ok(bpp) = start_rx.try_recv() {
+            let mut r = (&buf[..n]).chain(server);
+            return messages(
+                &mut r,
+                &mut BufWriter::new(local),
+                bpp,
+                supported,
+                &status_tx,
+            );
+        }
+        local.write_all(&buf[..n])?;
+    }
+}
+
+// copy server messages until server closed
+fn messages(
+    r: &mut impl Read,
+    w: &mut impl Write,
+    bpp: u64,
+    supported: &AtomicBool,
+    status_tx: &Sender<(u16, u16, u16)>,
+) -> io::Result<()> {
+    loop {
+        let mut kind = [0; 1];
+        match r.read_exact(&mut kind) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            res => res?,
+        }
+        w.write_all(&kind)?;
+        let mut statuses = Vec::new();
+        match kind[0] {
+            // framebuffer update
+            0 => {
+                let head: [u8; 3] = pass_bytes(r, w)?;
+                for _ in 0..BigEndian::read_u16(&head[1..]) {
+                    let mut rect = [0; 12];
+                    r.read_exact(&mut rect)?;
+                    let [x, y, width, height] =
+                        [0, 2, 4, 6].map(|i| BigEndian::read_u16(&rect[i..]));
+                    let encoding = BigEndian::read_i32(&rect[8..]);
+                    if encoding == EXTENDED_DESKTOP_SIZE {
+                        let mut screens = [0; 4];
+                        r.read_exact(&mut screens)?;
+                        skip(r, screens[0] as u64 * 16)?;
+                        supported.store(true, Ordering::SeqCst);
+                        // x is reason, y is status
+                        if x == REASON_CLIENT {
+                            statuses.push((y, width, height));
+                        }
+                        rect[..4].fill(0);
+                        BigEndian::write_i32(&mut rect[8..], DESKTOP_SIZE);
+                        w.write_all(&rect)?;
+                        continue;
+                    }
+                    w.write_all(&rect)?;
+                    copy_rect(r, w, encoding, width as u64, height as u64, bpp)?;
+                }
+            }
+            // set colour map entries
+            1 => {
+                let head: [u8; 5] = pass_bytes(r, w)?;
+                copy(r, w, BigEndian::read_u16(&head[3..]) as u64 * 6)?;
+            }
+            // bell
+            2 => {}
+            // server cut text
+            3 => {
+                let head: [u8; 7] = pass_bytes(r, w)?;
+                copy(r, w, BigEndian::read_u32(&head[3..]) as u64)?;
+            }
+            kind => return Err(invalid(format!("unknown vnc server message {kind}"))),
+        }
+        w.flush()?;
+        // t_vnc has the resize by now
+        for status in statuses {
+            let _ = status_tx.send(status);
+        }
+    }
+}
+
+// payload of one rect, encodings are those t_vnc can be asked for
+fn copy_rect(
+    r: &mut impl Read,
+    w: &mut impl Write,
+    encoding: i32,
+    width: u64,
+    height: u64,
+    bpp: u64,
+) -> io::Result<()> {
+    match encoding {
+        RAW => copy(r, w, width * height * bpp),
+        COPY_RECT => copy(r, w, 4),
+        RRE => {
+            let count: [u8; 4] = pass_bytes(r, w)?;
+            copy(r, w, bpp + BigEndian::read_u32(&count) as u64 * (bpp + 8))
+        }
+        HEXTILE => {
+            for top in (0..height).step_by(16) {
+                for left in (0..width).step_by(16) {
+                    let (tile_w, tile_h) = ((width - left).min(16), (height - top).min(16));
+                    let [mask]: [u8; 1] = pass_bytes(r, w)?;
+                    if mask & 1 != 0 {
+                        copy(r, w, tile_w * tile_h * bpp)?;
+                        continue;
+                    }
+                    // background and foreground
+                    copy(
+                        r,
+                        w,
+                        (mask >> 1 & 1) as u64 * bpp + (mask >> 2 & 1) as u64 * bpp,
+                    )?;
+                    if mask & 8 != 0 {
+                        let [count]: [u8; 1] = pass_bytes(r, w)?;
+                        let colour = if mask & 16 != 0 { bpp } else { 0 };
+                        copy(r, w, count as u64 * (colour + 2))?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        ZRLE => {
+            let len: [u8; 4] = pass_bytes(r, w)?;
+            copy(r, w, BigEndian::read_u32(&len) as u64)
+        }
+        CURSOR => copy(r, w, width * height * bpp + width.div_ceil(8) * height),
+        DESKTOP_SIZE => Ok(()),
+        encoding => Err(invalid(format!("unknown vnc encoding {encoding}"))),
+    }
+}
+
+fn pass_bytes<const N: usize>(r: &mut impl Read, w: &mut impl Write) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    w.write_all(&buf)?;
+    Ok(buf)
+}
+
+fn copy(r: &mut impl Read, w: &mut impl Write, len: u64) -> io::Result<()> {
+    if io::copy(&mut r.take(len), w)? < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+fn skip(r: &mut impl Read, len: u64) -> io::Result<()> {
+    copy(r, &mut io::sink(), len)
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rect(x: u16, y: u16, w: u16, h: u16, encoding: i32) -> Vec<u8> {
+        let mut rect = [0; 12];
+        for (i, v) in [x, y, w, h].into_iter().enumerate() {
+            BigEndian::write_u16(&mut rect[i * 2..], v);
+        }
+        BigEndian::write_i32(&mut rect[8..], encoding);
+        rect.to_vec()
+    }
+
+    #[test]
+    fn test_messages() {
+        let mut input = vec![0, 0, 0, 3];
+        // 1x2 raw, 1 byte per pixel
+        input.extend(rect(0, 0, 1, 2, RAW));
+        input.extend([7, 8]);
+        // answer of our resize to 4x3, one screen
+        input.extend(rect(REASON_CLIENT, 0, 4, 3, EXTENDED_DESKTOP_SIZE));
+        input.extend([1, 0, 0, 0]);
+        input.extend([0; 16]);
+        input.extend(rect(0, 0, 2, 2, ZRLE));
+        input.extend([0, 0, 0, 2, 9, 9]);
+        // bell and cut text
+        input.extend([2, 3, 0, 0, 0, 0, 0, 0, 2, b'o', b'k']);
+
+        let (supported, (status_tx, status_rx)) = (AtomicBool::new(false), mpsc::channel());
+        let mut out = Vec::new();
+        messages(&mut &input[..], &mut out, 1, &supported, &status_tx).unwrap();
+
+        let mut expected = vec![0, 0, 0, 3];
+        expected.extend(rect(0, 0, 1, 2, RAW));
+        expected.extend([7, 8]);
+        expected.extend(rect(0, 0, 4, 3, DESKTOP_SIZE));
+        expected.extend(rect(0, 0, 2, 2, ZRLE));
+        expected.extend([0, 0, 0, 2, 9, 9]);
+        expected.extend([2, 3, 0, 0, 0, 0, 0, 0, 2, b'o', b'k']);
+        assert_eq!(out, expected);
+        assert!(supported.load(Ordering::SeqCst));
+        assert_eq!(status_rx.try_recv().unwrap(), (0, 4, 3));
+
+        // rejected resize, and server telling support on its own
+        let mut input = vec![0, 0, 0, 2];
+        input.extend(rect(0, 0, 2, 2, EXTENDED_DESKTOP_SIZE));
+        input.extend([0; 4]);
+        input.extend(rect(REASON_CLIENT, 1, 2, 2, EXTENDED_DESKTOP_SIZE));
+        input.extend([0; 4]);
+        let mut out = Vec::new();
+        messages(&mut &input[..], &mut out, 1, &supported, &status_tx).unwrap();
+        assert_eq!(status_rx.try_recv().unwrap(), (1, 2, 2));
+        assert!(status_rx.try_recv().is_err());
+
+        let input = [9];
+        assert!(messages(&mut &input[..], &mut Vec::new(), 1, &supported, &status_tx).is_err());
+    }
+
+    #[test]
+    fn test_hextile() {
+        // 17x1 is two tiles, a raw one and one with coloured subrects
+        let mut input = vec![1];
+        input.extend([5; 16]);
+        input.extend([2 | 8 | 16, 6, 1, 5, 0, 0]);
+        let mut out = Vec::new();
+        copy_rect(&mut &input[..], &mut out, HEXTILE, 17, 1, 1).unwrap();
+        assert_eq!(out, input);
+    }
+
+    #[test]
+    fn test_filter() {
+        let (mut server, remote) = local_pair().unwrap();
+        let (mut client, desktop_size) = filter(remote).unwrap();
+
+        // handshake passes as is
+        server.write_all(b"RFB 003.008\n").unwrap();
+        let mut version = [0; 12];
+        client.read_exact(&mut version).unwrap();
+        assert_eq!(&version, b"RFB 003.008\n");
+        client.write_all(&version).unwrap();
+        server.read_exact(&mut version).unwrap();
+
+        desktop_size.start(4);
+        assert!(!desktop_size.supported());
+        let mut update = vec![0, 0, 0, 1];
+        update.extend(rect(REASON_CLIENT, 0, 8, 6, EXTENDED_DESKTOP_SIZE));
+        update.extend([0; 4]);
+        server.write_all(&update).unwrap();
+        let mut out = [0; 16];
+        client.read_exact(&mut out).unwrap();
+        assert_eq!(&out[4..], rect(0, 0, 8, 6, DESKTOP_SIZE));
+        assert_eq!(
+            desktop_size
+                .status_rx()
+                .recv_timeout(std::time::Duration::from_secs(5))
+                .unwrap(),
+            (0, 8, 6)
+        );
+        assert!(desktop_size.supported());
+
+        // server gone closes the client
+        drop(server);
+        assert_eq!(client.read(&mut out).unwrap(), 0);
+    }
+}
@@ -0,0 +1,265 @@
+use super::key;
+
+// keys to press for one char, key is the keysym at the same position on a us keyboard,
+// qemu turns it into a scancode, and the guest layout turns the scancode into the char
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyStroke {
+    pub key: u32,
+    pub shift: bool,
+    pub altgr: bool,
+}
+
+impl KeyStroke {
+    pub const fn key(c: char) -> Self {
+        Self {
+            key: c as u32,
+            shift: false,
+            altgr: false,
+        }
+    }
+
+    const fn shift(c: char) -> Self {
+        Self {
+            key: c as u32,
+            shift: true,
+            altgr: false,
+        }
+    }
+
+    const fn altgr(c: char) -> Self {
+        Self {
+            key: c as u32,
+            shift: false,
+            altgr: true,
+        }
+    }
+
+    pub fn modifiers(&self) -> Vec<u32> {
+        let mut res = Vec::new();
+        if self.shift {
+            res.push(key::SHIFT_L);
+        }
+        if self.altgr {
+            res.push(key::ALT_R);
+        }
+        res
+    }
+}
+
+// guest keyboard layout used by type_string
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Keymap {
+    #[default]
+    Us,
+    De,
+    Fr,
+}
+
+impl Keymap {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "us" => Some(Self::Us),
+            "de" => Some(Self::De),
+            "fr" => Some(Self::Fr),
+            _ => None,
+        }
+    }
+
+    // us: shifted symbols, others: chars which differ from us layout,
+    // dead keys and the extra iso key (<>|) are not listed
+    fn table(&self) -> &'static [(char, KeyStroke)] {
+        match self {
+            Self::Us => US,
+            Self::De => DE,
+            Self::Fr => FR,
+        }
+    }
+
+    // none if c can't be typed on this layout
+    pub fn stroke(&self, c: char) -> Option<KeyStroke> {
+        if let Some((_, stroke)) = self.table().iter().find(|(k, _)| *k == c) {
+            return Some(*stroke);
+        }
+        // guest may ignore keysym case, so uppercase is typed as shift and lowercase
+        if c.is_ascii_uppercase() {
+            return self.stroke(c.to_ascii_lowercase()).map(|stroke| KeyStroke {
+                shift: true,
+                ..stroke
+            });
+        }
+        // letters, digits, space and control chars not in table are at the same position as
+        // us layout, other non ascii chars have no key on us layout
+        if (*self == Self::Us && c.is_ascii())
+            || c.is_ascii_alphanumeric()
+            || c == ' '
+            || c.is_ascii_control()
+        {
+            return Some(KeyStroke::key(c));
+        }
+        None
+    }
+}
+
+// shifted symbols on us keyboard, like CAPS_MAP of gui viewer
+const US: &[(char, KeyStroke)] = &[
+    ('!', KeyStroke::shift('1')),
+    ('@', KeyStroke::shift('2')),
+    ('#', KeyStroke::shift('3')),
+    ('$', KeyStroke::shift('4')),
+    ('%', KeyStroke::shift('5')),
+    ('^', KeyStroke::shift('6')),
+    ('&', KeyStroke::shift('7')),
+    ('*', KeyStroke::shift('8')),
+    ('(', KeyStroke::shift('9')),
+    (')', KeyStroke::shift('0')),
+    ('_', KeyStroke::shift('-')),
+    ('+', KeyStroke::shift('=')),
+    ('{', KeyStroke::shift('[')),
+    ('}', KeyStroke::shift(']')),
+    ('|', KeyStroke::shift('\\')),
+    (':', KeyStroke::shift(';')),
+    ('"', KeyStroke::shift('\'')),
+    ('<', KeyStroke::shift(',')),
+    ('>', KeyStroke::shift('.')),
+    ('?', KeyStroke::shift('/')),
+    ('~', KeyStroke::shift('`')),
+];
+
+const DE: &[(char, KeyStroke)] = &[
+    ('y', KeyStroke::key('z')),
+    ('Y', KeyStroke::shift('z')),
+    ('z', KeyStroke::key('y')),
+    ('Z', KeyStroke::shift('y')),
+    ('!', KeyStroke::shift('1')),
+    ('"', KeyStroke::shift('2')),
+    ('$', KeyStroke::shift('4')),
+    ('%', KeyStroke::shift('5')),
+    ('&', KeyStroke::shift('6')),
+    ('/', KeyStroke::shift('7')),
+    ('(', KeyStroke::shift('8')),
+    (')', KeyStroke::shift('9')),
+    ('=', KeyStroke::shift('0')),
+    ('?', KeyStroke::shift('-')),
+    ('{', KeyStroke::altgr('7')),
+    ('[', KeyStroke::altgr('8')),
+    (']', KeyStroke::altgr('9')),
+    ('}', KeyStroke::altgr('0')),
+    ('\\', KeyStroke::altgr('-')),
+    ('@', KeyStroke::altgr('q')),
+    ('+', KeyStroke::key(']')),
+    ('*', KeyStroke::shift(']')),
+    ('#', KeyStroke::key('\\')),
+    ('\'', KeyStroke::shift('\\')),
+    (',', KeyStroke::key(',')),
+    (';', KeyStroke::shift(',')),
+    ('.', KeyStroke::key('.')),
+    (':', KeyStroke::shift('.')),
+    ('-', KeyStroke::key('/')),
+    ('_', KeyStroke::shift('/')),
+    ('ä', KeyStroke::key('\'')),
+    ('Ä', KeyStroke::shift('\'')),
+    ('ö', KeyStroke::key(';')),
+    ('Ö', KeyStroke::shift(';')),
+    ('ü', KeyStroke::key('[')),
+    ('Ü', KeyStroke::shift('[')),
+    ('ß', KeyStroke::key('-')),
+];
+
+const FR: &[(char, KeyStroke)] = &[
+    ('a', KeyStroke::key('q')),
+    ('A', KeyStroke::shift('q')),
+    ('q', KeyStroke::key('a')),
+    ('Q', KeyStroke::shift('a')),
+    ('z', KeyStroke::key('w')),
+    ('Z', KeyStroke::shift('w')),
+    ('w', KeyStroke::key('z')),
+    ('W', KeyStroke::shift('z')),
+    ('m', KeyStroke::key(';')),
+    ('M', KeyStroke::shift(';')),
+    ('1', KeyStroke::shift('1')),
+    ('2', KeyStroke::shift('2')),
+    ('3', KeyStroke::shift('3')),
+    ('4', KeyStroke::shift('4')),
+    ('5', KeyStroke::shift('5')),
+    ('6', KeyStroke::shift('6')),
+    ('7', KeyStroke::shift('7')),
+    ('8', KeyStroke::shift('8')),
+    ('9', KeyStroke::shift('9')),
+    ('0', KeyStroke::shift('0')),
+    ('&', KeyStroke::key('1')),
+    ('"', KeyStroke::key('3')),
+    ('\'', KeyStroke::key('4')),
+    ('(', KeyStroke::key('5')),
+    ('-', KeyStroke::key('6')),
+    ('_', KeyStroke::key('8')),
+    (')', KeyStroke::key('-')),
+    ('=', KeyStroke::key('=')),
+    ('+', KeyStroke::shift('=')),
+    ('#', KeyStroke::altgr('3')),
+    ('{', KeyStroke::altgr('4')),
+    ('[', KeyStroke::altgr('5')),
+    ('|', KeyStroke::altgr('6')),
+    ('\\', KeyStroke::altgr('8')),
+    ('^', KeyStroke::altgr('9')),
+    ('@', KeyStroke::altgr('0')),
+    (']', KeyStroke::altgr('-')),
+    ('}', KeyStroke::altgr('=')),
+    ('$', KeyStroke::key(']')),
+    ('*', KeyStroke::key('\\')),
+    ('%', KeyStroke::shift('\'')),
+    (',', KeyStroke::key('m')),
+    ('?', KeyStroke::shift('m')),
+    (';', KeyStroke::key(',')),
+    ('.', KeyStroke::shift(',')),
+    (':', KeyStroke::key('.')),
+    ('/', KeyStroke::shift('.')),
+    ('!', KeyStroke::key('/')),
+    ('é', KeyStroke::key('2')),
+    ('è', KeyStroke::key('7')),
+    ('ç', KeyStroke::key('9')),
+    ('à', KeyStroke::key('0')),
+    ('ù', KeyStroke::key('\'')),
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_keymap() {
+        assert_eq!(Keymap::from_name("DE"), Some(Keymap::De));
+        assert_eq!(Keymap::from_name("jp"), None);
+
+        assert_eq!(Keymap::Us.stroke('@'), Some(KeyStroke::shift('2')));
+        assert_eq!(Keymap::Us.stroke('a'), Some(KeyStroke::key('a')));
+        // type_string("A!") holds shift for both chars
+        for c in "A!".chars() {
+            assert_eq!(
+                Keymap::Us.stroke(c).unwrap().modifiers(),
+                vec![key::SHIFT_L]
+            );
+        }
+        assert_eq!(Keymap::Us.stroke('A').unwrap().key, 'a' as u32);
+        assert_eq!(Keymap::De.stroke('B'), Some(KeyStroke::shift('b')));
+        assert_eq!(Keymap::De.stroke('Z'), Some(KeyStroke::shift('y')));
+        assert_eq!(Keymap::De.stroke('z'), Some(KeyStroke::key('y')));
+        assert_eq!(Keymap::De.stroke('b'), Some(KeyStroke::key('b')));
+        let at = Keymap::De.stroke('@').unwrap();
+        assert_eq!(at.key, 'q' as u32);
+        assert_eq!(at.modifiers(), vec![key::ALT_R]);
+        assert_eq!(
+            Keymap::Fr.stroke('1').unwrap().modifiers(),
+            vec![key::SHIFT_L]
+        );
+        // extra iso key is not in table
+        assert_eq!(Keymap::De.stroke('|'), None);
+        assert_eq!(Keymap::De.stroke('\n'), Some(KeyStroke::key('\n')));
+        assert_eq!(Keymap::Us.stroke('ä'), None);
+        // umlauts are typed on a german guest, other non ascii chars are unmappable
+        assert_eq!(Keymap::De.stroke('ä'), Some(KeyStroke::key('\'')));
+        assert_eq!(Keymap::De.stroke('Ü'), Some(KeyStroke::shift('[')));
+        assert_eq!(Keymap::De.stroke('ß'), Some(KeyStroke::key('-')));
+        assert_eq!(Keymap::De.stroke('é'), None);
+        assert_eq!(Keymap::Fr.stroke('é'), Some(KeyStroke::key('2')));
+    }
+}
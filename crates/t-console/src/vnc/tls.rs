@@ -0,0 +1,240 @@
+use std::{
+    fs,
+    io::{self, Read, Write},
+    net::TcpStream,
+    thread,
+};
+
+use byteorder::{BigEndian, ByteOrder};
+use native_tls::{Certificate, TlsConnector};
+use t_config::ConsoleVNC;
+use tracing::{debug, info};
+
+use super::VNCAddr;
+use crate::base::relay::{local_pair, relay, socket_of, Interest, Socket};
+
+const SECURITY_NONE: u8 = 1;
+const SECURITY_VNC: u8 = 2;
+const SECURITY_VENCRYPT: u8 = 19;
+// anonymous tls subtypes need ADH ciphers, which native-tls can't enable, so only x509 ones
+const X509_NONE: u32 = 260;
+const X509_VNC: u32 = 261;
+
+// vnc client only reads from tcp stream, so vencrypt and tls are negotiated here and
+// the decrypted stream is bridged to a local tcp port, like unix socket,
+// the client sees a plain server asking for none or vnc auth.
+// server certificate is checked against tcp address ip, unix socket has no address to check
+pub(crate) fn connect_vencrypt(
+    mut stream: TcpStream,
+    addr: &VNCAddr,
+    c: &ConsoleVNC,
+) -> io::Result<TcpStream> {
+    let subtype = negotiate(&mut stream, c.password.is_some())?;
+    let verify = c.tls_verify.unwrap_or(true);
+    let mut builder = TlsConnector::builder();
+    builder
+        .danger_accept_invalid_certs(!verify)
+        .danger_accept_invalid_hostnames(!verify || matches!(addr, VNCAddr::Unix(_)))
+        .use_sni(false);
+    if let Some(ca_file) = c.tls_ca_file.as_ref() {
+        let pem = fs::read(ca_file)?;
+        builder.add_root_certificate(Certificate::from_pem(&pem).map_err(io::Error::other)?);
+    }
+    let domain = match addr {
+        VNCAddr::Tcp(addr) => addr.ip().to_string(),
+        VNCAddr::Unix(_) => String::new(),
+    };
+    let tls = builder
+        .build()
+        .map_err(io::Error::other)?
+        .connect(&domain, stream)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    info!(
+        msg = "vnc tls connected",
+        subtype = subtype,
+        verify = verify
+    );
+
+    let (local, remote) = local_pair()?;
+    let auth = if subtype == X509_VNC {
+        SECURITY_VNC
+    } else {
+        SECURITY_NONE
+    };
+    tls.get_ref().set_nonblocking(true)?;
+    let socket = socket_of(tls.get_ref());
+    thread::spawn(move || {
+        if let Err(e) = serve(remote, tls, socket, auth) {
+            debug!(msg = "vnc tls relay stopped", reason = ?e);
+        }
+    });
+    Ok(local)
+}
+
+// rfb 3.8 handshake until tls starts, returns the chosen vencrypt subtype
+fn negotiate(stream: &mut (impl Read + Write), password: bool) -> io::Result<u32> {
+    let mut version = [0; 12];
+    stream.read_exact(&mut version)?;
+    if !version.starts_with(b"RFB 003.") || version[..] < b"RFB 003.007\n"[..] {
+        return Err(invalid("vencrypt needs rfb 3.7 or later"));
+    }
+    stream.write_all(b"RFB 003.008\n")?;
+
+    let types = read_u8_list(stream)?;
+    if types.is_empty() {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len)?;
+        let mut reason = vec![0; BigEndian::read_u32(&len) as usize];
+        stream.read_exact(&mut reason)?;
+        return Err(invalid(&String::from_utf8_lossy(&reason)));
+    }
+    if !types.contains(&SECURITY_VENCRYPT) {
+        return Err(invalid("server doesn't offer vencrypt"));
+    }
+    stream.write_all(&[SECURITY_VENCRYPT])?;
+
+    let mut version = [0; 2];
+    stream.read_exact(&mut version)?;
+    if version < [0, 2] {
+        return Err(invalid("vencrypt needs version 0.2"));
+    }
+    stream.write_all(&[0, 2])?;
+    if read_u8(stream)? != 0 {
+        return Err(invalid("vencrypt version rejected"));
+    }
+
+    let mut subtypes = vec![0; read_u8(stream)? as usize * 4];
+    stream.read_exact(&mut subtypes)?;
+    let subtypes: Vec<u32> = subtypes.chunks(4).map(BigEndian::read_u32).collect();
+    let prefer = if password {
+        [X509_VNC, X509_NONE]
+    } else {
+        [X509_NONE, X509_VNC]
+    };
+    let Some(subtype) = prefer.into_iter().find(|t| subtypes.contains(t)) else {
+        return Err(invalid(&format!(
+            "no supported vencrypt subtype in {subtypes:?}"
+        )));
+    };
+    let mut buf = [0; 4];
+    BigEndian::write_u32(&mut buf, subtype);
+    stream.write_all(&buf)?;
+    if read_u8(stream)? != 1 {
+        return Err(invalid("vencrypt subtype rejected"));
+    }
+    Ok(subtype)
+}
+
+// act as a plain server offering auth to local client, then relay bytes until one side closed,
+// remote is non-blocking on remote_socket, dropping local on return closes the client
+fn serve(
+    mut local: TcpStream,
+    remote: impl Read + Write,
+    remote_socket: Socket,
+    auth: u8,
+) -> io::Result<()> {
+    local.write_all(b"RFB 003.008\n")?;
+    let mut version = [0; 12];
+    local.read_exact(&mut version)?;
+    local.write_all(&[1, auth])?;
+    if read_u8(&mut local)? != auth {
+        return Err(invalid("client chose another auth"));
+    }
+    // a blocked tls write may wait for a handshake read
+    relay(local, remote, remote_socket, |_| true, || Interest::BOTH)
+}
+
+fn read_u8(stream: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    stream.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u8_list(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut list = vec![0; read_u8(stream)? as usize];
+    stream.read_exact(&mut list)?;
+    Ok(list)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{net::TcpListener, time::Duration};
+
+    // server side of negotiate, offers x509 subtypes and accepts any
+    fn fake_server(server: &mut TcpStream) -> u32 {
+        server.write_all(b"RFB 003.008\n").unwrap();
+        let mut version = [0; 12];
+        server.read_exact(&mut version).unwrap();
+        server
+            .write_all(&[2, SECURITY_VNC, SECURITY_VENCRYPT])
+            .unwrap();
+        assert_eq!(read_u8(server).unwrap(), SECURITY_VENCRYPT);
+        server.write_all(&[0, 2]).unwrap();
+        let mut version = [0; 2];
+        server.read_exact(&mut version).unwrap();
+        server.write_all(&[0]).unwrap();
+        server
+            .write_all(&[3, 0, 0, 1, 1, 0, 0, 1, 4, 0, 0, 1, 5])
+            .unwrap();
+        let mut subtype = [0; 4];
+        server.read_exact(&mut subtype).unwrap();
+        server.write_all(&[1]).unwrap();
+        BigEndian::read_u32(&subtype)
+    }
+
+    #[test]
+    fn test_negotiate() {
+        for (password, expected) in [(false, X509_NONE), (true, X509_VNC)] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (mut server, _) = listener.accept().unwrap();
+            let server = thread::spawn(move || fake_server(&mut server));
+
+            assert_eq!(negotiate(&mut client, password).unwrap(), expected);
+            assert_eq!(server.join().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_serve() {
+        // plain tcp stands in for the tls stream
+        let (mut client, local) = local_pair().unwrap();
+        let (remote, mut server) = local_pair().unwrap();
+        remote.set_nonblocking(true).unwrap();
+        let socket = socket_of(&remote);
+        let t = thread::spawn(move || serve(local, remote, socket, SECURITY_VNC));
+
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut version = [0; 12];
+        client.read_exact(&mut version).unwrap();
+        assert_eq!(&version, b"RFB 003.008\n");
+        client.write_all(&version).unwrap();
+        let mut types = [0; 2];
+        client.read_exact(&mut types).unwrap();
+        assert_eq!(types, [1, SECURITY_VNC]);
+        client.write_all(&[SECURITY_VNC]).unwrap();
+
+        let mut buf = [0; 4];
+        client.write_all(b"ping").unwrap();
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        server.write_all(b"pong").unwrap();
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        // server closing tls side closes the client
+        drop(server);
+        t.join().unwrap().unwrap();
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+    }
+}
@@ -1,17 +1,26 @@
-use std::sync::{
-    mpsc::{self, Sender},
-    Arc,
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::AtomicBool,
+        mpsc::{self, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 use t_binding::api::ApiTx;
 use t_config::Config;
-use t_console::SSH;
-use tracing::warn;
+use t_console::{Serial, SSH, VNC};
+use tracing::{error, info, warn};
 
 use crate::{
     error::DriverError,
-    server::{Server, Service},
+    event::EventWriter,
+    metrics::Metrics,
+    server::{OutputHook, Server, Service},
 };
+use parking_lot::Mutex;
 use t_util::AMOption;
 
 pub struct Driver {
@@ -50,6 +59,14 @@ impl Driver {
         }
     }
 
+    // dir logs of this run are written to
+    pub fn log_dir(&self) -> Option<&Path> {
+        self.config
+            .as_ref()
+            .and_then(|c| c.log_dir.as_deref())
+            .map(Path::new)
+    }
+
     pub fn new_ssh(&mut self) -> StdResult<SSH, DriverError> {
         if let Some(ssh) = self.config.as_ref().and_then(|c| c.ssh.clone()) {
             SSH::new(ssh).map_err(DriverError::ConsoleError)
@@ -64,6 +81,12 @@ impl Driver {
 pub struct DriverBuilder {
     pub config: Option<Config>,
     disable_screenshot: bool,
+    events: Option<PathBuf>,
+    on_output: Option<OutputHook>,
+    ssh: Option<SSH>,
+    serial: Option<Serial>,
+    vnc: Option<VNC>,
+    connect_retry: Option<(u32, Duration)>,
 }
 
 type StdResult<T, E> = std::result::Result<T, E>;
@@ -73,6 +96,12 @@ impl DriverBuilder {
         Self {
             config,
             disable_screenshot: false,
+            events: None,
+            on_output: None,
+            ssh: None,
+            serial: None,
+            vnc: None,
+            connect_retry: None,
         }
     }
 
@@ -81,13 +110,62 @@ impl DriverBuilder {
         self
     }
 
-    pub fn build(self) -> StdResult<Driver, DriverError> {
+    // write handled requests as json lines to path, "-" means stdout
+    pub fn with_events(mut self, path: impl Into<PathBuf>) -> Self {
+        self.events = Some(path.into());
+        self
+    }
+
+    // tee live serial and ssh output, called with console name ("serial" or "ssh") and new bytes
+    pub fn with_output(mut self, hook: impl Fn(&str, &[u8]) + Send + Sync + 'static) -> Self {
+        self.on_output = Some(Arc::new(hook));
+        self
+    }
+
+    // use pre-built consoles, e.g. Serial::from_conn or a VNC handle answered by a test thread,
+    // config for the same console is ignored when connecting
+    pub fn with_ssh(mut self, ssh: SSH) -> Self {
+        self.ssh = Some(ssh);
+        self
+    }
+
+    pub fn with_serial(mut self, serial: Serial) -> Self {
+        self.serial = Some(serial);
+        self
+    }
+
+    pub fn with_vnc(mut self, vnc: VNC) -> Self {
+        self.vnc = Some(vnc);
+        self
+    }
+
+    // retry first connect this many more times before build fails, overrides connect_retries
+    // and connect_retry_interval_ms in config
+    pub fn with_connect_retry(mut self, retries: u32, interval: Duration) -> Self {
+        self.connect_retry = Some((retries, interval));
+        self
+    }
+
+    pub fn build(mut self) -> StdResult<Driver, DriverError> {
+        // every driver start writes logs into its own run dir
+        if let Some(c) = self.config.as_mut() {
+            let run_dir = c.start_run().map_err(DriverError::IO)?;
+            info!(msg = "logs saved to", dir = %run_dir.display());
+        }
+
         // init api request channel
         let (msg_tx, msg_rx) = mpsc::channel();
 
         // init stop tx
         let (stop_tx, stop_rx) = mpsc::channel();
 
+        let events = self
+            .events
+            .as_ref()
+            .map(EventWriter::new)
+            .transpose()
+            .map_err(DriverError::IO)?;
+
         let server = Server {
             msg_rx,
             stop_rx,
@@ -98,15 +176,67 @@ impl DriverBuilder {
                 ssh: AMOption::new(None),
                 serial: AMOption::new(None),
                 vnc: AMOption::new(None),
+                events,
+                on_output: self.on_output,
+                interrupted: AtomicBool::new(false),
+                soft_assert: AtomicBool::new(false),
+                failures: Mutex::new(Vec::new()),
+                log_saver: Mutex::new(None),
+                last_done: Mutex::new(Instant::now()),
+                metrics: Metrics::default(),
+                needles: Mutex::new(None),
             }),
         };
 
+        if let Some(addr) = self.config.as_ref().and_then(|c| c.metrics_addr.clone()) {
+            #[cfg(feature = "metrics")]
+            crate::metrics::serve(&addr, server.repo.clone()).map_err(DriverError::IO)?;
+            #[cfg(not(feature = "metrics"))]
+            warn!(
+                msg = "metrics_addr ignored, built without metrics feature",
+                addr = addr
+            );
+        }
+
         // try connect for the first time
         if let Some(ref c) = self.config {
-            server
-                .repo
-                .connect_with_config(c.clone())
-                .map_err(DriverError::ConsoleError)?;
+            let mut c = c.clone();
+            if self.ssh.is_some() {
+                c.ssh = None;
+            }
+            if self.serial.is_some() {
+                c.serial = None;
+            }
+            if self.vnc.is_some() {
+                c.vnc = None;
+            }
+            let (retries, interval) = self
+                .connect_retry
+                .unwrap_or_else(|| (c.connect_retries.unwrap_or(0), c.connect_retry_interval()));
+            let mut attempt = 0;
+            while let Err(e) = server.repo.connect_with_config(c.clone()) {
+                attempt += 1;
+                if attempt > retries {
+                    error!(msg = "connect failed, give up", attempts = attempt, reason = %e);
+                    return Err(DriverError::ConsoleError(e));
+                }
+                warn!(
+                    msg = "connect failed, retry",
+                    attempt = attempt,
+                    retries = retries,
+                    reason = %e
+                );
+                thread::sleep(interval);
+            }
+        }
+        if let Some(ssh) = self.ssh {
+            server.repo.set_ssh(ssh);
+        }
+        if let Some(serial) = self.serial {
+            server.repo.set_serial(serial);
+        }
+        if let Some(vnc) = self.vnc {
+            server.repo.vnc.set(Some(vnc));
         }
 
         let driver = Driver {
@@ -118,3 +248,864 @@ impl DriverBuilder {
         Ok(driver)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        collections::VecDeque,
+        io::{self, Read, Write},
+        sync::atomic::Ordering,
+        thread,
+        time::Duration,
+    };
+    use t_binding::{
+        api::{ActionArg, Api, RustApi},
+        msg, ApiError, MsgReq, MsgRes, MsgResError, TextConsole,
+    };
+    use t_config::ConsoleSerial;
+    use t_console::{Conn, VNCEventReq, VNCEventRes, VNCStats, PNG};
+
+    // loopback connection, everything written can be read back
+    #[derive(Clone, Default)]
+    struct Loopback(Arc<Mutex<VecDeque<u8>>>);
+
+    impl Read for Loopback {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut inner = self.0.lock();
+            let n = buf.len().min(inner.len());
+            for (i, b) in inner.drain(..n).enumerate() {
+                buf[i] = b;
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for Loopback {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Conn for Loopback {}
+
+    // link which fails on write, like an unplugged usb serial adapter
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("device gone"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Conn for Broken {}
+
+    // serial console on a fresh loopback connection
+    fn loopback_serial() -> Serial {
+        let conn = Loopback::default();
+        serial_on(move || Ok(conn.clone()))
+    }
+
+    fn serial_on<T: Conn>(connect: impl Fn() -> t_console::Result<T> + Send + 'static) -> Serial {
+        Serial::from_conn(
+            ConsoleSerial {
+                serial_file: "".to_string(),
+                bund_rate: None,
+                r#type: None,
+                disable_echo: None,
+                linebreak: None,
+                rows: None,
+                cols: None,
+                login: None,
+                log_file_max_mb: None,
+                log_file: None,
+            },
+            connect,
+        )
+        .unwrap()
+    }
+
+    // vnc handle answered by a thread instead of a real server, refresh completes one frame
+    fn fake_vnc() -> VNC {
+        fake_vnc_shots().0
+    }
+
+    // like fake_vnc, with names of screenshots taken
+    fn fake_vnc_shots() -> (VNC, Arc<Mutex<Vec<String>>>) {
+        fake_vnc_screen(Some(PNG::new(2, 2, 3)))
+    }
+
+    // like fake_vnc_shots, none answers screenshot requests like a server which sent no frame yet
+    fn fake_vnc_screen(screen: Option<PNG>) -> (VNC, Arc<Mutex<Vec<String>>>) {
+        let screen = screen.map(Arc::new);
+        let shots = Arc::new(Mutex::new(Vec::new()));
+        let taken = shots.clone();
+        let (event_tx, event_rx) = mpsc::channel::<(VNCEventReq, Sender<VNCEventRes>)>();
+        let (stop_tx, stop_rx) = mpsc::channel::<Sender<()>>();
+        let stats = Arc::new(VNCStats::default());
+        let frames = stats.clone();
+        // like a server, the refreshed frame arrives a bit after the request is answered
+        let mut frame_at = None;
+        // resized by set desktop size, screenshots stay 2x2
+        let mut size = (2, 2);
+        thread::spawn(move || loop {
+            if let Ok(tx) = stop_rx.try_recv() {
+                tx.send(()).ok();
+                break;
+            }
+            if frame_at.is_some_and(|at| Instant::now() >= at) {
+                frame_at = None;
+                frames.frames.fetch_add(1, Ordering::Relaxed);
+            }
+            if let Ok((req, tx)) = event_rx.recv_timeout(Duration::from_millis(10)) {
+                if matches!(req, VNCEventReq::Refresh) {
+                    frame_at = Some(Instant::now() + Duration::from_millis(20));
+                }
+                tx.send(match req {
+                    VNCEventReq::GetMousePos => VNCEventRes::MousePos(3, 4),
+                    VNCEventReq::GetSize => VNCEventRes::Size(size.0, size.1),
+                    VNCEventReq::SetDesktopSize(0, _) | VNCEventReq::SetDesktopSize(_, 0) => {
+                        VNCEventRes::DesktopSize(Err("invalid screen layout".to_string()))
+                    }
+                    VNCEventReq::SetDesktopSize(w, h) => {
+                        size = (w, h);
+                        VNCEventRes::DesktopSize(Ok(()))
+                    }
+                    VNCEventReq::GetScreenShot => match &screen {
+                        Some(s) => VNCEventRes::Screen(s.clone()),
+                        None => VNCEventRes::NoConnection,
+                    },
+                    // us keymap
+                    VNCEventReq::TypeString(s) | VNCEventReq::TypePassword(s) if !s.is_ascii() => {
+                        VNCEventRes::Error("unmappable character".to_string())
+                    }
+                    VNCEventReq::TakeScreenShot(name, _) => {
+                        taken.lock().push(name);
+                        VNCEventRes::Done
+                    }
+                    _ => VNCEventRes::Done,
+                })
+                .ok();
+            }
+        });
+        let vnc = VNC {
+            event_tx,
+            stop_tx,
+            stats,
+        };
+        (vnc, shots)
+    }
+
+    #[test]
+    fn test_auto_screenshot() {
+        let config = Config::from_toml_str("auto_screenshot_secs = 1").unwrap();
+        let (vnc, shots) = fake_vnc_shots();
+        let mut d = DriverBuilder::new(Some(config))
+            .with_vnc(vnc)
+            .build()
+            .unwrap();
+        d.start();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while shots.lock().len() < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+        d.stop();
+        let shots = shots.lock();
+        assert!(shots.len() >= 2, "{shots:?}");
+        assert!(shots.iter().all(|name| name == "auto"));
+    }
+
+    #[test]
+    fn test_injected_consoles() {
+        let serial = loopback_serial();
+
+        let mut d = DriverBuilder::new(None)
+            .with_serial(serial)
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        assert_eq!(api.vnc_mouse_pos().unwrap(), (3, 4));
+        api.vnc_refresh().unwrap();
+        assert_eq!(api.vnc_refresh_and_wait(1).unwrap().width, 2);
+        api.write("hello".to_string()).unwrap();
+        api.wait_string("hello".to_string(), 1).unwrap();
+
+        d.stop();
+    }
+
+    #[test]
+    fn test_resolution() {
+        let mut d = DriverBuilder::new(None)
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        assert_eq!(api.vnc_get_resolution().unwrap(), (2, 2));
+        api.vnc_set_resolution(4, 3).unwrap();
+        assert_eq!(api.vnc_get_resolution().unwrap(), (4, 3));
+        // refused by server, size is kept
+        assert!(api.vnc_set_resolution(0, 3).is_err());
+        assert_eq!(api.vnc_get_resolution().unwrap(), (4, 3));
+
+        d.stop();
+    }
+
+    #[test]
+    fn test_send_key_unknown() {
+        let mut d = DriverBuilder::new(None)
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        api.vnc_send_key("ctrl-c".to_string()).unwrap();
+        assert!(matches!(
+            api.vnc_send_key("crtl-c".to_string()),
+            Err(ApiError::String(e)) if e == "unknown key: crtl"
+        ));
+
+        // keypad 1, not in the key name table
+        api.vnc_send_keysym(vec![0xffb1]).unwrap();
+        assert!(matches!(
+            api.vnc_send_keysym(vec![]),
+            Err(ApiError::String(e)) if e == "no keysym given"
+        ));
+
+        d.stop();
+    }
+
+    #[test]
+    fn test_batch() {
+        let mut d = DriverBuilder::new(None)
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        let res = api
+            .batch(vec![
+                MsgReq::VNC(msg::VNC::MouseMove { x: 1, y: 1 }),
+                MsgReq::VNC(msg::VNC::GetMousePos),
+                MsgReq::VNC(msg::VNC::SendKey("crtl-c".to_string())),
+                MsgReq::VNC(msg::VNC::MouseClick(1)),
+            ])
+            .unwrap();
+        assert_eq!(res.len(), 3);
+        assert!(matches!(res[0], MsgRes::Done));
+        assert!(matches!(res[1], MsgRes::MousePos(3, 4)));
+        assert!(matches!(res[2], MsgRes::Error(MsgResError::String(_))));
+
+        let s = |s: &str| ActionArg::Str(s.to_string());
+        api.batch_actions(vec![
+            vec![s("mouse_move"), ActionArg::Int(1), ActionArg::Int(2)],
+            vec![s("mouse_click")],
+            vec![s("send_key"), s("ret")],
+        ])
+        .unwrap();
+        assert!(matches!(
+            api.batch_actions(vec![vec![s("send_key"), s("crtl-c")]]),
+            Err(ApiError::String(e)) if e == "unknown key: crtl"
+        ));
+        assert!(matches!(
+            api.batch_actions(vec![vec![s("mouse_click")], vec![s("nope")]]),
+            Err(ApiError::String(e)) if e == "batch action 1: nope can't be batched"
+        ));
+
+        d.stop();
+    }
+
+    #[test]
+    fn test_wait_boot() {
+        // fake vnc screen is 2x2 black
+        let (_, config) = needle_config("boot", &[("desktop", 0), ("white", 255)]);
+        let serial = loopback_serial();
+        let mut d = DriverBuilder::new(Some(config))
+            .with_serial(serial)
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        let boot = |serial: Option<&str>, screen: Option<&str>| {
+            api.wait_boot(serial.map(Into::into), screen.map(Into::into), 1)
+        };
+        assert_eq!(boot(None, Some("desktop")).unwrap(), "screen");
+        api.write("localhost login: ".to_string()).unwrap();
+        assert_eq!(boot(Some("login:\\s*$"), Some("white")).unwrap(), "serial");
+        assert!(matches!(
+            boot(Some("login:"), Some("white")),
+            Err(ApiError::Timeout)
+        ));
+        assert!(matches!(
+            boot(Some("("), None),
+            Err(ApiError::RegexBuild(_))
+        ));
+        assert!(matches!(
+            boot(None, Some("missing")),
+            Err(ApiError::String(_))
+        ));
+
+        d.stop();
+    }
+
+    #[test]
+    fn test_bell() {
+        let vnc = fake_vnc();
+        let stats = vnc.stats.clone();
+        let mut d = DriverBuilder::new(None).with_vnc(vnc).build().unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        assert_eq!(api.vnc_bell_count().unwrap(), 0);
+        let ring = stats.clone();
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            ring.bells.fetch_add(1, Ordering::Relaxed);
+        });
+        api.vnc_wait_bell(2).unwrap();
+        t.join().unwrap();
+        assert_eq!(api.vnc_bell_count().unwrap(), 1);
+        // rung before wait doesn't count
+        assert!(matches!(api.vnc_wait_bell(0), Err(ApiError::Timeout)));
+
+        d.stop();
+    }
+
+    #[test]
+    fn test_connect_retry() {
+        // config alone would retry for 10s, the override gives up after 2 retries of 100ms
+        let config = Config::from_toml_str(
+            "connect_retries = 5\nconnect_retry_interval_ms = 2000\n[serial]\nserial_file = \"/nonexistent/ttyS0\"",
+        )
+        .unwrap();
+        let start = Instant::now();
+        let res = DriverBuilder::new(Some(config))
+            .with_connect_retry(2, Duration::from_millis(100))
+            .build();
+        assert!(matches!(res, Err(DriverError::ConsoleError(_))));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_secs(5));
+    }
+
+    // 2x2 single color needles in a temp dir, with a vnc config using it
+    fn needle_config(name: &str, needles: &[(&str, u8)]) -> (std::path::PathBuf, Config) {
+        let dir = std::env::temp_dir().join(format!("t-runner-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let area = r#"{"areas": [{"type": "match", "left": 0, "top": 0, "width": 2, "height": 2}],
+            "properties": [], "tags": []}"#;
+        for (tag, pixel) in needles {
+            let png = PNG::new_with_data(2, 2, vec![*pixel; 12], 3);
+            std::fs::write(dir.join(format!("{tag}.png")), png.to_png_bytes()).unwrap();
+            std::fs::write(dir.join(format!("{tag}.json")), area).unwrap();
+        }
+        let config = Config::from_toml_str(&format!(
+            "[vnc]\nhost = \"127.0.0.1\"\nneedle_dir = {:?}",
+            dir.to_str().unwrap()
+        ))
+        .unwrap();
+        (dir, config)
+    }
+
+    #[test]
+    fn test_paste_string() {
+        // paste_key is only parsed when clipboard is used
+        let config = Config::from_toml_str(
+            "[vnc]\nhost = \"127.0.0.1\"\nclipboard = false\npaste_key = \"bogus\"",
+        )
+        .unwrap();
+        let mut d = DriverBuilder::new(Some(config))
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+        let api = RustApi::new(d.msg_tx.clone());
+        api.vnc_paste_string("hello".to_string()).unwrap();
+        // typed instead, text not on keymap is an error and console keeps working
+        assert!(matches!(
+            api.vnc_paste_string("key = \"värde\"".to_string()),
+            Err(ApiError::String(_))
+        ));
+        assert_eq!(api.vnc_mouse_pos().unwrap(), (3, 4));
+        d.stop();
+
+        let config = Config::from_toml_str(
+            "[vnc]\nhost = \"127.0.0.1\"\nclipboard = true\npaste_key = \"bogus\"",
+        )
+        .unwrap();
+        let mut d = DriverBuilder::new(Some(config))
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+        let api = RustApi::new(d.msg_tx.clone());
+        assert!(api.vnc_paste_string("hello".to_string()).is_err());
+        d.stop();
+    }
+
+    #[test]
+    fn test_type_password() {
+        let mut d = DriverBuilder::new(None)
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+        let api = RustApi::new(d.msg_tx.clone());
+        api.vnc_type_password("secret".to_string()).unwrap();
+        match api.vnc_type_password("geheimnisß".to_string()) {
+            Err(ApiError::String(e)) => assert!(!e.contains("geheim"), "{e}"),
+            res => panic!("{res:?}"),
+        }
+        assert_eq!(api.vnc_mouse_pos().unwrap(), (3, 4));
+        d.stop();
+    }
+
+    #[test]
+    fn test_wait_screen_still() {
+        let mut d = DriverBuilder::new(None)
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+        let api = RustApi::new(d.msg_tx.clone());
+        api.vnc_wait_screen_still(2, 0).unwrap();
+        d.stop();
+
+        // server never sends a frame, gives up instead of waiting forever
+        let mut d = DriverBuilder::new(None)
+            .with_vnc(fake_vnc_screen(None).0)
+            .build()
+            .unwrap();
+        d.start();
+        let api = RustApi::new(d.msg_tx.clone());
+        assert!(matches!(
+            api.vnc_wait_screen_still(1, 0),
+            Err(ApiError::Timeout)
+        ));
+        d.stop();
+    }
+
+    #[test]
+    fn test_wait_screen_gone() {
+        // fake vnc screen is 2x2 black
+        let (dir, config) = needle_config("gone", &[("spinner", 255), ("blank", 0)]);
+        let mut d = DriverBuilder::new(Some(config.clone()))
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        api.vnc_wait_screen_gone("spinner".to_string(), 2, 0)
+            .unwrap();
+        let err = api
+            .vnc_wait_screen_gone("blank".to_string(), 1, 0)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("still on screen"), "{err}");
+        assert!(api
+            .vnc_wait_screen_gone("missing".to_string(), 1, 0)
+            .is_err());
+        d.stop();
+
+        // no frame, the needle is not known to be gone
+        let mut d = DriverBuilder::new(Some(config))
+            .with_vnc(fake_vnc_screen(None).0)
+            .build()
+            .unwrap();
+        d.start();
+        let api = RustApi::new(d.msg_tx.clone());
+        assert!(matches!(
+            api.vnc_wait_screen_gone("spinner".to_string(), 1, 0),
+            Err(ApiError::Timeout)
+        ));
+
+        d.stop();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_assert_no_screen() {
+        let (dir, config) = needle_config("noscreen", &[("error_dialog", 255), ("blank", 0)]);
+        let mut d = DriverBuilder::new(Some(config.clone()))
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        api.vnc_assert_no_screen("error_dialog".to_string(), 1)
+            .unwrap();
+        assert!(matches!(
+            api.vnc_assert_no_screen("blank".to_string(), 10),
+            Err(ApiError::AssertFailed)
+        ));
+        assert!(matches!(
+            api.vnc_assert_no_screen("missing".to_string(), 1),
+            Err(ApiError::String(_))
+        ));
+        d.stop();
+
+        let mut d = DriverBuilder::new(Some(config))
+            .with_vnc(fake_vnc_screen(None).0)
+            .build()
+            .unwrap();
+        d.start();
+        let api = RustApi::new(d.msg_tx.clone());
+        assert!(matches!(
+            api.vnc_assert_no_screen("error_dialog".to_string(), 1),
+            Err(ApiError::String(e)) if e.contains("no frame received")
+        ));
+
+        d.stop();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_check_image() {
+        // fake vnc screen is 2x2 black
+        let (dir, config) = needle_config("image", &[("black", 0)]);
+        let big = dir.join("big.png");
+        std::fs::write(&big, PNG::new(4, 4, 3).to_png_bytes()).unwrap();
+        let mut d = DriverBuilder::new(Some(config))
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        let image = dir.join("black.png").to_str().unwrap().to_string();
+        assert!(api
+            .vnc_check_image(image.clone(), Some((1, 1, 1, 1)), 0.95, 1)
+            .unwrap());
+        // bad region or size fails at once instead of panicking or retrying
+        let start = Instant::now();
+        assert!(!api
+            .vnc_check_image(image.clone(), Some((1, 1, 2, 2)), 0.95, 10)
+            .unwrap());
+        assert!(!api
+            .vnc_check_image(image, Some((0, u16::MAX, 1, 2)), 0.95, 10)
+            .unwrap());
+        let big = big.to_str().unwrap().to_string();
+        assert!(!api.vnc_check_image(big, None, 0.95, 10).unwrap());
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        d.stop();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_golden_screen() {
+        // fake vnc screen is 2x2 black
+        let (dir, mut config) = needle_config("golden", &[("white", 255)]);
+        let mut d = DriverBuilder::new(Some(config.clone()))
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        api.vnc_golden_screen("black".to_string(), 1).unwrap();
+        assert!(dir.join("black.png").exists());
+        api.vnc_golden_screen("black".to_string(), 1).unwrap();
+        assert!(matches!(
+            api.vnc_golden_screen("white".to_string(), 1),
+            Err(ApiError::AssertFailed)
+        ));
+        d.stop();
+
+        // recapture instead of assert
+        config.env = Some(
+            [("UPDATE_GOLDEN".to_string(), toml::Value::Boolean(true))]
+                .into_iter()
+                .collect(),
+        );
+        let mut d = DriverBuilder::new(Some(config))
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+        let api = RustApi::new(d.msg_tx.clone());
+        api.vnc_golden_screen("white".to_string(), 1).unwrap();
+        assert!(api.vnc_check_screen("white".to_string(), 1).unwrap());
+
+        d.stop();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_get_env() {
+        let config = Config::from_toml_str(
+            r#"
+            [env]
+            NAME = "bob"
+            RETRIES = 3
+            DEBUG = true
+            PORT = " 22 "
+            FAST = "false"
+        "#,
+        )
+        .unwrap();
+        let mut d = DriverBuilder::new(Some(config)).build().unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        let env = |key: &str| api.get_env(key.to_string()).unwrap();
+        // strings are not quoted, other values in toml form
+        assert_eq!(env("NAME").as_deref(), Some("bob"));
+        assert_eq!(env("RETRIES").as_deref(), Some("3"));
+        assert_eq!(env("DEBUG").as_deref(), Some("true"));
+        assert_eq!(env("MISSING"), None);
+
+        let int = |key: &str| api.get_env_int(key.to_string());
+        assert_eq!(int("RETRIES").unwrap(), Some(3));
+        assert_eq!(int("PORT").unwrap(), Some(22));
+        assert_eq!(int("MISSING").unwrap(), None);
+        assert!(matches!(int("NAME"), Err(ApiError::InvalidEnvType(_))));
+        assert!(matches!(int("DEBUG"), Err(ApiError::InvalidEnvType(_))));
+
+        let bool = |key: &str| api.get_env_bool(key.to_string());
+        assert_eq!(bool("DEBUG").unwrap(), Some(true));
+        assert_eq!(bool("FAST").unwrap(), Some(false));
+        assert_eq!(bool("MISSING").unwrap(), None);
+        assert!(matches!(bool("RETRIES"), Err(ApiError::InvalidEnvType(_))));
+
+        d.stop();
+    }
+
+    #[test]
+    fn test_needle_queries() {
+        let (dir, config) = needle_config("needles", &[("lang_de_banner", 0), ("login", 0)]);
+        let mut d = DriverBuilder::new(Some(config))
+            .with_vnc(fake_vnc())
+            .build()
+            .unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        assert_eq!(
+            api.list_needles().unwrap(),
+            vec!["lang_de_banner".to_string(), "login".to_string()]
+        );
+        assert!(api.needle_exists("lang_de_banner".to_string()).unwrap());
+        assert!(!api.needle_exists("lang_fr_banner".to_string()).unwrap());
+
+        d.stop();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_dump_history() {
+        let log_dir = std::env::temp_dir().join(format!("t-runner-dump-{}", std::process::id()));
+        let config = Config::from_toml_str(&format!("log_dir = {:?}", log_dir)).unwrap();
+        let serial = loopback_serial();
+        let mut d = DriverBuilder::new(Some(config))
+            .with_serial(serial)
+            .build()
+            .unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        api.write("hello".to_string()).unwrap();
+        api.wait_string("hello".to_string(), 2).unwrap();
+        api.write("world".to_string()).unwrap();
+        d.stop();
+
+        // unread output is dumped too
+        let history =
+            std::fs::read_to_string(d.log_dir().unwrap().join("serial_full_log.txt")).unwrap();
+        assert!(history.contains("hello"));
+        assert!(history.contains("world"));
+        assert!(!d.log_dir().unwrap().join("ssh_full_log.txt").exists());
+
+        let _ = std::fs::remove_dir_all(&log_dir);
+    }
+
+    #[test]
+    fn test_idle_watchdog() {
+        let serial = loopback_serial();
+        let config = Config::from_toml_str("max_idle_secs = 1").unwrap();
+        let mut d = DriverBuilder::new(Some(config))
+            .with_serial(serial)
+            .build()
+            .unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        let start = std::time::Instant::now();
+        assert!(matches!(
+            api.wait_string("never".to_string(), 4),
+            Err(ApiError::Timeout)
+        ));
+        assert!(start.elapsed() < Duration::from_secs(3));
+        // server still answers, the aborted wait keeps serial busy until its own timeout
+        assert_eq!(api.get_env("A".to_string()).unwrap(), None);
+        d.stop();
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let serial = loopback_serial;
+
+        // nothing to reconnect from without config
+        let mut d = DriverBuilder::new(None)
+            .with_serial(serial())
+            .build()
+            .unwrap();
+        d.start();
+        let api = RustApi::new(d.msg_tx.clone());
+        assert!(api.reconnect().is_err());
+        d.stop();
+
+        // consoles are rebuilt from config, which has no serial
+        let config = Config::from_toml_str("").unwrap();
+        let mut d = DriverBuilder::new(Some(config))
+            .with_serial(serial())
+            .build()
+            .unwrap();
+        d.start();
+        let api = RustApi::new(d.msg_tx.clone());
+        api.write("hello".to_string()).unwrap();
+        assert!(api.reconnect().unwrap().is_empty());
+        assert!(api.write("hello".to_string()).is_err());
+        d.stop();
+    }
+
+    #[test]
+    fn test_assert_script_run_console() {
+        let serial = loopback_serial();
+        let mut d = DriverBuilder::new(None)
+            .with_serial(serial)
+            .build()
+            .unwrap();
+        d.start();
+
+        // asked for ssh, serial is not used instead, error names the console asked for
+        let api = RustApi::new(d.msg_tx.clone());
+        let ssh = Some(TextConsole::SSH);
+        let no_ssh =
+            |res: Result<_, ApiError>| matches!(res, Err(ApiError::NoConsole(c)) if c == "ssh");
+        assert!(no_ssh(
+            api.assert_script_run_console("true".to_string(), ssh, 1)
+                .map(|_| ())
+        ));
+        assert!(no_ssh(api._write("a".to_string(), ssh)));
+        assert!(no_ssh(api._wait_string(ssh, "a".to_string(), 1)));
+        assert!(no_ssh(api._wait_regex(ssh, "a".to_string(), 1).map(|_| ())));
+        assert!(no_ssh(api._expect(ssh, vec![], 1)));
+        d.stop();
+
+        // no console asked and none connected
+        let mut d = DriverBuilder::new(None).build().unwrap();
+        d.start();
+        let api = RustApi::new(d.msg_tx.clone());
+        assert!(matches!(
+            api._write("a".to_string(), None),
+            Err(ApiError::NoConsole(c)) if c == "ssh or serial"
+        ));
+        d.stop();
+    }
+
+    #[test]
+    fn test_console_errors() {
+        let mut d = DriverBuilder::new(None)
+            .with_serial(serial_on(|| Ok(Broken)))
+            .build()
+            .unwrap();
+        d.start();
+        let api = RustApi::new(d.msg_tx.clone());
+
+        // broken link is not a timeout
+        let res = api.write("hello".to_string());
+        assert!(matches!(res, Err(ApiError::ConnectionBroken(_))), "{res:?}");
+        let res = api.assert_script_run("true".to_string(), 1);
+        assert!(matches!(res, Err(ApiError::ConnectionBroken(_))), "{res:?}");
+        let res = api.ssh_assert_script_run_seperate("true".to_string(), 1);
+        assert!(
+            matches!(res, Err(ApiError::NoConsole(ref c)) if c == "ssh"),
+            "{res:?}"
+        );
+
+        d.stop();
+    }
+
+    #[test]
+    fn test_expect() {
+        let serial = loopback_serial();
+        let mut d = DriverBuilder::new(None)
+            .with_serial(serial)
+            .build()
+            .unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        api.write("login:".to_string()).unwrap();
+        // loopback echoes each response, which is the next prompt
+        let steps = vec![
+            ("login:".to_string(), "Password:".to_string()),
+            ("Password:".to_string(), "$ ".to_string()),
+        ];
+        api.expect(steps, 2).unwrap();
+        api.wait_string("$ ".to_string(), 1).unwrap();
+
+        api.write("ok".to_string()).unwrap();
+        let steps = vec![
+            ("ok".to_string(), "".to_string()),
+            ("never".to_string(), "".to_string()),
+        ];
+        let err = api.expect(steps, 1).unwrap_err().to_string();
+        assert!(err.contains("step 1"), "{err}");
+
+        d.stop();
+    }
+
+    #[test]
+    fn test_wait_any_string() {
+        let serial = loopback_serial();
+        let mut d = DriverBuilder::new(None)
+            .with_serial(serial)
+            .build()
+            .unwrap();
+        d.start();
+
+        let api = RustApi::new(d.msg_tx.clone());
+        api.write("Kernel panic".to_string()).unwrap();
+        // no ssh, its targets are ignored
+        let targets = vec![
+            (TextConsole::SSH, "panic".to_string()),
+            (TextConsole::Serial, "login:".to_string()),
+            (TextConsole::Serial, "panic".to_string()),
+        ];
+        assert_eq!(
+            api.wait_any_string(targets.clone(), 2).unwrap(),
+            (TextConsole::Serial, "panic".to_string())
+        );
+        // buffer consumed
+        assert!(api.wait_any_string(targets, 1).is_err());
+
+        d.stop();
+    }
+}
@@ -3,6 +3,7 @@ use crate::engine::EngineClient;
 use crate::error::DriverError;
 use crate::Driver;
 use crate::DriverBuilder;
+use std::path::{Path, PathBuf};
 use std::thread;
 use t_config::Config;
 use t_console::SSH;
@@ -16,8 +17,12 @@ pub struct DriverForScript {
 type Result<T> = std::result::Result<T, DriverError>;
 
 impl DriverForScript {
-    fn new(config: Config) -> Result<Self> {
-        let driver = DriverBuilder::new(Some(config.clone())).build()?;
+    fn new(config: Config, events: Option<PathBuf>) -> Result<Self> {
+        let mut builder = DriverBuilder::new(Some(config.clone()));
+        if let Some(events) = events {
+            builder = builder.with_events(events);
+        }
+        let driver = builder.build()?;
 
         Ok(Self {
             driver,
@@ -26,8 +31,8 @@ impl DriverForScript {
         })
     }
 
-    pub fn new_with_engine(config: Config, ext: &str) -> Result<Self> {
-        let mut res = Self::new(config)?;
+    pub fn new_with_engine(config: Config, ext: &str, events: Option<PathBuf>) -> Result<Self> {
+        let mut res = Self::new(config, events)?;
         let (engine, enginec) = Engine::new(ext, res.driver.msg_tx.clone());
         res.engine = Some(engine);
         res.engine_client = Some(enginec);
@@ -67,6 +72,10 @@ impl DriverForScript {
         self
     }
 
+    pub fn log_dir(&self) -> Option<&Path> {
+        self.driver.log_dir()
+    }
+
     pub fn new_ssh(&mut self) -> Result<SSH> {
         if let Some(ssh) = self.driver.config.as_ref().and_then(|c| c.ssh.clone()) {
             SSH::new(ssh.clone()).map_err(DriverError::ConsoleError)
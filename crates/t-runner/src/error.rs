@@ -7,6 +7,7 @@ use t_console::ConsoleError;
 pub enum DriverError {
     ConsoleError(ConsoleError),
     ApiError(ApiError),
+    IO(std::io::Error),
 }
 
 // impl Error for DriverError {};
@@ -15,6 +16,7 @@ impl Display for DriverError {
         match self {
             DriverError::ConsoleError(e) => write!(f, "console error, {}", e),
             DriverError::ApiError(e) => write!(f, "api error, {}", e),
+            DriverError::IO(e) => write!(f, "io error, {}", e),
         }
     }
 }
@@ -0,0 +1,268 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use t_binding::{msg::VNC, MsgReq, MsgRes};
+use tracing::warn;
+
+// one handled request, written as a single json line
+#[derive(Debug, Serialize)]
+pub(crate) struct Event {
+    #[serde(skip)]
+    start: Instant,
+    // unix timestamp in millis when request received
+    pub ts: u64,
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub similarity: Option<f32>,
+    // screenshots compared by a matched check screen
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iterations: Option<u32>,
+    // time to match, duration_ms also counts click, delay and screenshot after
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elapsed_ms: Option<u64>,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Event {
+    pub fn start(req: &MsgReq) -> Self {
+        let (kind, tag) = req_kind(req);
+        Self {
+            start: Instant::now(),
+            ts: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+            kind,
+            tag,
+            result: "ok",
+            similarity: None,
+            iterations: None,
+            elapsed_ms: None,
+            duration_ms: 0,
+            error: None,
+        }
+    }
+
+    pub fn finish(&mut self, res: &MsgRes, similarity: Option<f32>) {
+        self.error = match res {
+            MsgRes::Error(e) => Some(e.to_string()),
+            _ => None,
+        };
+        self.result = if self.error.is_some() { "error" } else { "ok" };
+        self.similarity = similarity;
+        if let MsgRes::ScreenMatched { stats, .. } = res {
+            self.iterations = Some(stats.iterations);
+            self.elapsed_ms = Some(stats.elapsed.as_millis() as u64);
+        }
+        self.duration_ms = self.start.elapsed().as_millis() as u64;
+    }
+}
+
+pub(crate) struct EventWriter {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl EventWriter {
+    // "-" means stdout
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let out: Box<dyn Write + Send> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            Box::new(fs::File::create(path)?)
+        };
+        Ok(Self {
+            out: Mutex::new(out),
+        })
+    }
+
+    pub fn write(&self, event: &Event) {
+        let Ok(line) = serde_json::to_string(event) else {
+            return;
+        };
+        let mut out = self.out.lock();
+        if let Err(e) = writeln!(out, "{line}").and_then(|_| out.flush()) {
+            warn!(msg = "write event failed", reason = ?e);
+        }
+    }
+}
+
+pub(crate) fn req_kind(req: &MsgReq) -> (&'static str, Option<String>) {
+    match req {
+        MsgReq::SetConfig { .. } => ("set_config", None),
+        MsgReq::GetConfig { key } => ("get_config", Some(key.clone())),
+        MsgReq::GetDefaultTimeout => ("get_default_timeout", None),
+        MsgReq::GetDefaultThreshold => ("get_default_threshold", None),
+        MsgReq::Reconnect => ("reconnect", None),
+        MsgReq::SetSoftAssert(_) => ("set_soft_assert", None),
+        MsgReq::AssertFailed { msg, .. } => ("assert_failed", Some(msg.clone())),
+        MsgReq::AssertPassed => ("assert_passed", None),
+        MsgReq::CollectFailures { .. } => ("collect_failures", None),
+        MsgReq::ListNeedles => ("list_needles", None),
+        MsgReq::NeedleExists { tag } => ("needle_exists", Some(tag.clone())),
+        MsgReq::SerialSendBreak { .. } => ("serial_send_break", None),
+        MsgReq::SSHScriptRunSeperate { cmd, .. } => ("ssh_script_run_seperate", Some(cmd.clone())),
+        MsgReq::ScriptRun { cmd, .. } => ("script_run", Some(cmd.clone())),
+        MsgReq::ScriptRunFull { cmd, .. } => ("script_run_full", Some(cmd.clone())),
+        MsgReq::WriteString { secret: true, .. } => ("write_password", Some("****".to_string())),
+        MsgReq::WriteString { s, .. } => ("write_string", Some(s.clone())),
+        MsgReq::WaitString { s, .. } => ("wait_string", Some(s.clone())),
+        MsgReq::WaitRegex { pattern, .. } => ("wait_regex", Some(pattern.clone())),
+        MsgReq::Expect { steps, .. } => (
+            "expect",
+            Some(
+                steps
+                    .iter()
+                    .map(|(pattern, _)| pattern.as_str())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+        ),
+        MsgReq::WaitAnyString { targets, .. } => (
+            "wait_any_string",
+            Some(
+                targets
+                    .iter()
+                    .map(|(console, s)| format!("{}:{s}", console.name()))
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+        ),
+        MsgReq::WaitBoot { serial, screen, .. } => (
+            "wait_boot",
+            Some(
+                [("serial", serial), ("screen", screen)]
+                    .iter()
+                    .filter_map(|(name, s)| s.as_ref().map(|s| format!("{name}:{s}")))
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+        ),
+        MsgReq::Batch(reqs) => ("batch", Some(reqs.len().to_string())),
+        MsgReq::VNC(req) => match req {
+            VNC::TakeScreenShot => ("take_screenshot", None),
+            VNC::GetScreenShot => ("get_screenshot", None),
+            VNC::GetScreenSize => ("get_screen_size", None),
+            VNC::SetScreenSize { width, height } => {
+                ("set_screen_size", Some(format!("{width}x{height}")))
+            }
+            VNC::ConnectionState => ("vnc_connection_state", None),
+            VNC::BellCount => ("bell_count", None),
+            VNC::WaitBell { .. } => ("wait_bell", None),
+            VNC::Refresh => ("refresh", None),
+            VNC::RefreshAndWait { .. } => ("refresh_and_wait", None),
+            VNC::CheckScreen { tags, .. } => ("check_screen", Some(tags.join(","))),
+            VNC::CheckImage { path, .. } => ("check_image", Some(path.clone())),
+            VNC::MouseMove { .. } => ("mouse_move", None),
+            VNC::MouseMoveRelative { .. } => ("mouse_move_rel", None),
+            VNC::GetMousePos => ("mouse_pos", None),
+            VNC::MouseDrag { .. } => ("mouse_drag", None),
+            VNC::MouseHide => ("mouse_hide", None),
+            VNC::WaitStill { .. } => ("wait_screen_still", None),
+            VNC::WaitGone { tag, .. } => ("wait_screen_gone", Some(tag.clone())),
+            VNC::AssertNoScreen { tag, .. } => ("assert_no_screen", Some(tag.clone())),
+            VNC::SaveGolden { tag } => ("golden_screen", Some(tag.clone())),
+            VNC::MouseClick(1) => ("mouse_click", None),
+            VNC::MouseClick(2) => ("mouse_mclick", None),
+            VNC::MouseClick(3) => ("mouse_rclick", None),
+            VNC::MouseClick(n) => ("mouse_click_button", Some(n.to_string())),
+            VNC::MouseDoubleClick => ("mouse_dclick", None),
+            VNC::MouseKeyDown(true) => ("mouse_keydown", None),
+            VNC::MouseKeyDown(false) => ("mouse_keyup", None),
+            VNC::SendKey(s) => ("send_key", Some(s.clone())),
+            VNC::SendKeysym(codes) => (
+                "send_keysym",
+                Some(
+                    codes
+                        .iter()
+                        .map(|c| format!("{c:#x}"))
+                        .collect::<Vec<_>>()
+                        .join(","),
+                ),
+            ),
+            VNC::KeyDown(s) => ("send_key_down", Some(s.clone())),
+            VNC::KeyUp(s) => ("send_key_up", Some(s.clone())),
+            VNC::TypeString(s) => ("type_string", Some(s.clone())),
+            VNC::TypePassword(_) => ("type_password", Some("****".to_string())),
+            VNC::PasteString(s) => ("paste_string", Some(s.clone())),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use t_binding::{msg::MatchStats, MsgResError};
+
+    #[test]
+    fn test_event_json() {
+        let req = MsgReq::VNC(VNC::CheckScreen {
+            tags: vec!["login".to_string()],
+            threshold: 0.95,
+            timeout: Duration::from_secs(1),
+            click: false,
+            r#move: false,
+            delay: None,
+            area: None,
+        });
+        let mut e = Event::start(&req);
+        e.finish(&MsgRes::Matched("login".to_string()), Some(0.5));
+        e.ts = 1000;
+        e.duration_ms = 20;
+        assert_eq!(
+            serde_json::to_string(&e).unwrap(),
+            r#"{"ts":1000,"kind":"check_screen","tag":"login","result":"ok","similarity":0.5,"duration_ms":20}"#
+        );
+
+        let mut e = Event::start(&req);
+        let stats = MatchStats {
+            similarity: 0.97,
+            elapsed: Duration::from_millis(1500),
+            iterations: 3,
+        };
+        let res = MsgRes::ScreenMatched {
+            tag: "login".to_string(),
+            stats,
+        };
+        e.finish(&res, Some(0.97));
+        e.ts = 1000;
+        e.duration_ms = 1600;
+        assert_eq!(
+            serde_json::to_string(&e).unwrap(),
+            r#"{"ts":1000,"kind":"check_screen","tag":"login","result":"ok","similarity":0.97,"iterations":3,"elapsed_ms":1500,"duration_ms":1600}"#
+        );
+
+        let mut e = Event::start(&MsgReq::VNC(VNC::MouseClick(1)));
+        e.finish(&MsgRes::Error(MsgResError::Timeout), None);
+        e.ts = 0;
+        e.duration_ms = 0;
+        assert_eq!(
+            serde_json::to_string(&e).unwrap(),
+            r#"{"ts":0,"kind":"mouse_click","result":"error","duration_ms":0,"error":"timeout"}"#
+        );
+    }
+
+    #[test]
+    fn test_password_redacted() {
+        let req = MsgReq::VNC(VNC::TypePassword("hunter2".to_string()));
+        assert_eq!(req_kind(&req), ("type_password", Some("****".to_string())));
+        let req = MsgReq::WriteString {
+            console: None,
+            s: "hunter2\n".to_string(),
+            timeout: Duration::from_secs(1),
+            secret: true,
+        };
+        assert_eq!(req_kind(&req), ("write_password", Some("****".to_string())));
+    }
+}
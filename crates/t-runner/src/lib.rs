@@ -1,12 +1,16 @@
 mod driver;
 mod driver_for_script;
 mod engine;
+mod event;
+mod metrics;
 pub mod needle;
 mod server;
+mod stepper;
 pub use driver_for_script::DriverForScript;
 pub mod error;
 pub use driver::{Driver, DriverBuilder};
 use std::fmt::Display;
+pub use stepper::Stepper;
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right
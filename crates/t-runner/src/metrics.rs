@@ -0,0 +1,203 @@
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    time::{Duration, Instant},
+};
+use t_binding::MsgRes;
+use t_console::VNCStats;
+
+// counters of handled requests, rendered in prometheus text format
+#[derive(Default)]
+pub(crate) struct Metrics {
+    // (kind, result) -> count
+    requests: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+    // (count, total) of time from request loop receiving a request to its handler starting
+    dispatch: Mutex<(u64, Duration)>,
+    // (time, frames) when last rendered, vnc fps is averaged between two scrapes
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    last_frames: Mutex<Option<(Instant, u64)>>,
+}
+
+impl Metrics {
+    pub fn record(&self, kind: &'static str, res: &MsgRes) {
+        let result = match res {
+            MsgRes::Error(_) => "error",
+            _ => "ok",
+        };
+        *self.requests.lock().entry((kind, result)).or_default() += 1;
+    }
+
+    pub fn record_dispatch(&self, d: Duration) {
+        let mut dispatch = self.dispatch.lock();
+        dispatch.0 += 1;
+        dispatch.1 += d;
+    }
+
+    // only served with metrics feature
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub fn render(&self, vnc: Option<&VNCStats>) -> String {
+        let mut out = String::new();
+        out.push_str("# TYPE t_autotest_requests_total counter\n");
+        let requests = self.requests.lock();
+        for ((kind, result), n) in requests.iter() {
+            let _ = writeln!(
+                out,
+                "t_autotest_requests_total{{kind=\"{kind}\",result=\"{result}\"}} {n}"
+            );
+        }
+        // failed asserts are answered with done in soft mode and error otherwise, count both
+        let count = |of: &str| -> u64 {
+            requests
+                .iter()
+                .filter(|((kind, _), _)| *kind == of)
+                .map(|(_, n)| n)
+                .sum()
+        };
+        let (passed, failed) = (count("assert_passed"), count("assert_failed"));
+        drop(requests);
+        out.push_str("# TYPE t_autotest_assertions_passed_total counter\n");
+        let _ = writeln!(out, "t_autotest_assertions_passed_total {passed}");
+        out.push_str("# TYPE t_autotest_assertions_failed_total counter\n");
+        let _ = writeln!(out, "t_autotest_assertions_failed_total {failed}");
+        // per request overhead of the request loop, tune poll_interval_ms with it
+        let (count, total) = *self.dispatch.lock();
+        out.push_str("# TYPE t_autotest_dispatch_seconds summary\n");
+        let _ = writeln!(
+            out,
+            "t_autotest_dispatch_seconds_sum {:.6}",
+            total.as_secs_f64()
+        );
+        let _ = writeln!(out, "t_autotest_dispatch_seconds_count {count}");
+
+        let Some(vnc) = vnc else {
+            return out;
+        };
+        let (frames, now) = (vnc.frames(), Instant::now());
+        let fps = match self.last_frames.lock().replace((now, frames)) {
+            Some((last, last_frames)) if now > last && frames >= last_frames => {
+                (frames - last_frames) as f64 / (now - last).as_secs_f64()
+            }
+            _ => 0.,
+        };
+        out.push_str("# TYPE t_autotest_vnc_frames_total counter\n");
+        let _ = writeln!(out, "t_autotest_vnc_frames_total {frames}");
+        out.push_str("# TYPE t_autotest_vnc_fps gauge\n");
+        let _ = writeln!(out, "t_autotest_vnc_fps {fps:.2}");
+        out.push_str("# TYPE t_autotest_vnc_reconnects_total counter\n");
+        let _ = writeln!(out, "t_autotest_vnc_reconnects_total {}", vnc.reconnects());
+        out.push_str("# TYPE t_autotest_vnc_bells_total counter\n");
+        let _ = writeln!(out, "t_autotest_vnc_bells_total {}", vnc.bells());
+        out
+    }
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn serve(
+    addr: &str,
+    service: std::sync::Arc<crate::server::Service>,
+) -> std::io::Result<()> {
+    use std::{sync::atomic::Ordering, thread, time::Duration};
+    use tracing::{info, warn};
+
+    let server = tiny_http::Server::http(addr).map_err(std::io::Error::other)?;
+    info!(msg = "metrics served", addr = addr);
+    thread::spawn(move || {
+        // stopped driver sets interrupted
+        while !service.interrupted.load(Ordering::SeqCst) {
+            let req = match server.recv_timeout(Duration::from_secs(1)) {
+                Ok(Some(req)) => req,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!(msg = "metrics server stopped", reason = ?e);
+                    break;
+                }
+            };
+            let header = tiny_http::Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+                .expect("valid header");
+            let res = tiny_http::Response::from_string(service.metrics_text()).with_header(header);
+            if let Err(e) = req.respond(res) {
+                warn!(msg = "metrics response failed", reason = ?e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::Ordering;
+    use t_binding::MsgResError;
+
+    #[test]
+    fn test_render() {
+        let m = Metrics::default();
+        m.record("check_screen", &MsgRes::Done);
+        m.record("check_screen", &MsgRes::Error(MsgResError::Timeout));
+        m.record("assert_failed", &MsgRes::Done);
+        m.record(
+            "assert_failed",
+            &MsgRes::Error(MsgResError::String("hard".to_string())),
+        );
+        m.record("assert_passed", &MsgRes::Done);
+        let out = m.render(None);
+        assert!(out.contains("t_autotest_requests_total{kind=\"check_screen\",result=\"ok\"} 1\n"));
+        assert!(
+            out.contains("t_autotest_requests_total{kind=\"check_screen\",result=\"error\"} 1\n")
+        );
+        assert!(out.contains("t_autotest_assertions_passed_total 1\n"));
+        assert!(out.contains("t_autotest_assertions_failed_total 2\n"));
+        assert!(out.contains("t_autotest_dispatch_seconds_count 0\n"));
+        assert!(!out.contains("vnc"));
+
+        m.record_dispatch(Duration::from_millis(2));
+        m.record_dispatch(Duration::from_millis(3));
+        let out = m.render(None);
+        assert!(out.contains("t_autotest_dispatch_seconds_sum 0.005000\n"));
+        assert!(out.contains("t_autotest_dispatch_seconds_count 2\n"));
+
+        let vnc = VNCStats::default();
+        vnc.frames.store(30, Ordering::Relaxed);
+        vnc.reconnects.store(2, Ordering::Relaxed);
+        let out = m.render(Some(&vnc));
+        assert!(out.contains("t_autotest_vnc_frames_total 30\n"));
+        assert!(out.contains("t_autotest_vnc_fps 0.00\n"));
+        assert!(out.contains("t_autotest_vnc_reconnects_total 2\n"));
+        assert!(out.contains("t_autotest_vnc_bells_total 0\n"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_serve() {
+        use crate::DriverBuilder;
+        use std::{
+            io::{Read, Write},
+            net::{TcpListener, TcpStream},
+        };
+        use t_binding::api::{Api, RustApi};
+
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config =
+            t_config::Config::from_toml_str(&format!("metrics_addr = \"127.0.0.1:{port}\""))
+                .unwrap();
+        let mut d = DriverBuilder::new(Some(config)).build().unwrap();
+        d.start();
+        let api = RustApi::new(d.msg_tx.clone());
+        api.get_env("A".to_string()).unwrap();
+
+        let mut conn = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        conn.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").unwrap();
+        let mut res = String::new();
+        conn.read_to_string(&mut res).unwrap();
+        assert!(
+            res.contains("kind=\"get_config\",result=\"ok\"} 1"),
+            "{res}"
+        );
+        d.stop();
+    }
+}
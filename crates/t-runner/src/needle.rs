@@ -1,10 +1,14 @@
+use parking_lot::Mutex;
 use std::{
-    fs::File,
-    io::{BufReader, Read},
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, BufReader},
     path::{Path, PathBuf},
 };
 
+use image::imageops::{self, FilterType};
 use serde::{Deserialize, Serialize};
+use t_binding::msg::AreaSelector;
 use t_console::{Rect, PNG};
 use tracing::{info, warn};
 
@@ -14,12 +18,70 @@ pub struct Needle {
 }
 
 impl Needle {
+    // needle without json, region or the whole image is the only match area,
+    // region must not be empty and must lie inside the image
+    pub fn from_image(data: PNG, region: Option<Rect>) -> Result<Self, String> {
+        let rect = region.unwrap_or(Rect {
+            left: 0,
+            top: 0,
+            width: data.width,
+            height: data.height,
+        });
+        let fits = |start: u16, len: u16, max: u16| {
+            len > 0 && start.checked_add(len).is_some_and(|end| end <= max)
+        };
+        if !fits(rect.left, rect.width, data.width) || !fits(rect.top, rect.height, data.height) {
+            return Err(format!(
+                "region {}x{} at ({}, {}) is not inside image {}x{}",
+                rect.width, rect.height, rect.left, rect.top, data.width, data.height
+            ));
+        }
+        Ok(Self {
+            config: NeedleConfig {
+                areas: vec![Area {
+                    type_field: "match".to_string(),
+                    left: rect.left,
+                    top: rect.top,
+                    width: rect.width,
+                    height: rect.height,
+                    click: None,
+                    name: None,
+                }],
+                ..Default::default()
+            },
+            data,
+        })
+    }
+
     pub fn cmp(s: &PNG, needle: &Needle, min_same: Option<f32>) -> (f32, bool) {
         if needle.config.areas.is_empty() {
             warn!("this needle has no match ares");
             return (1.0, true);
         }
+        let scaled = Self::scale_to_ref(s, needle);
+        let s = scaled.as_ref().unwrap_or(s);
 
+        let grayscale = needle
+            .config
+            .property("grayscale")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        // anti-aliased text edges may shift by a pixel, ssim ignores it
+        let edge = needle
+            .config
+            .property("edge_tolerance")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let res = match needle.config.property("algo").and_then(|v| v.as_str()) {
+            Some("ssim") => Self::cmp_ssim(s, needle),
+            _ if grayscale => Self::cmp_gray(s, needle, edge),
+            _ if edge => Self::cmp_edge(s, needle),
+            _ => Self::cmp_pixel(s, needle),
+        };
+        (res, res >= min_same.unwrap_or(0.95))
+    }
+
+    fn cmp_pixel(s: &PNG, needle: &Needle) -> f32 {
         let mut not_same = 0;
         let mut all = 0;
         for area in needle.config.areas.iter() {
@@ -30,24 +92,229 @@ impl Needle {
 
         let res = 1. - (not_same as f32 / all as f32);
         info!(res = res, all = all, not_same = not_same);
-        (res, res >= min_same.unwrap_or(0.95))
+        res
+    }
+
+    // like cmp_pixel, but a pixel also matches if any screen pixel within 1px is the same
+    fn cmp_edge(s: &PNG, needle: &Needle) -> f32 {
+        if s.width != needle.data.width || s.height != needle.data.height {
+            return 0.;
+        }
+
+        let (all, not_same) = count_not_same(s, needle, true, |a, b| a == b);
+        let res = 1. - (not_same as f32 / all as f32);
+        info!(
+            res = res,
+            all = all,
+            not_same = not_same,
+            algo = "edge_tolerance"
+        );
+        res
     }
+
+    // compare luminance only, tolerate small color drift
+    fn cmp_gray(s: &PNG, needle: &Needle, edge: bool) -> f32 {
+        if s.width != needle.data.width || s.height != needle.data.height {
+            return 0.;
+        }
+
+        const TOLERANCE: f32 = 16.;
+        let (all, not_same) = count_not_same(s, needle, edge, |a, b| {
+            (luma(a) - luma(b)).abs() <= TOLERANCE
+        });
+        let res = 1. - (not_same as f32 / all as f32);
+        info!(
+            res = res,
+            all = all,
+            not_same = not_same,
+            algo = "grayscale"
+        );
+        res
+    }
+
+    // mean ssim of 8x8 windows, weighted by area size
+    fn cmp_ssim(s: &PNG, needle: &Needle) -> f32 {
+        if s.width != needle.data.width || s.height != needle.data.height {
+            return 0.;
+        }
+
+        const WINDOW: u16 = 8;
+        let mut sum = 0.;
+        let mut all = 0.;
+        for area in needle.config.areas.iter() {
+            for top in (area.top..area.top + area.height).step_by(WINDOW as usize) {
+                for left in (area.left..area.left + area.width).step_by(WINDOW as usize) {
+                    let rect = Rect {
+                        left,
+                        top,
+                        width: WINDOW.min(area.left + area.width - left),
+                        height: WINDOW.min(area.top + area.height - top),
+                    };
+                    let n = rect.width as f32 * rect.height as f32;
+                    sum += ssim_rect(s, &needle.data, &rect) * n;
+                    all += n;
+                }
+            }
+        }
+
+        let res = (sum / all).clamp(0., 1.);
+        info!(res = res, algo = "ssim");
+        res
+    }
+
+    // live screen resized to needle size, only if needle sets scale_to_ref and sizes differ,
+    // scale_filter is one of nearest, triangle (default), catmullrom, gaussian, lanczos3
+    fn scale_to_ref(s: &PNG, needle: &Needle) -> Option<PNG> {
+        let scale = needle
+            .config
+            .property("scale_to_ref")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let (width, height) = (needle.data.width, needle.data.height);
+        if !scale || (s.width == width && s.height == height) {
+            return None;
+        }
+        let filter = match needle
+            .config
+            .property("scale_filter")
+            .and_then(|v| v.as_str())
+        {
+            Some("nearest") => FilterType::Nearest,
+            Some("catmullrom") => FilterType::CatmullRom,
+            Some("gaussian") => FilterType::Gaussian,
+            Some("lanczos3") => FilterType::Lanczos3,
+            Some("triangle") | None => FilterType::Triangle,
+            Some(other) => {
+                warn!(msg = "unknown scale_filter, use triangle", filter = other);
+                FilterType::Triangle
+            }
+        };
+        let img = imageops::resize(&s.as_img().into_rgb8(), width as u32, height as u32, filter);
+        Some(PNG::new_with_data(width, height, img.into_raw(), 3))
+    }
+
+    // mark pixels which differ from needle in red, only inside match areas,
+    // a channel may drift by (1 - threshold) of its range, e.g. compression noise
+    pub fn diff(s: &PNG, needle: &Needle, threshold: f32) -> PNG {
+        let tolerance = ((1. - threshold).clamp(0., 1.) * 255.) as u8;
+        let scaled = Self::scale_to_ref(s, needle);
+        let s = scaled.as_ref().unwrap_or(s);
+        let mut diff = s.clone();
+        if s.width != needle.data.width || s.height != needle.data.height {
+            return diff;
+        }
+        for area in needle.config.areas.iter() {
+            for row in area.top..area.top + area.height {
+                for col in area.left..area.left + area.width {
+                    let (a, b) = (s.get(row, col), needle.data.get(row, col));
+                    if a.iter().zip(b).any(|(a, b)| a.abs_diff(*b) > tolerance) {
+                        diff.set(row, col, &[255, 0, 0]);
+                    }
+                }
+            }
+        }
+        diff
+    }
+}
+
+// (all, not_same) pixels in match areas, screen and needle must have the same size,
+// with edge, needle pixel is compared with the 3x3 screen neighborhood (dilation)
+fn count_not_same(
+    s: &PNG,
+    needle: &Needle,
+    edge: bool,
+    same: impl Fn(&[u8], &[u8]) -> bool,
+) -> (u32, u32) {
+    let radius = if edge { 1 } else { 0 };
+    let mut not_same = 0;
+    let mut all = 0;
+    for area in needle.config.areas.iter() {
+        for row in area.top..area.top + area.height {
+            for col in area.left..area.left + area.width {
+                all += 1;
+                let expected = needle.data.get(row, col);
+                let found =
+                    (row.saturating_sub(radius)..=(row + radius).min(s.height - 1)).any(|r| {
+                        (col.saturating_sub(radius)..=(col + radius).min(s.width - 1))
+                            .any(|c| same(s.get(r, c), expected))
+                    });
+                if !found {
+                    not_same += 1;
+                }
+            }
+        }
+    }
+    (all, not_same)
+}
+
+fn luma(p: &[u8]) -> f32 {
+    0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32
+}
+
+fn ssim_rect(a: &PNG, b: &PNG, rect: &Rect) -> f32 {
+    const C1: f32 = (0.01 * 255.) * (0.01 * 255.);
+    const C2: f32 = (0.03 * 255.) * (0.03 * 255.);
+
+    let n = rect.width as f32 * rect.height as f32;
+    let (mut sum_a, mut sum_b) = (0., 0.);
+    let (mut sum_aa, mut sum_bb, mut sum_ab) = (0., 0., 0.);
+    for row in rect.top..rect.top + rect.height {
+        for col in rect.left..rect.left + rect.width {
+            let pa = luma(a.get(row, col));
+            let pb = luma(b.get(row, col));
+            sum_a += pa;
+            sum_b += pb;
+            sum_aa += pa * pa;
+            sum_bb += pb * pb;
+            sum_ab += pa * pb;
+        }
+    }
+    let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+    let var_a = sum_aa / n - mean_a * mean_a;
+    let var_b = sum_bb / n - mean_b * mean_b;
+    let cov = sum_ab / n - mean_a * mean_b;
+
+    ((2. * mean_a * mean_b + C1) * (2. * cov + C2))
+        / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2))
 }
 
 pub struct NeedleManager {
-    dir: PathBuf,
+    dirs: Vec<PathBuf>,
+    // tags already warned as conflicting
+    conflicts: Mutex<HashSet<String>>,
 }
 
 impl NeedleManager {
     pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self::with_dirs([dir])
+    }
+
+    // dirs are searched in order, the first one has the highest priority
+    pub fn with_dirs(dirs: impl IntoIterator<Item = impl AsRef<Path>>) -> Self {
         Self {
-            dir: dir.as_ref().to_path_buf(),
+            dirs: dirs.into_iter().map(|d| d.as_ref().to_path_buf()).collect(),
+            conflicts: Mutex::new(HashSet::new()),
         }
     }
 
     pub fn load(&self, tag: &str) -> Option<Needle> {
-        let needle_png = self.load_image(self.dir.join(format!("{}.png", tag)))?;
-        let json: NeedleConfig = self.load_json(self.dir.join(format!("{}.json", tag)))?;
+        let mut found = self.dirs.iter().filter(|dir| {
+            dir.join(format!("{}.png", tag)).exists() && dir.join(format!("{}.json", tag)).exists()
+        });
+        let dir = found.next()?;
+        if let Some(other) = found.next() {
+            if self.conflicts.lock().insert(tag.to_string()) {
+                warn!(
+                    msg = "needle tag conflict",
+                    tag = tag,
+                    used = ?dir,
+                    ignored = ?other
+                );
+            }
+        }
+
+        let needle_png = self.load_image(dir.join(format!("{}.png", tag)))?;
+        let json: NeedleConfig = self.load_json(dir.join(format!("{}.json", tag)))?;
         Some(Needle {
             config: json,
             data: needle_png,
@@ -57,19 +324,14 @@ impl NeedleManager {
     pub fn load_image(&self, tag: impl AsRef<Path>) -> Option<PNG> {
         let needle_file = File::open(tag).ok()?;
         let needle_png = image::load(BufReader::new(needle_file), image::ImageFormat::Png).ok()?;
-        match needle_png {
-            image::DynamicImage::ImageRgb8(img) => {
-                let data = img.bytes();
-                let data = data.map(|x| x.unwrap()).collect::<Vec<u8>>();
-                Some(PNG::new_with_data(
-                    img.width() as u16,
-                    img.height() as u16,
-                    data,
-                    3,
-                ))
-            }
-            _ => None,
-        }
+        // images from other tools may have alpha channel
+        let img = needle_png.into_rgb8();
+        Some(PNG::new_with_data(
+            img.width() as u16,
+            img.height() as u16,
+            img.into_raw(),
+            3,
+        ))
     }
 
     pub fn load_json(&self, tag: impl AsRef<Path>) -> Option<NeedleConfig> {
@@ -78,18 +340,165 @@ impl NeedleManager {
         Some(json)
     }
 
+    // write screen as needle tag into the first dir, like needles saved by gui editor,
+    // the whole screen is the only match area
+    pub fn save(&self, tag: &str, screen: &PNG, source: Option<String>) -> io::Result<()> {
+        let Some(dir) = self.dirs.first() else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no needle dir"));
+        };
+        let png = dir.join(format!("{tag}.png"));
+        if let Some(parent) = png.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut config = Needle::from_image(screen.clone(), None)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .config;
+        config.tags = vec![tag.to_string()];
+        config.capture = Some(Capture::now(screen, source));
+        fs::write(&png, screen.to_png_bytes())?;
+        fs::write(
+            dir.join(format!("{tag}.json")),
+            serde_json::to_string_pretty(&config).map_err(io::Error::other)?,
+        )?;
+        info!(msg = "needle saved", tag = tag, dir = ?dir);
+        Ok(())
+    }
+
     pub fn cmp(&self, s: &PNG, filename: &str, min_same: Option<f32>) -> Option<(f32, bool)> {
         let needle = self.load(filename)?;
         Some(Needle::cmp(s, &needle, min_same))
     }
+
+    // every needle in dirs sorted by tag, needles shadowed by a former dir are skipped,
+    // only json and png header are read
+    pub fn list(&self) -> Vec<NeedleMeta> {
+        let mut res: Vec<NeedleMeta> = Vec::new();
+        for dir in self.dirs.iter() {
+            let mut jsons = Vec::new();
+            find_json(dir, &mut jsons);
+            for json in jsons {
+                let png = json.with_extension("png");
+                let Ok(tag) = json.with_extension("").strip_prefix(dir).map(|t| {
+                    t.components()
+                        .map(|c| c.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/")
+                }) else {
+                    continue;
+                };
+                if !png.exists() || res.iter().any(|m| m.tag == tag) {
+                    continue;
+                }
+                let (Some(config), Ok((width, height))) =
+                    (self.load_json(&json), image::image_dimensions(&png))
+                else {
+                    warn!(msg = "needle not valid, skipped", tag = tag);
+                    continue;
+                };
+                let size = (width as u16, height as u16);
+                res.push(NeedleMeta {
+                    tag,
+                    dir: dir.clone(),
+                    width,
+                    height,
+                    areas: config.areas.len(),
+                    clicks: config
+                        .areas
+                        .iter()
+                        .filter_map(|a| a.click_point_sized(size, size))
+                        .collect(),
+                    tags: config.tags,
+                });
+            }
+        }
+        res.sort_by(|a, b| a.tag.cmp(&b.tag));
+        res
+    }
+}
+
+// needle summary for tooling
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NeedleMeta {
+    pub tag: String,
+    pub dir: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub areas: usize,
+    // click points of clickable areas, in needle image coordinates
+    pub clicks: Vec<(u16, u16)>,
+    // tags in needle json
+    pub tags: Vec<String>,
+}
+
+fn find_json(dir: &Path, res: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_json(&path, res);
+        } else if path.extension().is_some_and(|e| e == "json") {
+            res.push(path);
+        }
+    }
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct NeedleConfig {
     pub areas: Vec<Area>,
-    pub properties: Vec<String>,
+    pub properties: Vec<serde_json::Value>,
     pub tags: Vec<String>,
+    // missing in needles saved by older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture: Option<Capture>,
+}
+
+// screen the needle was taken from, only for humans and tools, matching ignores it
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capture {
+    pub width: u16,
+    pub height: u16,
+    // iso 8601 local time
+    pub time: String,
+    // free-form note, e.g. which tool or firmware version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl Capture {
+    pub const TIME_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%S%:z";
+
+    // captured from screen just now
+    pub fn now(screen: &PNG, source: Option<String>) -> Self {
+        Self {
+            width: screen.width,
+            height: screen.height,
+            time: t_util::get_time_with_format(Self::TIME_FORMAT).unwrap_or_default(),
+            source,
+        }
+    }
+}
+
+impl NeedleConfig {
+    // find value of key in object properties, e.g. `{"algo": "ssim"}`
+    pub fn property(&self, key: &str) -> Option<&serde_json::Value> {
+        self.properties.iter().find_map(|p| p.get(key))
+    }
+
+    // area to click, first one with click point if no selector
+    pub fn click_area(&self, selector: Option<&AreaSelector>) -> Option<&Area> {
+        match selector {
+            None => self.areas.iter().find(|a| a.click.is_some()),
+            Some(AreaSelector::Index(i)) => self.areas.get(*i),
+            Some(AreaSelector::Name(name)) => self
+                .areas
+                .iter()
+                .find(|a| a.name.as_deref() == Some(name.as_str())),
+        }
+    }
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -102,12 +511,56 @@ pub struct Area {
     pub width: u16,
     pub height: u16,
     pub click: Option<AreaClick>,
+    // lets assert_and_click pick this area by name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct AreaClick {
+    #[serde(default)]
     pub left: u16,
+    #[serde(default)]
     pub top: u16,
+    // fraction of area width and height, take precedence over left and top
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub left_ratio: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_ratio: Option<f32>,
+}
+
+impl Area {
+    // click point on live screen, area is scaled if screen size differs from needle
+    pub fn click_point(&self, needle: &PNG, screen: &PNG) -> Option<(u16, u16)> {
+        self.click_point_sized((needle.width, needle.height), (screen.width, screen.height))
+    }
+
+    // like click_point, with (width, height) of needle and screen
+    fn click_point_sized(&self, needle: (u16, u16), screen: (u16, u16)) -> Option<(u16, u16)> {
+        let click = self.click.as_ref()?;
+        let scale = |v: u16, from: u16, to: u16| {
+            if from == 0 {
+                v as f32
+            } else {
+                v as f32 * to as f32 / from as f32
+            }
+        };
+        let left = scale(self.left, needle.0, screen.0);
+        let top = scale(self.top, needle.1, screen.1);
+        let width = scale(self.width, needle.0, screen.0);
+        let height = scale(self.height, needle.1, screen.1);
+
+        let x = match click.left_ratio {
+            Some(r) => width * r.clamp(0., 1.),
+            None => scale(click.left, needle.0, screen.0),
+        };
+        let y = match click.top_ratio {
+            Some(r) => height * r.clamp(0., 1.),
+            None => scale(click.top, needle.1, screen.1),
+        };
+        Some(((left + x) as u16, (top + y) as u16))
+    }
 }
 
 impl From<&Area> for Rect {
@@ -125,10 +578,11 @@ impl From<&Area> for Rect {
 mod test {
     use std::fs;
 
-    use super::NeedleManager;
-    use crate::needle::{Area, NeedleConfig};
-    use image::{ImageBuffer, Rgb};
-    use t_console::Rect;
+    use super::{Needle, NeedleManager};
+    use crate::needle::{Area, AreaClick, Capture, NeedleConfig};
+    use image::{ImageBuffer, Rgb, Rgba};
+    use t_binding::msg::AreaSelector;
+    use t_console::{Rect, PNG};
 
     fn init_needle_manager() -> NeedleManager {
         // 创建临时文件夹
@@ -222,9 +676,11 @@ mod test {
                     width: 5,
                     height: 5,
                     click: None,
+                    name: None,
                 }],
                 properties: Vec::new(),
-                tags: vec!["output".to_string()]
+                tags: vec!["output".to_string()],
+                capture: None,
             }
         );
 
@@ -240,4 +696,418 @@ mod test {
         let png2 = needle_mg.load_image("output2").unwrap();
         assert!(png.data.cmp_rect(&png2, &rect));
     }
+
+    fn needle_with_properties(data: PNG, properties: Vec<serde_json::Value>) -> Needle {
+        Needle {
+            config: NeedleConfig {
+                areas: vec![Area {
+                    type_field: "match".to_string(),
+                    left: 0,
+                    top: 0,
+                    width: data.width,
+                    height: data.height,
+                    click: None,
+                    name: None,
+                }],
+                properties,
+                tags: Vec::new(),
+                capture: None,
+            },
+            data,
+        }
+    }
+
+    #[test]
+    fn test_cmp_ssim() {
+        let mut data = PNG::new(16, 16, 3);
+        for row in 0..16 {
+            for col in 0..16 {
+                let v = (row * 8 + col) as u8;
+                data.set(row, col, &[v, v, v]);
+            }
+        }
+        // every pixel is a little brighter, pixel compare fails but ssim not
+        let mut screen = data.clone();
+        screen.data.iter_mut().for_each(|x| *x += 2);
+
+        let needle = needle_with_properties(data.clone(), vec![]);
+        let (_, matched) = Needle::cmp(&screen, &needle, None);
+        assert!(!matched);
+
+        let needle = needle_with_properties(data, vec![serde_json::json!({"algo": "ssim"})]);
+        let (similarity, matched) = Needle::cmp(&screen, &needle, None);
+        assert!(matched);
+        assert!((0. ..=1.).contains(&similarity));
+
+        let (similarity, _) = Needle::cmp(&PNG::new(16, 16, 3), &needle, None);
+        assert!(similarity < 0.95);
+    }
+
+    #[test]
+    fn test_cmp_scale_to_ref() {
+        // left half white, needle captured at half of the live resolution
+        let half = |width: u16, height: u16| {
+            let mut png = PNG::new(width, height, 3);
+            for row in 0..height {
+                for col in 0..width / 2 {
+                    png.set(row, col, &[255, 255, 255]);
+                }
+            }
+            png
+        };
+        let screen = half(32, 32);
+
+        let needle = needle_with_properties(half(16, 16), vec![]);
+        let (_, matched) = Needle::cmp(&screen, &needle, Some(0.8));
+        assert!(!matched);
+
+        // smoothing filters blend the column at the edge
+        for filter in ["nearest", "triangle"] {
+            let properties =
+                vec![serde_json::json!({"scale_to_ref": true, "scale_filter": filter})];
+            let needle = needle_with_properties(half(16, 16), properties);
+            let (_, matched) = Needle::cmp(&screen, &needle, Some(0.8));
+            assert!(matched, "{filter}");
+            let diff = Needle::diff(&screen, &needle, 0.8);
+            assert_eq!((diff.width, diff.height), (16, 16));
+        }
+    }
+
+    #[test]
+    fn test_cmp_grayscale() {
+        let mut data = PNG::new(8, 8, 3);
+        for row in 0..8 {
+            for col in 0..8 {
+                let v = (row * 16 + col * 8) as u8;
+                data.set(row, col, &[v, v / 2, 100]);
+            }
+        }
+        // uniform red tint
+        let mut screen = data.clone();
+        for row in 0..8 {
+            for col in 0..8 {
+                let p = screen.get(row, col).to_vec();
+                screen.set(row, col, &[p[0] + 20, p[1], p[2]]);
+            }
+        }
+
+        let needle = needle_with_properties(data.clone(), vec![]);
+        let (_, matched) = Needle::cmp(&screen, &needle, None);
+        assert!(!matched);
+
+        let needle = needle_with_properties(data, vec![serde_json::json!({"grayscale": true})]);
+        let (similarity, matched) = Needle::cmp(&screen, &needle, None);
+        assert!(matched);
+        assert_eq!(similarity, 1.);
+
+        let (_, matched) = Needle::cmp(&PNG::new(8, 8, 3), &needle, None);
+        assert!(!matched);
+    }
+
+    #[test]
+    fn test_cmp_edge_tolerance() {
+        let line = |col: u16| {
+            let mut png = PNG::new(8, 8, 3);
+            for row in 0..8 {
+                png.set(row, col, &[255, 255, 255]);
+            }
+            png
+        };
+        // text edge shifted by one pixel
+        let screen = line(4);
+
+        let needle = needle_with_properties(line(3), vec![]);
+        let (_, matched) = Needle::cmp(&screen, &needle, None);
+        assert!(!matched);
+
+        let props = vec![serde_json::json!({"edge_tolerance": true})];
+        let needle = needle_with_properties(line(3), props.clone());
+        let (similarity, matched) = Needle::cmp(&screen, &needle, None);
+        assert!(matched);
+        assert_eq!(similarity, 1.);
+
+        // two pixels away is a real difference
+        let (_, matched) = Needle::cmp(&line(5), &needle, None);
+        assert!(!matched);
+
+        let mut props = props;
+        props.push(serde_json::json!({"grayscale": true}));
+        let needle = needle_with_properties(line(3), props);
+        let (_, matched) = Needle::cmp(&screen, &needle, None);
+        assert!(matched);
+    }
+
+    fn write_needle(dir: &std::path::Path, tag: &str, tags: &[&str]) {
+        let image_buffer: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::new(5, 5);
+        image_buffer
+            .save_with_format(dir.join(format!("{tag}.png")), image::ImageFormat::Png)
+            .unwrap();
+        let cfg = NeedleConfig {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        };
+        fs::write(
+            dir.join(format!("{tag}.json")),
+            serde_json::to_string(&cfg).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_load_from_dirs() {
+        let root = std::env::temp_dir().join("needle-dirs");
+        let (local, shared) = (root.join("local"), root.join("shared"));
+        if fs::metadata(&root).is_ok() {
+            fs::remove_dir_all(&root).unwrap();
+        }
+        fs::create_dir_all(&local).unwrap();
+        fs::create_dir_all(&shared).unwrap();
+        write_needle(&local, "login", &["local"]);
+        write_needle(&shared, "login", &["shared"]);
+        write_needle(&shared, "desktop", &["shared"]);
+
+        let nmg = NeedleManager::with_dirs([&local, &shared]);
+        assert_eq!(nmg.load("login").unwrap().config.tags, vec!["local"]);
+        assert_eq!(nmg.load("desktop").unwrap().config.tags, vec!["shared"]);
+        assert!(nmg.load("missing").is_none());
+    }
+
+    #[test]
+    fn test_save() {
+        let root = std::env::temp_dir().join("needle-save");
+        if fs::metadata(&root).is_ok() {
+            fs::remove_dir_all(&root).unwrap();
+        }
+        let (first, second) = (root.join("first"), root.join("second"));
+        let nmg = NeedleManager::with_dirs([&first, &second]);
+        let screen = PNG::new_with_data(3, 2, vec![7; 18], 3);
+        nmg.save("sub/golden", &screen, Some("golden_screen".to_string()))
+            .unwrap();
+
+        let needle = nmg.load("sub/golden").unwrap();
+        assert!(first.join("sub/golden.png").exists());
+        assert_eq!(needle.config.tags, vec!["sub/golden"]);
+        assert_eq!(
+            (needle.config.areas[0].width, needle.config.areas[0].height),
+            (3, 2)
+        );
+        assert!(Needle::cmp(&screen, &needle, None).1);
+        let capture = needle.config.capture.unwrap();
+        assert_eq!((capture.width, capture.height), (3, 2));
+        assert_eq!(capture.source.as_deref(), Some("golden_screen"));
+        assert!(capture.time.contains('T'));
+    }
+
+    #[test]
+    fn test_capture_json() {
+        let old = r#"{"areas": [], "properties": [], "tags": ["login"]}"#;
+        let cfg: NeedleConfig = serde_json::from_str(old).unwrap();
+        assert_eq!(cfg.capture, None);
+        assert!(!serde_json::to_string(&cfg).unwrap().contains("capture"));
+
+        let json = r#"{"areas": [], "properties": [], "tags": ["login"],
+            "capture": {"width": 1920, "height": 1080, "time": "2024-03-27T10:00:00+08:00"}}"#;
+        let cfg: NeedleConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            cfg.capture,
+            Some(Capture {
+                width: 1920,
+                height: 1080,
+                time: "2024-03-27T10:00:00+08:00".to_string(),
+                source: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_list() {
+        let root = std::env::temp_dir().join("needle-list");
+        let (local, shared) = (root.join("local"), root.join("shared"));
+        if fs::metadata(&root).is_ok() {
+            fs::remove_dir_all(&root).unwrap();
+        }
+        fs::create_dir_all(local.join("sub")).unwrap();
+        fs::create_dir_all(&shared).unwrap();
+        write_needle(&local, "login", &["local"]);
+        write_needle(&shared, "login", &["shared"]);
+        write_needle(&shared, "desktop", &["shared"]);
+        write_needle(&local.join("sub"), "icon", &[]);
+        let cfg = NeedleConfig {
+            areas: vec![Area {
+                left: 1,
+                top: 1,
+                width: 2,
+                height: 2,
+                click: Some(AreaClick {
+                    left: 1,
+                    top: 1,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        fs::write(
+            local.join("sub/icon.json"),
+            serde_json::to_string(&cfg).unwrap(),
+        )
+        .unwrap();
+        // json without png is not a needle
+        fs::write(shared.join("orphan.json"), "{}").unwrap();
+
+        let list = NeedleManager::with_dirs([&local, &shared]).list();
+        let tags: Vec<&str> = list.iter().map(|m| m.tag.as_str()).collect();
+        assert_eq!(tags, vec!["desktop", "login", "sub/icon"]);
+        assert_eq!(list[1].dir, local);
+        assert_eq!(list[1].tags, vec!["local"]);
+        assert_eq!((list[2].width, list[2].height), (5, 5));
+        assert_eq!(list[2].areas, 1);
+        assert_eq!(list[2].clicks, vec![(2, 2)]);
+    }
+
+    #[test]
+    fn test_click_point() {
+        let mut area: Area = serde_json::from_str(
+            r#"{"type": "match", "left": 10, "top": 10, "width": 20, "height": 10,
+                "click": {"leftRatio": 0.5, "topRatio": 0.5}}"#,
+        )
+        .unwrap();
+        let needle = PNG::new(100, 100, 3);
+        assert_eq!(area.click_point(&needle, &needle), Some((20, 15)));
+        // live screen is twice as large
+        let screen = PNG::new(200, 200, 3);
+        assert_eq!(area.click_point(&needle, &screen), Some((40, 30)));
+
+        area.click = Some(AreaClick {
+            left: 4,
+            top: 2,
+            ..Default::default()
+        });
+        assert_eq!(area.click_point(&needle, &needle), Some((14, 12)));
+
+        area.click = None;
+        assert_eq!(area.click_point(&needle, &needle), None);
+    }
+
+    #[test]
+    fn test_click_area() {
+        let config: NeedleConfig = serde_json::from_str(
+            r#"{"areas": [
+                {"type": "match", "left": 0, "top": 0, "width": 10, "height": 10},
+                {"type": "match", "left": 10, "top": 0, "width": 10, "height": 10,
+                    "click": {"left": 5, "top": 5}},
+                {"type": "match", "left": 20, "top": 0, "width": 10, "height": 10,
+                    "click": {"left": 5, "top": 5}, "name": "next"}
+            ], "properties": [], "tags": []}"#,
+        )
+        .unwrap();
+        assert_eq!(config.click_area(None).unwrap().left, 10);
+        let next = AreaSelector::Name("next".to_string());
+        assert_eq!(config.click_area(Some(&next)).unwrap().left, 20);
+        assert_eq!(AreaSelector::parse("2"), AreaSelector::Index(2));
+        assert_eq!(AreaSelector::parse("next"), next);
+        assert_eq!(
+            config
+                .click_area(Some(&AreaSelector::Index(0)))
+                .unwrap()
+                .left,
+            0
+        );
+        assert_eq!(config.click_area(Some(&AreaSelector::Index(3))), None);
+        assert_eq!(
+            config.click_area(Some(&AreaSelector::Name("back".to_string()))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let needle = Needle {
+            config: NeedleConfig {
+                areas: vec![Area {
+                    type_field: "match".to_string(),
+                    left: 0,
+                    top: 0,
+                    width: 2,
+                    height: 2,
+                    click: None,
+                    name: None,
+                }],
+                properties: Vec::new(),
+                tags: Vec::new(),
+                capture: None,
+            },
+            data: PNG::new(3, 3, 3),
+        };
+        let mut screen = PNG::new(3, 3, 3);
+        screen.set(1, 1, &[0, 0, 255]);
+        // noise within tolerance
+        screen.set(0, 1, &[0, 10, 0]);
+        // outside of match area, should not be marked
+        screen.set(2, 2, &[0, 0, 255]);
+
+        let diff = Needle::diff(&screen, &needle, 0.95);
+        assert_eq!(diff.get(0, 0), &[0, 0, 0]);
+        assert_eq!(diff.get(0, 1), &[0, 10, 0]);
+        assert_eq!(diff.get(1, 1), &[255, 0, 0]);
+        assert_eq!(diff.get(2, 2), &[0, 0, 255]);
+        // exact compare marks the noise too
+        let diff = Needle::diff(&screen, &needle, 1.);
+        assert_eq!(diff.get(0, 1), &[255, 0, 0]);
+    }
+
+    #[test]
+    fn test_from_image() {
+        let mut screen = PNG::new(4, 4, 3);
+        // differs only outside of region
+        screen.set(3, 3, &[255, 255, 255]);
+
+        let needle = Needle::from_image(PNG::new(4, 4, 3), None).unwrap();
+        assert_eq!(needle.config.areas.len(), 1);
+        assert!(!Needle::cmp(&screen, &needle, Some(1.)).1);
+
+        let region = Rect {
+            left: 0,
+            top: 0,
+            width: 2,
+            height: 2,
+        };
+        let needle = Needle::from_image(PNG::new(4, 4, 3), Some(region)).unwrap();
+        assert!(Needle::cmp(&screen, &needle, Some(1.)).1);
+
+        let region = |left, top, width, height| {
+            Some(Rect {
+                left,
+                top,
+                width,
+                height,
+            })
+        };
+        assert!(Needle::from_image(PNG::new(4, 4, 3), region(2, 2, 2, 2)).is_ok());
+        // out of image, overflowing u16, empty
+        assert!(Needle::from_image(PNG::new(4, 4, 3), region(3, 0, 2, 2)).is_err());
+        assert!(Needle::from_image(PNG::new(4, 4, 3), region(0, u16::MAX, 1, 2)).is_err());
+        assert!(Needle::from_image(PNG::new(4, 4, 3), region(0, 0, 0, 2)).is_err());
+    }
+
+    #[test]
+    fn test_load_image_rgba() {
+        let path = std::env::temp_dir().join(format!("needle-rgba-{}.png", std::process::id()));
+        let mut image_buffer: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::new(2, 1);
+        image_buffer.put_pixel(0, 0, Rgba([10, 20, 30, 255]));
+        image_buffer.put_pixel(1, 0, Rgba([40, 50, 60, 0]));
+        image_buffer
+            .save_with_format(&path, image::ImageFormat::Png)
+            .unwrap();
+
+        // alpha is dropped, same layout as vnc screens
+        let png = NeedleManager::new(std::env::temp_dir())
+            .load_image(&path)
+            .unwrap();
+        assert_eq!((png.width, png.height, png.pixel_size), (2, 1, 3));
+        assert_eq!(png.get(0, 0), &[10, 20, 30]);
+        assert_eq!(png.get(0, 1), &[40, 50, 60]);
+
+        let _ = fs::remove_file(&path);
+    }
 }
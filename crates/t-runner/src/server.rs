@@ -1,19 +1,31 @@
-use crate::needle::{Needle, NeedleManager};
+use crate::{
+    event::{req_kind, Event, EventWriter},
+    metrics::Metrics,
+    needle::{Needle, NeedleManager},
+};
+use image::{imageops, ImageFormat, RgbImage};
+use parking_lot::Mutex;
+use regex::Regex;
 use std::{
+    collections::{hash_map::DefaultHasher, VecDeque},
     env::current_dir,
-    path::PathBuf,
-    str::FromStr,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
     sync::{
-        mpsc::{self, Receiver, Sender},
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
         Arc,
     },
-    thread,
-    time::{self, Duration, Instant},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
-use t_binding::{MsgReq, MsgRes, MsgResError};
+use t_binding::{msg::MatchStats, MsgReq, MsgRes, MsgResError, TextConsole};
 use t_config::{Config, ConsoleVNC};
-use t_console::{key, ConsoleError, Log, Serial, VNCEventReq, VNCEventRes, PNG, SSH, VNC};
-use t_util::{get_time, AMOption};
+use t_console::{
+    key, ConnectionState, ConsoleError, Log, Rect, Serial, VNCAddr, VNCEventReq, VNCEventRes, PNG,
+    SSH, VNC,
+};
+use t_util::{get_time, get_time_with_format, AMOption};
 use tracing::{debug, error, info, warn};
 
 pub(crate) struct Server {
@@ -36,6 +48,7 @@ impl Server {
         if let Ok(tx) = self.stop_rx.try_recv() {
             info!(msg = "runner handler thread stopped");
 
+            self.repo.dump_histories();
             self.repo.ssh.map_ref(|c| c.stop());
             info!(msg = "ssh stopped");
             self.repo.serial.map_ref(|s| s.stop());
@@ -43,6 +56,17 @@ impl Server {
             self.repo.vnc.map_ref(|s| s.stop());
             info!(msg = "vnc stopped");
 
+            // in-flight requests should return interrupt
+            self.repo.interrupted.store(true, Ordering::SeqCst);
+
+            // wait for the last screenshot written, log sender is dropped after vnc stopped
+            if let Some(handle) = self.repo.log_saver.lock().take() {
+                if handle.join().is_err() {
+                    warn!(msg = "log save thread panicked");
+                }
+            }
+            info!(msg = "log save thread stopped");
+
             if let Err(e) = tx.send(()) {
                 warn!(msg = "runner handler thread stopped", reason = ?e);
             }
@@ -51,21 +75,88 @@ impl Server {
         false
     }
 
+    // abort pending requests if none completed in max_idle_secs, the script gets a timeout,
+    // handler threads keep running until their own timeout
+    fn check_idle(&self, pending: &mut Vec<Responder>) {
+        pending.retain(|tx| tx.lock().is_some());
+        let Some(max_idle) = self.repo.config.and_then_ref(|c| c.max_idle_secs) else {
+            return;
+        };
+        let idle = self.repo.last_done.lock().elapsed();
+        if pending.is_empty() || idle < Duration::from_secs(max_idle) {
+            return;
+        }
+        warn!(
+            msg = "watchdog tripped, no request completed in max_idle_secs, abort pending requests",
+            max_idle_secs = max_idle,
+            pending = pending.len()
+        );
+        for tx in pending.drain(..) {
+            if let Some(tx) = tx.lock().take() {
+                let _ = tx.send(MsgRes::Error(MsgResError::Timeout));
+            }
+        }
+        *self.repo.last_done.lock() = Instant::now();
+    }
+
+    // periodic screenshot, saved in another thread to not block requests
+    fn auto_screenshot(&self, last: &mut Instant) {
+        let Some(secs) = self
+            .repo
+            .config
+            .and_then_ref(|c| c.auto_screenshot_secs)
+            .filter(|secs| *secs > 0)
+        else {
+            return;
+        };
+        if !self.repo.enable_screenshot || last.elapsed() < Duration::from_secs(secs) {
+            return;
+        }
+        *last = Instant::now();
+        let repo = self.repo.clone();
+        thread::spawn(move || {
+            repo.vnc.map_ref(|c| {
+                if c.send(VNCEventReq::TakeScreenShot("auto".to_string(), None))
+                    .is_err()
+                {
+                    warn!(msg = "auto screenshot failed");
+                }
+            });
+        });
+    }
+
     fn pool(&self) {
         // start script engine if in case mode
         info!(msg = "start msg handler thread");
 
+        let mut pending: Vec<Responder> = Vec::new();
+        let mut last_auto_screenshot = Instant::now();
         loop {
-            let deadline = Instant::now() + Duration::from_millis(16);
             if self.try_stop() {
                 break;
             }
+            self.check_idle(&mut pending);
+            self.auto_screenshot(&mut last_auto_screenshot);
 
-            // handle msg
-            match self.msg_rx.try_recv() {
+            // wake on request, or after poll interval to run the checks above
+            let interval = self
+                .repo
+                .config
+                .map_ref(|c| c.poll_interval())
+                .unwrap_or(Duration::from_millis(20));
+            match self.msg_rx.recv_timeout(interval) {
                 Ok((req, tx)) => {
+                    let received = Instant::now();
+                    // idle time counts from here if nothing was running
+                    pending.retain(|tx| tx.lock().is_some());
+                    if pending.is_empty() {
+                        *self.repo.last_done.lock() = Instant::now();
+                    }
+                    let tx = Arc::new(Mutex::new(Some(tx)));
+                    pending.push(tx.clone());
                     let repo = self.repo.clone();
                     thread::spawn(move || {
+                        repo.metrics.record_dispatch(received.elapsed());
                         let mut enable_log = true;
                         if matches!(req, MsgReq::VNC(t_binding::msg::VNC::TakeScreenShot)) {
                             enable_log = false;
@@ -74,33 +165,42 @@ impl Server {
                         if enable_log {
                             // info!(msg = "server recv req", req = ?req);
                         }
-                        let res = repo.handle_req(req);
+                        let mut res = repo.handle_req(req);
+                        if repo.interrupted.load(Ordering::SeqCst) {
+                            res = MsgRes::Error(MsgResError::Interrupt);
+                        }
 
                         if enable_log {
                             // info!(msg = format!("sending res: {:?}", res));
                         }
 
+                        *repo.last_done.lock() = Instant::now();
+                        let Some(tx) = tx.lock().take() else {
+                            warn!(msg = "request finished after aborted by watchdog");
+                            return;
+                        };
                         if let Err(e) = tx.send(res) {
                             warn!(msg = "script engine receiver closed", reason = ?e);
                         }
                     });
                 }
-                Err(e) => match e {
-                    mpsc::TryRecvError::Empty => {
-                        thread::sleep(Duration::from_millis(20));
-                    }
-                    mpsc::TryRecvError::Disconnected => {
-                        warn!(msg = "request sender closed unexpected", reason = ?e);
-                        break;
-                    }
-                },
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(e @ RecvTimeoutError::Disconnected) => {
+                    warn!(msg = "request sender closed unexpected", reason = ?e);
+                    break;
+                }
             }
-            thread::sleep(deadline - Instant::now());
         }
         info!(msg = "Runner loop stopped")
     }
 }
 
+// sender of a running request, taken by whoever answers first, the handler or the watchdog
+type Responder = Arc<Mutex<Option<Sender<MsgRes>>>>;
+
+// receives console name ("serial" or "ssh") and new output bytes
+pub(crate) type OutputHook = Arc<dyn Fn(&str, &[u8]) + Send + Sync>;
+
 pub(crate) struct Service {
     pub(crate) enable_screenshot: bool,
 
@@ -108,14 +208,32 @@ pub(crate) struct Service {
     pub(crate) ssh: AMOption<SSH>,
     pub(crate) serial: AMOption<Serial>,
     pub(crate) vnc: AMOption<VNC>,
+
+    pub(crate) events: Option<EventWriter>,
+    pub(crate) on_output: Option<OutputHook>,
+
+    pub(crate) interrupted: AtomicBool,
+    pub(crate) soft_assert: AtomicBool,
+    pub(crate) failures: Mutex<Vec<String>>,
+    pub(crate) log_saver: Mutex<Option<JoinHandle<()>>>,
+    // when a request last completed, for the idle watchdog
+    pub(crate) last_done: Mutex<Instant>,
+    pub(crate) metrics: Metrics,
+    // built from needle_dir on first use, dropped when config changes
+    pub(crate) needles: Mutex<Option<Arc<NeedleManager>>>,
 }
 
 impl Service {
-    fn start_save_logs(log_rx: Receiver<Log>, dir: PathBuf) {
+    fn start_save_logs(
+        log_rx: Receiver<Log>,
+        dir: PathBuf,
+        dedup: usize,
+        naming: LogNaming,
+        min_interval: Duration,
+    ) -> JoinHandle<()> {
         let path = dir;
         thread::spawn(move || {
             info!(msg = "log save thread started");
-            let mut path = path;
             if let Err(e) = std::fs::create_dir_all(&path) {
                 warn!(msg="create dir failed", reason=?e);
                 return;
@@ -123,8 +241,36 @@ impl Service {
             let mut trace_id = 0;
             let mut span_id = 0;
             let mut last_png = None::<Arc<PNG>>;
+            let mut recent = RecentFrames::new(dedup);
             let mut last_span = None::<String>;
-            while let Ok(log) = log_rx.recv() {
+            let mut current = None::<ScreenshotSpan>;
+            let mut throttle = ScreenshotThrottle::new(min_interval);
+            // last action screenshot held back by throttle
+            let mut pending = None::<Shot>;
+            loop {
+                let log = match pending.as_ref().and(throttle.deadline()) {
+                    Some(deadline) => match log_rx
+                        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    {
+                        Ok(log) => log,
+                        Err(RecvTimeoutError::Timeout) => {
+                            if let Some(shot) = pending.take() {
+                                if !shot.save(&path, &naming) {
+                                    return;
+                                }
+                                recent.push(&shot.screen);
+                                last_png = Some(shot.screen);
+                                throttle.saved(Instant::now());
+                            }
+                            continue;
+                        }
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    },
+                    None => match log_rx.recv() {
+                        Ok(log) => log,
+                        Err(_) => break,
+                    },
+                };
                 trace_id += 1;
                 match log {
                     Log::Screenshot {
@@ -136,11 +282,17 @@ impl Service {
                         if span.is_none() || span != last_span {
                             span_id += 1;
                             last_span.clone_from(&span);
+                            if let Some(finished) = current.take() {
+                                finished.flush(&path);
+                            }
+                            current = span
+                                .as_ref()
+                                .map(|span| ScreenshotSpan::new(format!("{span_id:05}-{span}")));
                         }
 
                         // skip same screen
                         if let Some(ref last) = last_png {
-                            if last.cmp(screen.as_ref()) {
+                            if last.cmp(screen.as_ref()) || recent.contains(&screen) {
                                 if let Err(e) = done_tx.send(()) {
                                     warn!(msg="done send failed", reason=?e);
                                 }
@@ -149,48 +301,111 @@ impl Service {
                             }
                         }
 
-                        // prepare dir
-                        if let Some(span) = span.as_ref() {
-                            path.push(format!("{span_id:05}-{span}"));
-                            if let Err(e) = std::fs::create_dir_all(&path) {
-                                warn!(msg="create span dir failed", reason=?e);
-                                return;
+                        let shot = Shot {
+                            screen,
+                            name,
+                            span,
+                            span_id,
+                            trace_id,
+                        };
+                        // check screen frames and user screenshots are never held back
+                        let now = Instant::now();
+                        if shot.span.is_none() && shot.name != "user" && throttle.hold(now) {
+                            debug!(msg = "hold screenshot, saved one recently");
+                            pending = Some(shot);
+                            if let Err(e) = done_tx.send(()) {
+                                warn!(msg="done send failed", reason=?e);
                             }
+                            continue;
                         }
 
-                        // save file
-                        let image_name =
-                            format!("{span_id:05}-{trace_id:05}-{}-{name}.png", get_time());
-                        path.push(&image_name);
-                        if let Err(e) = screen.as_img().save(&path) {
-                            warn!(msg="screenshot save failed", reason=?e);
-                        }
-
-                        // reset path
-                        if span.is_some() {
-                            path.pop();
+                        // held one is older, save it first
+                        for shot in pending.take().into_iter().chain(Some(shot)) {
+                            if !shot.save(&path, &naming) {
+                                return;
+                            }
+                            recent.push(&shot.screen);
+                            if shot.span.is_some() {
+                                if let Some(current) = current.as_mut() {
+                                    current.push(shot.screen.clone());
+                                }
+                            }
+                            last_png = Some(shot.screen);
                         }
-                        path.pop();
+                        throttle.saved(now);
 
                         // done
-                        last_png = Some(screen);
                         if let Err(e) = done_tx.send(()) {
                             warn!(msg="done send failed", reason=?e);
                         }
                     }
                 }
             }
+            if let Some(shot) = pending.take() {
+                shot.save(&path, &naming);
+            }
+            if let Some(finished) = current.take() {
+                finished.flush(&path);
+            }
             info!(msg = "vnc log save thread stopped");
-        });
+        })
+    }
+
+    // write full text console transcripts into log dir, for post-mortem debugging
+    fn dump_histories(&self) {
+        let Some(dir) = self.config.and_then_ref(|c| c.log_dir.clone()) else {
+            return;
+        };
+        for (name, history) in [
+            ("ssh_full_log.txt", self.ssh.map_ref(|c| c.dump_history())),
+            (
+                "serial_full_log.txt",
+                self.serial.map_ref(|c| c.dump_history()),
+            ),
+        ] {
+            let Some(history) = history else {
+                continue;
+            };
+            if let Err(e) = std::fs::write(Path::new(&dir).join(name), history) {
+                warn!(msg = "dump console history failed", file = name, reason = ?e);
+            }
+        }
+    }
+
+    pub(crate) fn set_serial(&self, s: Serial) {
+        if let Some(hook) = self.on_output.clone() {
+            s.set_on_output(Some(Box::new(move |b| hook("serial", b))));
+        }
+        self.serial.set(Some(s));
+    }
+
+    pub(crate) fn set_ssh(&self, s: SSH) {
+        if let Some(hook) = self.on_output.clone() {
+            s.set_on_output(Some(Box::new(move |b| hook("ssh", b))));
+        }
+        self.ssh.set(Some(s));
     }
 
     pub fn connect_with_config(&self, c: Config) -> Result<(), ConsoleError> {
-        // init serial
+        self.connect_serial(&c)?;
+        self.connect_ssh(&c)?;
+        self.connect_vnc(&c)
+    }
+
+    fn connect_serial(&self, c: &Config) -> Result<(), ConsoleError> {
+        let login_timeout = c.defaults().timeout();
         if let Some(c) = c.serial.clone() {
             self.serial.map_ref(|c| c.stop());
-            match Serial::new(c) {
-                Ok(s) => {
-                    self.serial.set(Some(s));
+            match Serial::new(c.clone()) {
+                Ok(mut s) => {
+                    if let Some(login) = c.login.as_ref() {
+                        if let Err(e) = s.login(login, login_timeout) {
+                            error!(msg="serial login failed", reason = ?e);
+                            s.stop();
+                            return Err(e);
+                        }
+                    }
+                    self.set_serial(s);
                     info!(msg = "serial connect success");
                 }
                 Err(e) => {
@@ -201,13 +416,15 @@ impl Service {
         } else {
             self.serial.set(None);
         }
+        Ok(())
+    }
 
-        // init ssh
+    fn connect_ssh(&self, c: &Config) -> Result<(), ConsoleError> {
         if let Some(c) = c.ssh.clone() {
             self.ssh.map_ref(|s| s.stop());
             match SSH::new(c) {
                 Ok(s) => {
-                    self.ssh.set(Some(s));
+                    self.set_ssh(s);
                     info!("ssh connect success");
                 }
                 Err(e) => {
@@ -218,21 +435,32 @@ impl Service {
         } else {
             self.ssh.set(None);
         }
+        Ok(())
+    }
 
-        // init vnc
+    fn connect_vnc(&self, c: &Config) -> Result<(), ConsoleError> {
         let build_vnc = move |vnc: ConsoleVNC| {
-            let addr = format!("{}:{}", vnc.host, vnc.port)
-                .parse()
+            let addr = VNCAddr::parse(&vnc.host, vnc.port)
                 .map_err(|e| ConsoleError::NoConnection(format!("vnc addr is not valid, {}", e)))?;
 
             let tx = if let Some(log_dir) = c.log_dir.as_ref() {
                 let (tx, rx) = mpsc::channel();
-                Self::start_save_logs(rx, log_dir.clone().into());
+                *self.log_saver.lock() = Some(Self::start_save_logs(
+                    rx,
+                    log_dir.clone().into(),
+                    c.screenshot_dedup.unwrap_or(0),
+                    LogNaming::new(
+                        c.log_filename_template.clone(),
+                        c.log_time_format.clone(),
+                        c.screenshot_format.clone(),
+                    ),
+                    vnc.min_screenshot_interval(),
+                ));
                 Some(tx)
             } else {
                 None
             };
-            let vnc_client = VNC::connect(addr, vnc.password.clone(), tx)
+            let vnc_client = VNC::connect(addr, &vnc, tx)
                 .map_err(|e| ConsoleError::NoConnection(e.to_string()))?;
             Ok::<VNC, ConsoleError>(vnc_client)
         };
@@ -252,16 +480,61 @@ impl Service {
         Ok(())
     }
 
+    // stop all consoles and connect again with current config, return consoles reconnected,
+    // failed ones are removed
+    fn reconnect(&self) -> Result<Vec<String>, MsgResError> {
+        let Some(c) = self.config.map_ref(|c| c.clone()) else {
+            return Err(MsgResError::String("no config to reconnect".to_string()));
+        };
+        self.ssh.map_ref(|c| c.stop());
+        self.serial.map_ref(|c| c.stop());
+        self.vnc.map_ref(|c| c.stop());
+        // old log sender is dropped with vnc, a new save thread starts on vnc connect
+        if let Some(handle) = self.log_saver.lock().take() {
+            if handle.join().is_err() {
+                warn!(msg = "log save thread panicked");
+            }
+        }
+
+        let mut reconnected = Vec::new();
+        for (name, configured, res) in [
+            ("serial", c.serial.is_some(), self.connect_serial(&c)),
+            ("ssh", c.ssh.is_some(), self.connect_ssh(&c)),
+            ("vnc", c.vnc.is_some(), self.connect_vnc(&c)),
+        ] {
+            match res {
+                Ok(()) if configured => reconnected.push(name.to_string()),
+                Ok(()) => {}
+                Err(e) => {
+                    warn!(msg = "reconnect failed", console = name, reason = ?e);
+                    match name {
+                        "serial" => self.serial.set(None),
+                        "ssh" => self.ssh.set(None),
+                        _ => self.vnc.set(None),
+                    }
+                }
+            }
+        }
+        info!(msg = "reconnect done", consoles = ?reconnected);
+        Ok(reconnected)
+    }
+
     fn handle_req(&self, req: MsgReq) -> MsgRes {
+        let event = self.events.as_ref().map(|_| Event::start(&req));
+        let (kind, _) = req_kind(&req);
+        // vnc requests take screenshot after each action already
+        let failure_name = (!matches!(req, MsgReq::VNC(_))).then(|| format!("failure-{kind}"));
+        let mut similarity = None;
         let res = match req {
             // common
             MsgReq::SetConfig { toml_str } => match Config::from_toml_str(&toml_str) {
                 Ok(c) => match &mut self.connect_with_config(c.clone()) {
                     Ok(()) => {
                         self.config.set(Some(c));
+                        *self.needles.lock() = None;
                         MsgRes::Done
                     }
-                    Err(e) => MsgRes::Error(MsgResError::String(format!(
+                    Err(e) => MsgRes::Error(MsgResError::ConnectionBroken(format!(
                         "connect failed, reason = {}",
                         e
                     ))),
@@ -271,23 +544,122 @@ impl Service {
                     e
                 ))),
             },
+            MsgReq::Reconnect => match self.reconnect() {
+                Ok(consoles) => MsgRes::Reconnected(consoles),
+                Err(e) => MsgRes::Error(e),
+            },
             MsgReq::GetConfig { key } => {
-                let v = self.config.and_then_ref(|c| {
-                    c.env
-                        .as_ref()
-                        .and_then(|e| e.get(&key).map(|v| v.to_string()))
-                });
+                let v = self
+                    .config
+                    .and_then_ref(|c| c.env.as_ref().and_then(|e| e.get(&key).cloned()));
                 MsgRes::ConfigValue(v)
             }
+            MsgReq::GetDefaultTimeout => MsgRes::DefaultTimeout(
+                self.config
+                    .map_ref(|c| c.defaults())
+                    .unwrap_or_default()
+                    .timeout(),
+            ),
+            MsgReq::GetDefaultThreshold => MsgRes::DefaultThreshold(
+                self.config
+                    .map_ref(|c| c.defaults())
+                    .unwrap_or_default()
+                    .threshold(),
+            ),
+            MsgReq::SetSoftAssert(on) => {
+                self.soft_assert.store(on, Ordering::SeqCst);
+                MsgRes::Done
+            }
+            MsgReq::AssertFailed { msg, code } => {
+                if self.soft_assert.load(Ordering::SeqCst) {
+                    warn!(msg = "soft assert failed, continue", reason = msg);
+                    self.failures.lock().push(msg);
+                    MsgRes::Done
+                } else if let Some(code) = code {
+                    MsgRes::Error(MsgResError::CommandFailed { code })
+                } else {
+                    MsgRes::Error(MsgResError::String(msg))
+                }
+            }
+            MsgReq::AssertPassed => MsgRes::Done,
+            MsgReq::CollectFailures { clear } => {
+                let mut failures = self.failures.lock();
+                MsgRes::Failures(if clear {
+                    std::mem::take(&mut *failures)
+                } else {
+                    failures.clone()
+                })
+            }
+            MsgReq::Batch(reqs) => {
+                let mut results = Vec::with_capacity(reqs.len());
+                for req in reqs {
+                    let res = self.handle_req(req);
+                    let failed = matches!(res, MsgRes::Error(_));
+                    results.push(res);
+                    if failed || self.interrupted.load(Ordering::SeqCst) {
+                        break;
+                    }
+                }
+                MsgRes::Batch(results)
+            }
+            MsgReq::ListNeedles => MsgRes::Needles(
+                self.needle_manager()
+                    .list()
+                    .into_iter()
+                    .map(|m| m.tag)
+                    .collect(),
+            ),
+            MsgReq::NeedleExists { tag } => {
+                MsgRes::NeedleExists(self.needle_manager().load(&tag).is_some())
+            }
+            // serial
+            MsgReq::SerialSendBreak { duration } => {
+                match self
+                    .serial
+                    .map_ref(|c| c.send_break(duration, Duration::from_secs(1)))
+                {
+                    Some(Ok(())) => MsgRes::Done,
+                    Some(Err(e)) => MsgRes::Error(MsgResError::ConnectionBroken(format!(
+                        "send break failed, reason = {}",
+                        e
+                    ))),
+                    None => MsgRes::Error(MsgResError::NoConsole("serial".to_string())),
+                }
+            }
             // ssh
-            MsgReq::SSHScriptRunSeperate { cmd, timeout: _ } => {
+            MsgReq::SSHScriptRunSeperate { cmd, timeout } => {
                 let client = &self.ssh;
                 let res = client
-                    .map_mut(|c| c.exec_seperate(&cmd))
-                    .unwrap_or(Ok((-1, "no ssh".to_string())))
-                    .map_err(|_| MsgResError::Timeout);
+                    .map_mut(|c| c.exec_seperate(&cmd, timeout).map_err(exec_err))
+                    .unwrap_or(Err(MsgResError::NoConsole("ssh".to_string())));
                 match res {
-                    Ok((code, value)) => MsgRes::ScriptRun { code, value },
+                    Ok((code, value, _)) => MsgRes::ScriptRun { code, value },
+                    Err(e) => MsgRes::Error(e),
+                }
+            }
+            MsgReq::ScriptRunFull { cmd, timeout } => {
+                // only ssh can split stderr from stdout, serial return empty stderr
+                let res = match (self.ssh.is_some(), self.serial.is_some()) {
+                    (true, _) => self
+                        .ssh
+                        .map_mut(|c| c.exec_seperate(&cmd, timeout).map_err(exec_err))
+                        .unwrap_or(Err(MsgResError::NoConsole("ssh".to_string()))),
+                    (_, true) => self
+                        .serial
+                        .map_mut(|c| {
+                            c.exec(timeout, &cmd)
+                                .map(|(code, value)| (code, value, "".to_string()))
+                                .map_err(console_err)
+                        })
+                        .unwrap_or(Err(MsgResError::NoConsole("serial".to_string()))),
+                    _ => Err(MsgResError::NoConsole("ssh or serial".to_string())),
+                };
+                match res {
+                    Ok((code, stdout, stderr)) => MsgRes::ScriptRunFull {
+                        code,
+                        stdout,
+                        stderr,
+                    },
                     Err(e) => MsgRes::Error(e),
                 }
             }
@@ -299,15 +671,15 @@ impl Service {
                 let res = match (console, self.ssh.is_some(), self.serial.is_some()) {
                     (None | Some(t_binding::TextConsole::Serial), _, true) => self
                         .serial
-                        .map_mut(|c| c.exec(timeout, &cmd))
-                        .unwrap_or(Ok((1, "no serial".to_string())))
-                        .map_err(|_| MsgResError::Timeout),
+                        .map_mut(|c| c.exec(timeout, &cmd).map_err(console_err))
+                        .unwrap_or(Err(MsgResError::NoConsole("serial".to_string()))),
                     (None | Some(t_binding::TextConsole::SSH), true, _) => self
                         .ssh
-                        .map_mut(|c| c.exec(timeout, &cmd))
-                        .unwrap_or(Ok((-1, "no ssh".to_string())))
-                        .map_err(|_| MsgResError::Timeout),
-                    _ => Err(MsgResError::String("no console supported".to_string())),
+                        .map_mut(|c| c.exec(timeout, &cmd).map_err(console_err))
+                        .unwrap_or(Err(MsgResError::NoConsole("ssh".to_string()))),
+                    _ => Err(MsgResError::NoConsole(
+                        console.map_or("ssh or serial", |c| c.name()).to_string(),
+                    )),
                 };
                 match res {
                     Ok((code, value)) => MsgRes::ScriptRun { code, value },
@@ -318,19 +690,32 @@ impl Service {
                 console,
                 s,
                 timeout,
+                secret,
             } => {
                 if let Err(e) = match (console, self.ssh.is_some(), self.serial.is_some()) {
                     (None | Some(t_binding::TextConsole::Serial), _, true) => self
                         .serial
-                        .map_mut(|c| c.write_string(&s, timeout))
-                        .expect("no serial")
-                        .map_err(|_| MsgResError::Timeout),
+                        .map_mut(|c| {
+                            match secret {
+                                true => c.write_password(&s, timeout),
+                                false => c.write_string(&s, timeout),
+                            }
+                            .map_err(console_err)
+                        })
+                        .unwrap_or(Err(MsgResError::NoConsole("serial".to_string()))),
                     (None | Some(t_binding::TextConsole::SSH), true, _) => self
                         .ssh
-                        .map_mut(|c| c.write_string(&s, timeout))
-                        .expect("no ssh")
-                        .map_err(|_| MsgResError::Timeout),
-                    _ => Err(MsgResError::String("no console supported".to_string())),
+                        .map_mut(|c| {
+                            match secret {
+                                true => c.write_password(&s, timeout),
+                                false => c.write_string(&s, timeout),
+                            }
+                            .map_err(console_err)
+                        })
+                        .unwrap_or(Err(MsgResError::NoConsole("ssh".to_string()))),
+                    _ => Err(MsgResError::NoConsole(
+                        console.map_or("ssh or serial", |c| c.name()).to_string(),
+                    )),
                 } {
                     MsgRes::Error(e)
                 } else {
@@ -345,39 +730,297 @@ impl Service {
                 if let Err(e) = match (console, self.ssh.is_some(), self.serial.is_some()) {
                     (None | Some(t_binding::TextConsole::Serial), _, true) => self
                         .serial
-                        .map_mut(|c| c.wait_string(timeout, &s))
-                        .expect("no serial")
-                        .map_err(|_| MsgResError::Timeout),
+                        .map_mut(|c| c.wait_string(timeout, &s).map_err(console_err))
+                        .unwrap_or(Err(MsgResError::NoConsole("serial".to_string()))),
                     (None | Some(t_binding::TextConsole::SSH), true, _) => self
                         .ssh
-                        .map_mut(|c| c.wait_string(timeout, &s))
-                        .expect("no ssh")
-                        .map_err(|_| MsgResError::Timeout),
-                    _ => Err(MsgResError::String("no console supported".to_string())),
+                        .map_mut(|c| c.wait_string(timeout, &s).map_err(console_err))
+                        .unwrap_or(Err(MsgResError::NoConsole("ssh".to_string()))),
+                    _ => Err(MsgResError::NoConsole(
+                        console.map_or("ssh or serial", |c| c.name()).to_string(),
+                    )),
                 } {
                     MsgRes::Error(e)
                 } else {
                     MsgRes::Done
                 }
             }
-            MsgReq::VNC(e) => self.handle_vnc_req(e),
+            MsgReq::WaitRegex {
+                console,
+                pattern,
+                timeout,
+            } => {
+                match match (console, self.ssh.is_some(), self.serial.is_some()) {
+                    (None | Some(t_binding::TextConsole::Serial), _, true) => self
+                        .serial
+                        .map_mut(|c| c.wait_regex(timeout, &pattern).map_err(console_err))
+                        .unwrap_or(Err(MsgResError::NoConsole("serial".to_string()))),
+                    (None | Some(t_binding::TextConsole::SSH), true, _) => self
+                        .ssh
+                        .map_mut(|c| c.wait_regex(timeout, &pattern).map_err(console_err))
+                        .unwrap_or(Err(MsgResError::NoConsole("ssh".to_string()))),
+                    _ => Err(MsgResError::NoConsole(
+                        console.map_or("ssh or serial", |c| c.name()).to_string(),
+                    )),
+                } {
+                    Ok(matched) => MsgRes::Matched(matched),
+                    Err(e) => MsgRes::Error(e),
+                }
+            }
+            MsgReq::Expect {
+                console,
+                steps,
+                timeout,
+            } => self.expect(console, &steps, timeout),
+            MsgReq::WaitAnyString { targets, timeout } => self.wait_any_string(&targets, timeout),
+            MsgReq::WaitBoot {
+                serial,
+                screen,
+                threshold,
+                timeout,
+            } => self.wait_boot(serial.as_deref(), screen.as_deref(), threshold, timeout),
+            MsgReq::VNC(e) => self.handle_vnc_req(e, &mut similarity),
         };
+        if let (MsgRes::Error(_), Some(name)) = (&res, failure_name) {
+            self.take_failure_screenshot(name);
+        }
+        if let (Some(events), Some(mut event)) = (self.events.as_ref(), event) {
+            event.finish(&res, similarity);
+            events.write(&event);
+        }
+        self.metrics.record(kind, &res);
         res
     }
 
-    pub fn handle_vnc_req(&self, req: t_binding::msg::VNC) -> MsgRes {
-        let nmg = NeedleManager::new(
-            self.config
-                .and_then_ref(|c| {
-                    c.vnc.as_ref().and_then(|vnc| {
-                        vnc.needle_dir
-                            .as_ref()
-                            .and_then(|d| PathBuf::from_str(d).ok())
-                    })
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub(crate) fn metrics_text(&self) -> String {
+        let vnc = self.vnc.map_ref(|c| c.stats.clone());
+        self.metrics.render(vnc.as_deref())
+    }
+
+    // poll every console in turn, so output on one console is not missed while waiting on another
+    fn wait_any_string(&self, targets: &[(TextConsole, String)], timeout: Duration) -> MsgRes {
+        // (target index, pattern) of each console, targets of missing consoles are ignored
+        let of = |console: TextConsole| -> Vec<(usize, &str)> {
+            targets
+                .iter()
+                .enumerate()
+                .filter(|(_, (c, _))| *c == console)
+                .map(|(i, (_, s))| (i, s.as_str()))
+                .collect()
+        };
+        let serial = of(TextConsole::Serial);
+        let ssh = of(TextConsole::SSH);
+        let serial = (self.serial.is_some() && !serial.is_empty()).then_some(serial);
+        let ssh = (self.ssh.is_some() && !ssh.is_empty()).then_some(ssh);
+        if serial.is_none() && ssh.is_none() {
+            return MsgRes::Error(MsgResError::NoConsole("ssh or serial".to_string()));
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            for (targets, is_serial) in [(&serial, true), (&ssh, false)] {
+                let Some(targets) = targets else {
+                    continue;
+                };
+                let patterns: Vec<&str> = targets.iter().map(|(_, s)| *s).collect();
+                let res = if is_serial {
+                    self.serial.map_ref(|c| c.poll_strings(&patterns))
+                } else {
+                    self.ssh.map_ref(|c| c.poll_strings(&patterns))
+                };
+                match res {
+                    Some(Ok(Some(i))) => return MsgRes::AnyMatched(targets[i].0),
+                    Some(Err(e)) => return MsgRes::Error(console_err(e)),
+                    _ => {}
+                }
+            }
+            if Instant::now() > deadline {
+                return MsgRes::Error(MsgResError::Timeout);
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+
+    // poll serial and screen in turn under one deadline
+    fn wait_boot(
+        &self,
+        serial: Option<&str>,
+        screen: Option<&str>,
+        threshold: f32,
+        timeout: Duration,
+    ) -> MsgRes {
+        let serial = match serial.filter(|_| self.serial.is_some()).map(Regex::new) {
+            Some(Err(e)) => return MsgRes::Error(MsgResError::RegexBuild(e.to_string())),
+            serial => serial.and_then(Result::ok),
+        };
+        let needle = match screen.filter(|_| self.vnc.is_some()) {
+            Some(tag) => match self.needle_manager().load(tag) {
+                Some(needle) => Some(needle),
+                None => {
+                    return MsgRes::Error(MsgResError::String(format!("needle {tag:?} not found")))
+                }
+            },
+            None => None,
+        };
+        if serial.is_none() && needle.is_none() {
+            return MsgRes::Error(MsgResError::NoConsole("serial or vnc".to_string()));
+        }
+
+        let interval = self
+            .config
+            .map_ref(|c| c.defaults())
+            .unwrap_or_default()
+            .assert_screen_retry_interval();
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(re) = serial.as_ref() {
+                match self.serial.map_ref(|c| c.poll_regex(re)) {
+                    Some(Ok(Some(matched))) => {
+                        info!(msg = "boot signaled by serial", matched = matched);
+                        return MsgRes::Booted("serial".to_string());
+                    }
+                    Some(Err(e)) => return MsgRes::Error(console_err(e)),
+                    _ => {}
+                }
+            }
+            if let Some(needle) = needle.as_ref() {
+                if let Some(Ok(VNCEventRes::Screen(s))) =
+                    self.vnc.map_ref(|c| c.send(VNCEventReq::GetScreenShot))
+                {
+                    if Needle::cmp(&s, needle, Some(threshold)).1 {
+                        info!(msg = "boot signaled by screen", tag = screen);
+                        return MsgRes::Booted("screen".to_string());
+                    }
+                }
+            }
+            if Instant::now() > deadline {
+                return MsgRes::Error(MsgResError::Timeout);
+            }
+            thread::sleep(interval);
+        }
+    }
+
+    // like expect(1), console lock is held for all steps so no output is taken by other requests
+    fn expect(
+        &self,
+        console: Option<TextConsole>,
+        steps: &[(String, String)],
+        timeout: Duration,
+    ) -> MsgRes {
+        let res = match (console, self.ssh.is_some(), self.serial.is_some()) {
+            (None | Some(TextConsole::Serial), _, true) => self.serial.map_mut(|c| {
+                expect_steps(steps, |pattern, response| {
+                    c.wait_string(timeout, pattern)
+                        .and_then(|_| c.write_string(response, timeout))
                 })
-                .unwrap_or(current_dir().unwrap()),
-        );
+            }),
+            (None | Some(TextConsole::SSH), true, _) => self.ssh.map_mut(|c| {
+                expect_steps(steps, |pattern, response| {
+                    c.wait_string(timeout, pattern)
+                        .and_then(|_| c.write_string(response, timeout))
+                })
+            }),
+            _ => None,
+        };
+        match res {
+            Some(Ok(())) => MsgRes::Done,
+            Some(Err((i, ConsoleError::Timeout))) => MsgRes::Error(MsgResError::String(format!(
+                "expect step {i} timeout, waiting for {:?}",
+                steps[i].0
+            ))),
+            Some(Err((_, e))) => MsgRes::Error(console_err(e)),
+            None => MsgRes::Error(MsgResError::NoConsole(
+                console.map_or("ssh or serial", |c| c.name()).to_string(),
+            )),
+        }
+    }
+
+    // (click settle, double click interval) from vnc config
+    fn click_delays(&self) -> (Duration, Duration) {
+        self.config
+            .and_then_ref(|c| {
+                c.vnc
+                    .as_ref()
+                    .map(|v| (v.click_settle(), v.double_click_interval()))
+            })
+            .unwrap_or((Duration::from_millis(1000), Duration::from_millis(100)))
+    }
+
+    // save current screen when text console request failed, to correlate with console log
+    fn take_failure_screenshot(&self, name: String) {
+        if !self.enable_screenshot {
+            return;
+        }
+        self.vnc.map_ref(|c| {
+            if c.send(VNCEventReq::TakeScreenShot(name, None)).is_err() {
+                warn!(msg = "take failure screenshot failed");
+            }
+        });
+    }
+
+    fn needle_manager(&self) -> Arc<NeedleManager> {
+        self.needles
+            .lock()
+            .get_or_insert_with(|| {
+                Arc::new(NeedleManager::with_dirs(
+                    self.config
+                        .and_then_ref(|c| {
+                            c.vnc
+                                .as_ref()
+                                .and_then(|vnc| vnc.needle_dir.as_ref().map(|d| d.dirs()))
+                        })
+                        .unwrap_or(vec![current_dir().unwrap()]),
+                ))
+            })
+            .clone()
+    }
+
+    // UPDATE_GOLDEN=1 in process env or config env recaptures golden needles
+    fn golden_update(&self) -> bool {
+        let on = |s: &str| matches!(s.trim(), "1" | "true");
+        std::env::var("UPDATE_GOLDEN").is_ok_and(|v| on(&v))
+            || self
+                .config
+                .and_then_ref(|c| c.env.as_ref()?.get("UPDATE_GOLDEN").cloned())
+                .is_some_and(|v| match v {
+                    toml::Value::Boolean(b) => b,
+                    toml::Value::Integer(i) => i == 1,
+                    toml::Value::String(s) => on(&s),
+                    _ => false,
+                })
+    }
+
+    // similarity is set if req is check screen
+    pub fn handle_vnc_req(
+        &self,
+        req: t_binding::msg::VNC,
+        similarity_out: &mut Option<f32>,
+    ) -> MsgRes {
+        let nmg = self.needle_manager();
         let mut take_screenshot = false;
+        let state_req = matches!(req, t_binding::msg::VNC::ConnectionState);
+        // inline image runs through the check_screen loop, with path as tag
+        let (req, image_region) = match req {
+            t_binding::msg::VNC::CheckImage {
+                path,
+                region,
+                threshold,
+                timeout,
+            } => (
+                t_binding::msg::VNC::CheckScreen {
+                    tags: vec![path],
+                    threshold,
+                    timeout,
+                    click: false,
+                    r#move: false,
+                    delay: None,
+                    area: None,
+                },
+                Some(region),
+            ),
+            req => (req, None),
+        };
         if let Some(res) = self.vnc.map_ref(|c| {
             let screenshotname;
             let res = match req {
@@ -399,38 +1042,148 @@ impl Service {
                         _ => MsgRes::Error(MsgResError::Timeout),
                     }
                 }
+                t_binding::msg::VNC::GetScreenSize => {
+                    screenshotname = "getscreensize".to_string();
+                    match c.send(VNCEventReq::GetSize) {
+                        Ok(VNCEventRes::Size(w, h)) => MsgRes::ScreenSize(w, h),
+                        _ => MsgRes::Error(MsgResError::Timeout),
+                    }
+                }
+                t_binding::msg::VNC::SetScreenSize { width, height } => {
+                    screenshotname = "setscreensize".to_string();
+                    match c.send(VNCEventReq::SetDesktopSize(width, height)) {
+                        Ok(VNCEventRes::DesktopSize(Ok(()))) => MsgRes::Done,
+                        Ok(VNCEventRes::DesktopSize(Err(reason))) => {
+                            MsgRes::Error(MsgResError::String(format!(
+                                "vnc server rejected resize to {width}x{height}, {reason}"
+                            )))
+                        }
+                        _ => MsgRes::Error(MsgResError::Timeout),
+                    }
+                }
+                t_binding::msg::VNC::ConnectionState => {
+                    screenshotname = "connectionstate".to_string();
+                    MsgRes::ConnectionState(c.connection_state())
+                }
+                t_binding::msg::VNC::BellCount => {
+                    screenshotname = "bellcount".to_string();
+                    MsgRes::BellCount(c.stats.bells())
+                }
+                t_binding::msg::VNC::WaitBell { timeout } => {
+                    screenshotname = "bell".to_string();
+                    // bells are counted by vnc event loop, like frames
+                    let before = c.stats.bells();
+                    let deadline = Instant::now() + timeout;
+                    loop {
+                        if c.stats.bells() > before {
+                            break MsgRes::Done;
+                        }
+                        if Instant::now() > deadline {
+                            break MsgRes::Error(MsgResError::Timeout);
+                        }
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                }
                 t_binding::msg::VNC::Refresh => {
                     screenshotname = "refresh".to_string();
                     match c.send(VNCEventReq::Refresh) {
-                        Ok(VNCEventRes::Screen(res)) => MsgRes::Screenshot(res),
+                        Ok(VNCEventRes::Done) => MsgRes::Done,
+                        _ => MsgRes::Error(MsgResError::Timeout),
+                    }
+                }
+                t_binding::msg::VNC::RefreshAndWait { timeout } => {
+                    screenshotname = "refresh".to_string();
+                    let deadline = Instant::now() + timeout;
+                    match c.send(VNCEventReq::Refresh) {
+                        Ok(VNCEventRes::Done) => {
+                            // frames are counted by vnc event loop, sampled once the request is
+                            // sent so frames finished before it don't count
+                            let before = c.stats.frames();
+                            loop {
+                                if c.stats.frames() > before {
+                                    break match c.send(VNCEventReq::GetScreenShot) {
+                                        Ok(VNCEventRes::Screen(res)) => MsgRes::Screenshot(res),
+                                        _ => MsgRes::Error(MsgResError::Timeout),
+                                    };
+                                }
+                                if Instant::now() > deadline {
+                                    break MsgRes::Error(MsgResError::Timeout);
+                                }
+                                thread::sleep(Duration::from_millis(10));
+                            }
+                        }
                         _ => MsgRes::Error(MsgResError::Timeout),
                     }
                 }
                 t_binding::msg::VNC::CheckScreen {
-                    tag,
+                    tags,
                     threshold,
                     timeout,
                     click,
                     r#move,
                     delay,
+                    area,
                 } => {
                     take_screenshot = false;
-                    screenshotname = format!("checkscreen-{tag}");
-                    let deadline = time::Instant::now() + timeout;
+                    let tag = tags.join(",");
+                    screenshotname = format!("checkscreen-{}", tag.replace(['/', '\\'], "_"));
+                    let start = Instant::now();
+                    let deadline = start + timeout;
+                    // zero timeout means compare once and return immediately
+                    let once = timeout.is_zero();
+                    let retry_interval = self
+                        .config
+                        .map_ref(|c| c.defaults())
+                        .unwrap_or_default()
+                        .assert_screen_retry_interval();
+                    let mut near_miss = NearMiss::new(
+                        self.config
+                            .map_ref(|c| c.defaults())
+                            .unwrap_or_default()
+                            .near_miss_margin(),
+                    );
                     let mut similarity: f32 = 0.;
+                    let mut last_failed: Option<(Arc<PNG>, Needle)> = None;
                     let mut i = 0;
-                    'res: loop {
+                    let res = 'res: loop {
                         i += 1;
-                        if Instant::now() > deadline {
+                        // always compare at least once
+                        if i > 1 && Instant::now() > deadline {
                             let msg = "match timeout";
                             info!(msg = msg, tag = tag, similarity = similarity);
+                            if let Some((s, needle)) = last_failed.take() {
+                                if self.enable_screenshot && c.send(VNCEventReq::SaveScreenShot(
+                                    Arc::new(Needle::diff(&s, &needle, threshold)), format!("{i}-diff"), Some(screenshotname.clone())
+                                )).is_err() {
+                                    warn!("save diff screenshot failed, vnc server may stopped unexpectedly")
+                                }
+                            }
                             break 'res MsgRes::Error(MsgResError::String(
                                 msg.to_string()
                             ));
                         }
                         match c.send(VNCEventReq::GetScreenShot) {
                             Ok(VNCEventRes::Screen(s)) => {
-                                let Some(needle) = nmg.load(&tag) else {
+                                let mut needles: Vec<(&String, Needle)> = Vec::new();
+                                for tag in tags.iter() {
+                                    let needle = match image_region {
+                                        Some(region) => match nmg.load_image(tag).map(|png| image_needle(png, region, &s)) {
+                                            Some(Err(msg)) => {
+                                                // would fail on every frame, give up now
+                                                let msg = format!("check image failed, {msg}");
+                                                error!(msg = msg, tag = tag);
+                                                break 'res MsgRes::Error(MsgResError::String(msg));
+                                            }
+                                            Some(Ok(needle)) => Some(needle),
+                                            None => None,
+                                        },
+                                        None => nmg.load(tag),
+                                    };
+                                    if let Some(needle) = needle {
+                                        needles.push((tag, needle));
+                                    }
+                                }
+                                if needles.is_empty() {
                                     let msg = "assert screen failed, needle file not found";
                                     error!(msg = msg, tag = tag);
                                     if self.enable_screenshot && c.send(VNCEventReq::TakeScreenShot(format!(
@@ -449,49 +1202,65 @@ impl Service {
                                     continue;
                                 };
 
-                                let (res_similarity, needle_match) = Needle::cmp(
-                                    &s,
-                                    &needle,
-                                    Some(threshold),
-                                ) ;
-
-                                similarity = res_similarity;
+                                let mut matched = None;
+                                let mut best: Option<(f32, Needle)> = None;
+                                for (needle_tag, needle) in needles {
+                                    let (res_similarity, needle_match) = Needle::cmp(
+                                        &s,
+                                        &needle,
+                                        Some(threshold),
+                                    );
+                                    if needle_match {
+                                        similarity = res_similarity;
+                                        matched = Some((needle_tag, needle));
+                                        break;
+                                    }
+                                    if !matches!(&best, Some((v, _)) if *v >= res_similarity) {
+                                        best = Some((res_similarity, needle));
+                                    }
+                                }
 
-                                if needle_match {
+                                if let Some((needle_tag, needle)) = matched {
+                                    let elapsed = start.elapsed();
                                     info!(
                                         msg = "match success",
-                                        tag = tag,
+                                        tag = needle_tag,
                                         similarity = similarity
                                     );
                                     if let Some(delay) = delay {
                                         thread::sleep(delay);
                                     }
                                     if click || r#move {
-                                        for area in needle.config.areas {
-                                            if let Some(point) = area.click {
-                                                let x = point.left + area.left;
-                                                let y = point.top + area.top;
-                                                    if r#move && !matches!(c.send(VNCEventReq::MouseMove(x, y)), Ok(VNCEventRes::Done)) {
-                                                        let msg ="check screen success, but mouse move failed";
-                                                        warn!(msg = msg);
-                                                        break 'res MsgRes::Error(MsgResError::String(msg.to_string()));
+                                        let point = needle
+                                            .config
+                                            .click_area(area.as_ref())
+                                            .and_then(|a| a.click_point(&needle.data, &s));
+                                        if area.is_some() && point.is_none() {
+                                            let msg = "check screen success, but selected area not found or not clickable";
+                                            warn!(msg = msg, area = ?area);
+                                            break 'res MsgRes::Error(MsgResError::String(msg.to_string()));
+                                        }
+                                        if let Some((x, y)) = point {
+                                                if r#move && !matches!(c.send(VNCEventReq::MouseMove(x, y)), Ok(VNCEventRes::Done)) {
+                                                    let msg ="check screen success, but mouse move failed";
+                                                    warn!(msg = msg);
+                                                    break 'res MsgRes::Error(MsgResError::String(msg.to_string()));
+                                            }
+                                            if click {
+                                                let (settle, _) = self.click_delays();
+                                                thread::sleep(settle);
+                                                if !matches!(c.send(VNCEventReq::MouseMove(x, y)), Ok(VNCEventRes::Done)) {
+                                                    let msg ="check screen success, but mouse move failed";
+                                                    warn!(msg = msg);
+                                                    break 'res MsgRes::Error(MsgResError::String(msg.to_string()));
                                                 }
-                                                if click {
-                                                    thread::sleep(Duration::from_millis(1000));
-                                                    if !matches!(c.send(VNCEventReq::MouseMove(x, y)), Ok(VNCEventRes::Done)) {
-                                                        let msg ="check screen success, but mouse move failed";
-                                                        warn!(msg = msg);
-                                                        break 'res MsgRes::Error(MsgResError::String(msg.to_string()));
-                                                    }
-                                                    thread::sleep(Duration::from_millis(1000));
-                                                    if !matches!(c.send(VNCEventReq::MouseClick(1)), Ok(VNCEventRes::Done)) {
-                                                        let msg ="check screen and mouse move success, but mouse click failed";
-                                                        warn!(msg = msg);
-                                                        break 'res MsgRes::Error(MsgResError::String(msg.to_string()));
-                                                    }
-                                                    thread::sleep(Duration::from_millis(1000));
+                                                thread::sleep(settle);
+                                                if !matches!(c.send(VNCEventReq::MouseClick(1)), Ok(VNCEventRes::Done)) {
+                                                    let msg ="check screen and mouse move success, but mouse click failed";
+                                                    warn!(msg = msg);
+                                                    break 'res MsgRes::Error(MsgResError::String(msg.to_string()));
                                                 }
-                                                break;
+                                                thread::sleep(settle);
                                             }
                                         }
                                             if !r#move && !matches!(c.send(VNCEventReq::MouseHide), Ok(VNCEventRes::Done)) {
@@ -500,14 +1269,27 @@ impl Service {
                                                 break 'res MsgRes::Error(MsgResError::String(msg.to_string()));
                                             }
                                     }
-                                    break 'res MsgRes::Done;
-                                } else {
+                                    break 'res MsgRes::ScreenMatched {
+                                        tag: needle_tag.clone(),
+                                        stats: MatchStats { similarity, elapsed, iterations: i },
+                                    };
+                                } else if let Some((res_similarity, needle)) = best {
+                                    similarity = res_similarity;
                                     if  self.enable_screenshot && c.send(VNCEventReq::TakeScreenShot(
                                         format!("{i}-success"), Some(screenshotname.clone())
                                     )).is_err() {
                                         warn!("take screenshot failed, vnc server may stopped unexpectedly")
                                     }
                                     warn!(msg = "match failed", tag = tag, similarity = similarity);
+                                    if near_miss.observe(similarity, threshold) {
+                                        warn!(
+                                            msg = "needle keeps scoring just below threshold, threshold may be too strict",
+                                            tag = tag,
+                                            best_similarity = near_miss.best,
+                                            threshold = threshold
+                                        );
+                                    }
+                                    last_failed = Some((s, needle));
                                 }
                             }
                             Ok(_) => {
@@ -515,8 +1297,18 @@ impl Service {
                             }
                             Err(_e) => break MsgRes::Error(MsgResError::Timeout),
                         }
-                        thread::sleep(Duration::from_millis(200));
-                    }
+                        if once {
+                            break 'res MsgRes::Error(MsgResError::String(
+                                "match failed".to_string()
+                            ));
+                        }
+                        thread::sleep(retry_interval);
+                    };
+                    *similarity_out = Some(similarity);
+                    res
+                }
+                t_binding::msg::VNC::CheckImage { .. } => {
+                    unreachable!("check image is converted to check screen")
                 }
                 t_binding::msg::VNC::MouseMove { x, y } => {
                     screenshotname = "mousemove".to_string();
@@ -525,6 +1317,20 @@ impl Service {
                         _ => MsgRes::Error(MsgResError::Timeout),
                     }
                 }
+                t_binding::msg::VNC::MouseMoveRelative { dx, dy } => {
+                    screenshotname = "mousemoverel".to_string();
+                    match c.send(VNCEventReq::MouseMoveRelative(dx, dy)) {
+                        Ok(VNCEventRes::Done) => MsgRes::Done,
+                        _ => MsgRes::Error(MsgResError::Timeout),
+                    }
+                }
+                t_binding::msg::VNC::GetMousePos => {
+                    screenshotname = "mousepos".to_string();
+                    match c.send(VNCEventReq::GetMousePos) {
+                        Ok(VNCEventRes::MousePos(x, y)) => MsgRes::MousePos(x, y),
+                        _ => MsgRes::Error(MsgResError::Timeout),
+                    }
+                }
                 t_binding::msg::VNC::MouseDrag { x, y } => {
                     screenshotname = "mousedrag".to_string();
                     match c.send(VNCEventReq::MouseDrag(x, y)) {
@@ -532,6 +1338,189 @@ impl Service {
                         _ => MsgRes::Error(MsgResError::Timeout),
                     }
                 }
+                t_binding::msg::VNC::WaitStill {
+                    timeout,
+                    stable_for,
+                } => {
+                    take_screenshot = false;
+                    screenshotname = "waitstill".to_string();
+                    let start = Instant::now();
+                    let deadline = start + timeout;
+                    let mut last: Option<Arc<PNG>> = None;
+                    let mut last_change = start;
+                    loop {
+                        // checked on every reply, no frame may ever arrive
+                        let now = Instant::now();
+                        if now > deadline {
+                            if last.is_none() {
+                                warn!(msg = "wait screen still timeout, no frame received");
+                                break MsgRes::Error(MsgResError::Timeout);
+                            }
+                            let msg = format!(
+                                "wait screen still timeout, screen was active for {:?}, still for {:?}",
+                                last_change - start,
+                                now - last_change
+                            );
+                            warn!(msg = msg);
+                            break MsgRes::Error(MsgResError::String(msg));
+                        }
+                        match c.send(VNCEventReq::GetScreenShot) {
+                            Ok(VNCEventRes::Screen(s)) => {
+                                let now = Instant::now();
+                                if !last.as_ref().is_some_and(|l| l.cmp(&s)) {
+                                    last_change = now;
+                                    last = Some(s);
+                                }
+                                if now - last_change >= stable_for {
+                                    break MsgRes::Done;
+                                }
+                            }
+                            Ok(_) => {
+                                warn!(msg = "invalid msg type");
+                            }
+                            Err(_e) => break MsgRes::Error(MsgResError::Timeout),
+                        }
+                        thread::sleep(Duration::from_millis(200));
+                    }
+                }
+                t_binding::msg::VNC::WaitGone {
+                    tag,
+                    threshold,
+                    timeout,
+                    stable_for,
+                } => {
+                    take_screenshot = false;
+                    screenshotname = format!("waitgone-{}", tag.replace(['/', '\\'], "_"));
+                    'res: {
+                        let Some(needle) = nmg.load(&tag) else {
+                            let msg = "wait screen gone failed, needle file not found";
+                            error!(msg = msg, tag = tag);
+                            break 'res MsgRes::Error(MsgResError::String(msg.to_string()));
+                        };
+                        let retry_interval = self
+                            .config
+                            .map_ref(|c| c.defaults())
+                            .unwrap_or_default()
+                            .assert_screen_retry_interval();
+                        let deadline = Instant::now() + timeout;
+                        let mut gone_since: Option<Instant> = None;
+                        // none until the first frame is compared
+                        let mut last_similarity: Option<f32> = None;
+                        loop {
+                            // checked on every reply, no frame may ever arrive
+                            let now = Instant::now();
+                            if now > deadline {
+                                let Some(similarity) = last_similarity else {
+                                    warn!(msg = "wait screen gone timeout, no frame received", tag = tag);
+                                    break 'res MsgRes::Error(MsgResError::Timeout);
+                                };
+                                let msg = match gone_since {
+                                    Some(since) => format!(
+                                        "wait screen gone timeout, gone for {:?} only, similarity {similarity}",
+                                        now - since
+                                    ),
+                                    None => format!(
+                                        "wait screen gone timeout, needle still on screen, similarity {similarity}"
+                                    ),
+                                };
+                                warn!(msg = msg, tag = tag);
+                                break 'res MsgRes::Error(MsgResError::String(msg));
+                            }
+                            match c.send(VNCEventReq::GetScreenShot) {
+                                Ok(VNCEventRes::Screen(s)) => {
+                                    let now = Instant::now();
+                                    let (similarity, matched) =
+                                        Needle::cmp(&s, &needle, Some(threshold));
+                                    *similarity_out = Some(similarity);
+                                    last_similarity = Some(similarity);
+                                    if matched {
+                                        gone_since = None;
+                                    } else if now - *gone_since.get_or_insert(now) >= stable_for {
+                                        break 'res MsgRes::Done;
+                                    }
+                                }
+                                Ok(_) => {
+                                    warn!(msg = "invalid msg type");
+                                }
+                                Err(_e) => break 'res MsgRes::Error(MsgResError::Timeout),
+                            }
+                            thread::sleep(retry_interval);
+                        }
+                    }
+                }
+                t_binding::msg::VNC::SaveGolden { tag } => {
+                    screenshotname = "golden".to_string();
+                    if nmg.load(&tag).is_some() && !self.golden_update() {
+                        MsgRes::GoldenSaved(false)
+                    } else {
+                        match c.send(VNCEventReq::GetScreenShot) {
+                            Ok(VNCEventRes::Screen(s)) => match nmg.save(&tag, &s, Some("golden_screen".to_string())) {
+                                Ok(()) => MsgRes::GoldenSaved(true),
+                                Err(e) => MsgRes::Error(MsgResError::String(format!(
+                                    "save golden needle failed, {e}"
+                                ))),
+                            },
+                            _ => MsgRes::Error(MsgResError::Timeout),
+                        }
+                    }
+                }
+                t_binding::msg::VNC::AssertNoScreen {
+                    tag,
+                    threshold,
+                    duration,
+                } => {
+                    take_screenshot = false;
+                    screenshotname = format!("noscreen-{}", tag.replace(['/', '\\'], "_"));
+                    'res: {
+                        let Some(needle) = nmg.load(&tag) else {
+                            let msg = "assert no screen failed, needle file not found";
+                            error!(msg = msg, tag = tag);
+                            break 'res MsgRes::Error(MsgResError::String(msg.to_string()));
+                        };
+                        let retry_interval = self
+                            .config
+                            .map_ref(|c| c.defaults())
+                            .unwrap_or_default()
+                            .assert_screen_retry_interval();
+                        let deadline = Instant::now() + duration;
+                        let mut seen = false;
+                        loop {
+                            // checked on every reply, no frame may ever arrive
+                            if Instant::now() > deadline {
+                                if seen {
+                                    break 'res MsgRes::Done;
+                                }
+                                // needle absent from a screen never seen is no pass
+                                let msg = "assert no screen failed, no frame received";
+                                warn!(msg = msg, tag = tag);
+                                break 'res MsgRes::Error(MsgResError::String(msg.to_string()));
+                            }
+                            match c.send(VNCEventReq::GetScreenShot) {
+                                Ok(VNCEventRes::Screen(s)) => {
+                                    seen = true;
+                                    let (similarity, matched) =
+                                        Needle::cmp(&s, &needle, Some(threshold));
+                                    *similarity_out = Some(similarity);
+                                    if matched {
+                                        warn!(msg = "needle appeared", tag = tag, similarity = similarity);
+                                        // keep the matched frame for the failure report
+                                        if self.enable_screenshot && c.send(VNCEventReq::SaveScreenShot(
+                                            s, "matched".to_string(), Some(screenshotname.clone())
+                                        )).is_err() {
+                                            warn!("save matched screenshot failed, vnc server may stopped unexpectedly")
+                                        }
+                                        break 'res MsgRes::Matched(tag);
+                                    }
+                                }
+                                Ok(_) => {
+                                    warn!(msg = "invalid msg type");
+                                }
+                                Err(_e) => break 'res MsgRes::Error(MsgResError::Timeout),
+                            }
+                            thread::sleep(retry_interval);
+                        }
+                    }
+                }
                 t_binding::msg::VNC::MouseHide => {
                     screenshotname = "mousehide".to_string();
                     match c.send(VNCEventReq::MouseHide) {
@@ -539,16 +1528,27 @@ impl Service {
                         _ => MsgRes::Error(MsgResError::Timeout),
                     }
                 }
-                t_binding::msg::VNC::MouseClick
-                | t_binding::msg::VNC::MouseRClick => {
+                t_binding::msg::VNC::MouseClick(n) => {
                     screenshotname = "mouseclick".to_string();
-                    let button = match req {
-                        t_binding::msg::VNC::MouseClick => 1,
-                        t_binding::msg::VNC::MouseRClick => 1 << 2,
-                        _ => unreachable!(),
-                    };
-                    match c.send(VNCEventReq::MouseClick(button)) {
-                        Ok(VNCEventRes::Done) => MsgRes::Done,
+                    match button_mask(n) {
+                        Some(button) => match c.send(VNCEventReq::MouseClick(button)) {
+                            Ok(VNCEventRes::Done) => MsgRes::Done,
+                            _ => MsgRes::Error(MsgResError::Timeout),
+                        },
+                        None => MsgRes::Error(MsgResError::String(format!(
+                            "mouse button should be in 1..=8, got {n}"
+                        ))),
+                    }
+                }
+                t_binding::msg::VNC::MouseDoubleClick => {
+                    screenshotname = "mousedoubleclick".to_string();
+                    let (_, interval) = self.click_delays();
+                    let res = c.send(VNCEventReq::MouseClick(1)).and_then(|res| {
+                        thread::sleep(interval);
+                        c.send(VNCEventReq::MouseClick(1)).map(|res2| (res, res2))
+                    });
+                    match res {
+                        Ok((VNCEventRes::Done, VNCEventRes::Done)) => MsgRes::Done,
                         _ => MsgRes::Error(MsgResError::Timeout),
                     }
                 }
@@ -566,27 +1566,94 @@ impl Service {
                 }
                 t_binding::msg::VNC::SendKey(s) => {
                     screenshotname = "sendkey".to_string();
-                    let mut keys = Vec::new();
-                    if s == "-" { keys.push(b'-' as u32)} else {
-                        let parts = s.split('-');
-                        for part in parts {
-                            if let Some(key) = key::from_str(part) {
-                                keys.push(key);
-                            }
+                    match parse_keys(&s) {
+                        Ok(keys) => match c.send(VNCEventReq::SendKey { keys }) {
+                            Ok(VNCEventRes::Done) => MsgRes::Done,
+                            _ => MsgRes::Error(MsgResError::Timeout),
+                        },
+                        Err(e) => MsgRes::Error(MsgResError::String(e)),
+                    }
+                }
+                t_binding::msg::VNC::SendKeysym(keys) => {
+                    screenshotname = "sendkey".to_string();
+                    if keys.is_empty() {
+                        MsgRes::Error(MsgResError::String("no keysym given".to_string()))
+                    } else {
+                        match c.send(VNCEventReq::SendKey { keys }) {
+                            Ok(VNCEventRes::Done) => MsgRes::Done,
+                            _ => MsgRes::Error(MsgResError::Timeout),
                         }
                     }
-                    match c.send(VNCEventReq::SendKey { keys }) {
-                        Ok(VNCEventRes::Done) => MsgRes::Done,
-                        _ => MsgRes::Error(MsgResError::Timeout),
+                }
+                t_binding::msg::VNC::KeyDown(s) => {
+                    screenshotname = "keydown".to_string();
+                    match parse_keys(&s) {
+                        Ok(keys) => match c.send(VNCEventReq::KeyDown(keys)) {
+                            Ok(VNCEventRes::Done) => MsgRes::Done,
+                            _ => MsgRes::Error(MsgResError::Timeout),
+                        },
+                        Err(e) => MsgRes::Error(MsgResError::String(e)),
+                    }
+                }
+                t_binding::msg::VNC::KeyUp(s) => {
+                    screenshotname = "keyup".to_string();
+                    match parse_keys(&s) {
+                        Ok(keys) => match c.send(VNCEventReq::KeyUp(keys)) {
+                            Ok(VNCEventRes::Done) => MsgRes::Done,
+                            _ => MsgRes::Error(MsgResError::Timeout),
+                        },
+                        Err(e) => MsgRes::Error(MsgResError::String(e)),
                     }
                 }
                 t_binding::msg::VNC::TypeString(s) => {
                     screenshotname = "typestring".to_string();
                     match c.send(VNCEventReq::TypeString(s)) {
                         Ok(VNCEventRes::Done) => MsgRes::Done,
+                        Ok(VNCEventRes::Error(e)) => MsgRes::Error(MsgResError::String(e)),
                         _ => MsgRes::Error(MsgResError::Timeout),
                     }
                 }
+                t_binding::msg::VNC::TypePassword(s) => {
+                    screenshotname = "typepassword".to_string();
+                    match c.send(VNCEventReq::TypePassword(s)) {
+                        Ok(VNCEventRes::Done) => MsgRes::Done,
+                        // message never has the password
+                        Ok(VNCEventRes::Error(e)) => MsgRes::Error(MsgResError::String(e)),
+                        _ => MsgRes::Error(MsgResError::Timeout),
+                    }
+                }
+                t_binding::msg::VNC::PasteString(s) => {
+                    screenshotname = "pastestring".to_string();
+                    let (clipboard, paste_key) = self
+                        .config
+                        .and_then_ref(|config| {
+                            config
+                                .vnc
+                                .as_ref()
+                                .map(|v| (v.clipboard.unwrap_or(false), v.paste_key.clone()))
+                        })
+                        .unwrap_or_default();
+                    if clipboard && can_paste(&s) {
+                        // paste_key is only pressed when clipboard is used
+                        match parse_keys(paste_key.as_deref().unwrap_or("ctrl-v")) {
+                            Err(e) => MsgRes::Error(MsgResError::String(format!("paste_key: {e}"))),
+                            Ok(keys) => match c.send(VNCEventReq::SetClipboard(s)) {
+                                Ok(VNCEventRes::Done) => match c.send(VNCEventReq::SendKey { keys }) {
+                                    Ok(VNCEventRes::Done) => MsgRes::Done,
+                                    _ => MsgRes::Error(MsgResError::Timeout),
+                                },
+                                _ => MsgRes::Error(MsgResError::Timeout),
+                            },
+                        }
+                    } else {
+                        info!(msg = "clipboard unavailable, type string instead");
+                        match c.send(VNCEventReq::TypeString(s)) {
+                            Ok(VNCEventRes::Done) => MsgRes::Done,
+                            Ok(VNCEventRes::Error(e)) => MsgRes::Error(MsgResError::String(e)),
+                            _ => MsgRes::Error(MsgResError::Timeout),
+                        }
+                    }
+                }
             };
             // take a screenshot after the action
             if self.enable_screenshot && c.send(VNCEventReq::TakeScreenShot(screenshotname, None)).is_err() {
@@ -595,15 +1662,587 @@ impl Service {
             res
         }) {
             res
+        } else if state_req {
+            MsgRes::ConnectionState(ConnectionState::Disconnected)
         } else {
-            MsgRes::Error(MsgResError::String("no vnc".to_string()))
+            MsgRes::Error(MsgResError::NoConsole("vnc".to_string()))
         }
     }
 }
 
+// hashes of recently saved frames, skip saving screen flickering between few states
+struct RecentFrames {
+    hashes: VecDeque<u64>,
+    cap: usize,
+}
+
+impl RecentFrames {
+    fn new(cap: usize) -> Self {
+        Self {
+            hashes: VecDeque::with_capacity(cap),
+            cap,
+        }
+    }
+
+    fn hash(screen: &PNG) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (screen.width, screen.height).hash(&mut hasher);
+        screen.data.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn contains(&mut self, screen: &PNG) -> bool {
+        if self.cap == 0 {
+            return false;
+        }
+        let hash = Self::hash(screen);
+        match self.hashes.iter().position(|h| *h == hash) {
+            Some(i) => {
+                // move to most recent
+                self.hashes.remove(i);
+                self.hashes.push_back(hash);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn push(&mut self, screen: &PNG) {
+        if self.cap == 0 {
+            return;
+        }
+        if self.hashes.len() == self.cap {
+            self.hashes.pop_front();
+        }
+        self.hashes.push_back(Self::hash(screen));
+    }
+}
+
+// one screenshot to save, with ids it got when received
+struct Shot {
+    screen: Arc<PNG>,
+    name: String,
+    span: Option<String>,
+    span_id: usize,
+    trace_id: usize,
+}
+
+impl Shot {
+    // false if span dir can't be created
+    fn save(&self, dir: &Path, naming: &LogNaming) -> bool {
+        let mut path = dir.to_path_buf();
+        if let Some(span) = self.span.as_ref() {
+            path.push(format!("{:05}-{span}", self.span_id));
+            if let Err(e) = std::fs::create_dir_all(&path) {
+                warn!(msg="create span dir failed", reason=?e);
+                return false;
+            }
+        }
+        path.push(naming.file_name(self.span_id, self.trace_id, &self.name));
+        if let Err(e) = self.screen.as_img().save_with_format(&path, naming.format) {
+            warn!(msg="screenshot save failed", reason=?e);
+        }
+        true
+    }
+}
+
+// limits how often action screenshots are saved, zero interval never holds
+struct ScreenshotThrottle {
+    interval: Duration,
+    last_saved: Option<Instant>,
+}
+
+impl ScreenshotThrottle {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_saved: None,
+        }
+    }
+
+    // true if a screenshot at now is too close to the last saved one
+    fn hold(&self, now: Instant) -> bool {
+        self.deadline().is_some_and(|deadline| now < deadline)
+    }
+
+    // when a held screenshot may be saved
+    fn deadline(&self) -> Option<Instant> {
+        self.last_saved
+            .filter(|_| !self.interval.is_zero())
+            .map(|t| t + self.interval)
+    }
+
+    fn saved(&mut self, now: Instant) {
+        self.last_saved = Some(now);
+    }
+}
+
+// distinct frames saved within one span, written as a contact sheet when span changes
+struct ScreenshotSpan {
+    data: Vec<Arc<PNG>>,
+    name: String,
+}
+
+impl ScreenshotSpan {
+    const THUMB_WIDTH: u32 = 320;
+    const COLS: u32 = 4;
+
+    fn new(name: String) -> Self {
+        Self {
+            data: Vec::new(),
+            name,
+        }
+    }
+
+    fn push(&mut self, screen: Arc<PNG>) {
+        self.data.push(screen);
+    }
+
+    // thumbnails in a grid, none if there is nothing to summarize
+    fn contact_sheet(&self) -> Option<RgbImage> {
+        if self.data.len() < 2 {
+            return None;
+        }
+        let thumbs: Vec<RgbImage> = self
+            .data
+            .iter()
+            .map(|screen| {
+                let img = screen.as_img().into_rgb8();
+                let height = (img.height() * Self::THUMB_WIDTH / img.width().max(1)).max(1);
+                imageops::thumbnail(&img, Self::THUMB_WIDTH, height)
+            })
+            .collect();
+        let cell_height = thumbs.iter().map(|t| t.height()).max().unwrap_or(1);
+        let cols = Self::COLS.min(thumbs.len() as u32);
+        let rows = (thumbs.len() as u32).div_ceil(cols);
+        let mut sheet = RgbImage::new(cols * Self::THUMB_WIDTH, rows * cell_height);
+        for (i, thumb) in thumbs.iter().enumerate() {
+            let (col, row) = (i as u32 % cols, i as u32 / cols);
+            imageops::overlay(
+                &mut sheet,
+                thumb,
+                (col * Self::THUMB_WIDTH) as i64,
+                (row * cell_height) as i64,
+            );
+        }
+        Some(sheet)
+    }
+
+    // dir is the log dir, sheet is saved into span dir
+    fn flush(self, dir: &Path) {
+        let Some(sheet) = self.contact_sheet() else {
+            return;
+        };
+        let path = dir.join(&self.name).join("contact-sheet.png");
+        if let Err(e) = sheet.save(&path) {
+            warn!(msg="contact sheet save failed", reason=?e);
+        }
+    }
+}
+
+// screenshot file name, built from `log_filename_template` and `log_time_format`
+struct LogNaming {
+    template: String,
+    time_format: String,
+    format: ImageFormat,
+}
+
+impl LogNaming {
+    const DEFAULT_TEMPLATE: &'static str = "{span}-{trace}-{ts}-{name}.png";
+
+    // format is an extension like "webp", default png
+    fn new(template: Option<String>, time_format: Option<String>, format: Option<String>) -> Self {
+        let format = format
+            .and_then(|f| {
+                let res = ImageFormat::from_extension(&f);
+                if res.is_none() {
+                    warn!(msg = "unsupported screenshot_format, use png", format = f);
+                }
+                res
+            })
+            .unwrap_or(ImageFormat::Png);
+        let mut template = template.unwrap_or_else(|| Self::DEFAULT_TEMPLATE.to_string());
+        // file extension follows format
+        if let Some(base) = template.strip_suffix(".png") {
+            template = format!("{base}.{}", format.extensions_str()[0]);
+        }
+        Self {
+            template,
+            time_format: time_format.unwrap_or_else(|| "%H:%M:%S".to_string()),
+            format,
+        }
+    }
+
+    fn file_name(&self, span_id: usize, trace_id: usize, name: &str) -> String {
+        let ts = get_time_with_format(&self.time_format).unwrap_or_else(|| {
+            warn!(
+                msg = "invalid log_time_format, fallback to default",
+                format = self.time_format
+            );
+            get_time()
+        });
+        self.render(span_id, trace_id, &ts, name)
+    }
+
+    fn render(&self, span_id: usize, trace_id: usize, ts: &str, name: &str) -> String {
+        self.template
+            .replace("{span}", &format!("{span_id:05}"))
+            .replace("{trace}", &format!("{trace_id:05}"))
+            .replace("{ts}", ts)
+            .replace("{name}", name)
+    }
+}
+
+// bare image compared with screen pixel by pixel, so both must have the same size
+fn image_needle(png: PNG, region: Option<Rect>, screen: &PNG) -> Result<Needle, String> {
+    if (png.width, png.height) != (screen.width, screen.height) {
+        return Err(format!(
+            "image is {}x{}, screen is {}x{}",
+            png.width, png.height, screen.width, screen.height
+        ));
+    }
+    Needle::from_image(png, region)
+}
+
+// parse key combo like "ctrl-alt-t"
+fn parse_keys(s: &str) -> Result<Vec<u32>, String> {
+    if s == "-" {
+        return Ok(vec![b'-' as u32]);
+    }
+    // check every part before sending, a typo must not press half of the combo
+    let mut keys = Vec::new();
+    let mut unknown = Vec::new();
+    for part in s.split('-') {
+        match key::from_str(part) {
+            Some(key) => keys.push(key),
+            None => unknown.push(part),
+        }
+    }
+    if unknown.is_empty() {
+        Ok(keys)
+    } else {
+        Err(format!("unknown key: {}", unknown.join(", ")))
+    }
+}
+
+// near misses in a row before warning
+const NEAR_MISS_TIMES: u32 = 5;
+
+// tracks failed matches scoring just below threshold in one check screen, warns only once
+struct NearMiss {
+    margin: f32,
+    count: u32,
+    best: f32,
+    warned: bool,
+}
+
+impl NearMiss {
+    fn new(margin: f32) -> Self {
+        Self {
+            margin,
+            count: 0,
+            best: 0.,
+            warned: false,
+        }
+    }
+
+    // true when the warning should be logged now
+    fn observe(&mut self, similarity: f32, threshold: f32) -> bool {
+        if self.margin <= 0. || self.warned {
+            return false;
+        }
+        if similarity < threshold && threshold - similarity <= self.margin {
+            self.count += 1;
+            self.best = self.best.max(similarity);
+        } else {
+            // best only reports the current run of near misses
+            self.count = 0;
+            self.best = 0.;
+        }
+        self.warned = self.count >= NEAR_MISS_TIMES;
+        self.warned
+    }
+}
+
+// cancel comes from a stop signal, keep it apart from timeout
+fn console_err(e: ConsoleError) -> MsgResError {
+    match e {
+        ConsoleError::Timeout => MsgResError::Timeout,
+        ConsoleError::Cancel => MsgResError::Interrupt,
+        ConsoleError::InvalidRegex(e) => MsgResError::RegexBuild(e.to_string()),
+        e => MsgResError::ConnectionBroken(e.to_string()),
+    }
+}
+
+fn exec_err(e: std::io::Error) -> MsgResError {
+    match e.kind() {
+        std::io::ErrorKind::TimedOut => MsgResError::Timeout,
+        _ => MsgResError::ConnectionBroken(e.to_string()),
+    }
+}
+
+// run steps in order, return index and error of the first failed step
+fn expect_steps<E>(
+    steps: &[(String, String)],
+    mut step: impl FnMut(&str, &str) -> Result<(), E>,
+) -> Result<(), (usize, E)> {
+    for (i, (pattern, response)) in steps.iter().enumerate() {
+        step(pattern, response).map_err(|e| (i, e))?;
+    }
+    Ok(())
+}
+
+// rfb pointer event has 8 button bits, button n is bit n-1
+fn button_mask(n: u8) -> Option<u8> {
+    (1..=8).contains(&n).then(|| 1 << (n - 1))
+}
+
+// client cut text is latin-1 only
+fn can_paste(s: &str) -> bool {
+    s.chars().all(|c| (c as u32) < 0x100)
+}
+
 #[cfg(test)]
 mod test {
+    use super::{
+        button_mask, can_paste, console_err, exec_err, expect_steps, parse_keys, ImageFormat,
+        LogNaming, NearMiss, RecentFrames, ScreenshotSpan, ScreenshotThrottle, Service,
+        NEAR_MISS_TIMES,
+    };
+    use std::{
+        sync::{mpsc, Arc},
+        time::{Duration, Instant},
+    };
+    use t_binding::MsgResError;
+    use t_console::Log;
+    use t_console::PNG;
 
     #[test]
     fn test_runner() {}
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(parse_keys("-").unwrap(), vec![b'-' as u32]);
+        assert_eq!(parse_keys("ctrl-alt-t").unwrap().len(), 3);
+        assert_eq!(parse_keys("crtl-c").unwrap_err(), "unknown key: crtl");
+        assert_eq!(
+            parse_keys("crtl-alt-dle").unwrap_err(),
+            "unknown key: crtl, dle"
+        );
+    }
+
+    #[test]
+    fn test_button_mask() {
+        assert_eq!(button_mask(1), Some(1));
+        assert_eq!(button_mask(3), Some(1 << 2));
+        assert_eq!(button_mask(8), Some(1 << 7));
+        assert_eq!(button_mask(0), None);
+        assert_eq!(button_mask(9), None);
+    }
+
+    #[test]
+    fn test_exec_err() {
+        use std::io::ErrorKind;
+        assert!(matches!(
+            exec_err(ErrorKind::TimedOut.into()),
+            MsgResError::Timeout
+        ));
+        assert!(matches!(
+            exec_err(ErrorKind::BrokenPipe.into()),
+            MsgResError::ConnectionBroken(_)
+        ));
+    }
+
+    #[test]
+    fn test_console_err() {
+        use t_console::ConsoleError;
+        assert!(matches!(
+            console_err(ConsoleError::Cancel),
+            MsgResError::Interrupt
+        ));
+        assert!(matches!(
+            console_err(ConsoleError::Timeout),
+            MsgResError::Timeout
+        ));
+        assert!(matches!(
+            console_err(ConsoleError::NoConnection("closed".to_string())),
+            MsgResError::ConnectionBroken(_)
+        ));
+    }
+
+    #[test]
+    fn test_expect_steps() {
+        let steps = [
+            ("login:".to_string(), "root".to_string()),
+            ("Password:".to_string(), "1234".to_string()),
+        ];
+        let mut seen = Vec::new();
+        assert!(expect_steps(&steps, |pattern, _| {
+            seen.push(pattern.to_string());
+            Ok::<_, ()>(())
+        })
+        .is_ok());
+        assert_eq!(seen, ["login:", "Password:"]);
+        assert_eq!(
+            expect_steps(&steps, |pattern, _| match pattern {
+                "Password:" => Err("cancel"),
+                _ => Ok(()),
+            }),
+            Err((1, "cancel"))
+        );
+    }
+
+    #[test]
+    fn test_can_paste() {
+        assert!(can_paste("key = \"värde\"\n"));
+        assert!(!can_paste("中文"));
+    }
+
+    #[test]
+    fn test_near_miss() {
+        let mut near_miss = NearMiss::new(0.02);
+        for _ in 1..NEAR_MISS_TIMES {
+            assert!(!near_miss.observe(0.99, 1.0));
+        }
+        // far below resets the count
+        assert!(!near_miss.observe(0.5, 1.0));
+        for _ in 1..NEAR_MISS_TIMES {
+            assert!(!near_miss.observe(0.985, 1.0));
+        }
+        assert!(near_miss.observe(0.98, 1.0));
+        assert_eq!(near_miss.best, 0.985);
+        // only once
+        assert!(!near_miss.observe(0.99, 1.0));
+
+        let mut disabled = NearMiss::new(0.);
+        for _ in 0..NEAR_MISS_TIMES * 2 {
+            assert!(!disabled.observe(0.99, 1.0));
+        }
+    }
+
+    #[test]
+    fn test_log_naming() {
+        let naming = LogNaming::new(None, None, None);
+        assert_eq!(
+            naming.render(1, 12, "10:00:00", "login"),
+            "00001-00012-10:00:00-login.png"
+        );
+
+        let naming = LogNaming::new(Some("{trace}_{name}_{ts}.png".to_string()), None, None);
+        assert_eq!(
+            naming.render(1, 12, "20240101T100000", "login"),
+            "00012_login_20240101T100000.png"
+        );
+
+        let naming = LogNaming::new(None, Some("%Y%m%dT%H%M%S".to_string()), None);
+        assert!(!naming.file_name(1, 1, "login").contains(':'));
+
+        let naming = LogNaming::new(None, None, Some("webp".to_string()));
+        assert_eq!(naming.format, ImageFormat::WebP);
+        assert!(naming.file_name(1, 1, "login").ends_with("-login.webp"));
+
+        // unknown format falls back to png
+        let naming = LogNaming::new(None, None, Some("xyz".to_string()));
+        assert_eq!(naming.format, ImageFormat::Png);
+    }
+
+    #[test]
+    fn test_screenshot_span() {
+        let mut span = ScreenshotSpan::new("00001-login".to_string());
+        span.push(Arc::new(PNG::new(8, 6, 3)));
+        assert!(span.contact_sheet().is_none());
+
+        span.push(Arc::new(PNG::new(8, 6, 3)));
+        span.push(Arc::new(PNG::new(8, 4, 3)));
+        let sheet = span.contact_sheet().unwrap();
+        assert_eq!(sheet.dimensions(), (3 * 320, 240));
+    }
+
+    #[test]
+    fn test_recent_frames() {
+        let a = PNG::new_with_data(1, 1, vec![1, 1, 1], 3);
+        let b = PNG::new_with_data(1, 1, vec![2, 2, 2], 3);
+        let c = PNG::new_with_data(1, 1, vec![3, 3, 3], 3);
+
+        let mut recent = RecentFrames::new(2);
+        recent.push(&a);
+        recent.push(&b);
+        assert!(recent.contains(&a));
+        // a is most recent now, so b is dropped
+        recent.push(&c);
+        assert!(recent.contains(&a));
+        assert!(!recent.contains(&b));
+        assert!(recent.contains(&c));
+
+        let mut recent = RecentFrames::new(0);
+        recent.push(&a);
+        assert!(!recent.contains(&a));
+    }
+
+    #[test]
+    fn test_screenshot_throttle() {
+        let now = Instant::now();
+        let mut throttle = ScreenshotThrottle::new(Duration::from_secs(1));
+        assert!(!throttle.hold(now));
+        throttle.saved(now);
+        assert!(throttle.hold(now + Duration::from_millis(500)));
+        assert!(!throttle.hold(now + Duration::from_secs(1)));
+        assert_eq!(throttle.deadline(), Some(now + Duration::from_secs(1)));
+
+        let mut throttle = ScreenshotThrottle::new(Duration::ZERO);
+        throttle.saved(now);
+        assert!(!throttle.hold(now));
+        assert_eq!(throttle.deadline(), None);
+    }
+
+    #[test]
+    fn test_save_logs_throttled() {
+        let dir = std::env::temp_dir().join(format!("t-runner-throttle-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (tx, rx) = mpsc::channel();
+        let saver = Service::start_save_logs(
+            rx,
+            dir.clone(),
+            0,
+            LogNaming::new(Some("{trace}-{name}.png".to_string()), None, None),
+            Duration::from_secs(3600),
+        );
+        let send = |pixel: u8, name: &str, span: Option<&str>| {
+            let (done_tx, done_rx) = mpsc::channel();
+            tx.send(Log::Screenshot {
+                screen: Arc::new(PNG::new_with_data(1, 1, vec![pixel; 3], 3)),
+                name: name.to_string(),
+                span: span.map(|s| s.to_string()),
+                done_tx,
+            })
+            .unwrap();
+            done_rx.recv().unwrap();
+        };
+        send(1, "mousemove", None);
+        send(2, "mousemove", None);
+        send(3, "mousemove", None);
+        send(4, "user", None);
+        send(5, "mousemove", None);
+        send(6, "1-failed", Some("check"));
+        drop(tx);
+        saver.join().unwrap();
+
+        let mut files: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        // 2 is replaced by 3, held 3 is saved before user, 5 before check frame
+        assert_eq!(
+            files,
+            vec![
+                "00001-mousemove.png",
+                "00003-mousemove.png",
+                "00004-user.png",
+                "00005-mousemove.png",
+                "00006-check"
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,138 @@
+use crate::event::req_kind;
+use parking_lot::{Condvar, Mutex};
+use std::{
+    sync::{mpsc, Arc},
+    thread,
+};
+use t_binding::{api::ApiTx, MsgReq, MsgRes};
+
+// sits between a script and the driver, while paused each request is held until step or resume,
+// requests not sent through bridge, e.g. the gui's own, are never held
+#[derive(Clone, Default)]
+pub struct Stepper {
+    inner: Arc<(Mutex<StepState>, Condvar)>,
+}
+
+#[derive(Default)]
+struct StepState {
+    paused: bool,
+    // held request is allowed to go on
+    step: bool,
+    // kind and tag of the request held now
+    held: Option<String>,
+}
+
+impl Stepper {
+    // api tx for a script, requests are forwarded to driver
+    pub fn bridge(&self, driver: ApiTx) -> ApiTx {
+        let (tx, rx) = mpsc::channel::<(MsgReq, mpsc::Sender<MsgRes>)>();
+        let stepper = self.clone();
+        thread::spawn(move || {
+            for (req, res_tx) in rx {
+                stepper.wait(&req);
+                if driver.send((req, res_tx)).is_err() {
+                    break;
+                }
+            }
+        });
+        tx
+    }
+
+    fn wait(&self, req: &MsgReq) {
+        let (state, cvar) = &*self.inner;
+        let mut state = state.lock();
+        if !state.paused {
+            return;
+        }
+        let (kind, tag) = req_kind(req);
+        state.held = Some(match tag {
+            Some(tag) => format!("{kind} {tag}"),
+            None => kind.to_string(),
+        });
+        while state.paused && !state.step {
+            cvar.wait(&mut state);
+        }
+        state.step = false;
+        state.held = None;
+    }
+
+    // resuming lets held request go on
+    pub fn set_paused(&self, paused: bool) {
+        let (state, cvar) = &*self.inner;
+        let mut state = state.lock();
+        state.paused = paused;
+        cvar.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.inner.0.lock().paused
+    }
+
+    // let the held request go on, the next one is held again
+    pub fn step(&self) {
+        let (state, cvar) = &*self.inner;
+        let mut state = state.lock();
+        // a second click before held one wakes up doesn't let the next one go
+        if state.held.is_some() {
+            state.step = true;
+            cvar.notify_all();
+        }
+    }
+
+    pub fn held(&self) -> Option<String> {
+        self.inner.0.lock().held.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, Instant};
+    use t_binding::msg::VNC;
+
+    fn wait_held(stepper: &Stepper) -> Option<String> {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            if let Some(held) = stepper.held() {
+                return Some(held);
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        None
+    }
+
+    #[test]
+    fn test_step() {
+        // driver answering everything with done
+        let (driver, rx) = mpsc::channel::<(MsgReq, mpsc::Sender<MsgRes>)>();
+        thread::spawn(move || {
+            for (_, tx) in rx {
+                let _ = tx.send(MsgRes::Done);
+            }
+        });
+        let stepper = Stepper::default();
+        let script = stepper.bridge(driver);
+        let send = |req: MsgReq| {
+            let (tx, rx) = mpsc::channel();
+            script.send((req, tx)).unwrap();
+            rx
+        };
+
+        assert!(send(MsgReq::VNC(VNC::MouseClick(1))).recv().is_ok());
+
+        stepper.set_paused(true);
+        // nothing is held, step is not kept for later
+        stepper.step();
+        let res = send(MsgReq::VNC(VNC::SendKey("ret".to_string())));
+        assert_eq!(wait_held(&stepper).as_deref(), Some("send_key ret"));
+        assert!(res.recv_timeout(Duration::from_millis(100)).is_err());
+        stepper.step();
+        assert!(res.recv_timeout(Duration::from_secs(2)).is_ok());
+
+        let res = send(MsgReq::VNC(VNC::TypePassword("secret".to_string())));
+        assert_eq!(wait_held(&stepper).as_deref(), Some("type_password ****"));
+        stepper.set_paused(false);
+        assert!(res.recv_timeout(Duration::from_secs(2)).is_ok());
+        assert_eq!(stepper.held(), None);
+    }
+}
@@ -72,6 +72,14 @@ pub fn get_time() -> String {
     now.format("%H:%M:%S").to_string()
 }
 
+// None if fmt is not a valid strftime format
+pub fn get_time_with_format(fmt: &str) -> Option<String> {
+    use std::fmt::Write;
+    let mut s = String::new();
+    write!(s, "{}", Local::now().format(fmt)).ok()?;
+    Some(s)
+}
+
 pub fn get_date() -> String {
     let now: DateTime<Local> = Local::now();
     now.format("%Y-%m-%d").to_string()